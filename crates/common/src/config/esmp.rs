@@ -0,0 +1,454 @@
+/*
+ * SPDX-FileCopyrightText: 2020 Stalwart Labs LLC <hello@stalw.art>
+ *
+ * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
+ */
+
+use std::time::Duration;
+
+use ahash::AHashMap;
+use base64::{Engine, engine::general_purpose};
+use utils::config::{Config, Rate, cron::SimpleCron, utils::ParseValue};
+
+#[derive(Default, Clone)]
+pub struct EsmpConfig {
+    pub replay_window: Duration,
+    pub limits: EsmpLimits,
+    pub listener_limits: AHashMap<String, EsmpLimits>,
+    pub federation: EsmpFederation,
+    pub gateway: EsmpGateway,
+    pub encryption: EsmpEncryption,
+    pub blob: EsmpBlobs,
+    pub spam: EsmpSpamFilter,
+    pub invite: EsmpInvites,
+}
+
+#[derive(Default, Clone)]
+pub struct EsmpFederation {
+    pub enable: bool,
+    pub domain: String,
+    pub key: Option<[u8; 32]>,
+    pub port: u16,
+    pub tls: bool,
+    pub allow_invalid_certs: bool,
+    pub timeout: Duration,
+    pub retry: Vec<Duration>,
+    pub expire: Duration,
+    pub peers: AHashMap<String, EsmpPeer>,
+}
+
+#[derive(Default, Clone)]
+pub struct EsmpEncryption {
+    pub active: Option<String>,
+    pub keys: AHashMap<String, [u8; 32]>,
+    pub reencrypt_frequency: SimpleCron,
+}
+
+#[derive(Default, Clone)]
+pub struct EsmpBlobs {
+    pub max_size: usize,
+    pub quota: u64,
+    pub thumbnail_size: u32,
+}
+
+#[derive(Default, Clone)]
+pub struct EsmpSpamFilter {
+    pub enable: bool,
+    pub action: EsmpSpamAction,
+    pub threshold: f64,
+    pub check_url: bool,
+    pub check_bayes: bool,
+    pub check_llm: bool,
+    pub reputation_weight: f64,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum EsmpSpamAction {
+    Reject,
+    Quarantine,
+    #[default]
+    Tag,
+}
+
+#[derive(Default, Clone)]
+pub struct EsmpInvites {
+    pub max_expiry: Duration,
+}
+
+#[derive(Default, Clone)]
+pub struct EsmpGateway {
+    pub outbound: bool,
+    pub inbound: bool,
+}
+
+#[derive(Clone)]
+pub struct EsmpPeer {
+    pub host: String,
+    pub port: u16,
+    pub tls: bool,
+    pub key: Option<String>,
+}
+
+#[derive(Default, Clone)]
+pub struct EsmpLimits {
+    pub max_frame_size: usize,
+    pub max_recipients: usize,
+    pub timeout_idle: Duration,
+    pub timeout_session: Duration,
+    pub rate_connection: Option<Rate>,
+    pub rate_sender: Option<Rate>,
+    pub rate_key_package_requester: Option<Rate>,
+    pub rate_key_package_target: Option<Rate>,
+}
+
+impl EsmpConfig {
+    pub fn parse(config: &mut Config) -> Self {
+        // Limits can be overridden for each ESMP listener
+        let listener_ids = config
+            .sub_keys("server.listener", ".protocol")
+            .map(|id| id.to_string())
+            .collect::<Vec<_>>();
+        let mut listener_limits = AHashMap::new();
+        for id in listener_ids {
+            if config.value(("server.listener", id.as_str(), "protocol")) == Some("esmp") {
+                let limits = EsmpLimits::parse(config, Some(&id));
+                listener_limits.insert(id, limits);
+            }
+        }
+
+        let federation = EsmpFederation::parse(config);
+        let gateway = EsmpGateway::parse(config, &federation);
+
+        EsmpConfig {
+            replay_window: config
+                .property_or_default("esmp.replay.window", "5m")
+                .unwrap_or_else(|| Duration::from_secs(300)),
+            limits: EsmpLimits::parse(config, None),
+            listener_limits,
+            federation,
+            gateway,
+            encryption: EsmpEncryption::parse(config),
+            blob: EsmpBlobs::parse(config),
+            spam: EsmpSpamFilter::parse(config),
+            invite: EsmpInvites::parse(config),
+        }
+    }
+
+    pub fn limits(&self, listener_id: &str) -> &EsmpLimits {
+        self.listener_limits
+            .get(listener_id)
+            .unwrap_or(&self.limits)
+    }
+}
+
+impl EsmpLimits {
+    fn parse(config: &mut Config, listener_id: Option<&str>) -> Self {
+        EsmpLimits {
+            max_frame_size: property(config, listener_id, "esmp.limits.frame-size", "1048576")
+                .unwrap_or(1048576),
+            max_recipients: property(config, listener_id, "esmp.limits.recipients", "100")
+                .unwrap_or(100),
+            timeout_idle: property(config, listener_id, "esmp.timeout.idle", "30m")
+                .unwrap_or_else(|| Duration::from_secs(30 * 60)),
+            timeout_session: property(config, listener_id, "esmp.timeout.session", "1d")
+                .unwrap_or_else(|| Duration::from_secs(24 * 60 * 60)),
+            rate_connection: property::<Option<Rate>>(
+                config,
+                listener_id,
+                "esmp.rate-limit.connection",
+                "300/1m",
+            )
+            .unwrap_or_default(),
+            rate_sender: property::<Option<Rate>>(
+                config,
+                listener_id,
+                "esmp.rate-limit.sender",
+                "1000/1h",
+            )
+            .unwrap_or_default(),
+            rate_key_package_requester: property::<Option<Rate>>(
+                config,
+                listener_id,
+                "esmp.rate-limit.key-package.requester",
+                "100/1h",
+            )
+            .unwrap_or_default(),
+            rate_key_package_target: property::<Option<Rate>>(
+                config,
+                listener_id,
+                "esmp.rate-limit.key-package.target",
+                "200/1h",
+            )
+            .unwrap_or_default(),
+        }
+    }
+}
+
+impl EsmpFederation {
+    fn parse(config: &mut Config) -> Self {
+        let domain = config
+            .value("esmp.federation.domain")
+            .or_else(|| config.value("lookup.default.domain"))
+            .or_else(|| config.value("server.hostname"))
+            .unwrap_or("localhost")
+            .to_lowercase();
+
+        // Peers discover the server key through the published records of its domain
+        let key = config
+            .value("esmp.federation.key")
+            .map(|key| key.to_string())
+            .and_then(|key| {
+                match general_purpose::STANDARD
+                    .decode(key.trim())
+                    .ok()
+                    .and_then(|key| <[u8; 32]>::try_from(key).ok())
+                {
+                    Some(key) => Some(key),
+                    None => {
+                        config.new_parse_error(
+                            "esmp.federation.key",
+                            "Expected a base64 encoded Ed25519 private key",
+                        );
+                        None
+                    }
+                }
+            });
+        let enable = config
+            .property_or_default("esmp.federation.enable", "false")
+            .unwrap_or(false);
+        if enable && key.is_none() {
+            config.new_build_error(
+                "esmp.federation.enable",
+                "Federation requires a server key in esmp.federation.key",
+            );
+        }
+
+        // Static routes take precedence over DNS and .well-known discovery
+        let peer_ids = config
+            .sub_keys("esmp.federation.peer", ".host")
+            .map(|id| id.to_string())
+            .collect::<Vec<_>>();
+        let mut peers = AHashMap::new();
+        for id in peer_ids {
+            let Some(host) = config
+                .value_require(("esmp.federation.peer", id.as_str(), "host"))
+                .map(|host| host.to_string())
+            else {
+                continue;
+            };
+            peers.insert(
+                id.to_lowercase(),
+                EsmpPeer {
+                    host,
+                    port: config
+                        .property_or_default(("esmp.federation.peer", id.as_str(), "port"), "5888")
+                        .unwrap_or(5888),
+                    tls: config
+                        .property_or_default(("esmp.federation.peer", id.as_str(), "tls"), "true")
+                        .unwrap_or(true),
+                    key: config
+                        .value(("esmp.federation.peer", id.as_str(), "key"))
+                        .map(|key| key.to_string()),
+                },
+            );
+        }
+
+        let mut retry = config
+            .properties::<Duration>("esmp.queue.retry")
+            .into_iter()
+            .map(|(_, duration)| duration)
+            .collect::<Vec<_>>();
+        if retry.is_empty() {
+            retry = [60, 300, 900, 1800, 3600, 7200]
+                .into_iter()
+                .map(Duration::from_secs)
+                .collect();
+        }
+
+        EsmpFederation {
+            enable: enable && key.is_some(),
+            domain,
+            key,
+            port: config
+                .property_or_default("esmp.federation.port", "5888")
+                .unwrap_or(5888),
+            tls: config
+                .property_or_default("esmp.federation.tls", "true")
+                .unwrap_or(true),
+            allow_invalid_certs: config
+                .property_or_default("esmp.federation.allow-invalid-certs", "false")
+                .unwrap_or(false),
+            timeout: config
+                .property_or_default("esmp.federation.timeout", "1m")
+                .unwrap_or_else(|| Duration::from_secs(60)),
+            retry,
+            expire: config
+                .property_or_default("esmp.queue.expire", "5d")
+                .unwrap_or_else(|| Duration::from_secs(5 * 86400)),
+            peers,
+        }
+    }
+}
+
+impl EsmpGateway {
+    fn parse(config: &mut Config, federation: &EsmpFederation) -> Self {
+        let inbound = config
+            .property_or_default("esmp.gateway.inbound", "false")
+            .unwrap_or(false);
+        if inbound && federation.key.is_none() {
+            config.new_build_error(
+                "esmp.gateway.inbound",
+                "Inbound e-mail requires a server key in esmp.federation.key",
+            );
+        }
+
+        EsmpGateway {
+            outbound: config
+                .property_or_default("esmp.gateway.outbound", "false")
+                .unwrap_or(false),
+            inbound: inbound && federation.key.is_some(),
+        }
+    }
+}
+
+impl EsmpEncryption {
+    fn parse(config: &mut Config) -> Self {
+        // Retired master keys are kept to decrypt values until they are re-encrypted
+        let values = config
+            .iterate_prefix("esmp.encryption.key")
+            .map(|(id, key)| (id.to_string(), key.to_string()))
+            .collect::<Vec<_>>();
+        let mut keys = AHashMap::with_capacity(values.len());
+        for (id, key) in values {
+            match general_purpose::STANDARD
+                .decode(key.trim())
+                .ok()
+                .and_then(|key| <[u8; 32]>::try_from(key).ok())
+            {
+                Some(key) => {
+                    keys.insert(id, key);
+                }
+                None => {
+                    config.new_parse_error(
+                        ("esmp.encryption.key", id.as_str()),
+                        "Expected a base64 encoded 256-bit key",
+                    );
+                }
+            }
+        }
+
+        let active = config
+            .value("esmp.encryption.active")
+            .map(|id| id.to_string())
+            .or_else(|| (keys.len() == 1).then(|| keys.keys().next().unwrap().clone()));
+        if let Some(id) = &active
+            && !keys.contains_key(id)
+        {
+            config.new_build_error(
+                "esmp.encryption.active",
+                format!("Master key {id:?} is not defined in esmp.encryption.key"),
+            );
+        }
+
+        EsmpEncryption {
+            active: active.filter(|id| keys.contains_key(id)),
+            keys,
+            reencrypt_frequency: config
+                .property_or_default::<SimpleCron>("esmp.encryption.reencrypt-frequency", "15 * *")
+                .unwrap_or_else(|| SimpleCron::parse_value("15 * *").unwrap()),
+        }
+    }
+
+    pub fn active_key(&self) -> Option<(&str, &[u8; 32])> {
+        self.active
+            .as_deref()
+            .and_then(|id| self.keys.get(id).map(|key| (id, key)))
+    }
+}
+
+impl EsmpBlobs {
+    fn parse(config: &mut Config) -> Self {
+        EsmpBlobs {
+            max_size: config
+                .property_or_default("esmp.blob.max-size", "10485760")
+                .unwrap_or(10485760),
+            quota: config
+                .property_or_default("esmp.blob.quota", "104857600")
+                .unwrap_or(104857600),
+            thumbnail_size: config
+                .property_or_default("esmp.blob.thumbnail-size", "256")
+                .unwrap_or(256),
+        }
+    }
+}
+
+impl EsmpSpamFilter {
+    fn parse(config: &mut Config) -> Self {
+        EsmpSpamFilter {
+            enable: config
+                .property_or_default("esmp.spam.enable", "false")
+                .unwrap_or(false),
+            action: config
+                .property_or_default("esmp.spam.action", "tag")
+                .unwrap_or_default(),
+            threshold: config
+                .property_or_default("esmp.spam.threshold", "5.0")
+                .unwrap_or(5.0),
+            check_url: config
+                .property_or_default("esmp.spam.check.url", "true")
+                .unwrap_or(true),
+            check_bayes: config
+                .property_or_default("esmp.spam.check.bayes", "true")
+                .unwrap_or(true),
+            check_llm: config
+                .property_or_default("esmp.spam.check.llm", "false")
+                .unwrap_or(false),
+            reputation_weight: config
+                .property_or_default("esmp.spam.reputation.weight", "0.5")
+                .unwrap_or(0.5),
+        }
+    }
+}
+
+impl EsmpInvites {
+    fn parse(config: &mut Config) -> Self {
+        EsmpInvites {
+            max_expiry: config
+                .property_or_default("esmp.invite.max-expiry", "30d")
+                .unwrap_or_else(|| Duration::from_secs(30 * 86400)),
+        }
+    }
+}
+
+impl EsmpSpamAction {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            EsmpSpamAction::Reject => "reject",
+            EsmpSpamAction::Quarantine => "quarantine",
+            EsmpSpamAction::Tag => "tag",
+        }
+    }
+}
+
+impl ParseValue for EsmpSpamAction {
+    fn parse_value(value: &str) -> utils::config::Result<Self> {
+        match value {
+            "reject" => Ok(EsmpSpamAction::Reject),
+            "quarantine" => Ok(EsmpSpamAction::Quarantine),
+            "tag" => Ok(EsmpSpamAction::Tag),
+            other => Err(format!("Invalid ESMP spam action {other:?}.")),
+        }
+    }
+}
+
+fn property<T: ParseValue>(
+    config: &mut Config,
+    listener_id: Option<&str>,
+    key: &str,
+    default: &str,
+) -> Option<T> {
+    match listener_id {
+        Some(id) => config.property_or_else(("server.listener", id, key), key, default),
+        None => config.property_or_default(key, default),
+    }
+}
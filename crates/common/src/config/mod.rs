@@ -5,8 +5,8 @@
  */
 
 use self::{
-    imap::ImapConfig, jmap::settings::JmapConfig, scripts::Scripting, smtp::SmtpConfig,
-    storage::Storage,
+    esmp::EsmpConfig, imap::ImapConfig, jmap::settings::JmapConfig, scripts::Scripting,
+    smtp::SmtpConfig, storage::Storage,
 };
 use crate::{
     Core, Network, Security, auth::oauth::config::OAuthConfig, expr::*,
@@ -27,6 +27,7 @@ use store::{BlobBackend, BlobStore, FtsStore, InMemoryStore, Store, Stores};
 use telemetry::Metrics;
 use utils::config::{Config, utils::AsKey};
 
+pub mod esmp;
 pub mod groupware;
 pub mod imap;
 pub mod inner;
@@ -196,6 +197,7 @@ impl Core {
             smtp: SmtpConfig::parse(config).await,
             jmap: JmapConfig::parse(config),
             imap: ImapConfig::parse(config),
+            esmp: EsmpConfig::parse(config),
             oauth: OAuthConfig::parse(config),
             acme: AcmeProviders::parse(config),
             metrics: Metrics::parse(config),
@@ -334,6 +334,8 @@ impl ParseValue for ServerProtocol {
             Ok(Self::ManageSieve)
         } else if value.eq_ignore_ascii_case("pop3") {
             Ok(Self::Pop3)
+        } else if value.eq_ignore_ascii_case("esmp") {
+            Ok(Self::Esmp)
         } else {
             Err(format!("Invalid server protocol type {:?}.", value,))
         }
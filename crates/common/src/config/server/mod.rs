@@ -54,6 +54,7 @@ pub enum ServerProtocol {
     Pop3,
     Http,
     ManageSieve,
+    Esmp,
 }
 
 impl ServerProtocol {
@@ -65,6 +66,7 @@ impl ServerProtocol {
             ServerProtocol::Http => "http",
             ServerProtocol::Pop3 => "pop3",
             ServerProtocol::ManageSieve => "managesieve",
+            ServerProtocol::Esmp => "esmp",
         }
     }
 }
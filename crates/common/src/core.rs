@@ -474,6 +474,11 @@ impl Server {
         self.inner.ipc.task_tx.notify_one();
     }
 
+    #[inline(always)]
+    pub fn notify_esmp_queue(&self) {
+        self.inner.ipc.esmp_queue_tx.notify_one();
+    }
+
     pub async fn total_queued_messages(&self) -> trc::Result<u64> {
         let mut total = 0;
         self.store()
@@ -543,6 +548,8 @@ impl Server {
             SyncCollection::FileNode,
             SyncCollection::AddressBook,
             SyncCollection::Calendar,
+            SyncCollection::Esmp,
+            SyncCollection::EsmpInbox,
         ] {
             let collection = sync_collection.into();
             let from_key = LogKey {
@@ -4,7 +4,7 @@
  * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
  */
 
-use std::{sync::Arc, time::Instant};
+use std::{borrow::Cow, sync::Arc, time::Instant};
 
 use ahash::RandomState;
 use jmap_proto::types::{state::StateChange, type_state::DataType};
@@ -14,8 +14,8 @@ use mail_auth::{
     report::{Record, tlsrpt::FailureDetails},
 };
 use store::{BlobStore, InMemoryStore, Store};
-use tokio::sync::mpsc;
-use utils::map::bitmap::Bitmap;
+use tokio::sync::{mpsc, oneshot};
+use utils::{BlobHash, map::bitmap::Bitmap};
 
 use crate::config::smtp::{
     report::AggregateFrequency,
@@ -52,6 +52,10 @@ pub enum StateEvent {
         types: Bitmap<DataType>,
         tx: mpsc::Sender<StateChange>,
     },
+    SubscribeEsmp {
+        account_id: u32,
+        tx: mpsc::Sender<EsmpStateChange>,
+    },
     Publish {
         state_change: StateChange,
         broadcast: bool,
@@ -63,9 +67,36 @@ pub enum StateEvent {
         account_id: u32,
         subscriptions: Vec<UpdateSubscription>,
     },
+    Notify {
+        account_id: u32,
+        subscription_ids: Vec<u32>,
+        state_change: EsmpStateChange,
+    },
     Stop,
 }
 
+/// A change to the ESMP inboxes or groups, which are not JMAP data types.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EsmpStateChange {
+    pub change_id: u64,
+    pub state_type: EsmpStateType,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EsmpStateType {
+    InboxMessage,
+    Message,
+}
+
+impl EsmpStateType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            EsmpStateType::InboxMessage => "EsmpInboxMessage",
+            EsmpStateType::Message => "EsmpMessage",
+        }
+    }
+}
+
 #[derive(Debug)]
 pub enum BroadcastEvent {
     StateChange(StateChange),
@@ -117,6 +148,24 @@ pub enum QueueEventStatus {
     Deferred,
 }
 
+#[derive(Debug)]
+pub enum EsmpGatewayEvent {
+    Ingest {
+        sender_address: String,
+        recipients: Vec<String>,
+        message_blob: BlobHash,
+        session_id: u64,
+        result_tx: oneshot::Sender<Vec<EsmpIngestStatus>>,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EsmpIngestStatus {
+    Delivered,
+    Skipped,
+    TemporaryFailure { reason: Cow<'static, str> },
+}
+
 #[derive(Debug)]
 pub enum ReportingEvent {
     Dmarc(Box<DmarcEvent>),
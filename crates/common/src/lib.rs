@@ -11,6 +11,7 @@ use arc_swap::ArcSwap;
 use auth::{AccessToken, oauth::config::OAuthConfig, roles::RolePermissions};
 use calcard::common::timezone::Tz;
 use config::{
+    esmp::EsmpConfig,
     groupware::GroupwareConfig,
     imap::ImapConfig,
     jmap::settings::{JmapConfig, SpecialUse},
@@ -24,7 +25,9 @@ use config::{
     storage::Storage,
     telemetry::Metrics,
 };
-use ipc::{BroadcastEvent, HousekeeperEvent, QueueEvent, ReportingEvent, StateEvent};
+use ipc::{
+    BroadcastEvent, EsmpGatewayEvent, HousekeeperEvent, QueueEvent, ReportingEvent, StateEvent,
+};
 use jmap_proto::types::value::AclGrant;
 use listener::{asn::AsnGeoLookupData, blocked::Security, tls::AcmeProviders};
 use mail_auth::{MX, Txt};
@@ -106,10 +109,35 @@ pub const KV_LOCK_TASK: u8 = 23;
 pub const KV_LOCK_HOUSEKEEPER: u8 = 24;
 pub const KV_LOCK_DAV: u8 = 25;
 pub const KV_SIEVE_ID: u8 = 26;
+pub const KV_LOCK_ESMP_GROUP: u8 = 27;
+pub const KV_ESMP_REPLAY: u8 = 28;
+pub const KV_LOCK_ESMP_INBOX: u8 = 29;
+pub const KV_RATE_LIMIT_ESMP: u8 = 30;
+pub const KV_LOCK_ESMP_KEY_PACKAGE: u8 = 31;
+pub const KV_LOCK_ESMP_KEY: u8 = 32;
+pub const KV_LOCK_ESMP_QUEUE: u8 = 33;
+pub const KV_REPUTATION_ESMP: u8 = 34;
 
 pub const IDX_UID: u8 = 0;
 pub const IDX_EMAIL: u8 = 1;
 
+// ESMP objects are stored under a global account that has no principal. It is
+// kept below `u32::MAX`, which the directory and backups use as a sentinel
+pub const ESMP_ACCOUNT_ID: u32 = u32::MAX - 1;
+
+// Inbox change logs are kept under accounts counting down from the global
+// ESMP account, which are never assigned to principals
+pub const ESMP_MAX_INBOXES: u32 = 1 << 24;
+
+pub fn esmp_inbox_account_id(inbox_id: u32) -> u32 {
+    debug_assert!(inbox_id < ESMP_MAX_INBOXES);
+    ESMP_ACCOUNT_ID - 1 - inbox_id
+}
+
+pub fn is_esmp_account_id(account_id: u32) -> bool {
+    (ESMP_ACCOUNT_ID - ESMP_MAX_INBOXES..=ESMP_ACCOUNT_ID).contains(&account_id)
+}
+
 #[derive(Clone)]
 pub struct Server {
     pub inner: Arc<Inner>,
@@ -231,9 +259,11 @@ pub struct Ipc {
     pub state_tx: mpsc::Sender<StateEvent>,
     pub housekeeper_tx: mpsc::Sender<HousekeeperEvent>,
     pub task_tx: Arc<Notify>,
+    pub esmp_queue_tx: Arc<Notify>,
     pub queue_tx: mpsc::Sender<QueueEvent>,
     pub report_tx: mpsc::Sender<ReportingEvent>,
     pub broadcast_tx: Option<mpsc::Sender<BroadcastEvent>>,
+    pub esmp_gateway_tx: mpsc::Sender<EsmpGatewayEvent>,
     pub local_delivery_sm: Arc<Semaphore>,
 }
 
@@ -324,6 +354,7 @@ pub struct Core {
     pub groupware: GroupwareConfig,
     pub spam: SpamFilterConfig,
     pub imap: ImapConfig,
+    pub esmp: EsmpConfig,
     pub metrics: Metrics,
     #[cfg(feature = "enterprise")]
     pub enterprise: Option<enterprise::Enterprise>,
@@ -475,9 +506,11 @@ impl Default for Ipc {
             state_tx: mpsc::channel(IPC_CHANNEL_BUFFER).0,
             housekeeper_tx: mpsc::channel(IPC_CHANNEL_BUFFER).0,
             task_tx: Default::default(),
+            esmp_queue_tx: Default::default(),
             queue_tx: mpsc::channel(IPC_CHANNEL_BUFFER).0,
             report_tx: mpsc::channel(IPC_CHANNEL_BUFFER).0,
             broadcast_tx: None,
+            esmp_gateway_tx: mpsc::channel(IPC_CHANNEL_BUFFER).0,
             local_delivery_sm: Arc::new(Semaphore::new(10)),
         }
     }
@@ -14,7 +14,7 @@ use proxy_header::io::ProxiedStream;
 use rustls::crypto::ring::cipher_suite::TLS13_AES_128_GCM_SHA256;
 use tokio::{net::TcpStream, sync::watch};
 use tokio_rustls::server::TlsStream;
-use trc::{EventType, HttpEvent, ImapEvent, ManageSieveEvent, Pop3Event, SmtpEvent};
+use trc::{EsmpEvent, EventType, HttpEvent, ImapEvent, ManageSieveEvent, Pop3Event, SmtpEvent};
 use utils::{UnwrapFailure, config::Config};
 
 use crate::{
@@ -115,6 +115,10 @@ impl Listener {
                         EventType::ManageSieve(ManageSieveEvent::ConnectionStart),
                         EventType::ManageSieve(ManageSieveEvent::ConnectionEnd),
                     ),
+                    ServerProtocol::Esmp => (
+                        EventType::Esmp(EsmpEvent::ConnectionStart),
+                        EventType::Esmp(EsmpEvent::ConnectionEnd),
+                    ),
                 };
 
                 loop {
@@ -425,6 +425,8 @@ impl Core {
 
                             let hash = key.range(0..BLOB_HASH_LEN)?.to_vec();
 
+                            // Commit markers use the `u32::MAX` sentinel, which is not assigned to
+                            // principals or to the accounts reserved for ESMP
                             if account_id != u32::MAX && document_id != u32::MAX {
                                 writer
                                     .send(Op::AccountId(account_id))
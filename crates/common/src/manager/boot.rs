@@ -27,7 +27,9 @@ use crate::{
     Caches, Core, Data, IPC_CHANNEL_BUFFER, Inner, Ipc,
     config::{network::AsnGeoLookupConfig, server::Listeners, telemetry::Telemetry},
     core::BuildServer,
-    ipc::{BroadcastEvent, HousekeeperEvent, QueueEvent, ReportingEvent, StateEvent},
+    ipc::{
+        BroadcastEvent, EsmpGatewayEvent, HousekeeperEvent, QueueEvent, ReportingEvent, StateEvent,
+    },
 };
 
 use super::{
@@ -50,6 +52,7 @@ pub struct IpcReceivers {
     pub queue_rx: Option<mpsc::Receiver<QueueEvent>>,
     pub report_rx: Option<mpsc::Receiver<ReportingEvent>>,
     pub broadcast_rx: Option<mpsc::Receiver<BroadcastEvent>>,
+    pub esmp_gateway_rx: Option<mpsc::Receiver<EsmpGatewayEvent>>,
 }
 
 const HELP: &str = concat!(
@@ -499,6 +502,7 @@ pub fn build_ipc(config: &mut Config, has_pubsub: bool) -> (Ipc, IpcReceivers) {
     let (queue_tx, queue_rx) = mpsc::channel(IPC_CHANNEL_BUFFER);
     let (report_tx, report_rx) = mpsc::channel(IPC_CHANNEL_BUFFER);
     let (broadcast_tx, broadcast_rx) = mpsc::channel(IPC_CHANNEL_BUFFER);
+    let (esmp_gateway_tx, esmp_gateway_rx) = mpsc::channel(IPC_CHANNEL_BUFFER);
     (
         Ipc {
             state_tx,
@@ -506,7 +510,9 @@ pub fn build_ipc(config: &mut Config, has_pubsub: bool) -> (Ipc, IpcReceivers) {
             queue_tx,
             report_tx,
             broadcast_tx: has_pubsub.then_some(broadcast_tx),
+            esmp_gateway_tx,
             task_tx: Arc::new(Notify::new()),
+            esmp_queue_tx: Arc::new(Notify::new()),
             local_delivery_sm: Arc::new(Semaphore::new(
                 config
                     .property_or_default::<usize>("queue.threads.local", "10")
@@ -520,6 +526,7 @@ pub fn build_ipc(config: &mut Config, has_pubsub: bool) -> (Ipc, IpcReceivers) {
             queue_rx: Some(queue_rx),
             report_rx: Some(report_rx),
             broadcast_rx: has_pubsub.then_some(broadcast_rx),
+            esmp_gateway_rx: Some(esmp_gateway_rx),
         },
     )
 }
@@ -597,6 +604,10 @@ tls.implicit = true
 bind = "[::]:4190"
 protocol = "managesieve"
 
+[server.listener.esmp]
+bind = "[::]:5888"
+protocol = "esmp"
+
 [server.listener.https]
 protocol = "http"
 bind = "[::]:443"
@@ -672,6 +683,10 @@ tls.implicit = true
 bind = "[::]:4190"
 protocol = "managesieve"
 
+[server.listener.esmp]
+bind = "[::]:5888"
+protocol = "esmp"
+
 [server.listener.https]
 protocol = "http"
 bind = "[::]:443"
@@ -144,6 +144,8 @@ async fn restore_file(store: Store, blob_store: BlobStore, path: &Path) {
                     Family::Blob => {
                         let hash = BlobHash::try_from_hash_slice(&key).expect("Invalid blob hash");
 
+                        // Commit markers use the `u32::MAX` sentinel, which is not assigned to
+                        // principals or to the accounts reserved for ESMP
                         if account_id != u32::MAX && document_id != u32::MAX {
                             if reader.version == 1 && collection == email_collection {
                                 batch.set(
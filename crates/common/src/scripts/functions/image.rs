@@ -4,8 +4,13 @@
  * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
  */
 
+use std::io::Cursor;
+
 use sieve::{Context, runtime::Variable};
 
+// Larger images are not decoded to avoid decompression bombs
+const MAX_DECODE_AREA: u64 = 50_000_000;
+
 pub fn fn_img_metadata<'x>(ctx: &'x Context<'x>, v: Vec<Variable>) -> Variable {
     ctx.message()
         .part(ctx.part())
@@ -13,32 +18,7 @@ pub fn fn_img_metadata<'x>(ctx: &'x Context<'x>, v: Vec<Variable>) -> Variable {
         .and_then(|bytes| {
             let arg = v[1].to_string();
             match arg.as_ref() {
-                "type" => imagesize::image_type(bytes).ok().map(|t| {
-                    Variable::from(match t {
-                        imagesize::ImageType::Aseprite => "aseprite",
-                        imagesize::ImageType::Bmp => "bmp",
-                        imagesize::ImageType::Dds => "dds",
-                        imagesize::ImageType::Exr => "exr",
-                        imagesize::ImageType::Farbfeld => "farbfeld",
-                        imagesize::ImageType::Gif => "gif",
-                        imagesize::ImageType::Hdr => "hdr",
-                        imagesize::ImageType::Heif(_) => "heif",
-                        imagesize::ImageType::Ico => "ico",
-                        imagesize::ImageType::Jpeg => "jpeg",
-                        imagesize::ImageType::Jxl => "jxl",
-                        imagesize::ImageType::Ktx2 => "ktx2",
-                        imagesize::ImageType::Png => "png",
-                        imagesize::ImageType::Pnm => "pnm",
-                        imagesize::ImageType::Psd => "psd",
-                        imagesize::ImageType::Qoi => "qoi",
-                        imagesize::ImageType::Tga => "tga",
-                        imagesize::ImageType::Tiff => "tiff",
-                        imagesize::ImageType::Vtf => "vtf",
-                        imagesize::ImageType::Webp => "webp",
-                        imagesize::ImageType::Ilbm => "ilbm",
-                        _ => "unknown",
-                    })
-                }),
+                "type" => image_type(bytes).map(Variable::from),
                 "width" => imagesize::blob_size(bytes)
                     .ok()
                     .map(|s| Variable::Integer(s.width as i64)),
@@ -56,3 +36,46 @@ pub fn fn_img_metadata<'x>(ctx: &'x Context<'x>, v: Vec<Variable>) -> Variable {
         })
         .unwrap_or_default()
 }
+
+pub fn image_type(bytes: &[u8]) -> Option<&'static str> {
+    imagesize::image_type(bytes).ok().map(|t| match t {
+        imagesize::ImageType::Aseprite => "aseprite",
+        imagesize::ImageType::Bmp => "bmp",
+        imagesize::ImageType::Dds => "dds",
+        imagesize::ImageType::Exr => "exr",
+        imagesize::ImageType::Farbfeld => "farbfeld",
+        imagesize::ImageType::Gif => "gif",
+        imagesize::ImageType::Hdr => "hdr",
+        imagesize::ImageType::Heif(_) => "heif",
+        imagesize::ImageType::Ico => "ico",
+        imagesize::ImageType::Jpeg => "jpeg",
+        imagesize::ImageType::Jxl => "jxl",
+        imagesize::ImageType::Ktx2 => "ktx2",
+        imagesize::ImageType::Png => "png",
+        imagesize::ImageType::Pnm => "pnm",
+        imagesize::ImageType::Psd => "psd",
+        imagesize::ImageType::Qoi => "qoi",
+        imagesize::ImageType::Tga => "tga",
+        imagesize::ImageType::Tiff => "tiff",
+        imagesize::ImageType::Vtf => "vtf",
+        imagesize::ImageType::Webp => "webp",
+        imagesize::ImageType::Ilbm => "ilbm",
+        _ => "unknown",
+    })
+}
+
+/// Scales an image down to fit within `max_dimension` pixels and encodes it as PNG.
+pub fn thumbnail(bytes: &[u8], max_dimension: u32) -> Option<Vec<u8>> {
+    let size = imagesize::blob_size(bytes).ok()?;
+    if (size.width as u64).saturating_mul(size.height as u64) > MAX_DECODE_AREA {
+        return None;
+    }
+
+    let mut thumbnail = Cursor::new(Vec::new());
+    image::load_from_memory(bytes)
+        .ok()?
+        .thumbnail(max_dimension, max_dimension)
+        .write_to(&mut thumbnail, image::ImageFormat::Png)
+        .ok()?;
+    Some(thumbnail.into_inner())
+}
@@ -8,7 +8,10 @@ use jmap_proto::types::{state::StateChange, type_state::DataType};
 use tokio::sync::mpsc;
 use utils::map::bitmap::Bitmap;
 
-use crate::{IPC_CHANNEL_BUFFER, Server, ipc::StateEvent};
+use crate::{
+    IPC_CHANNEL_BUFFER, Server,
+    ipc::{EsmpStateChange, StateEvent},
+};
 
 impl Server {
     pub async fn subscribe_state_manager(
@@ -36,4 +39,51 @@ impl Server {
 
         Ok(change_rx)
     }
+
+    pub async fn subscribe_esmp_state(
+        &self,
+        account_id: u32,
+    ) -> trc::Result<mpsc::Receiver<EsmpStateChange>> {
+        let (change_tx, change_rx) = mpsc::channel::<EsmpStateChange>(IPC_CHANNEL_BUFFER);
+
+        self.inner
+            .ipc
+            .state_tx
+            .clone()
+            .send(StateEvent::SubscribeEsmp {
+                account_id,
+                tx: change_tx,
+            })
+            .await
+            .map_err(|err| {
+                trc::EventType::Server(trc::ServerEvent::ThreadError)
+                    .reason(err)
+                    .caused_by(trc::location!())
+            })?;
+
+        Ok(change_rx)
+    }
+
+    pub async fn notify_esmp_state(
+        &self,
+        account_id: u32,
+        subscription_ids: Vec<u32>,
+        state_change: EsmpStateChange,
+    ) -> trc::Result<()> {
+        self.inner
+            .ipc
+            .state_tx
+            .clone()
+            .send(StateEvent::Notify {
+                account_id,
+                subscription_ids,
+                state_change,
+            })
+            .await
+            .map_err(|err| {
+                trc::EventType::Server(trc::ServerEvent::ThreadError)
+                    .reason(err)
+                    .caused_by(trc::location!())
+            })
+    }
 }
@@ -381,6 +381,9 @@ impl ManageDirectory for Store {
         if let Some(urls) = principal_set.take_str_array(PrincipalField::Urls) {
             principal_create.data.push(PrincipalData::Urls(urls));
         }
+        if let Some(keys) = principal_set.take_str_array(PrincipalField::EsmpKeys) {
+            principal_create.data.push(PrincipalData::EsmpKeys(keys));
+        }
         if let Some(urls) = principal_set.take_str_array(PrincipalField::ExternalMembers) {
             principal_create
                 .data
@@ -1782,9 +1785,24 @@ impl ManageDirectory for Store {
                         principal.data.push(PrincipalData::Urls(items));
                     }
                 }
+                (
+                    PrincipalAction::Set,
+                    PrincipalField::EsmpKeys,
+                    PrincipalValue::StringList(items),
+                ) => {
+                    principal
+                        .data
+                        .retain(|v| !matches!(v, PrincipalData::EsmpKeys(_)));
+
+                    if !items.is_empty() {
+                        principal.data.push(PrincipalData::EsmpKeys(items));
+                    }
+                }
                 (
                     PrincipalAction::AddItem,
-                    PrincipalField::Urls | PrincipalField::ExternalMembers,
+                    PrincipalField::Urls
+                    | PrincipalField::ExternalMembers
+                    | PrincipalField::EsmpKeys,
                     PrincipalValue::String(mut item),
                 ) => {
                     if matches!(change.field, PrincipalField::ExternalMembers) {
@@ -1817,6 +1835,13 @@ impl ManageDirectory for Store {
                                 found = true;
                                 break;
                             }
+                            (PrincipalData::EsmpKeys(keys), PrincipalField::EsmpKeys) => {
+                                if !keys.contains(&item) {
+                                    keys.push(item.clone());
+                                }
+                                found = true;
+                                break;
+                            }
                             _ => {}
                         }
                     }
@@ -1829,13 +1854,18 @@ impl ManageDirectory for Store {
                             PrincipalField::ExternalMembers => principal
                                 .data
                                 .push(PrincipalData::ExternalMembers(vec![item])),
+                            PrincipalField::EsmpKeys => {
+                                principal.data.push(PrincipalData::EsmpKeys(vec![item]))
+                            }
                             _ => {}
                         }
                     }
                 }
                 (
                     PrincipalAction::RemoveItem,
-                    PrincipalField::Urls | PrincipalField::ExternalMembers,
+                    PrincipalField::Urls
+                    | PrincipalField::ExternalMembers
+                    | PrincipalField::EsmpKeys,
                     PrincipalValue::String(item),
                 ) => {
                     for data in &mut principal.data {
@@ -1851,6 +1881,10 @@ impl ManageDirectory for Store {
                                 emails.retain(|v| *v != item);
                                 break;
                             }
+                            (PrincipalData::EsmpKeys(keys), PrincipalField::EsmpKeys) => {
+                                keys.retain(|v| *v != item);
+                                break;
+                            }
                             _ => {}
                         }
                     }
@@ -2194,6 +2228,11 @@ impl ManageDirectory for Store {
                         result.set(PrincipalField::Urls, compact_strings);
                     }
                 }
+                PrincipalData::EsmpKeys(compact_strings) => {
+                    if fields.is_empty() || fields.contains(&PrincipalField::EsmpKeys) {
+                        result.set(PrincipalField::EsmpKeys, compact_strings);
+                    }
+                }
                 PrincipalData::PrincipalQuota(principal_quotas_) => {
                     principal_quotas = principal_quotas_;
                 }
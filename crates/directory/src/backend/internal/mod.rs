@@ -111,6 +111,7 @@ pub enum PrincipalField {
     Picture,
     Urls,
     ExternalMembers,
+    EsmpKeys,
 }
 
 #[derive(Debug, Default, Clone, PartialEq, Eq)]
@@ -198,6 +199,7 @@ impl PrincipalField {
             PrincipalField::Picture => 14,
             PrincipalField::Urls => 15,
             PrincipalField::ExternalMembers => 16,
+            PrincipalField::EsmpKeys => 17,
         }
     }
 
@@ -220,6 +222,7 @@ impl PrincipalField {
             14 => Some(PrincipalField::Picture),
             15 => Some(PrincipalField::Urls),
             16 => Some(PrincipalField::ExternalMembers),
+            17 => Some(PrincipalField::EsmpKeys),
             _ => None,
         }
     }
@@ -243,6 +246,7 @@ impl PrincipalField {
             PrincipalField::Picture => "picture",
             PrincipalField::Urls => "urls",
             PrincipalField::ExternalMembers => "externalMembers",
+            PrincipalField::EsmpKeys => "esmpKeys",
         }
     }
 
@@ -265,6 +269,7 @@ impl PrincipalField {
             "picture" => Some(PrincipalField::Picture),
             "urls" => Some(PrincipalField::Urls),
             "externalMembers" => Some(PrincipalField::ExternalMembers),
+            "esmpKeys" => Some(PrincipalField::EsmpKeys),
             _ => None,
         }
     }
@@ -427,6 +427,7 @@ impl LdapMappings {
         let mut principal = Principal::new(0, Type::Individual);
         let mut role = ROLE_USER;
         let mut member_of = vec![];
+        let mut esmp_keys = vec![];
 
         for (attr, value) in entry.attrs {
             if self.attr_name.contains(&attr) {
@@ -467,6 +468,8 @@ impl LdapMappings {
                 }
             } else if self.attr_groups.contains(&attr) {
                 member_of.extend(value);
+            } else if self.attr_esmp_keys.contains(&attr) {
+                esmp_keys.extend(value);
             } else if self.attr_quota.contains(&attr) {
                 if let Ok(quota) = value.into_iter().next().unwrap_or_default().parse::<u64>() {
                     principal.quota = quota.into();
@@ -492,6 +495,9 @@ impl LdapMappings {
         }
 
         principal.data.push(PrincipalData::Roles(vec![role]));
+        if !esmp_keys.is_empty() {
+            principal.data.push(PrincipalData::EsmpKeys(esmp_keys));
+        }
 
         LdapResult {
             dn: entry.dn,
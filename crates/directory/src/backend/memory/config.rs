@@ -147,6 +147,13 @@ impl MemoryDirectory {
             {
                 principal.quota = quota.into();
             }
+            let esmp_keys = config
+                .values((prefix.as_str(), "principals", lookup_id, "esmp-key"))
+                .map(|(_, key)| key.to_string())
+                .collect::<Vec<_>>();
+            if !esmp_keys.is_empty() {
+                principal.data.push(PrincipalData::EsmpKeys(esmp_keys));
+            }
 
             directory.principals.push(principal);
         }
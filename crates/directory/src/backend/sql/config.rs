@@ -57,6 +57,7 @@ impl SqlDirectory {
             ("emails", &mut mappings.query_emails),
             ("recipients", &mut mappings.query_recipients),
             ("secrets", &mut mappings.query_secrets),
+            ("esmp-keys", &mut mappings.query_esmp_keys),
         ] {
             *query = config
                 .value(("store", store_id.as_str(), "query", query_id))
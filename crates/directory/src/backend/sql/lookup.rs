@@ -174,6 +174,23 @@ impl SqlDirectory {
             );
         }
 
+        // Obtain ESMP keys
+        if !self.mappings.query_esmp_keys.is_empty() {
+            let keys = self
+                .sql_store
+                .sql_query::<Rows>(
+                    &self.mappings.query_esmp_keys,
+                    vec![external_principal.name().into()],
+                )
+                .await
+                .caused_by(trc::location!())?;
+            if !keys.rows.is_empty() {
+                external_principal
+                    .data
+                    .push(PrincipalData::EsmpKeys(keys.into()));
+            }
+        }
+
         // Obtain account ID if not available
         let mut principal = if let Some(stored_principal) = stored_principal {
             stored_principal
@@ -22,6 +22,7 @@ pub(crate) struct SqlMappings {
     query_emails: String,
     query_recipients: String,
     query_secrets: String,
+    query_esmp_keys: String,
     column_description: String,
     column_secret: String,
     column_email: String,
@@ -146,6 +146,19 @@ impl Principal {
             .unwrap_or_default()
     }
 
+    pub fn esmp_keys(&self) -> &[String] {
+        self.data
+            .iter()
+            .find_map(|item| {
+                if let PrincipalData::EsmpKeys(items) = item {
+                    items.as_slice().into()
+                } else {
+                    None
+                }
+            })
+            .unwrap_or_default()
+    }
+
     pub fn roles_mut(&mut self) -> Option<&mut Vec<u32>> {
         self.data.iter_mut().find_map(|item| {
             if let PrincipalData::Roles(items) = item {
@@ -270,6 +283,19 @@ impl Principal {
             }
         }
 
+        // ESMP keys published by the external directory replace the stored ones
+        let esmp_keys = external.esmp_keys();
+        if !esmp_keys.is_empty() && esmp_keys != self.esmp_keys() {
+            let esmp_keys = esmp_keys.to_vec();
+            self.data
+                .retain(|item| !matches!(item, PrincipalData::EsmpKeys(_)));
+            self.data.push(PrincipalData::EsmpKeys(esmp_keys.clone()));
+            updates.push(PrincipalUpdate::set(
+                PrincipalField::EsmpKeys,
+                PrincipalValue::StringList(esmp_keys),
+            ));
+        }
+
         if external.description.as_ref().is_some_and(|v| !v.is_empty())
             && self.description != external.description
         {
@@ -1122,18 +1148,17 @@ impl<'de> serde::Deserialize<'de> for PrincipalSet {
                         | PrincipalField::EnabledPermissions
                         | PrincipalField::DisabledPermissions
                         | PrincipalField::Urls
-                        | PrincipalField::ExternalMembers => {
-                            match map.next_value::<StringOrMany>()? {
-                                StringOrMany::One(v) => PrincipalValue::StringList(vec![v]),
-                                StringOrMany::Many(v) => {
-                                    if !v.is_empty() {
-                                        PrincipalValue::StringList(v)
-                                    } else {
-                                        continue;
-                                    }
+                        | PrincipalField::ExternalMembers
+                        | PrincipalField::EsmpKeys => match map.next_value::<StringOrMany>()? {
+                            StringOrMany::One(v) => PrincipalValue::StringList(vec![v]),
+                            StringOrMany::Many(v) => {
+                                if !v.is_empty() {
+                                    PrincipalValue::StringList(v)
+                                } else {
+                                    continue;
                                 }
                             }
-                        }
+                        },
                         PrincipalField::UsedQuota => {
                             // consume and ignore
                             map.next_value::<IgnoredAny>()?;
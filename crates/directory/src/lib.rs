@@ -57,6 +57,7 @@ pub enum PrincipalData {
     Urls(Vec<String>),
     PrincipalQuota(Vec<PrincipalQuota>),
     Locale(String),
+    EsmpKeys(Vec<String>),
 }
 
 #[derive(rkyv::Archive, rkyv::Deserialize, rkyv::Serialize, Debug, Clone, PartialEq, Eq)]
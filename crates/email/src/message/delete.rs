@@ -6,7 +6,10 @@
 
 use super::metadata::MessageData;
 use crate::{cache::MessageCacheFetch, mailbox::*, message::metadata::MessageMetadata};
-use common::{KV_LOCK_PURGE_ACCOUNT, Server, storage::index::ObjectIndexBuilder};
+use common::{
+    ESMP_ACCOUNT_ID, KV_LOCK_PURGE_ACCOUNT, Server, esmp_inbox_account_id, is_esmp_account_id,
+    storage::index::ObjectIndexBuilder,
+};
 use jmap_proto::types::collection::VanishedCollection;
 use jmap_proto::types::{collection::Collection, property::Property};
 use std::future::Future;
@@ -110,6 +113,18 @@ impl EmailDeletion for Server {
                 self.purge_account(account_id).await;
             }
         }
+
+        // ESMP change logs are kept under accounts that have no principal
+        let mut account_ids = vec![ESMP_ACCOUNT_ID];
+        if let Ok(Some(inbox_ids)) = self
+            .get_document_ids(ESMP_ACCOUNT_ID, Collection::EsmpInbox)
+            .await
+        {
+            account_ids.extend(inbox_ids.into_iter().map(esmp_inbox_account_id));
+        }
+        for account_id in account_ids {
+            self.purge_account(account_id).await;
+        }
     }
 
     async fn purge_account(&self, account_id: u32) {
@@ -135,8 +150,13 @@ impl EmailDeletion for Server {
             }
         }
 
-        // Auto-expunge deleted and junk messages
-        if let Some(period) = self.core.jmap.mail_autoexpunge_after {
+        // Auto-expunge deleted and junk messages, ESMP accounts have none
+        if let Some(period) = self
+            .core
+            .jmap
+            .mail_autoexpunge_after
+            .filter(|_| !is_esmp_account_id(account_id))
+        {
             if let Err(err) = self.emails_auto_expunge(account_id, period).await {
                 trc::error!(
                     err.details("Failed to auto-expunge messages.")
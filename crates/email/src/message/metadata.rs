@@ -414,7 +414,11 @@ impl ArchivedMessageMetadataPart {
         })
     }
 
-    // Removed subject() for EMSG protocol
+    pub fn subject(&self) -> Option<&str> {
+        self.headers
+            .header_value(&ArchivedHeaderName::Subject)
+            .and_then(|header| header.as_text())
+    }
 
     pub fn date(&self) -> Option<DateTime> {
         self.headers
@@ -0,0 +1,307 @@
+/*
+ * SPDX-FileCopyrightText: 2020 Stalwart Labs LLC <hello@stalw.art>
+ *
+ * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
+ */
+
+use std::future::Future;
+
+use common::{KV_ESMP_REPLAY, Server};
+use serde_json::{Value, json};
+use store::write::now;
+
+use crate::{
+    ESMP_VERSION, MAX_ID_LENGTH,
+    canonical::{canonicalize, signing_input},
+    crypto::verify_signature,
+    group::{
+        invite::verify_invite,
+        persist::{GroupStore, resolve_participants},
+        policy::authorize,
+    },
+    handler::{EsmpMessage, SignedObject, replay_key},
+    identity::EsmpIdentity,
+    keyset::persist::KeySetStore,
+};
+
+/// A request to the HTTP API signed with an ESMP key.
+#[derive(Debug, Clone)]
+pub struct SignedHttpRequest<'x> {
+    pub pubkey: &'x str,
+    pub id: &'x str,
+    pub timestamp: u64,
+    pub signature: &'x str,
+    pub method: &'x str,
+    pub path: &'x str,
+    pub body: &'x Value,
+}
+
+impl SignedHttpRequest<'_> {
+    /// Returns the canonical bytes covered by the request signature.
+    pub fn signing_input(&self) -> Vec<u8> {
+        signing_input(&json!({
+            "version": ESMP_VERSION,
+            "id": self.id,
+            "timestamp": self.timestamp,
+            "method": self.method,
+            "path": self.path,
+            "body": self.body,
+        }))
+        .unwrap_or_default()
+    }
+}
+
+impl SignedObject for SignedHttpRequest<'_> {
+    fn version(&self) -> u32 {
+        ESMP_VERSION
+    }
+
+    fn id(&self) -> &str {
+        self.id
+    }
+
+    fn timestamp(&self) -> u64 {
+        self.timestamp
+    }
+
+    fn signature(&self) -> &str {
+        self.signature
+    }
+
+    fn sender_pubkey(&self) -> &str {
+        self.pubkey
+    }
+}
+
+pub trait EsmpApi: Sync + Send {
+    fn verify_signed_request(
+        &self,
+        request: &SignedHttpRequest<'_>,
+    ) -> impl Future<Output = trc::Result<Result<String, &'static str>>> + Send;
+
+    fn check_group_message(
+        &self,
+        identities: &[String],
+        message: &Value,
+        msg: &EsmpMessage,
+        session_id: u64,
+    ) -> impl Future<Output = trc::Result<Result<(), &'static str>>> + Send;
+
+    fn submit_group_message(
+        &self,
+        identities: &[String],
+        message: &Value,
+        msg: &EsmpMessage,
+        session_id: u64,
+    ) -> impl Future<Output = trc::Result<Result<String, &'static str>>> + Send;
+}
+
+impl EsmpApi for Server {
+    async fn verify_signed_request(
+        &self,
+        request: &SignedHttpRequest<'_>,
+    ) -> trc::Result<Result<String, &'static str>> {
+        if request.id.is_empty() || request.id.len() > MAX_ID_LENGTH {
+            return Ok(Err("Invalid request id"));
+        }
+        if !verify_signature(request.pubkey, request.signature, &request.signing_input()) {
+            return Ok(Err("Rejected unsigned or tampered request"));
+        }
+
+        // Keys are no longer accepted once revoked
+        if self
+            .key_set(request.pubkey)
+            .await?
+            .is_some_and(|key_set| !key_set.is_active(request.pubkey, request.timestamp))
+        {
+            return Ok(Err("Signing key has been revoked"));
+        }
+
+        // Requests share the replay window of messages sent over ESMP sessions
+        let window = self.core.esmp.replay_window.as_secs();
+        if now().abs_diff(request.timestamp) > window {
+            return Ok(Err("Request timestamp outside of acceptance window"));
+        }
+        if !self
+            .in_memory_store()
+            .try_lock(KV_ESMP_REPLAY, &replay_key(request), window * 2)
+            .await?
+        {
+            return Ok(Err("Request has already been received"));
+        }
+
+        self.resolve_identity(request.pubkey).await.map(Ok)
+    }
+
+    /// Checks a group message submitted over the HTTP API without storing it,
+    /// as `submit_group_message` would before accepting it.
+    async fn check_group_message(
+        &self,
+        identities: &[String],
+        message: &Value,
+        msg: &EsmpMessage,
+        session_id: u64,
+    ) -> trc::Result<Result<(), &'static str>> {
+        let result = verify_group_message(self, identities, message, msg, session_id).await?;
+        if let Err(reason) = result {
+            trc::event!(
+                Esmp(trc::EsmpEvent::MessageRejected),
+                SpanId = session_id,
+                AccountName = msg.sender_pubkey.clone(),
+                Id = msg.id.clone(),
+                Reason = reason,
+            );
+        }
+        Ok(result)
+    }
+
+    async fn submit_group_message(
+        &self,
+        identities: &[String],
+        message: &Value,
+        msg: &EsmpMessage,
+        session_id: u64,
+    ) -> trc::Result<Result<String, &'static str>> {
+        if let Err(reason) = self
+            .check_group_message(identities, message, msg, session_id)
+            .await?
+        {
+            return Ok(Err(reason));
+        }
+        let Some(group_id) = msg.group_id.as_deref() else {
+            return Ok(Err("Message is not addressed to a group"));
+        };
+
+        // Messages share their ids with ESMP sessions
+        let window = self.core.esmp.replay_window.as_secs();
+        let key = replay_key(msg);
+        if !self
+            .in_memory_store()
+            .try_lock(KV_ESMP_REPLAY, &key, window * 2)
+            .await?
+        {
+            trc::event!(
+                Esmp(trc::EsmpEvent::Replay),
+                SpanId = session_id,
+                AccountName = msg.sender_pubkey.clone(),
+                Id = msg.id.clone(),
+            );
+            return Ok(Err("Message has already been received"));
+        }
+
+        let contents = canonicalize(message);
+        let size = contents.len();
+        let result = self
+            .persist_group_message(group_id, msg, contents.clone())
+            .await;
+        match &result {
+            Ok(Ok(_)) => {
+                trc::event!(
+                    Esmp(trc::EsmpEvent::MessageAccepted),
+                    SpanId = session_id,
+                    AccountName = msg.sender_pubkey.clone(),
+                    Id = msg.id.clone(),
+                    Type = msg.r#type.clone(),
+                    Size = size,
+                    Total = 1,
+                );
+            }
+            Ok(Err(reason)) => {
+                trc::event!(
+                    Esmp(trc::EsmpEvent::MessageRejected),
+                    SpanId = session_id,
+                    AccountName = msg.sender_pubkey.clone(),
+                    Id = msg.id.clone(),
+                    Reason = *reason,
+                );
+            }
+            Err(_) => (),
+        }
+        if !matches!(result, Ok(Ok(_))) {
+            // Rejected messages can be submitted again
+            if let Err(err) = self
+                .in_memory_store()
+                .remove_lock(KV_ESMP_REPLAY, &key)
+                .await
+            {
+                trc::error!(err.span_id(session_id).caused_by(trc::location!()));
+            }
+        }
+
+        result.map(|result| result.map(|_| contents))
+    }
+}
+
+/// Verifies the signature, signer and timing of a group message and whether
+/// the group currently accepts it. Replays are detected when it is submitted.
+async fn verify_group_message(
+    server: &Server,
+    identities: &[String],
+    message: &Value,
+    msg: &EsmpMessage,
+    session_id: u64,
+) -> trc::Result<Result<(), &'static str>> {
+    let Some(group_id) = msg.group_id.as_deref() else {
+        return Ok(Err("Message is not addressed to a group"));
+    };
+    if msg.id.is_empty() || msg.id.len() > MAX_ID_LENGTH {
+        return Ok(Err("Invalid message id"));
+    }
+    if msg.version != ESMP_VERSION {
+        return Ok(Err("Unsupported protocol version"));
+    }
+    if !verify_signature(
+        &msg.sender_pubkey,
+        &msg.signature,
+        &signing_input(message).unwrap_or_default(),
+    ) {
+        trc::event!(
+            Esmp(trc::EsmpEvent::SignatureFailure),
+            SpanId = session_id,
+            AccountName = msg.sender_pubkey.clone(),
+            Id = msg.id.clone(),
+        );
+        return Ok(Err("Rejected unsigned or tampered ESMP message"));
+    }
+
+    // Messages are submitted by the requester, with a key that was not revoked
+    let participants = resolve_participants(server, msg).await?;
+    if !identities.contains(&participants.sender) {
+        return Ok(Err("Message is not signed by the requester"));
+    }
+    if server
+        .key_set(&msg.sender_pubkey)
+        .await?
+        .is_some_and(|key_set| !key_set.is_active(&msg.sender_pubkey, msg.timestamp))
+    {
+        return Ok(Err("Signing key has been revoked"));
+    }
+    if let Some(from) = &msg.from
+        && !server.is_address_key(from, &msg.sender_pubkey).await?
+    {
+        return Ok(Err("Sender address is not bound to the signing key"));
+    }
+
+    // Messages share their acceptance window with ESMP sessions
+    if now().abs_diff(msg.timestamp) > server.core.esmp.replay_window.as_secs() {
+        return Ok(Err("Message timestamp outside of acceptance window"));
+    }
+
+    // Authorize against the current state of the group
+    let group = server.fetch_group(group_id).await?;
+    let invited = match &group {
+        Some((document_id, group)) if msg.r#type == "system" => {
+            match verify_invite(server, *document_id, group, msg, &participants).await? {
+                Ok(invited) => invited,
+                Err(reason) => return Ok(Err(reason)),
+            }
+        }
+        _ => false,
+    };
+    Ok(authorize(
+        group.as_ref().map(|(_, group)| group),
+        msg,
+        &participants,
+        invited,
+    ))
+}
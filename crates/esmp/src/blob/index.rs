@@ -0,0 +1,63 @@
+/*
+ * SPDX-FileCopyrightText: 2020 Stalwart Labs LLC <hello@stalw.art>
+ *
+ * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
+ */
+
+use common::storage::index::{IndexValue, IndexableAndSerializableObject, IndexableObject};
+use utils::BlobHash;
+
+use crate::{IDX_BLOB, IDX_PUBKEY};
+
+use super::{ArchivedStoredBlob, StoredBlob};
+
+// Records link their contents and thumbnail so that they outlive the upload reservation
+impl IndexableObject for StoredBlob {
+    fn index_values(&self) -> impl Iterator<Item = IndexValue<'_>> {
+        [
+            IndexValue::Index {
+                field: IDX_BLOB,
+                value: self.hash.as_slice().into(),
+            },
+            IndexValue::Index {
+                field: IDX_PUBKEY,
+                value: self.owner.as_str().into(),
+            },
+            IndexValue::Blob {
+                value: self.hash.clone(),
+            },
+        ]
+        .into_iter()
+        .chain(self.thumbnail.as_ref().map(|thumbnail| IndexValue::Blob {
+            value: thumbnail.hash.clone(),
+        }))
+    }
+}
+
+impl IndexableAndSerializableObject for StoredBlob {
+    fn is_versioned() -> bool {
+        false
+    }
+}
+
+impl IndexableObject for &ArchivedStoredBlob {
+    fn index_values(&self) -> impl Iterator<Item = IndexValue<'_>> {
+        [
+            IndexValue::Index {
+                field: IDX_BLOB,
+                value: self.hash.0.as_slice().into(),
+            },
+            IndexValue::Index {
+                field: IDX_PUBKEY,
+                value: self.owner.as_str().into(),
+            },
+            IndexValue::Blob {
+                value: BlobHash::from(&self.hash),
+            },
+        ]
+        .into_iter()
+        .chain(self.thumbnail.as_ref().map(|thumbnail| IndexValue::Blob {
+            value: BlobHash::from(&thumbnail.hash),
+        }))
+    }
+}
@@ -0,0 +1,147 @@
+/*
+ * SPDX-FileCopyrightText: 2020 Stalwart Labs LLC <hello@stalw.art>
+ *
+ * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
+ */
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use utils::{BLOB_HASH_LEN, BlobHash};
+
+use crate::handler::EsmpMessage;
+
+pub mod index;
+pub mod persist;
+
+const MAX_ATTACHMENTS: usize = 32;
+
+#[derive(
+    rkyv::Archive,
+    rkyv::Deserialize,
+    rkyv::Serialize,
+    Debug,
+    Clone,
+    Copy,
+    PartialEq,
+    Eq,
+    Serialize,
+    Deserialize,
+)]
+#[rkyv(derive(Debug))]
+pub enum BlobKind {
+    #[serde(rename = "picture")]
+    Picture,
+    #[serde(rename = "attachment")]
+    Attachment,
+}
+
+/// A file uploaded to the blob store. Contents are shared between uploads with
+/// the same hash, while each owner and scope keeps a record of its own.
+#[derive(rkyv::Archive, rkyv::Deserialize, rkyv::Serialize, Debug, Clone, PartialEq, Eq)]
+#[rkyv(derive(Debug))]
+pub struct StoredBlob {
+    pub hash: BlobHash,
+    pub owner: String,
+    pub kind: BlobKind,
+    pub content_type: String,
+    pub size: u32,
+    pub group_id: Option<String>, // Readable by the members of the group
+    pub posted: Vec<u32>,         // Group messages attaching the file
+    pub recipients: Vec<String>,  // Identities sent the file in a direct message
+    pub thumbnail: Option<Thumbnail>,
+    pub created_at: u64,
+}
+
+#[derive(rkyv::Archive, rkyv::Deserialize, rkyv::Serialize, Debug, Clone, PartialEq, Eq)]
+#[rkyv(derive(Debug))]
+pub struct Thumbnail {
+    pub hash: BlobHash,
+    pub size: u32,
+}
+
+/// Who a file is shared with by a message that attaches it.
+#[derive(Debug, Clone, Copy)]
+pub enum BlobShare<'x> {
+    /// Group files are readable by the members that can read the message
+    Group { group_id: &'x str, message_id: u32 },
+    /// Direct attachments are readable by the recipients of their uploader
+    Direct {
+        sender: &'x str,
+        recipients: &'x [String],
+    },
+}
+
+/// The contents of a new upload.
+#[derive(Debug, Clone)]
+pub struct BlobUpload<'x> {
+    pub owner: String,
+    pub kind: BlobKind,
+    pub content_type: String,
+    pub group_id: Option<String>,
+    pub data: &'x [u8],
+    pub thumbnail: Option<Vec<u8>>,
+}
+
+impl StoredBlob {
+    pub fn blob_id(&self) -> String {
+        self.hash.to_hex()
+    }
+
+    /// Bytes charged to the owner's quota
+    pub fn used(&self) -> u64 {
+        self.size as u64
+            + self
+                .thumbnail
+                .as_ref()
+                .map_or(0, |thumbnail| thumbnail.size as u64)
+    }
+}
+
+impl BlobUpload<'_> {
+    pub fn used(&self) -> u64 {
+        self.data.len() as u64
+            + self
+                .thumbnail
+                .as_ref()
+                .map_or(0, |thumbnail| thumbnail.len() as u64)
+    }
+}
+
+/// Blob ids are the hex encoded hash of the contents.
+pub fn parse_blob_id(blob_id: &str) -> Option<BlobHash> {
+    if blob_id.len() != BLOB_HASH_LEN * 2 {
+        return None;
+    }
+
+    let mut hash = BlobHash::default();
+    for (byte, hex) in hash.0.iter_mut().zip(blob_id.as_bytes().chunks(2)) {
+        *byte = u8::from_str_radix(std::str::from_utf8(hex).ok()?, 16).ok()?;
+    }
+    Some(hash)
+}
+
+impl EsmpMessage {
+    /// Returns the blobs listed in the `attachments` of the message body.
+    pub fn attachments(&self) -> Result<Vec<BlobHash>, &'static str> {
+        let Some(attachments) = self.body.get("attachments") else {
+            return Ok(vec![]);
+        };
+        let attachments = attachments
+            .as_array()
+            .ok_or("Attachments must be a list of blob references")?;
+        if attachments.len() > MAX_ATTACHMENTS {
+            return Err("Too many attachments");
+        }
+
+        attachments
+            .iter()
+            .map(|attachment| {
+                attachment
+                    .get("blob_id")
+                    .and_then(Value::as_str)
+                    .and_then(parse_blob_id)
+                    .ok_or("Invalid attachment blob_id")
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,303 @@
+/*
+ * SPDX-FileCopyrightText: 2020 Stalwart Labs LLC <hello@stalw.art>
+ *
+ * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
+ */
+
+use std::future::Future;
+
+use common::{Server, storage::index::ObjectIndexBuilder};
+use jmap_proto::types::collection::Collection;
+use store::{
+    BlobClass,
+    query::Filter,
+    write::{BatchBuilder, BlobOp, now},
+};
+use trc::AddContext;
+use utils::BlobHash;
+
+use crate::{ESMP_ACCOUNT_ID, IDX_BLOB, IDX_PUBKEY};
+
+use super::{BlobKind, BlobShare, BlobUpload, StoredBlob, Thumbnail};
+
+pub trait EsmpBlobStore: Sync + Send {
+    fn blob_records(
+        &self,
+        hash: &BlobHash,
+    ) -> impl Future<Output = trc::Result<Vec<StoredBlob>>> + Send;
+
+    fn blob_usage(&self, owner: &str) -> impl Future<Output = trc::Result<u64>> + Send;
+
+    fn store_blob(
+        &self,
+        upload: BlobUpload<'_>,
+    ) -> impl Future<Output = trc::Result<StoredBlob>> + Send;
+
+    fn delete_blob(
+        &self,
+        hash: &BlobHash,
+        owner: &str,
+    ) -> impl Future<Output = trc::Result<bool>> + Send;
+
+    fn is_group_blob(
+        &self,
+        hash: &BlobHash,
+        group_id: &str,
+    ) -> impl Future<Output = trc::Result<bool>> + Send;
+
+    fn blob_contents(
+        &self,
+        hash: &BlobHash,
+    ) -> impl Future<Output = trc::Result<Option<Vec<u8>>>> + Send;
+}
+
+impl EsmpBlobStore for Server {
+    async fn blob_records(&self, hash: &BlobHash) -> trc::Result<Vec<StoredBlob>> {
+        blob_documents(self, Filter::eq(IDX_BLOB, hash.as_slice().to_vec()))
+            .await
+            .map(|records| records.into_iter().map(|(_, blob)| blob).collect())
+    }
+
+    async fn blob_usage(&self, owner: &str) -> trc::Result<u64> {
+        blob_documents(self, Filter::eq(IDX_PUBKEY, owner.as_bytes().to_vec()))
+            .await
+            .map(|records| records.iter().map(|(_, blob)| blob.used()).sum())
+    }
+
+    async fn store_blob(&self, upload: BlobUpload<'_>) -> trc::Result<StoredBlob> {
+        // Uploading the same contents to the same scope is not charged twice
+        let hash = BlobHash::generate(upload.data);
+        if let Some(blob) = self.blob_records(&hash).await?.into_iter().find(|blob| {
+            blob.owner == upload.owner
+                && blob.kind == upload.kind
+                && blob.group_id == upload.group_id
+        }) {
+            return Ok(blob);
+        }
+
+        let quota = self.core.esmp.blob.quota;
+        if quota > 0 && self.blob_usage(&upload.owner).await? + upload.used() > quota {
+            return Err(trc::LimitEvent::Quota
+                .into_err()
+                .ctx(trc::Key::Size, quota)
+                .id(upload.owner));
+        }
+
+        // Contents are reserved until the record linking them is written
+        let mut reservations = Vec::with_capacity(2);
+        let blob_id = self
+            .put_blob(ESMP_ACCOUNT_ID, upload.data, false)
+            .await
+            .caused_by(trc::location!())?;
+        reservations.push(blob_id.clone());
+        let thumbnail = if let Some(thumbnail) = &upload.thumbnail {
+            let thumbnail_id = self
+                .put_blob(ESMP_ACCOUNT_ID, thumbnail, false)
+                .await
+                .caused_by(trc::location!())?;
+            reservations.push(thumbnail_id.clone());
+            Some(Thumbnail {
+                hash: thumbnail_id.hash,
+                size: thumbnail.len() as u32,
+            })
+        } else {
+            None
+        };
+        let blob = StoredBlob {
+            hash: blob_id.hash,
+            owner: upload.owner,
+            kind: upload.kind,
+            content_type: upload.content_type,
+            size: upload.data.len() as u32,
+            group_id: upload.group_id,
+            posted: Vec::new(),
+            recipients: Vec::new(),
+            thumbnail,
+            created_at: now(),
+        };
+
+        let document_id = self
+            .store()
+            .assign_document_ids(ESMP_ACCOUNT_ID, Collection::EsmpBlob, 1)
+            .await
+            .caused_by(trc::location!())?;
+        let mut batch = BatchBuilder::new();
+        batch
+            .with_account_id(ESMP_ACCOUNT_ID)
+            .with_collection(Collection::EsmpBlob)
+            .create_document(document_id)
+            .custom(ObjectIndexBuilder::<(), _>::new().with_changes(blob.clone()))
+            .caused_by(trc::location!())?;
+        for blob_id in reservations {
+            if let BlobClass::Reserved { expires, .. } = blob_id.class {
+                batch.clear(BlobOp::Reserve {
+                    hash: blob_id.hash,
+                    until: expires,
+                });
+            }
+        }
+        self.commit_batch(batch)
+            .await
+            .caused_by(trc::location!())
+            .map(|_| blob)
+    }
+
+    async fn delete_blob(&self, hash: &BlobHash, owner: &str) -> trc::Result<bool> {
+        let document_ids = blob_documents(self, Filter::eq(IDX_BLOB, hash.as_slice().to_vec()))
+            .await?
+            .into_iter()
+            .filter(|(_, blob)| blob.owner == owner)
+            .map(|(document_id, _)| document_id)
+            .collect::<Vec<_>>();
+        if document_ids.is_empty() {
+            return Ok(false);
+        }
+
+        // Unlinked contents are removed by the blob store purge
+        let mut batch = BatchBuilder::new();
+        batch
+            .with_account_id(ESMP_ACCOUNT_ID)
+            .with_collection(Collection::EsmpBlob);
+        for document_id in document_ids {
+            let Some(archive) = self
+                .get_archive(ESMP_ACCOUNT_ID, Collection::EsmpBlob, document_id)
+                .await?
+            else {
+                continue;
+            };
+            batch
+                .delete_document(document_id)
+                .custom(
+                    ObjectIndexBuilder::<_, ()>::new().with_current(
+                        archive
+                            .to_unarchived::<StoredBlob>()
+                            .caused_by(trc::location!())?,
+                    ),
+                )
+                .caused_by(trc::location!())?;
+        }
+        self.commit_batch(batch)
+            .await
+            .caused_by(trc::location!())
+            .map(|_| true)
+    }
+
+    async fn is_group_blob(&self, hash: &BlobHash, group_id: &str) -> trc::Result<bool> {
+        self.blob_records(hash).await.map(|records| {
+            records
+                .iter()
+                .any(|blob| blob.group_id.as_deref() == Some(group_id))
+        })
+    }
+
+    async fn blob_contents(&self, hash: &BlobHash) -> trc::Result<Option<Vec<u8>>> {
+        self.blob_store()
+            .get_blob(hash.as_slice(), 0..usize::MAX)
+            .await
+            .caused_by(trc::location!())
+    }
+}
+
+/// Adds the readers of the files attached to a message to `batch`, which
+/// asserts the records so that concurrent shares are retried.
+pub(crate) async fn share_blobs(
+    server: &Server,
+    batch: &mut BatchBuilder,
+    hashes: &[BlobHash],
+    share: BlobShare<'_>,
+) -> trc::Result<()> {
+    for (pos, hash) in hashes.iter().enumerate() {
+        if hashes[..pos].contains(hash) {
+            continue;
+        }
+        let document_ids = server
+            .store()
+            .filter(
+                ESMP_ACCOUNT_ID,
+                Collection::EsmpBlob,
+                vec![Filter::eq(IDX_BLOB, hash.as_slice().to_vec())],
+            )
+            .await
+            .caused_by(trc::location!())?
+            .results;
+
+        for document_id in document_ids {
+            let Some(archive) = server
+                .get_archive(ESMP_ACCOUNT_ID, Collection::EsmpBlob, document_id)
+                .await?
+            else {
+                continue;
+            };
+            let mut blob = archive
+                .deserialize::<StoredBlob>()
+                .caused_by(trc::location!())?;
+            match share {
+                BlobShare::Group {
+                    group_id,
+                    message_id,
+                } if blob.group_id.as_deref() == Some(group_id) => {
+                    blob.posted.push(message_id);
+                }
+                BlobShare::Direct { sender, recipients }
+                    if blob.group_id.is_none()
+                        && blob.kind == BlobKind::Attachment
+                        && blob.owner == sender =>
+                {
+                    let count = blob.recipients.len();
+                    for recipient in recipients {
+                        if *recipient != blob.owner && !blob.recipients.contains(recipient) {
+                            blob.recipients.push(recipient.clone());
+                        }
+                    }
+                    if blob.recipients.len() == count {
+                        continue;
+                    }
+                }
+                _ => continue,
+            }
+
+            batch
+                .with_account_id(ESMP_ACCOUNT_ID)
+                .with_collection(Collection::EsmpBlob)
+                .update_document(document_id)
+                .custom(
+                    ObjectIndexBuilder::new()
+                        .with_current(
+                            archive
+                                .to_unarchived::<StoredBlob>()
+                                .caused_by(trc::location!())?,
+                        )
+                        .with_changes(blob),
+                )
+                .caused_by(trc::location!())?;
+        }
+    }
+
+    Ok(())
+}
+
+async fn blob_documents(server: &Server, filter: Filter) -> trc::Result<Vec<(u32, StoredBlob)>> {
+    let document_ids = server
+        .store()
+        .filter(ESMP_ACCOUNT_ID, Collection::EsmpBlob, vec![filter])
+        .await
+        .caused_by(trc::location!())?
+        .results;
+    let mut records = Vec::with_capacity(document_ids.len() as usize);
+
+    for document_id in document_ids {
+        if let Some(archive) = server
+            .get_archive(ESMP_ACCOUNT_ID, Collection::EsmpBlob, document_id)
+            .await?
+        {
+            records.push((
+                document_id,
+                archive
+                    .deserialize::<StoredBlob>()
+                    .caused_by(trc::location!())?,
+            ));
+        }
+    }
+
+    Ok(records)
+}
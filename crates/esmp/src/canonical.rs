@@ -0,0 +1,393 @@
+/*
+ * SPDX-FileCopyrightText: 2020 Stalwart Labs LLC <hello@stalw.art>
+ *
+ * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
+ */
+
+// JSON Canonicalization Scheme (RFC 8785)
+
+use std::fmt::{self, Write};
+
+use serde::{
+    Deserialize, Deserializer,
+    de::{self, MapAccess, SeqAccess, Visitor},
+};
+use serde_json::{Map, Number, Value};
+
+/// Top-level members that are never part of the signature input.
+pub const UNSIGNED_MEMBERS: &[&str] = &["signature", "sender_pubkey"];
+
+/// Largest integer magnitude represented exactly by an IEEE 754 double.
+pub const MAX_SAFE_INTEGER: u64 = (1 << 53) - 1;
+
+/// Parses a JSON document that can be canonicalized without ambiguity,
+/// rejecting duplicate object members and integers outside the safe range.
+pub fn parse_json(json: &[u8]) -> serde_json::Result<Value> {
+    serde_json::from_slice::<StrictValue>(json).map(|value| value.0)
+}
+
+pub fn canonicalize(value: &Value) -> String {
+    let mut out = String::with_capacity(128);
+    write_value(&mut out, value);
+    out
+}
+
+/// Returns the canonical bytes covered by the signature of a signed ESMP object.
+pub fn signing_input(value: &Value) -> Option<Vec<u8>> {
+    let object = value.as_object()?;
+    if !has_safe_integers(value) {
+        return None;
+    }
+    let mut out = String::with_capacity(128);
+    write_object(
+        &mut out,
+        object
+            .iter()
+            .filter(|(key, _)| !UNSIGNED_MEMBERS.contains(&key.as_str())),
+    );
+    Some(out.into_bytes())
+}
+
+fn has_safe_integers(value: &Value) -> bool {
+    match value {
+        Value::Number(number) => is_safe_number(number),
+        Value::Array(array) => array.iter().all(has_safe_integers),
+        Value::Object(object) => object.values().all(has_safe_integers),
+        _ => true,
+    }
+}
+
+fn is_safe_number(number: &Number) -> bool {
+    if let Some(value) = number.as_u64() {
+        value <= MAX_SAFE_INTEGER
+    } else if let Some(value) = number.as_i64() {
+        value.unsigned_abs() <= MAX_SAFE_INTEGER
+    } else {
+        true
+    }
+}
+
+fn write_value(out: &mut String, value: &Value) {
+    match value {
+        Value::Null => out.push_str("null"),
+        Value::Bool(true) => out.push_str("true"),
+        Value::Bool(false) => out.push_str("false"),
+        Value::Number(number) => write_number(out, number.as_f64().unwrap_or_default()),
+        Value::String(string) => write_string(out, string),
+        Value::Array(array) => {
+            out.push('[');
+            for (pos, item) in array.iter().enumerate() {
+                if pos > 0 {
+                    out.push(',');
+                }
+                write_value(out, item);
+            }
+            out.push(']');
+        }
+        Value::Object(object) => write_object(out, object.iter()),
+    }
+}
+
+fn write_object<'x>(out: &mut String, members: impl Iterator<Item = (&'x String, &'x Value)>) {
+    // Members are sorted by their UTF-16 code units
+    let mut members = members.collect::<Vec<_>>();
+    members.sort_unstable_by(|(a, _), (b, _)| a.encode_utf16().cmp(b.encode_utf16()));
+
+    out.push('{');
+    for (pos, (key, value)) in members.into_iter().enumerate() {
+        if pos > 0 {
+            out.push(',');
+        }
+        write_string(out, key);
+        out.push(':');
+        write_value(out, value);
+    }
+    out.push('}');
+}
+
+fn write_string(out: &mut String, value: &str) {
+    out.push('"');
+    for ch in value.chars() {
+        match ch {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\u{08}' => out.push_str("\\b"),
+            '\t' => out.push_str("\\t"),
+            '\n' => out.push_str("\\n"),
+            '\u{0C}' => out.push_str("\\f"),
+            '\r' => out.push_str("\\r"),
+            '\u{00}'..='\u{1F}' => {
+                let _ = write!(out, "\\u{:04x}", ch as u32);
+            }
+            _ => out.push(ch),
+        }
+    }
+    out.push('"');
+}
+
+// Serializes a number as ECMAScript's Number.prototype.toString()
+fn write_number(out: &mut String, value: f64) {
+    if value == 0.0 || !value.is_finite() {
+        out.push('0');
+        return;
+    }
+
+    // Shortest round-trip digits in scientific notation, e.g. "-1.25e-7"
+    let repr = format!("{:e}", value.abs());
+    let (mantissa, exponent) = repr.split_once('e').unwrap_or((&repr, "0"));
+    let digits = mantissa.replace('.', "");
+    let k = digits.len() as i32;
+    let n = exponent.parse::<i32>().unwrap_or_default() + 1;
+
+    if value.is_sign_negative() {
+        out.push('-');
+    }
+
+    if k <= n && n <= 21 {
+        out.push_str(&digits);
+        out.extend(std::iter::repeat_n('0', (n - k) as usize));
+    } else if 0 < n && n <= 21 {
+        out.push_str(&digits[..n as usize]);
+        out.push('.');
+        out.push_str(&digits[n as usize..]);
+    } else if -6 < n && n <= 0 {
+        out.push_str("0.");
+        out.extend(std::iter::repeat_n('0', -n as usize));
+        out.push_str(&digits);
+    } else {
+        out.push_str(&digits[..1]);
+        if k > 1 {
+            out.push('.');
+            out.push_str(&digits[1..]);
+        }
+        let _ = write!(out, "e{}{}", if n > 0 { '+' } else { '-' }, (n - 1).abs());
+    }
+}
+
+struct StrictValue(Value);
+
+impl<'de> Deserialize<'de> for StrictValue {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer
+            .deserialize_any(StrictValueVisitor)
+            .map(StrictValue)
+    }
+}
+
+struct StrictValueVisitor;
+
+impl<'de> Visitor<'de> for StrictValueVisitor {
+    type Value = Value;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a JSON value")
+    }
+
+    fn visit_bool<E: de::Error>(self, value: bool) -> Result<Value, E> {
+        Ok(Value::Bool(value))
+    }
+
+    fn visit_i64<E: de::Error>(self, value: i64) -> Result<Value, E> {
+        safe_integer(Number::from(value))
+    }
+
+    fn visit_u64<E: de::Error>(self, value: u64) -> Result<Value, E> {
+        safe_integer(Number::from(value))
+    }
+
+    fn visit_f64<E: de::Error>(self, value: f64) -> Result<Value, E> {
+        Ok(Number::from_f64(value).map_or(Value::Null, Value::Number))
+    }
+
+    fn visit_str<E: de::Error>(self, value: &str) -> Result<Value, E> {
+        Ok(Value::String(value.to_string()))
+    }
+
+    fn visit_string<E: de::Error>(self, value: String) -> Result<Value, E> {
+        Ok(Value::String(value))
+    }
+
+    fn visit_unit<E: de::Error>(self) -> Result<Value, E> {
+        Ok(Value::Null)
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Value, A::Error> {
+        let mut array = Vec::new();
+        while let Some(StrictValue(value)) = seq.next_element()? {
+            array.push(value);
+        }
+        Ok(Value::Array(array))
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Value, A::Error> {
+        let mut object = Map::new();
+        while let Some(key) = map.next_key::<String>()? {
+            if object.contains_key(&key) {
+                return Err(de::Error::custom(format_args!(
+                    "duplicate object member {key:?}"
+                )));
+            }
+            let StrictValue(value) = map.next_value()?;
+            object.insert(key, value);
+        }
+        Ok(Value::Object(object))
+    }
+}
+
+fn safe_integer<E: de::Error>(number: Number) -> Result<Value, E> {
+    if is_safe_number(&number) {
+        Ok(Value::Number(number))
+    } else {
+        Err(de::Error::custom(format_args!(
+            "integer {number} is outside of the safe range"
+        )))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, path::PathBuf};
+
+    use serde::Deserialize;
+
+    use crate::crypto::verify_signature;
+
+    use super::*;
+
+    #[derive(Deserialize)]
+    struct Vector {
+        name: String,
+        input: String,
+        expected: String,
+    }
+
+    #[derive(Deserialize)]
+    struct RejectedSignatureVector {
+        name: String,
+        public_key: String,
+        signing_input: String,
+        signature: String,
+    }
+
+    #[derive(Deserialize)]
+    struct SignatureVector {
+        public_key: String,
+        message: String,
+        signing_input: String,
+        signature: String,
+    }
+
+    fn resources() -> PathBuf {
+        PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+            .parent()
+            .unwrap()
+            .parent()
+            .unwrap()
+            .join("tests")
+            .join("resources")
+            .join("esmp")
+    }
+
+    #[test]
+    fn canonical_json() {
+        let vectors: Vec<Vector> =
+            serde_json::from_str(&fs::read_to_string(resources().join("jcs.json")).unwrap())
+                .unwrap();
+
+        for vector in vectors {
+            let value: Value = serde_json::from_str(&vector.input).unwrap();
+            assert_eq!(canonicalize(&value), vector.expected, "{}", vector.name);
+        }
+    }
+
+    #[test]
+    fn canonical_numbers() {
+        for (value, expected) in [
+            (0.0, "0"),
+            (-0.0, "0"),
+            (1.0, "1"),
+            (-1.5, "-1.5"),
+            (1e21, "1e+21"),
+            (1e20, "100000000000000000000"),
+            (123456789012345680000.0, "123456789012345680000"),
+            (0.000001, "0.000001"),
+            (0.0000001, "1e-7"),
+            (4.5, "4.5"),
+            (2e-3, "0.002"),
+            (333333333.3333333, "333333333.3333333"),
+            (1e30, "1e+30"),
+            (5e-324, "5e-324"),
+            (1.7976931348623157e308, "1.7976931348623157e+308"),
+            (9007199254740992.0, "9007199254740992"),
+            (295147905179352830000.0, "295147905179352830000"),
+        ] {
+            let mut out = String::new();
+            write_number(&mut out, value);
+            assert_eq!(out, expected, "{value:?}");
+        }
+    }
+
+    #[test]
+    fn strict_parsing() {
+        for json in [
+            r#"{"a":9007199254740991,"b":-9007199254740991,"c":1e300}"#,
+            r#"{"a":{"b":1},"c":{"b":2}}"#,
+        ] {
+            let value = parse_json(json.as_bytes()).unwrap();
+            assert_eq!(value, serde_json::from_str::<Value>(json).unwrap());
+            assert!(signing_input(&value).is_some(), "{json}");
+        }
+
+        for json in [
+            r#"{"a":9007199254740992}"#,
+            r#"{"a":[-9007199254740992]}"#,
+            r#"{"a":18446744073709551615}"#,
+            r#"{"a":1,"a":1}"#,
+            r#"{"a":{"b":1,"b":2}}"#,
+            r#"[{"a":1,"a":2}]"#,
+        ] {
+            assert!(parse_json(json.as_bytes()).is_err(), "{json}");
+        }
+
+        assert!(signing_input(&serde_json::json!({"a": u64::MAX})).is_none());
+        assert!(signing_input(&serde_json::json!({"a": [i64::MIN]})).is_none());
+    }
+
+    #[test]
+    fn signature_vectors() {
+        let vectors: Vec<SignatureVector> =
+            serde_json::from_str(&fs::read_to_string(resources().join("signatures.json")).unwrap())
+                .unwrap();
+
+        for vector in vectors {
+            let value: Value = serde_json::from_str(&vector.message).unwrap();
+            let input = signing_input(&value).unwrap();
+            assert_eq!(std::str::from_utf8(&input).unwrap(), vector.signing_input);
+            assert!(verify_signature(
+                &vector.public_key,
+                &vector.signature,
+                &input
+            ));
+        }
+    }
+
+    #[test]
+    fn rejected_signature_vectors() {
+        let vectors: Vec<RejectedSignatureVector> = serde_json::from_str(
+            &fs::read_to_string(resources().join("signatures-rejected.json")).unwrap(),
+        )
+        .unwrap();
+
+        for vector in vectors {
+            assert!(
+                !verify_signature(
+                    &vector.public_key,
+                    &vector.signature,
+                    vector.signing_input.as_bytes()
+                ),
+                "{}",
+                vector.name
+            );
+        }
+    }
+}
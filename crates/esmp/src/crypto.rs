@@ -0,0 +1,97 @@
+/*
+ * SPDX-FileCopyrightText: 2020 Stalwart Labs LLC <hello@stalw.art>
+ *
+ * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
+ */
+
+use aes_gcm::{
+    Aes256Gcm, KeyInit, Nonce,
+    aead::{Aead, Payload, generic_array::GenericArray},
+};
+use base64::{Engine, engine::general_purpose};
+use ed25519_dalek::{Signature, Signer, SigningKey, VerifyingKey};
+use store::rand::{self, RngCore};
+
+/// Returns whether `pubkey_b64` is a valid base64 encoded Ed25519 public key.
+pub fn is_public_key(pubkey_b64: &str) -> bool {
+    general_purpose::STANDARD
+        .decode(pubkey_b64)
+        .ok()
+        .and_then(|bytes| <[u8; 32]>::try_from(bytes).ok())
+        .is_some_and(|bytes| VerifyingKey::from_bytes(&bytes).is_ok())
+}
+
+pub fn verify_signature(pubkey_b64: &str, signature_b64: &str, message: &[u8]) -> bool {
+    let Some(pubkey) = general_purpose::STANDARD
+        .decode(pubkey_b64)
+        .ok()
+        .and_then(|bytes| <[u8; 32]>::try_from(bytes).ok())
+        .and_then(|bytes| VerifyingKey::from_bytes(&bytes).ok())
+    else {
+        return false;
+    };
+    let Some(signature) = general_purpose::STANDARD
+        .decode(signature_b64)
+        .ok()
+        .and_then(|bytes| Signature::from_slice(&bytes).ok())
+    else {
+        return false;
+    };
+
+    // Strict verification rejects malleable signatures and small-order keys,
+    // so that each message has a single valid signature
+    pubkey.verify_strict(message, &signature).is_ok()
+}
+
+pub fn sign_message(key: &SigningKey, message: &[u8]) -> String {
+    general_purpose::STANDARD.encode(key.sign(message).to_bytes())
+}
+
+pub fn generate_nonce() -> String {
+    let mut nonce = [0u8; 32];
+    rand::rng().fill_bytes(&mut nonce);
+    general_purpose::STANDARD.encode(nonce)
+}
+
+const NONCE_LEN: usize = 12;
+
+pub fn generate_key() -> [u8; 32] {
+    let mut key = [0u8; 32];
+    rand::rng().fill_bytes(&mut key);
+    key
+}
+
+pub fn encrypt_aes(key: &[u8; 32], plaintext: &[u8], aad: &[u8]) -> Result<Vec<u8>, String> {
+    let mut nonce = [0u8; NONCE_LEN];
+    rand::rng().fill_bytes(&mut nonce);
+    let ciphertext = Aes256Gcm::new(&GenericArray::clone_from_slice(key))
+        .encrypt(
+            Nonce::from_slice(&nonce),
+            Payload {
+                msg: plaintext,
+                aad,
+            },
+        )
+        .map_err(|err| err.to_string())?;
+
+    let mut output = Vec::with_capacity(NONCE_LEN + ciphertext.len());
+    output.extend_from_slice(&nonce);
+    output.extend_from_slice(&ciphertext);
+    Ok(output)
+}
+
+pub fn decrypt_aes(key: &[u8; 32], ciphertext: &[u8], aad: &[u8]) -> Result<Vec<u8>, String> {
+    if ciphertext.len() <= NONCE_LEN {
+        return Err("Ciphertext too short".to_string());
+    }
+    let (nonce, ciphertext) = ciphertext.split_at(NONCE_LEN);
+    Aes256Gcm::new(&GenericArray::clone_from_slice(key))
+        .decrypt(
+            Nonce::from_slice(nonce),
+            Payload {
+                msg: ciphertext,
+                aad,
+            },
+        )
+        .map_err(|err| err.to_string())
+}
@@ -0,0 +1,37 @@
+/*
+ * SPDX-FileCopyrightText: 2020 Stalwart Labs LLC <hello@stalw.art>
+ *
+ * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
+ */
+
+use common::storage::index::{IndexValue, IndexableAndSerializableObject, IndexableObject};
+
+use crate::IDX_PUBKEY;
+
+use super::{ArchivedKeyPackage, KeyPackage};
+
+impl IndexableObject for KeyPackage {
+    fn index_values(&self) -> impl Iterator<Item = IndexValue<'_>> {
+        [IndexValue::Index {
+            field: IDX_PUBKEY,
+            value: self.pubkey.as_str().into(),
+        }]
+        .into_iter()
+    }
+}
+
+impl IndexableAndSerializableObject for KeyPackage {
+    fn is_versioned() -> bool {
+        false
+    }
+}
+
+impl IndexableObject for &ArchivedKeyPackage {
+    fn index_values(&self) -> impl Iterator<Item = IndexValue<'_>> {
+        [IndexValue::Index {
+            field: IDX_PUBKEY,
+            value: self.pubkey.as_str().into(),
+        }]
+        .into_iter()
+    }
+}
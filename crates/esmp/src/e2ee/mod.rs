@@ -0,0 +1,158 @@
+/*
+ * SPDX-FileCopyrightText: 2020 Stalwart Labs LLC <hello@stalw.art>
+ *
+ * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
+ */
+
+use base64::{Engine, engine::general_purpose};
+use serde::{Deserialize, Serialize};
+
+use crate::handler::EsmpMessage;
+
+pub mod index;
+pub mod persist;
+
+/// Content key wrapped for each recipient with an X25519 key agreement.
+pub const ENVELOPE_DIRECT: &str = "x25519-aes256gcm";
+/// Content encrypted with the key of the current group epoch.
+pub const ENVELOPE_GROUP: &str = "mls-aes256gcm";
+
+pub const MAX_KEY_PACKAGES: usize = 100;
+
+const MAX_PACKAGE_ID_LENGTH: usize = 128;
+const KEY_LEN: usize = 32;
+const NONCE_LEN: usize = 12;
+const WRAPPED_KEY_LEN: usize = KEY_LEN + 16;
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Envelope {
+    pub alg: String,
+    pub nonce: String,
+    pub ciphertext: String,
+    #[serde(default)]
+    pub epoch: Option<u64>,
+    #[serde(default)]
+    pub recipients: Vec<WrappedKey>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct WrappedKey {
+    pub pubkey: String,
+    #[serde(default)]
+    pub key_package: Option<String>,
+    pub ephemeral_key: String,
+    pub wrapped_key: String,
+}
+
+#[derive(
+    rkyv::Archive,
+    rkyv::Deserialize,
+    rkyv::Serialize,
+    Debug,
+    Default,
+    Clone,
+    PartialEq,
+    Eq,
+    Serialize,
+    Deserialize,
+)]
+#[rkyv(derive(Debug))]
+pub struct KeyPackage {
+    #[serde(skip)]
+    pub pubkey: String,
+    #[serde(rename = "id")]
+    pub package_id: String,
+    pub key: String,
+    #[serde(default)]
+    pub last_resort: bool,
+    pub signature: String,
+    #[serde(skip)]
+    pub created_at: u64,
+}
+
+impl EsmpMessage {
+    /// Validates the envelope of an encrypted message. The ciphertext itself
+    /// is opaque to the server.
+    pub fn validate_envelope(&self) -> Result<Envelope, &'static str> {
+        let envelope = serde_json::from_value::<Envelope>(self.body.clone())
+            .map_err(|_| "Encrypted messages require an envelope body")?;
+
+        if !is_base64_len(&envelope.nonce, NONCE_LEN) {
+            return Err("Invalid envelope nonce");
+        }
+        if !general_purpose::STANDARD
+            .decode(&envelope.ciphertext)
+            .is_ok_and(|ciphertext| !ciphertext.is_empty())
+        {
+            return Err("Invalid envelope ciphertext");
+        }
+
+        if self.group_id.is_some() {
+            if envelope.alg != ENVELOPE_GROUP {
+                return Err("Unsupported group envelope algorithm");
+            }
+            if envelope.epoch.is_none() {
+                return Err("Group envelopes require an epoch");
+            }
+            if !envelope.recipients.is_empty() {
+                return Err("Group envelopes cannot contain wrapped keys");
+            }
+        } else {
+            if envelope.alg != ENVELOPE_DIRECT {
+                return Err("Unsupported direct envelope algorithm");
+            }
+            for wrapped in &envelope.recipients {
+                if !is_base64_len(&wrapped.ephemeral_key, KEY_LEN)
+                    || !is_base64_len(&wrapped.wrapped_key, WRAPPED_KEY_LEN)
+                {
+                    return Err("Invalid wrapped content key");
+                }
+            }
+
+            // Every recipient needs a content key, the sender may keep one for itself
+            let recipients = self.recipients();
+            if recipients.iter().any(|recipient| {
+                !envelope
+                    .recipients
+                    .iter()
+                    .any(|wrapped| wrapped.pubkey == *recipient)
+            }) {
+                return Err("Missing wrapped content key for a recipient");
+            }
+            if envelope
+                .recipients
+                .iter()
+                .enumerate()
+                .any(|(pos, wrapped)| {
+                    (wrapped.pubkey != self.sender_pubkey
+                        && !recipients.contains(&wrapped.pubkey.as_str()))
+                        || envelope.recipients[..pos]
+                            .iter()
+                            .any(|other| other.pubkey == wrapped.pubkey)
+                })
+            {
+                return Err("Wrapped content keys must match the recipients");
+            }
+        }
+
+        Ok(envelope)
+    }
+}
+
+impl KeyPackage {
+    pub fn validate(&self) -> Result<(), &'static str> {
+        if self.package_id.is_empty() || self.package_id.len() > MAX_PACKAGE_ID_LENGTH {
+            Err("Invalid key package id")
+        } else if !is_base64_len(&self.key, KEY_LEN) {
+            Err("Key packages must contain an X25519 public key")
+        } else {
+            Ok(())
+        }
+    }
+}
+
+fn is_base64_len(value: &str, len: usize) -> bool {
+    general_purpose::STANDARD
+        .decode(value)
+        .is_ok_and(|bytes| bytes.len() == len)
+}
@@ -0,0 +1,253 @@
+/*
+ * SPDX-FileCopyrightText: 2020 Stalwart Labs LLC <hello@stalw.art>
+ *
+ * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
+ */
+
+use std::{future::Future, time::Duration};
+
+use common::{KV_LOCK_ESMP_KEY_PACKAGE, Server, storage::index::ObjectIndexBuilder};
+use jmap_proto::types::collection::Collection;
+use store::{query::Filter, rand::Rng, write::BatchBuilder};
+use trc::AddContext;
+
+use crate::{ESMP_ACCOUNT_ID, IDX_PUBKEY, MAX_RETRIES};
+
+use super::{KeyPackage, MAX_KEY_PACKAGES};
+
+const KEY_PACKAGE_LOCK_EXPIRY: u64 = 30;
+
+pub trait KeyPackageStore: Sync + Send {
+    fn key_packages(
+        &self,
+        pubkey: &str,
+    ) -> impl Future<Output = trc::Result<Vec<(u32, KeyPackage)>>> + Send;
+
+    fn publish_key_packages(
+        &self,
+        pubkey: &str,
+        packages: Vec<KeyPackage>,
+    ) -> impl Future<Output = trc::Result<Result<usize, &'static str>>> + Send;
+
+    fn claim_key_package(
+        &self,
+        pubkey: &str,
+    ) -> impl Future<Output = trc::Result<Option<KeyPackage>>> + Send;
+}
+
+impl KeyPackageStore for Server {
+    async fn key_packages(&self, pubkey: &str) -> trc::Result<Vec<(u32, KeyPackage)>> {
+        let document_ids = self
+            .store()
+            .filter(
+                ESMP_ACCOUNT_ID,
+                Collection::EsmpKeyPackage,
+                vec![Filter::eq(IDX_PUBKEY, pubkey.as_bytes().to_vec())],
+            )
+            .await
+            .caused_by(trc::location!())?
+            .results;
+
+        let mut packages = Vec::with_capacity(document_ids.len() as usize);
+        for document_id in document_ids {
+            if let Some(archive) = self
+                .get_archive(ESMP_ACCOUNT_ID, Collection::EsmpKeyPackage, document_id)
+                .await?
+            {
+                packages.push((
+                    document_id,
+                    archive
+                        .deserialize::<KeyPackage>()
+                        .caused_by(trc::location!())?,
+                ));
+            }
+        }
+
+        Ok(packages)
+    }
+
+    async fn publish_key_packages(
+        &self,
+        pubkey: &str,
+        packages: Vec<KeyPackage>,
+    ) -> trc::Result<Result<usize, &'static str>> {
+        // Package ids and counts are checked against the stored packages,
+        // so concurrent publications for the same key are serialized
+        let mut try_count = 0;
+        while !self
+            .in_memory_store()
+            .try_lock(
+                KV_LOCK_ESMP_KEY_PACKAGE,
+                pubkey.as_bytes(),
+                KEY_PACKAGE_LOCK_EXPIRY,
+            )
+            .await
+            .caused_by(trc::location!())?
+        {
+            if try_count >= MAX_RETRIES {
+                return Err(trc::StoreEvent::AssertValueFailed
+                    .into_err()
+                    .details("Key packages are being published by another request")
+                    .caused_by(trc::location!()));
+            }
+            let backoff = store::rand::rng().random_range(50..=300);
+            tokio::time::sleep(Duration::from_millis(backoff)).await;
+            try_count += 1;
+        }
+
+        let result = store_key_packages(self, pubkey, &packages).await;
+        if let Err(err) = self
+            .in_memory_store()
+            .remove_lock(KV_LOCK_ESMP_KEY_PACKAGE, pubkey.as_bytes())
+            .await
+        {
+            trc::error!(err.details("Failed to release key package lock."));
+        }
+        result
+    }
+
+    async fn claim_key_package(&self, pubkey: &str) -> trc::Result<Option<KeyPackage>> {
+        let mut try_count = 0;
+
+        loop {
+            let packages = self.key_packages(pubkey).await?;
+            let Some((document_id, package)) =
+                packages.iter().find(|(_, package)| !package.last_resort)
+            else {
+                // The last resort package is handed out once one-time packages run out
+                return Ok(packages
+                    .into_iter()
+                    .find(|(_, package)| package.last_resort)
+                    .map(|(_, package)| package));
+            };
+
+            // One-time packages are removed when claimed
+            let Some(package_) = self
+                .get_archive(ESMP_ACCOUNT_ID, Collection::EsmpKeyPackage, *document_id)
+                .await?
+            else {
+                continue;
+            };
+            let current = package_
+                .to_unarchived::<KeyPackage>()
+                .caused_by(trc::location!())?;
+            let mut batch = BatchBuilder::new();
+            batch
+                .with_account_id(ESMP_ACCOUNT_ID)
+                .with_collection(Collection::EsmpKeyPackage)
+                .delete_document(*document_id)
+                .custom(ObjectIndexBuilder::<_, ()>::new().with_current(current))
+                .caused_by(trc::location!())?;
+
+            match self.commit_batch(batch).await {
+                Ok(_) => return Ok(Some(package.clone())),
+                Err(err) if err.is_assertion_failure() && try_count < MAX_RETRIES => {
+                    let backoff = store::rand::rng().random_range(50..=300);
+                    tokio::time::sleep(Duration::from_millis(backoff)).await;
+                    try_count += 1;
+                }
+                Err(err) => {
+                    return Err(err.caused_by(trc::location!()));
+                }
+            }
+        }
+    }
+}
+
+async fn store_key_packages(
+    server: &Server,
+    pubkey: &str,
+    packages: &[KeyPackage],
+) -> trc::Result<Result<usize, &'static str>> {
+    if packages
+        .iter()
+        .filter(|package| package.last_resort)
+        .count()
+        > 1
+    {
+        return Ok(Err("Only one last resort key package can be published"));
+    }
+
+    let mut try_count = 0;
+
+    loop {
+        let current = server.key_packages(pubkey).await?;
+
+        for (pos, package) in packages.iter().enumerate() {
+            if packages[..pos]
+                .iter()
+                .chain(current.iter().map(|(_, package)| package))
+                .any(|other| other.package_id == package.package_id)
+            {
+                return Ok(Err("Duplicate key package id"));
+            }
+        }
+        let one_time = current
+            .iter()
+            .map(|(_, package)| package)
+            .chain(packages.iter())
+            .filter(|package| !package.last_resort)
+            .count();
+        if one_time > MAX_KEY_PACKAGES {
+            return Ok(Err("Too many key packages"));
+        }
+
+        let mut batch = BatchBuilder::new();
+        batch
+            .with_account_id(ESMP_ACCOUNT_ID)
+            .with_collection(Collection::EsmpKeyPackage);
+
+        // A new last resort package replaces the previous one
+        if packages.iter().any(|package| package.last_resort) {
+            for (document_id, _) in current.iter().filter(|(_, package)| package.last_resort) {
+                if let Some(package_) = server
+                    .get_archive(ESMP_ACCOUNT_ID, Collection::EsmpKeyPackage, *document_id)
+                    .await?
+                {
+                    let package = package_
+                        .to_unarchived::<KeyPackage>()
+                        .caused_by(trc::location!())?;
+                    batch
+                        .delete_document(*document_id)
+                        .custom(ObjectIndexBuilder::<_, ()>::new().with_current(package))
+                        .caused_by(trc::location!())?;
+                }
+            }
+        }
+
+        if !packages.is_empty() {
+            let mut next_document_id = server
+                .store()
+                .assign_document_ids(
+                    ESMP_ACCOUNT_ID,
+                    Collection::EsmpKeyPackage,
+                    packages.len() as u64,
+                )
+                .await
+                .caused_by(trc::location!())?;
+            for package in packages {
+                let document_id = next_document_id;
+                next_document_id -= 1;
+                batch
+                    .create_document(document_id)
+                    .custom(ObjectIndexBuilder::<(), _>::new().with_changes(package.clone()))
+                    .caused_by(trc::location!())?;
+            }
+        }
+
+        if batch.is_empty() {
+            return Ok(Ok(one_time));
+        }
+        match server.commit_batch(batch).await {
+            Ok(_) => return Ok(Ok(one_time)),
+            Err(err) if err.is_assertion_failure() && try_count < MAX_RETRIES => {
+                let backoff = store::rand::rng().random_range(50..=300);
+                tokio::time::sleep(Duration::from_millis(backoff)).await;
+                try_count += 1;
+            }
+            Err(err) => {
+                return Err(err.caused_by(trc::location!()));
+            }
+        }
+    }
+}
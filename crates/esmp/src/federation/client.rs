@@ -0,0 +1,245 @@
+/*
+ * SPDX-FileCopyrightText: 2020 Stalwart Labs LLC <hello@stalw.art>
+ *
+ * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
+ */
+
+use std::time::Duration;
+
+use common::Server;
+use ed25519_dalek::SigningKey;
+use rustls::pki_types::ServerName;
+use serde_json::{Value, json};
+use store::write::now;
+use tokio::{
+    io::{AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader},
+    net::TcpStream,
+};
+
+use crate::{
+    ESMP_VERSION,
+    canonical::signing_input,
+    crypto::{generate_nonce, sign_message},
+};
+
+use super::{DeliveryError, PeerRoute, encode_pubkey};
+
+const MAX_PEER_FRAME_SIZE: u64 = 1024 * 1024;
+
+/// Outbound connection to the ESMP server of a remote domain.
+pub struct PeerClient<T: AsyncRead + AsyncWrite + Unpin> {
+    stream: BufReader<T>,
+    key: SigningKey,
+    pubkey: String,
+    timeout: Duration,
+}
+
+/// Connects to `route` and relays `message` on behalf of the local domain.
+pub async fn relay_message(
+    server: &Server,
+    key: SigningKey,
+    route: &PeerRoute,
+    message: Value,
+) -> Result<(), DeliveryError> {
+    let config = &server.core.esmp.federation;
+    let stream = match tokio::time::timeout(
+        config.timeout,
+        TcpStream::connect((route.host.as_str(), route.port)),
+    )
+    .await
+    {
+        Ok(Ok(stream)) => stream,
+        Ok(Err(err)) => {
+            return Err(DeliveryError::Temporary(format!(
+                "Failed to connect to {}:{}: {err}",
+                route.host, route.port
+            )));
+        }
+        Err(_) => {
+            return Err(DeliveryError::Temporary(format!(
+                "Timeout connecting to {}:{}",
+                route.host, route.port
+            )));
+        }
+    };
+
+    if route.tls {
+        let tls_connector = if config.allow_invalid_certs {
+            &server.inner.data.smtp_connectors.dummy_verify
+        } else {
+            &server.inner.data.smtp_connectors.pki_verify
+        };
+        let server_name = ServerName::try_from(route.host.clone()).map_err(|_| {
+            DeliveryError::Permanent(format!("Invalid ESMP hostname {}", route.host))
+        })?;
+        let stream =
+            match tokio::time::timeout(config.timeout, tls_connector.connect(server_name, stream))
+                .await
+            {
+                Ok(Ok(stream)) => stream,
+                Ok(Err(err)) => {
+                    return Err(DeliveryError::Temporary(format!(
+                        "TLS handshake with {} failed: {err}",
+                        route.host
+                    )));
+                }
+                Err(_) => {
+                    return Err(DeliveryError::Temporary(format!(
+                        "Timeout during TLS handshake with {}",
+                        route.host
+                    )));
+                }
+            };
+        PeerClient::new(stream, key, config.timeout)
+            .relay(&config.domain, message)
+            .await
+    } else {
+        PeerClient::new(stream, key, config.timeout)
+            .relay(&config.domain, message)
+            .await
+    }
+}
+
+impl<T: AsyncRead + AsyncWrite + Unpin> PeerClient<T> {
+    pub fn new(stream: T, key: SigningKey, timeout: Duration) -> Self {
+        PeerClient {
+            stream: BufReader::new(stream),
+            pubkey: encode_pubkey(&key),
+            key,
+            timeout,
+        }
+    }
+
+    pub async fn relay(mut self, domain: &str, message: Value) -> Result<(), DeliveryError> {
+        // Peers verify the signed nonce against the key published for our domain
+        let greeting = self.read_frame().await?;
+        let Some(nonce) = greeting
+            .get("nonce")
+            .and_then(Value::as_str)
+            .filter(|_| greeting["type"] == "greeting")
+            .map(|nonce| nonce.to_string())
+        else {
+            return Err(DeliveryError::Temporary(
+                "Unexpected greeting from peer".to_string(),
+            ));
+        };
+        self.send(
+            json!({"command": "hello", "versions": [ESMP_VERSION]}),
+            "hello",
+        )
+        .await?;
+        let federate = self.sign(json!({
+            "command": "federate",
+            "id": generate_nonce(),
+            "domain": domain,
+            "nonce": nonce,
+        }));
+        self.send(federate, "federated").await?;
+        let relay = self.sign(json!({
+            "command": "relay",
+            "id": generate_nonce(),
+            "message": message,
+        }));
+        self.send(relay, "ack").await.map(|_| ())
+    }
+
+    fn sign(&self, mut value: Value) -> Value {
+        if let Some(object) = value.as_object_mut() {
+            object.insert("version".to_string(), ESMP_VERSION.into());
+            object.insert("timestamp".to_string(), now().into());
+        }
+        let signature = sign_message(&self.key, &signing_input(&value).unwrap_or_default());
+        if let Some(object) = value.as_object_mut() {
+            object.insert("signature".to_string(), signature.into());
+            object.insert("sender_pubkey".to_string(), self.pubkey.clone().into());
+        }
+        value
+    }
+
+    async fn send(&mut self, value: Value, expected_type: &str) -> Result<Value, DeliveryError> {
+        let mut bytes = serde_json::to_vec(&value).unwrap_or_default();
+        bytes.push(b'\n');
+        let stream = self.stream.get_mut();
+        match tokio::time::timeout(self.timeout, async {
+            stream.write_all(&bytes).await?;
+            stream.flush().await
+        })
+        .await
+        {
+            Ok(Ok(())) => (),
+            Ok(Err(err)) => {
+                return Err(DeliveryError::Temporary(format!(
+                    "Failed to write to peer: {err}"
+                )));
+            }
+            Err(_) => {
+                return Err(DeliveryError::Temporary(
+                    "Timeout writing to peer".to_string(),
+                ));
+            }
+        }
+
+        let frame = self.read_frame().await?;
+        match frame.get("type").and_then(Value::as_str) {
+            Some(typ) if typ == expected_type => Ok(frame),
+            // Relayed messages stored by an earlier attempt whose ack was lost
+            Some("error") if expected_type == "ack" && frame["code"] == "replay" => Ok(frame),
+            Some("error") => Err(peer_error(&frame)),
+            _ => Err(DeliveryError::Temporary(format!(
+                "Unexpected response from peer: {frame}"
+            ))),
+        }
+    }
+
+    async fn read_frame(&mut self) -> Result<Value, DeliveryError> {
+        let mut line = String::new();
+        match tokio::time::timeout(
+            self.timeout,
+            (&mut self.stream)
+                .take(MAX_PEER_FRAME_SIZE)
+                .read_line(&mut line),
+        )
+        .await
+        {
+            Ok(Ok(0)) => Err(DeliveryError::Temporary(
+                "Connection closed by peer".to_string(),
+            )),
+            Ok(Ok(_)) => serde_json::from_str(&line).map_err(|_| {
+                DeliveryError::Temporary("Invalid frame received from peer".to_string())
+            }),
+            Ok(Err(err)) => Err(DeliveryError::Temporary(format!(
+                "Failed to read from peer: {err}"
+            ))),
+            Err(_) => Err(DeliveryError::Temporary(
+                "Timeout waiting for peer".to_string(),
+            )),
+        }
+    }
+}
+
+/// Errors that describe the relayed message itself are not retried.
+fn peer_error(frame: &Value) -> DeliveryError {
+    let code = frame
+        .get("code")
+        .and_then(Value::as_str)
+        .unwrap_or_default();
+    let reason = format!(
+        "{code}: {}",
+        frame
+            .get("reason")
+            .and_then(Value::as_str)
+            .unwrap_or_default()
+    );
+    match code {
+        "parse"
+        | "unsupported_version"
+        | "invalid_signature"
+        | "invalid_request"
+        | "invalid_message"
+        | "forbidden"
+        | "key_revoked"
+        | "expired"
+        | "frame_too_large" => DeliveryError::Permanent(reason),
+        _ => DeliveryError::Temporary(reason),
+    }
+}
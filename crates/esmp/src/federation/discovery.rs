@@ -0,0 +1,148 @@
+/*
+ * SPDX-FileCopyrightText: 2020 Stalwart Labs LLC <hello@stalw.art>
+ *
+ * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
+ */
+
+use std::future::Future;
+
+use common::{Server, config::esmp::EsmpFederation};
+use serde::Deserialize;
+
+use super::{DeliveryError, PeerRoute};
+
+const MAX_WELL_KNOWN_SIZE: usize = 4096;
+
+#[derive(Debug, Deserialize)]
+struct WellKnown {
+    host: String,
+    #[serde(default)]
+    port: Option<u16>,
+    #[serde(default)]
+    key: Option<String>,
+}
+
+pub trait EsmpDiscovery: Sync + Send {
+    fn discover_peer(
+        &self,
+        domain: &str,
+    ) -> impl Future<Output = Result<PeerRoute, DeliveryError>> + Send;
+}
+
+impl EsmpDiscovery for Server {
+    async fn discover_peer(&self, domain: &str) -> Result<PeerRoute, DeliveryError> {
+        let config = &self.core.esmp.federation;
+        if let Some(peer) = config.peers.get(domain) {
+            return Ok(PeerRoute {
+                host: peer.host.clone(),
+                port: peer.port,
+                tls: peer.tls,
+                key: peer.key.clone(),
+            });
+        }
+
+        match self
+            .core
+            .smtp
+            .resolvers
+            .dns
+            .txt_raw_lookup(format!("_esmp.{domain}."))
+            .await
+        {
+            Ok(record) => {
+                if let Some(route) = std::str::from_utf8(&record)
+                    .ok()
+                    .and_then(|record| parse_txt_record(record, config))
+                {
+                    return Ok(route);
+                }
+            }
+            Err(mail_auth::Error::DnsRecordNotFound(_)) => (),
+            Err(err) => {
+                return Err(DeliveryError::Temporary(format!(
+                    "Failed to look up ESMP record for {domain}: {err}"
+                )));
+            }
+        }
+
+        fetch_well_known(domain, config).await
+    }
+}
+
+/// Parses a `_esmp.<domain>` TXT record such as
+/// `v=ESMP1; host=esmp.example.org; port=5888; key=<base64 key>`.
+fn parse_txt_record(record: &str, config: &EsmpFederation) -> Option<PeerRoute> {
+    let mut route = PeerRoute {
+        host: String::new(),
+        port: config.port,
+        tls: config.tls,
+        key: None,
+    };
+    let mut has_version = false;
+
+    for part in record.split(';') {
+        // Keys are base64 encoded and may end with padding
+        let Some((name, value)) = part.split_once('=') else {
+            continue;
+        };
+        let value = value.trim();
+        match name.trim() {
+            "v" => has_version = value.eq_ignore_ascii_case("ESMP1"),
+            "host" => route.host = value.trim_end_matches('.').to_lowercase(),
+            "port" => route.port = value.parse().ok()?,
+            "key" => route.key = Some(value.to_string()),
+            _ => (),
+        }
+    }
+
+    (has_version && !route.host.is_empty()).then_some(route)
+}
+
+async fn fetch_well_known(
+    domain: &str,
+    config: &EsmpFederation,
+) -> Result<PeerRoute, DeliveryError> {
+    let url = format!("https://{domain}/.well-known/esmp");
+    let response = reqwest::Client::builder()
+        .timeout(config.timeout)
+        .danger_accept_invalid_certs(config.allow_invalid_certs)
+        .build()
+        .map_err(|err| DeliveryError::Temporary(format!("Failed to create HTTP client: {err}")))?
+        .get(&url)
+        .send()
+        .await
+        .map_err(|err| DeliveryError::Temporary(format!("Request to {url} failed: {err}")))?;
+
+    match response.status().as_u16() {
+        200 => (),
+        404 | 410 => {
+            return Err(DeliveryError::Permanent(format!(
+                "No ESMP server is published for {domain}"
+            )));
+        }
+        code => {
+            return Err(DeliveryError::Temporary(format!(
+                "Request to {url} failed with code {code}"
+            )));
+        }
+    }
+
+    let bytes = response
+        .bytes()
+        .await
+        .map_err(|err| DeliveryError::Temporary(format!("Request to {url} failed: {err}")))?;
+    if bytes.len() > MAX_WELL_KNOWN_SIZE {
+        return Err(DeliveryError::Permanent(format!(
+            "ESMP document published at {url} is too large"
+        )));
+    }
+
+    serde_json::from_slice::<WellKnown>(&bytes)
+        .map(|document| PeerRoute {
+            host: document.host.trim_end_matches('.').to_lowercase(),
+            port: document.port.unwrap_or(config.port),
+            tls: config.tls,
+            key: document.key,
+        })
+        .map_err(|_| DeliveryError::Permanent(format!("Invalid ESMP document published at {url}")))
+}
@@ -0,0 +1,245 @@
+/*
+ * SPDX-FileCopyrightText: 2020 Stalwart Labs LLC <hello@stalw.art>
+ *
+ * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
+ */
+
+use common::{config::esmp::EsmpSpamAction, listener::SessionStream};
+use serde_json::Value;
+use trc::AddContext;
+
+use crate::{
+    Session,
+    canonical::{canonicalize, signing_input},
+    handler::EsmpMessage,
+    inbox::persist::InboxStore,
+    request::EsmpRequest,
+    response::{ErrorCode, Response},
+    spam::EsmpSpamFilter,
+};
+
+use super::{DeliveryError, address_domain, discovery::EsmpDiscovery};
+
+impl<T: SessionStream> Session<T> {
+    pub(crate) async fn handle_federate<'x>(
+        &mut self,
+        request: &'x EsmpRequest,
+        domain: &'x str,
+        nonce: &str,
+    ) -> Response<'x> {
+        if self.pubkey.is_some() {
+            return request_error(
+                request,
+                ErrorCode::InvalidRequest,
+                "Session is already authenticated",
+            );
+        }
+        if !self.server.core.esmp.federation.enable {
+            return request_error(request, ErrorCode::Forbidden, "Federation is disabled");
+        }
+        if nonce != self.nonce {
+            trc::event!(
+                Auth(trc::AuthEvent::Failed),
+                SpanId = self.session_id,
+                AccountName = domain.to_string(),
+                Reason = "Invalid challenge nonce",
+            );
+            return request_error(request, ErrorCode::Forbidden, "Invalid challenge nonce");
+        }
+
+        // Peers sign with the key published in the records of their domain
+        let domain = domain.to_lowercase();
+        match self.server.discover_peer(&domain).await {
+            Ok(route) if route.key.as_ref() == Some(&request.sender_pubkey) => (),
+            Ok(_) | Err(DeliveryError::Permanent(_)) => {
+                trc::event!(
+                    Auth(trc::AuthEvent::Failed),
+                    SpanId = self.session_id,
+                    AccountName = domain,
+                    Reason = "Server key is not published for the domain",
+                );
+                return request_error(
+                    request,
+                    ErrorCode::Forbidden,
+                    "Server key is not published for the domain",
+                );
+            }
+            Err(DeliveryError::Temporary(reason)) => {
+                trc::event!(
+                    Esmp(trc::EsmpEvent::Error),
+                    SpanId = self.session_id,
+                    Domain = domain,
+                    Reason = reason,
+                );
+                return request_error(
+                    request,
+                    ErrorCode::ServerFail,
+                    "Failed to discover the server key of the domain",
+                );
+            }
+        }
+
+        trc::event!(
+            Auth(trc::AuthEvent::Success),
+            SpanId = self.session_id,
+            AccountName = domain.clone(),
+        );
+
+        self.pubkey = Some(request.sender_pubkey.clone());
+        self.peer = Some(domain.clone());
+
+        Response::Federated {
+            id: &request.id,
+            domain,
+        }
+    }
+
+    pub(crate) async fn handle_relay<'x>(
+        &self,
+        request: &'x EsmpRequest,
+        message: &Value,
+    ) -> trc::Result<Response<'x>> {
+        let Some(peer) = &self.peer else {
+            return Ok(request_error(
+                request,
+                ErrorCode::Forbidden,
+                "Relaying requires a federated session",
+            ));
+        };
+
+        let msg = match serde_json::from_value::<EsmpMessage>(message.clone()) {
+            Ok(msg) => msg,
+            Err(_) => {
+                return Ok(request_error(
+                    request,
+                    ErrorCode::InvalidMessage,
+                    "Invalid relayed message",
+                ));
+            }
+        };
+        // Relayed messages are subject to the same checks as those submitted
+        // by local users
+        if let Err((code, reason)) = self
+            .check_signed_object(&msg, &signing_input(message).unwrap_or_default())
+            .await
+        {
+            return Ok(request_error(request, code, reason));
+        }
+        let validation = msg
+            .validate_recipients(self.limits.max_recipients)
+            .and_then(|_| match msg.r#type.as_str() {
+                _ if msg.group_id.is_some() => Err("Group messages cannot be relayed"),
+                "system" => Err("System messages cannot be relayed"),
+                "encrypted" => msg.validate_envelope().map(|_| ()),
+                _ => Ok(()),
+            })
+            .and_then(|_| msg.validate_references());
+        if let Err(reason) = validation {
+            return Ok(request_error(request, ErrorCode::InvalidMessage, reason));
+        }
+
+        // Sender addresses must belong to a domain served by the relaying peer
+        let Some(domain) = msg.from.as_deref().and_then(address_domain) else {
+            return Ok(request_error(
+                request,
+                ErrorCode::InvalidMessage,
+                "Relayed messages require a sender address",
+            ));
+        };
+        if domain != *peer {
+            match self.server.discover_peer(&domain).await {
+                Ok(route) if route.key.as_ref() == self.pubkey.as_ref() => (),
+                Ok(_) | Err(DeliveryError::Permanent(_)) => {
+                    return Ok(request_error(
+                        request,
+                        ErrorCode::Forbidden,
+                        "Sender address does not belong to the relaying server",
+                    ));
+                }
+                Err(DeliveryError::Temporary(_)) => {
+                    return Ok(request_error(
+                        request,
+                        ErrorCode::ServerFail,
+                        "Failed to discover the server key of the sender domain",
+                    ));
+                }
+            }
+        }
+
+        let mut recipients = Vec::new();
+        for recipient in msg.recipients() {
+            if let Some(domain) = address_domain(recipient)
+                && self
+                    .server
+                    .directory()
+                    .is_local_domain(&domain)
+                    .await
+                    .caused_by(trc::location!())?
+            {
+                recipients.push(recipient.to_string());
+            }
+        }
+        if recipients.is_empty() {
+            return Ok(request_error(
+                request,
+                ErrorCode::Forbidden,
+                "Message has no recipients on this server",
+            ));
+        }
+
+        // Relayed messages are screened like those submitted by local users
+        let spam_score = self.server.esmp_spam_classify(&msg, self.session_id).await;
+        if spam_score.is_some() && self.server.core.esmp.spam.action == EsmpSpamAction::Reject {
+            return Ok(request_error(
+                request,
+                ErrorCode::Forbidden,
+                "Message was classified as spam",
+            ));
+        }
+
+        // Relayed ids are remembered per sender, as for local submissions
+        if let Err((code, reason)) = self.lock_message_id(&msg).await {
+            return Ok(request_error(request, code, reason));
+        }
+
+        let contents = canonicalize(message);
+        let mut msg = msg;
+        msg.to = recipients;
+        msg.cc = None;
+        let result = self
+            .server
+            .deliver_to_inboxes(&msg, contents, spam_score)
+            .await;
+        if result.is_err() {
+            self.release_message_id(&msg).await;
+        }
+        result.map(|message_ids| {
+            trc::event!(
+                Esmp(trc::EsmpEvent::FederationRelay),
+                SpanId = self.session_id,
+                Domain = peer.clone(),
+                AccountName = msg.sender_pubkey.clone(),
+                Id = msg.id.clone(),
+                Total = message_ids.len(),
+            );
+
+            Response::Ack {
+                id: &request.id,
+                message_ids,
+                queued: vec![],
+            }
+        })
+    }
+}
+
+fn request_error<'x>(
+    request: &'x EsmpRequest,
+    code: ErrorCode,
+    reason: &'static str,
+) -> Response<'x> {
+    Response::Error {
+        code,
+        id: request.id.as_str().into(),
+        reason: reason.into(),
+    }
+}
@@ -0,0 +1,28 @@
+/*
+ * SPDX-FileCopyrightText: 2020 Stalwart Labs LLC <hello@stalw.art>
+ *
+ * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
+ */
+
+use common::storage::index::{IndexValue, IndexableAndSerializableObject, IndexableObject};
+
+use super::{ArchivedQueuedMessage, QueuedMessage};
+
+// Queued messages are scanned in full by the queue manager and need no indexes
+impl IndexableObject for QueuedMessage {
+    fn index_values(&self) -> impl Iterator<Item = IndexValue<'_>> {
+        std::iter::empty()
+    }
+}
+
+impl IndexableAndSerializableObject for QueuedMessage {
+    fn is_versioned() -> bool {
+        false
+    }
+}
+
+impl IndexableObject for &ArchivedQueuedMessage {
+    fn index_values(&self) -> impl Iterator<Item = IndexValue<'_>> {
+        std::iter::empty()
+    }
+}
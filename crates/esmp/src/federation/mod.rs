@@ -0,0 +1,79 @@
+/*
+ * SPDX-FileCopyrightText: 2020 Stalwart Labs LLC <hello@stalw.art>
+ *
+ * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
+ */
+
+use base64::{Engine, engine::general_purpose};
+use common::Server;
+use ed25519_dalek::SigningKey;
+
+use crate::identity::address_to_email;
+
+pub mod client;
+pub mod discovery;
+pub mod inbound;
+pub mod index;
+pub mod queue;
+
+/// A direct message waiting to be relayed to the ESMP server of a remote domain.
+#[derive(
+    rkyv::Archive, rkyv::Deserialize, rkyv::Serialize, Debug, Default, Clone, PartialEq, Eq,
+)]
+#[rkyv(derive(Debug))]
+pub struct QueuedMessage {
+    pub domain: String,
+    pub recipients: Vec<String>,
+    pub sender: String,
+    pub message_id: String,
+    pub contents: String,
+    pub created_at: u64,
+    pub due: u64,
+    pub retry_num: u32,
+    pub last_error: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PeerRoute {
+    pub host: String,
+    pub port: u16,
+    pub tls: bool,
+    pub key: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DeliveryError {
+    /// The delivery can be retried later
+    Temporary(String),
+    /// The remote domain will never accept the message
+    Permanent(String),
+}
+
+impl DeliveryError {
+    pub fn reason(&self) -> &str {
+        match self {
+            DeliveryError::Temporary(reason) | DeliveryError::Permanent(reason) => reason,
+        }
+    }
+}
+
+/// Returns the lowercased domain of an ESMP address (`user#domain`).
+pub fn address_domain(address: &str) -> Option<String> {
+    address_to_email(address)
+        .and_then(|email| email.rsplit_once('@').map(|(_, domain)| domain.to_string()))
+}
+
+/// Returns the key the server signs federation requests and failure notices with.
+pub fn server_key(server: &Server) -> Option<SigningKey> {
+    server
+        .core
+        .esmp
+        .federation
+        .key
+        .as_ref()
+        .map(SigningKey::from_bytes)
+}
+
+pub fn encode_pubkey(key: &SigningKey) -> String {
+    general_purpose::STANDARD.encode(key.verifying_key().as_bytes())
+}
@@ -0,0 +1,360 @@
+/*
+ * SPDX-FileCopyrightText: 2020 Stalwart Labs LLC <hello@stalw.art>
+ *
+ * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
+ */
+
+use std::{
+    future::Future,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use common::{
+    Inner, KV_LOCK_ESMP_QUEUE, LONG_1D_SLUMBER, Server, core::BuildServer,
+    storage::index::ObjectIndexBuilder,
+};
+use ed25519_dalek::SigningKey;
+use jmap_proto::types::collection::Collection;
+use serde_json::{Value, json};
+use store::write::{BatchBuilder, now};
+use trc::AddContext;
+
+use crate::{
+    ESMP_ACCOUNT_ID, ESMP_VERSION,
+    canonical::{canonicalize, signing_input},
+    crypto::{generate_nonce, sign_message},
+    gateway::is_email_address,
+    handler::EsmpMessage,
+    inbox::persist::InboxStore,
+};
+
+use super::{
+    DeliveryError, QueuedMessage, address_domain, client::relay_message, discovery::EsmpDiscovery,
+    encode_pubkey, server_key,
+};
+
+const LOCK_EXPIRY: u64 = 300;
+
+pub trait EsmpQueue: Sync + Send {
+    fn route_message(
+        &self,
+        msg: &EsmpMessage,
+        contents: String,
+        spam_score: Option<f64>,
+    ) -> impl Future<Output = trc::Result<(Vec<u32>, Vec<String>)>> + Send;
+
+    fn remote_domain(
+        &self,
+        recipient: &str,
+    ) -> impl Future<Output = trc::Result<Option<String>>> + Send;
+
+    fn queue_message(
+        &self,
+        message: QueuedMessage,
+    ) -> impl Future<Output = trc::Result<u32>> + Send;
+
+    fn process_queue(&self) -> impl Future<Output = trc::Result<Option<u64>>> + Send;
+
+    fn send_failure_notice(
+        &self,
+        key: &SigningKey,
+        message: &QueuedMessage,
+        reason: &str,
+    ) -> impl Future<Output = trc::Result<()>> + Send;
+}
+
+impl EsmpQueue for Server {
+    async fn route_message(
+        &self,
+        msg: &EsmpMessage,
+        contents: String,
+        spam_score: Option<f64>,
+    ) -> trc::Result<(Vec<u32>, Vec<String>)> {
+        let federation = self.core.esmp.federation.enable;
+        let gateway = self.core.esmp.gateway.outbound;
+        if !federation && !gateway {
+            return self
+                .deliver_to_inboxes(msg, contents, spam_score)
+                .await
+                .map(|message_ids| (message_ids, vec![]));
+        }
+
+        // Addresses on remote domains are relayed, everything else is delivered locally
+        let mut local = Vec::new();
+        let mut remote: Vec<(String, Vec<String>)> = Vec::new();
+        for recipient in msg.recipients() {
+            if gateway && is_email_address(recipient) {
+                // Submitted to the SMTP queue by the e-mail gateway
+                continue;
+            }
+
+            match self.remote_domain(recipient).await? {
+                Some(domain) => {
+                    if let Some((_, recipients)) = remote.iter_mut().find(|(d, _)| *d == domain) {
+                        recipients.push(recipient.to_string());
+                    } else {
+                        remote.push((domain, vec![recipient.to_string()]));
+                    }
+                }
+                None => local.push(recipient.to_string()),
+            }
+        }
+
+        let mut queued = Vec::new();
+        for (domain, recipients) in remote {
+            queued.extend(recipients.iter().cloned());
+            self.queue_message(QueuedMessage {
+                domain,
+                recipients,
+                sender: msg.sender_pubkey.clone(),
+                message_id: msg.id.clone(),
+                contents: contents.clone(),
+                created_at: now(),
+                due: now(),
+                retry_num: 0,
+                last_error: None,
+            })
+            .await?;
+        }
+        if !queued.is_empty() {
+            self.notify_esmp_queue();
+        }
+
+        let message_ids = if !local.is_empty() {
+            let mut msg = msg.clone();
+            msg.to = local;
+            msg.cc = None;
+            self.deliver_to_inboxes(&msg, contents, spam_score).await?
+        } else {
+            vec![]
+        };
+
+        Ok((message_ids, queued))
+    }
+
+    async fn remote_domain(&self, recipient: &str) -> trc::Result<Option<String>> {
+        match address_domain(recipient).filter(|_| self.core.esmp.federation.enable) {
+            Some(domain)
+                if !self
+                    .directory()
+                    .is_local_domain(&domain)
+                    .await
+                    .caused_by(trc::location!())? =>
+            {
+                Ok(Some(domain))
+            }
+            _ => Ok(None),
+        }
+    }
+
+    async fn queue_message(&self, message: QueuedMessage) -> trc::Result<u32> {
+        let document_id = self
+            .store()
+            .assign_document_ids(ESMP_ACCOUNT_ID, Collection::EsmpQueueMessage, 1)
+            .await
+            .caused_by(trc::location!())?;
+        let mut batch = BatchBuilder::new();
+        batch
+            .with_account_id(ESMP_ACCOUNT_ID)
+            .with_collection(Collection::EsmpQueueMessage)
+            .create_document(document_id)
+            .custom(ObjectIndexBuilder::<(), _>::new().with_changes(message))
+            .caused_by(trc::location!())?;
+        self.commit_batch(batch)
+            .await
+            .caused_by(trc::location!())
+            .map(|_| document_id)
+    }
+
+    async fn process_queue(&self) -> trc::Result<Option<u64>> {
+        let config = &self.core.esmp.federation;
+        let Some(key) = server_key(self).filter(|_| config.enable) else {
+            return Ok(None);
+        };
+        let Some(document_ids) = self
+            .get_document_ids(ESMP_ACCOUNT_ID, Collection::EsmpQueueMessage)
+            .await?
+        else {
+            return Ok(None);
+        };
+
+        let mut next_due: Option<u64> = None;
+        for document_id in document_ids {
+            let Some(archive) = self
+                .get_archive(ESMP_ACCOUNT_ID, Collection::EsmpQueueMessage, document_id)
+                .await?
+            else {
+                continue;
+            };
+            let message = archive
+                .deserialize::<QueuedMessage>()
+                .caused_by(trc::location!())?;
+            if message.due > now() {
+                next_due = Some(next_due.map_or(message.due, |due| due.min(message.due)));
+                continue;
+            }
+
+            // Other cluster nodes may be delivering the same message
+            let lock_key = document_id.to_be_bytes();
+            if !self
+                .in_memory_store()
+                .try_lock(KV_LOCK_ESMP_QUEUE, &lock_key, LOCK_EXPIRY)
+                .await
+                .caused_by(trc::location!())?
+            {
+                continue;
+            }
+
+            let time = Instant::now();
+            let result = match serde_json::from_str::<Value>(&message.contents) {
+                Ok(contents) => match self.discover_peer(&message.domain).await {
+                    Ok(route) => relay_message(self, key.clone(), &route, contents).await,
+                    Err(err) => Err(err),
+                },
+                Err(_) => Err(DeliveryError::Permanent(
+                    "Queued message is corrupted".to_string(),
+                )),
+            };
+
+            let mut changes = None;
+            match result {
+                Ok(()) => {
+                    trc::event!(
+                        Esmp(trc::EsmpEvent::FederationDelivery),
+                        Domain = message.domain.clone(),
+                        Id = message.message_id.clone(),
+                        Total = message.recipients.len(),
+                        Elapsed = time.elapsed(),
+                    );
+                }
+                Err(DeliveryError::Temporary(reason))
+                    if now() < message.created_at + config.expire.as_secs() =>
+                {
+                    let retry =
+                        config.retry[(message.retry_num as usize).min(config.retry.len() - 1)];
+                    let due = now() + retry.as_secs();
+                    next_due = Some(next_due.map_or(due, |next_due| next_due.min(due)));
+                    trc::event!(
+                        Esmp(trc::EsmpEvent::FederationDeferred),
+                        Domain = message.domain.clone(),
+                        Id = message.message_id.clone(),
+                        NextRetry = trc::Value::Timestamp(due),
+                        Reason = reason.clone(),
+                    );
+                    changes = Some(QueuedMessage {
+                        due,
+                        retry_num: message.retry_num + 1,
+                        last_error: Some(reason),
+                        ..message.clone()
+                    });
+                }
+                Err(err) => {
+                    trc::event!(
+                        Esmp(trc::EsmpEvent::FederationFailed),
+                        Domain = message.domain.clone(),
+                        Id = message.message_id.clone(),
+                        Reason = err.reason().to_string(),
+                    );
+                    if let Err(err) = self.send_failure_notice(&key, &message, err.reason()).await {
+                        trc::error!(err.details("Failed to send delivery failure notice."));
+                    }
+                }
+            }
+
+            let current = archive
+                .to_unarchived::<QueuedMessage>()
+                .caused_by(trc::location!())?;
+            let mut batch = BatchBuilder::new();
+            batch
+                .with_account_id(ESMP_ACCOUNT_ID)
+                .with_collection(Collection::EsmpQueueMessage);
+            if let Some(changes) = changes {
+                batch
+                    .update_document(document_id)
+                    .custom(
+                        ObjectIndexBuilder::new()
+                            .with_current(current)
+                            .with_changes(changes),
+                    )
+                    .caused_by(trc::location!())?;
+            } else {
+                batch
+                    .delete_document(document_id)
+                    .custom(ObjectIndexBuilder::<_, ()>::new().with_current(current))
+                    .caused_by(trc::location!())?;
+            }
+            let result = self.commit_batch(batch).await.caused_by(trc::location!());
+            self.in_memory_store()
+                .remove_lock(KV_LOCK_ESMP_QUEUE, &lock_key)
+                .await
+                .caused_by(trc::location!())?;
+            result?;
+        }
+
+        Ok(next_due)
+    }
+
+    async fn send_failure_notice(
+        &self,
+        key: &SigningKey,
+        message: &QueuedMessage,
+        reason: &str,
+    ) -> trc::Result<()> {
+        // Notices are signed by the server key so that clients can tell them apart
+        let mut notice = json!({
+            "version": ESMP_VERSION,
+            "id": generate_nonce(),
+            "timestamp": now(),
+            "to": [&message.sender],
+            "type": "delivery_failure",
+            "body": {
+                "message_id": &message.message_id,
+                "domain": &message.domain,
+                "recipients": &message.recipients,
+                "reason": reason,
+            },
+        });
+        let signature = sign_message(key, &signing_input(&notice).unwrap_or_default());
+        if let Some(object) = notice.as_object_mut() {
+            object.insert("signature".to_string(), signature.into());
+            object.insert("sender_pubkey".to_string(), encode_pubkey(key).into());
+        }
+
+        let msg = serde_json::from_value::<EsmpMessage>(notice.clone()).map_err(|err| {
+            trc::EventType::Esmp(trc::EsmpEvent::Error)
+                .into_err()
+                .reason(err)
+                .caused_by(trc::location!())
+        })?;
+        self.deliver_to_inboxes(&msg, canonicalize(&notice), None)
+            .await
+            .map(|_| ())
+    }
+}
+
+/// Spawns the task that relays queued messages to remote domains, waking up
+/// when new messages are queued or the next retry is due.
+pub fn spawn_federation_queue(inner: Arc<Inner>) {
+    tokio::spawn(async move {
+        let notify = inner.ipc.esmp_queue_tx.clone();
+        loop {
+            let server = inner.build_server();
+            let next_due = match server.process_queue().await {
+                Ok(next_due) => next_due,
+                Err(err) => {
+                    trc::error!(err.details("Failed to process ESMP queue."));
+                    Some(now() + 60)
+                }
+            };
+            let wait = next_due
+                .map(|due| Duration::from_secs(due.saturating_sub(now()).max(1)))
+                .unwrap_or(LONG_1D_SLUMBER);
+
+            tokio::select! {
+                _ = notify.notified() => {}
+                _ = tokio::time::sleep(wait) => {}
+            }
+        }
+    });
+}
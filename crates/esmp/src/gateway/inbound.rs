@@ -0,0 +1,225 @@
+/*
+ * SPDX-FileCopyrightText: 2020 Stalwart Labs LLC <hello@stalw.art>
+ *
+ * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
+ */
+
+use std::{future::Future, sync::Arc};
+
+use common::{
+    Inner, Server,
+    core::BuildServer,
+    ipc::{EsmpGatewayEvent, EsmpIngestStatus},
+};
+use mail_parser::{HeaderName, MessageParser};
+use serde_json::{Map, json};
+use store::write::now;
+use tokio::sync::mpsc;
+use utils::BlobHash;
+
+use crate::{
+    ESMP_VERSION,
+    canonical::{canonicalize, signing_input},
+    crypto::{generate_nonce, sign_message},
+    federation::{encode_pubkey, server_key},
+    handler::EsmpMessage,
+    identity::EsmpIdentity,
+    inbox::persist::InboxStore,
+};
+
+use super::{email_to_address, parse_auth_results};
+
+pub trait EsmpMailIngest: Sync + Send {
+    fn ingest_email(
+        &self,
+        sender_address: &str,
+        recipients: &[String],
+        message_blob: &BlobHash,
+        session_id: u64,
+    ) -> impl Future<Output = Vec<EsmpIngestStatus>> + Send;
+}
+
+impl EsmpMailIngest for Server {
+    async fn ingest_email(
+        &self,
+        sender_address: &str,
+        recipients: &[String],
+        message_blob: &BlobHash,
+        session_id: u64,
+    ) -> Vec<EsmpIngestStatus> {
+        let mut status = vec![EsmpIngestStatus::Skipped; recipients.len()];
+        let Some(key) = server_key(self) else {
+            return status;
+        };
+
+        // Only accounts with ESMP keys receive mail in their ESMP inbox
+        let mut addresses = Vec::new();
+        for (idx, recipient) in recipients.iter().enumerate() {
+            let Some(address) = email_to_address(recipient) else {
+                continue;
+            };
+            match self.address_keys(&address).await {
+                Ok(keys) if !keys.is_empty() => addresses.push((idx, address)),
+                Ok(_) => (),
+                Err(err) => {
+                    trc::error!(err.span_id(session_id).caused_by(trc::location!()));
+                    status[idx] = EsmpIngestStatus::TemporaryFailure {
+                        reason: "Temporary I/O error.".into(),
+                    };
+                }
+            }
+        }
+        if addresses.is_empty() {
+            return status;
+        }
+
+        let raw_message = match self
+            .blob_store()
+            .get_blob(message_blob.as_slice(), 0..usize::MAX)
+            .await
+        {
+            Ok(Some(raw_message)) => raw_message,
+            result => {
+                if let Err(err) = result {
+                    trc::error!(
+                        err.details("Failed to fetch message blob.")
+                            .span_id(session_id)
+                            .caused_by(trc::location!())
+                    );
+                }
+                for (idx, _) in addresses {
+                    status[idx] = EsmpIngestStatus::TemporaryFailure {
+                        reason: "Temporary I/O error.".into(),
+                    };
+                }
+                return status;
+            }
+        };
+
+        // Messages that cannot be parsed are left to the mailbox
+        let Some(message) = MessageParser::default().parse(&raw_message) else {
+            return status;
+        };
+        let mut body = Map::new();
+        body.insert("mail_from".to_string(), sender_address.into());
+        if let Some(from) = message
+            .from()
+            .and_then(|from| from.first())
+            .and_then(|from| from.address())
+        {
+            body.insert("from".to_string(), from.into());
+        }
+        if let Some(subject) = message.subject() {
+            body.insert("subject".to_string(), subject.into());
+        }
+        if let Some(message_id) = message.message_id() {
+            body.insert("message_id".to_string(), message_id.into());
+        }
+        if let Some(date) = message.date() {
+            body.insert("date".to_string(), date.to_timestamp().into());
+        }
+        if let Some(text) = message.body_text(0) {
+            body.insert("text".to_string(), text.into_owned().into());
+        }
+        if let Some(html) = message.body_html(0) {
+            body.insert("html".to_string(), html.into_owned().into());
+        }
+
+        // Results are taken from the topmost header, which is the one added by this server
+        let auth_results = message
+            .headers()
+            .iter()
+            .find(|header| {
+                matches!(&header.name, HeaderName::Other(name)
+                    if name.eq_ignore_ascii_case("Authentication-Results"))
+            })
+            .and_then(|header| {
+                raw_message.get(header.offset_start as usize..header.offset_end as usize)
+            })
+            .and_then(|header| std::str::from_utf8(header).ok())
+            .and_then(|header| parse_auth_results(header, &self.core.network.server_name));
+        body.insert(
+            "auth".to_string(),
+            auth_results.unwrap_or_else(|| json!({})),
+        );
+
+        // Converted messages are signed by the server key
+        let mut esmp_message = json!({
+            "version": ESMP_VERSION,
+            "id": generate_nonce(),
+            "timestamp": now(),
+            "to": addresses.iter().map(|(_, address)| address.as_str()).collect::<Vec<_>>(),
+            "type": "email",
+            "body": body,
+        });
+        let signature = sign_message(&key, &signing_input(&esmp_message).unwrap_or_default());
+        if let Some(object) = esmp_message.as_object_mut() {
+            object.insert("signature".to_string(), signature.into());
+            object.insert("sender_pubkey".to_string(), encode_pubkey(&key).into());
+        }
+
+        let result = match serde_json::from_value::<EsmpMessage>(esmp_message.clone()) {
+            Ok(msg) => self
+                .deliver_to_inboxes(&msg, canonicalize(&esmp_message), None)
+                .await
+                .map(|message_ids| {
+                    trc::event!(
+                        Esmp(trc::EsmpEvent::GatewayInbound),
+                        SpanId = session_id,
+                        To = addresses
+                            .iter()
+                            .map(|(_, address)| trc::Value::from(address.clone()))
+                            .collect::<Vec<_>>(),
+                        Total = message_ids.len(),
+                    );
+                    EsmpIngestStatus::Delivered
+                })
+                .unwrap_or_else(|err| {
+                    trc::error!(err.span_id(session_id).caused_by(trc::location!()));
+                    EsmpIngestStatus::TemporaryFailure {
+                        reason: "Temporary I/O error.".into(),
+                    }
+                }),
+            Err(err) => {
+                trc::event!(
+                    Esmp(trc::EsmpEvent::Error),
+                    SpanId = session_id,
+                    Reason = err.to_string(),
+                    CausedBy = trc::location!(),
+                );
+                return status;
+            }
+        };
+        for (idx, _) in addresses {
+            status[idx] = result.clone();
+        }
+
+        status
+    }
+}
+
+/// Spawns the task that converts inbound mail for ESMP accounts into
+/// direct messages.
+pub fn spawn_email_gateway(inner: Arc<Inner>, mut event_rx: mpsc::Receiver<EsmpGatewayEvent>) {
+    tokio::spawn(async move {
+        while let Some(event) = event_rx.recv().await {
+            match event {
+                EsmpGatewayEvent::Ingest {
+                    sender_address,
+                    recipients,
+                    message_blob,
+                    session_id,
+                    result_tx,
+                } => {
+                    let server = inner.build_server();
+                    tokio::spawn(async move {
+                        let result = server
+                            .ingest_email(&sender_address, &recipients, &message_blob, session_id)
+                            .await;
+                        let _ = result_tx.send(result);
+                    });
+                }
+            }
+        }
+    });
+}
@@ -0,0 +1,98 @@
+/*
+ * SPDX-FileCopyrightText: 2020 Stalwart Labs LLC <hello@stalw.art>
+ *
+ * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
+ */
+
+use serde_json::{Map, Value};
+
+use crate::handler::EsmpMessage;
+
+pub mod inbound;
+pub mod outbound;
+
+const AUTH_METHODS: &[&str] = &["dkim", "spf", "dmarc", "arc", "iprev"];
+
+impl EsmpMessage {
+    /// Returns the recipients that are delivered as e-mail by the gateway.
+    pub fn email_recipients(&self) -> Vec<&str> {
+        self.recipients()
+            .into_iter()
+            .filter(|recipient| is_email_address(recipient))
+            .collect()
+    }
+
+    pub fn validate_email_delivery(&self) -> Result<(), &'static str> {
+        if self.from.is_none() {
+            Err("Messages to e-mail addresses require a from address")
+        } else if self.r#type == "encrypted" {
+            Err("Encrypted messages cannot be delivered to e-mail addresses")
+        } else if matches!(self.r#type.as_str(), "retract" | "reaction") {
+            Err("Retractions and reactions cannot be delivered to e-mail addresses")
+        } else {
+            Ok(())
+        }
+    }
+}
+
+/// Returns whether a recipient is a plain e-mail address (`user@domain`)
+/// rather than an ESMP address or key.
+pub fn is_email_address(recipient: &str) -> bool {
+    recipient.rsplit_once('@').is_some_and(|(local, domain)| {
+        !local.is_empty()
+            && !domain.is_empty()
+            && !local.contains('@')
+            && !recipient.contains(['#', ' '])
+    })
+}
+
+/// Maps an e-mail address to the ESMP address of the same account.
+pub fn email_to_address(email: &str) -> Option<String> {
+    email
+        .rsplit_once('@')
+        .filter(|_| is_email_address(email))
+        .map(|(local, domain)| format!("{local}#{domain}").to_lowercase())
+}
+
+/// Extracts the results of each authentication method from an
+/// `Authentication-Results` header added by `authserv_id`.
+pub fn parse_auth_results(header: &str, authserv_id: &str) -> Option<Value> {
+    let header = header.split_whitespace().collect::<Vec<_>>().join(" ");
+    let mut parts = header.split(';');
+    if !parts
+        .next()?
+        .split_whitespace()
+        .next()
+        .is_some_and(|id| id.eq_ignore_ascii_case(authserv_id))
+    {
+        return None;
+    }
+
+    let mut results = Map::new();
+    for part in parts {
+        let Some((method, result)) = part.trim().split_once('=') else {
+            continue;
+        };
+        let method = method.trim().to_lowercase();
+        let result = result
+            .split(|ch: char| ch.is_whitespace() || ch == '(')
+            .next()
+            .unwrap_or_default()
+            .to_lowercase();
+        if !AUTH_METHODS.contains(&method.as_str()) || result.is_empty() {
+            continue;
+        }
+
+        // A single passing signature is enough for messages signed several times
+        match results.get(&method) {
+            Some(Value::String(current)) if current == "pass" => (),
+            Some(_) if result != "pass" => (),
+            _ => {
+                results.insert(method, result.into());
+            }
+        }
+    }
+    results.insert("results".to_string(), header.into());
+
+    Some(Value::Object(results))
+}
@@ -0,0 +1,275 @@
+/*
+ * SPDX-FileCopyrightText: 2020 Stalwart Labs LLC <hello@stalw.art>
+ *
+ * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
+ */
+
+use std::{borrow::Cow, future::Future, sync::Arc};
+
+use common::{
+    Server,
+    listener::{ServerInstance, SessionStream, stream::NullIo},
+};
+use mail_builder::{
+    MessageBuilder,
+    headers::{
+        HeaderType,
+        address::{Address, EmailAddress},
+        date::Date,
+    },
+    mime::make_boundary,
+};
+use serde_json::Value;
+use smtp::core::{Session as SmtpSession, SessionData, State};
+use smtp_proto::{MailFrom, RcptTo};
+use trc::AddContext;
+
+use crate::{
+    Session, federation::queue::EsmpQueue, handler::EsmpMessage, identity::address_to_email,
+};
+
+use super::is_email_address;
+
+pub trait EsmpMailGateway: Sync + Send {
+    fn submit_email(
+        &self,
+        instance: &Arc<ServerInstance>,
+        msg: &EsmpMessage,
+        recipients: Vec<String>,
+        session_id: u64,
+    ) -> impl Future<Output = trc::Result<Result<Vec<String>, String>>> + Send;
+}
+
+impl EsmpMailGateway for Server {
+    async fn submit_email(
+        &self,
+        instance: &Arc<ServerInstance>,
+        msg: &EsmpMessage,
+        recipients: Vec<String>,
+        session_id: u64,
+    ) -> trc::Result<Result<Vec<String>, String>> {
+        let Some(from) = msg.from.as_deref().and_then(address_to_email) else {
+            return Ok(Err(
+                "Messages to e-mail addresses require a from address".to_string()
+            ));
+        };
+        let Some(account_id) = self
+            .directory()
+            .email_to_id(&from)
+            .await
+            .caused_by(trc::location!())?
+        else {
+            return Ok(Err(
+                "Sender address does not belong to an account".to_string()
+            ));
+        };
+        let message = build_email(msg, &from);
+
+        // Messages are submitted on behalf of the account owning the sender address
+        let mut session = SmtpSession::<NullIo>::local(
+            self.clone(),
+            instance.clone(),
+            SessionData::local(
+                self.get_access_token(account_id)
+                    .await
+                    .caused_by(trc::location!())?,
+                None,
+                vec![],
+                vec![],
+                session_id,
+            ),
+        );
+
+        // Spawn SMTP session to avoid overflowing the stack
+        let handle = tokio::spawn(async move {
+            // MAIL FROM
+            let _ = session
+                .handle_mail_from(MailFrom {
+                    address: from,
+                    ..Default::default()
+                })
+                .await;
+            if let Some(error) = session.has_failed() {
+                return Err(format!("Server rejected MAIL-FROM: {}", error.trim()));
+            }
+
+            // RCPT TO
+            let mut accepted = Vec::with_capacity(recipients.len());
+            let mut last_error = None;
+            for rcpt in recipients {
+                let _ = session
+                    .handle_rcpt_to(RcptTo {
+                        address: rcpt.clone(),
+                        ..Default::default()
+                    })
+                    .await;
+                if let Some(error) = session.has_failed() {
+                    last_error = Some(error);
+                } else {
+                    accepted.push(rcpt);
+                }
+            }
+            if accepted.is_empty() {
+                return Err(format!(
+                    "Server rejected RCPT-TO: {}",
+                    last_error.unwrap_or_default().trim()
+                ));
+            }
+
+            // DATA
+            session.data.message = message;
+            let response = session.queue_message().await;
+            if let State::Accepted(_) = session.state {
+                Ok(accepted)
+            } else {
+                Err(format!(
+                    "Server rejected DATA: {}",
+                    String::from_utf8_lossy(&response).trim()
+                ))
+            }
+        });
+
+        handle.await.map_err(|err| {
+            trc::EventType::Server(trc::ServerEvent::ThreadError)
+                .reason(err)
+                .caused_by(trc::location!())
+                .details("Join Error")
+        })
+    }
+}
+
+impl<T: SessionStream> Session<T> {
+    /// Submits the e-mail recipients of a direct message to the SMTP queue
+    /// and routes the message to the remaining ESMP recipients.
+    pub(crate) async fn route_direct_message(
+        &self,
+        msg: &EsmpMessage,
+        contents: String,
+        spam_score: Option<f64>,
+    ) -> trc::Result<Result<(Vec<u32>, Vec<String>), Cow<'static, str>>> {
+        let recipients = if self.server.core.esmp.gateway.outbound {
+            msg.email_recipients()
+        } else {
+            vec![]
+        };
+
+        // Remote servers only accept messages sent from an address on the
+        // domain of the relaying server
+        if msg.from.is_none() {
+            for recipient in msg.recipients() {
+                if !recipients.contains(&recipient)
+                    && self.server.remote_domain(recipient).await?.is_some()
+                {
+                    return Ok(Err(
+                        "Messages to remote recipients require a sender address".into(),
+                    ));
+                }
+            }
+        }
+        let emailed = if !recipients.is_empty() {
+            match self
+                .server
+                .submit_email(
+                    &self.instance,
+                    msg,
+                    recipients.into_iter().map(String::from).collect(),
+                    self.session_id,
+                )
+                .await?
+            {
+                Ok(accepted) => {
+                    trc::event!(
+                        Esmp(trc::EsmpEvent::GatewayOutbound),
+                        SpanId = self.session_id,
+                        AccountName = msg.sender_pubkey.clone(),
+                        Id = msg.id.clone(),
+                        To = accepted
+                            .iter()
+                            .map(|rcpt| trc::Value::from(rcpt.clone()))
+                            .collect::<Vec<_>>(),
+                    );
+                    accepted
+                }
+                Err(reason) => return Ok(Err(reason.into())),
+            }
+        } else {
+            vec![]
+        };
+
+        let (message_ids, mut queued) =
+            self.server.route_message(msg, contents, spam_score).await?;
+        queued.extend(emailed);
+
+        Ok(Ok((message_ids, queued)))
+    }
+}
+
+/// Builds the MIME message sent to the e-mail recipients of an ESMP message.
+fn build_email(msg: &EsmpMessage, from: &str) -> Vec<u8> {
+    let (subject, text, html) = body_parts(&msg.body);
+    let domain = from
+        .rsplit_once('@')
+        .map_or("localhost", |(_, domain)| domain);
+
+    let mut builder = MessageBuilder::new()
+        .from(Address::Address(EmailAddress {
+            name: None,
+            email: from.into(),
+        }))
+        .message_id(format!("<{}@{}>", make_boundary("."), domain))
+        .date(Date::new(msg.timestamp as i64))
+        .subject(subject.unwrap_or("ESMP message"))
+        .header(
+            "X-ESMP-Sender-Key",
+            HeaderType::Text(msg.sender_pubkey.as_str().into()),
+        )
+        .header(
+            "X-ESMP-Message-Id",
+            HeaderType::Text(msg.id.as_str().into()),
+        );
+    for (header, recipients) in [("To", Some(&msg.to)), ("Cc", msg.cc.as_ref())] {
+        let addresses = recipients
+            .into_iter()
+            .flatten()
+            .map(|recipient| recipient.trim())
+            .filter(|recipient| is_email_address(recipient))
+            .map(|recipient| {
+                Address::Address(EmailAddress {
+                    name: None,
+                    email: recipient.into(),
+                })
+            })
+            .collect::<Vec<_>>();
+        if !addresses.is_empty() {
+            builder = builder.header(header, HeaderType::Address(Address::List(addresses)));
+        }
+    }
+    if let Some(text) = text {
+        builder = builder.text_body(text);
+    }
+    if let Some(html) = html {
+        builder = builder.html_body(html);
+    }
+
+    builder.write_to_vec().unwrap_or_default()
+}
+
+/// Splits a message body into its subject, text and HTML parts. Bodies
+/// that are neither a string nor an object with `text` or `html` members
+/// are returned as JSON text.
+pub(crate) fn body_parts(body: &Value) -> (Option<&str>, Option<String>, Option<String>) {
+    match body {
+        Value::String(text) => (None, Some(text.clone()), None),
+        Value::Object(parts) => {
+            let subject = parts.get("subject").and_then(Value::as_str);
+            let text = parts.get("text").and_then(Value::as_str).map(String::from);
+            let html = parts.get("html").and_then(Value::as_str).map(String::from);
+            if text.is_none() && html.is_none() {
+                (subject, serde_json::to_string_pretty(body).ok(), None)
+            } else {
+                (subject, text, html)
+            }
+        }
+        body => (None, serde_json::to_string_pretty(body).ok(), None),
+    }
+}
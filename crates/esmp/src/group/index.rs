@@ -0,0 +1,110 @@
+/*
+ * SPDX-FileCopyrightText: 2020 Stalwart Labs LLC <hello@stalw.art>
+ *
+ * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
+ */
+
+use common::storage::index::{IndexValue, IndexableAndSerializableObject, IndexableObject};
+use jmap_proto::types::collection::SyncCollection;
+
+use crate::{IDX_GROUP, IDX_GROUP_ID, IDX_MEMBER, IDX_MESSAGE_ID};
+
+use super::{ArchivedGroupMessage, ArchivedGroupMetadata, GroupMessage, GroupMetadata};
+
+impl IndexableObject for GroupMetadata {
+    fn index_values(&self) -> impl Iterator<Item = IndexValue<'_>> {
+        [
+            IndexValue::Index {
+                field: IDX_GROUP_ID,
+                value: self.group_id.as_str().into(),
+            },
+            IndexValue::IndexList {
+                field: IDX_MEMBER,
+                value: self
+                    .members
+                    .iter()
+                    .map(|member| member.as_str().into())
+                    .collect(),
+            },
+            IndexValue::LogContainer {
+                sync_collection: SyncCollection::Esmp.into(),
+            },
+        ]
+        .into_iter()
+    }
+}
+
+impl IndexableAndSerializableObject for GroupMetadata {
+    fn is_versioned() -> bool {
+        true
+    }
+}
+
+impl IndexableObject for &ArchivedGroupMetadata {
+    fn index_values(&self) -> impl Iterator<Item = IndexValue<'_>> {
+        [
+            IndexValue::Index {
+                field: IDX_GROUP_ID,
+                value: self.group_id.as_str().into(),
+            },
+            IndexValue::IndexList {
+                field: IDX_MEMBER,
+                value: self
+                    .members
+                    .iter()
+                    .map(|member| member.as_str().into())
+                    .collect(),
+            },
+            IndexValue::LogContainer {
+                sync_collection: SyncCollection::Esmp.into(),
+            },
+        ]
+        .into_iter()
+    }
+}
+
+impl IndexableObject for GroupMessage {
+    fn index_values(&self) -> impl Iterator<Item = IndexValue<'_>> {
+        [
+            IndexValue::Index {
+                field: IDX_GROUP,
+                value: self.group_id.into(),
+            },
+            IndexValue::Index {
+                field: IDX_MESSAGE_ID,
+                value: self.message_id.as_str().into(),
+            },
+            IndexValue::LogItem {
+                sync_collection: SyncCollection::Esmp.into(),
+                prefix: Some(self.group_id),
+            },
+        ]
+        .into_iter()
+    }
+}
+
+impl IndexableAndSerializableObject for GroupMessage {
+    fn is_versioned() -> bool {
+        false
+    }
+}
+
+impl IndexableObject for &ArchivedGroupMessage {
+    fn index_values(&self) -> impl Iterator<Item = IndexValue<'_>> {
+        [
+            IndexValue::Index {
+                field: IDX_GROUP,
+                value: self.group_id.into(),
+            },
+            IndexValue::Index {
+                field: IDX_MESSAGE_ID,
+                value: self.message_id.as_str().into(),
+            },
+            IndexValue::LogItem {
+                sync_collection: SyncCollection::Esmp.into(),
+                prefix: Some(self.group_id.to_native()),
+            },
+        ]
+        .into_iter()
+    }
+}
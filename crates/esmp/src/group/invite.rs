@@ -0,0 +1,157 @@
+/*
+ * SPDX-FileCopyrightText: 2020 Stalwart Labs LLC <hello@stalw.art>
+ *
+ * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
+ */
+
+use base64::{Engine, engine::general_purpose};
+use common::Server;
+use jmap_proto::types::collection::Collection;
+use store::{query::Filter, write::now};
+use trc::AddContext;
+
+use crate::{
+    ESMP_ACCOUNT_ID, IDX_GROUP, IDX_MESSAGE_ID, handler::EsmpMessage, keyset::persist::KeySetStore,
+    system::SystemMessageType,
+};
+
+use super::{GroupMessage, GroupMetadata, Participants};
+
+/// Encodes the canonical contents of an `invited` message as an invite token.
+pub fn encode_invite(contents: &str) -> String {
+    general_purpose::URL_SAFE_NO_PAD.encode(contents)
+}
+
+/// Decodes an invite token into the `invited` message it carries, along with
+/// its canonical contents.
+pub fn decode_invite(token: &str) -> Option<(EsmpMessage, String)> {
+    let contents = String::from_utf8(general_purpose::URL_SAFE_NO_PAD.decode(token).ok()?).ok()?;
+    let msg = serde_json::from_str::<EsmpMessage>(&contents).ok()?;
+    Some((msg, contents))
+}
+
+pub fn invite_link(domain: &str, token: &str) -> String {
+    format!("esmp://{domain}/join?invite={token}")
+}
+
+impl EsmpMessage {
+    /// Returns the invite token presented by a `joined` message.
+    pub fn invite(&self) -> Option<&str> {
+        self.body.get("invite").and_then(|v| v.as_str())
+    }
+
+    /// Returns the time an `invited` message stops being accepted.
+    pub fn invite_expires(&self) -> Option<u64> {
+        self.body.get("expires").and_then(|v| v.as_u64())
+    }
+
+    /// Returns whether an `invited` message expires in the future, within
+    /// `max_lifetime` seconds from now.
+    pub fn is_invite_lifetime_valid(&self, max_lifetime: u64) -> bool {
+        let now = now();
+        self.invite_expires()
+            .is_some_and(|expires| expires > now && expires <= now.saturating_add(max_lifetime))
+    }
+}
+
+impl GroupMetadata {
+    /// Returns whether the `invited` message has been revoked by an admin.
+    pub fn is_revoked(&self, invite: &EsmpMessage) -> bool {
+        self.revoked_invites
+            .iter()
+            .any(|revoked| revoked.id == invite.id && revoked.sender == invite.sender_pubkey)
+    }
+}
+
+/// Checks the invite issued, presented or revoked by `msg`, returning whether
+/// the sender joins the group with a valid invite.
+pub(crate) async fn verify_invite(
+    server: &Server,
+    group_document_id: u32,
+    group: &GroupMetadata,
+    msg: &EsmpMessage,
+    participants: &Participants,
+) -> trc::Result<Result<bool, &'static str>> {
+    let is_revocation = match msg.subtype.as_deref().and_then(SystemMessageType::parse) {
+        Some(SystemMessageType::Invited) => {
+            return Ok(
+                if msg.is_invite_lifetime_valid(server.core.esmp.invite.max_expiry.as_secs()) {
+                    Ok(false)
+                } else {
+                    Err("Invites must expire within the maximum invite lifetime")
+                },
+            );
+        }
+        Some(SystemMessageType::Joined) => false,
+        Some(SystemMessageType::InviteRevoked) => true,
+        _ => return Ok(Ok(false)),
+    };
+    let Some(token) = msg.invite() else {
+        return Ok(Ok(false));
+    };
+
+    let Some((invite, contents)) = decode_invite(token) else {
+        return Ok(Err("Invalid invite"));
+    };
+    if invite.subtype.as_deref() != Some(SystemMessageType::Invited.as_str())
+        || invite.group_id.as_deref() != Some(group.group_id.as_str())
+    {
+        return Ok(Err("Invite is not valid for this group"));
+    }
+    if invite
+        .invite_expires()
+        .is_none_or(|expires| expires <= now())
+    {
+        return Ok(Err("Invite has expired"));
+    }
+    if group.is_revoked(&invite) {
+        return Ok(Err("Invite has been revoked"));
+    }
+    if !is_revocation {
+        if let Some(target) = &invite.target
+            && server.resolve_identity(target).await? != participants.sender
+        {
+            return Ok(Err("Invite was issued to another identity"));
+        }
+        let inviter = match &invite.actor {
+            Some(actor) => server.resolve_identity(actor).await?,
+            None => return Ok(Err("Invalid invite")),
+        };
+        if !group.is_admin(&inviter) {
+            return Ok(Err(
+                "Invite was issued by an identity that is no longer an admin",
+            ));
+        }
+    }
+
+    // Invites are only accepted as stored in the group, where their signature
+    // was verified when they were issued
+    let message_ids = server
+        .store()
+        .filter(
+            ESMP_ACCOUNT_ID,
+            Collection::EsmpMessage,
+            vec![
+                Filter::eq(IDX_GROUP, group_document_id.to_be_bytes().to_vec()),
+                Filter::eq(IDX_MESSAGE_ID, invite.id.as_bytes().to_vec()),
+            ],
+        )
+        .await
+        .caused_by(trc::location!())?
+        .results;
+    for message_id in message_ids {
+        if let Some(archive) = server
+            .get_archive(ESMP_ACCOUNT_ID, Collection::EsmpMessage, message_id)
+            .await?
+        {
+            let message = archive
+                .deserialize::<GroupMessage>()
+                .caused_by(trc::location!())?;
+            if message.sender == invite.sender_pubkey && message.contents == contents {
+                return Ok(Ok(!is_revocation));
+            }
+        }
+    }
+
+    Ok(Err("Invite was not issued to this group"))
+}
@@ -0,0 +1,378 @@
+/*
+ * SPDX-FileCopyrightText: 2020 Stalwart Labs LLC <hello@stalw.art>
+ *
+ * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
+ */
+
+use serde::{Deserialize, Serialize};
+
+use crate::{handler::EsmpMessage, system::SystemMessageType};
+
+use invite::decode_invite;
+
+pub mod index;
+pub mod invite;
+pub mod persist;
+pub mod policy;
+
+#[derive(
+    rkyv::Archive,
+    rkyv::Deserialize,
+    rkyv::Serialize,
+    Debug,
+    Default,
+    Clone,
+    PartialEq,
+    Eq,
+    Serialize,
+    Deserialize,
+)]
+#[rkyv(derive(Debug))]
+pub struct GroupMetadata {
+    pub group_id: String,
+    pub group_name: Option<String>,
+    pub group_description: Option<String>,
+    pub group_display_picture: Option<String>,
+    pub created_at: Option<u64>,
+    pub updated_at: Option<u64>,
+    pub admins: Vec<String>,
+    pub members: Vec<String>,
+    #[serde(default)]
+    pub epoch: u64,
+    #[serde(default)]
+    pub join_policy: JoinPolicy,
+    #[serde(default, skip_serializing)]
+    pub membership: Vec<Membership>,
+    #[serde(default, skip_serializing)]
+    pub pending: Vec<JoinRequest>,
+    #[serde(default, skip_serializing)]
+    pub revoked_invites: Vec<RevokedInvite>,
+}
+
+/// How identities that are not members can join the group.
+#[derive(
+    rkyv::Archive,
+    rkyv::Deserialize,
+    rkyv::Serialize,
+    Debug,
+    Default,
+    Clone,
+    Copy,
+    PartialEq,
+    Eq,
+    Serialize,
+    Deserialize,
+)]
+#[rkyv(derive(Debug))]
+#[serde(rename_all = "snake_case")]
+pub enum JoinPolicy {
+    #[default]
+    Open,
+    InviteOnly,
+    ApprovalRequired,
+}
+
+/// A request to join the group waiting for the decision of an admin.
+#[derive(
+    rkyv::Archive,
+    rkyv::Deserialize,
+    rkyv::Serialize,
+    Debug,
+    Default,
+    Clone,
+    PartialEq,
+    Eq,
+    Serialize,
+    Deserialize,
+)]
+#[rkyv(derive(Debug))]
+pub struct JoinRequest {
+    pub identity: String,
+    pub message_id: u32,
+    pub requested_at: u64,
+}
+
+/// An invite that can no longer be used to join the group, kept until it
+/// expires.
+#[derive(
+    rkyv::Archive,
+    rkyv::Deserialize,
+    rkyv::Serialize,
+    Debug,
+    Default,
+    Clone,
+    PartialEq,
+    Eq,
+    Serialize,
+    Deserialize,
+)]
+#[rkyv(derive(Debug))]
+pub struct RevokedInvite {
+    pub id: String,
+    pub sender: String,
+    pub expires: u64,
+}
+
+/// The span of the group history an identity was present for, bounded by the
+/// ids of the messages that added and removed it.
+#[derive(
+    rkyv::Archive,
+    rkyv::Deserialize,
+    rkyv::Serialize,
+    Debug,
+    Default,
+    Clone,
+    PartialEq,
+    Eq,
+    Serialize,
+    Deserialize,
+)]
+#[rkyv(derive(Debug))]
+pub struct Membership {
+    pub identity: String,
+    pub joined: u32,
+    pub left: Option<u32>,
+}
+
+#[derive(
+    rkyv::Archive, rkyv::Deserialize, rkyv::Serialize, Debug, Default, Clone, PartialEq, Eq,
+)]
+#[rkyv(derive(Debug))]
+pub struct GroupMessage {
+    pub group_id: u32,
+    pub message_id: String,
+    pub sender: String,
+    pub received_at: u64,
+    pub contents: String,
+    pub edit: Option<String>,
+    pub reactions: Vec<GroupReaction>,
+    pub retracted: bool,
+}
+
+/// A reaction to a group message, kept with the message it reacts to along
+/// with the id of the `reaction` message that added it.
+#[derive(
+    rkyv::Archive, rkyv::Deserialize, rkyv::Serialize, Debug, Default, Clone, PartialEq, Eq,
+)]
+#[rkyv(derive(Debug))]
+pub struct GroupReaction {
+    pub reaction_id: u32,
+    pub identity: String,
+    pub reaction: String,
+}
+
+#[derive(Debug, Default)]
+pub struct GroupHistoryPage {
+    pub messages: Vec<(u32, GroupMessage)>,
+    pub has_more: bool,
+}
+
+/// Position in the group history a page is read from. Pages read before a
+/// cursor move back towards the start of the group, while pages read since a
+/// cursor move forward to the most recent message.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HistoryCursor {
+    Before(u32),
+    Since(u32),
+}
+
+/// Keys named by a message, resolved to the identities they act for. Group
+/// membership is tracked by identity so that it survives key rotation.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Participants {
+    pub sender: String,
+    pub actor: Option<String>,
+    pub target: Option<String>,
+}
+
+impl GroupMetadata {
+    pub fn new(group_id: impl Into<String>) -> Self {
+        GroupMetadata {
+            group_id: group_id.into(),
+            ..Default::default()
+        }
+    }
+
+    pub fn apply(
+        &mut self,
+        msg: &EsmpMessage,
+        participants: &Participants,
+        message_id: u32,
+        now: u64,
+    ) {
+        let Some(subtype) = msg.subtype.as_deref().and_then(SystemMessageType::parse) else {
+            return;
+        };
+
+        match subtype {
+            SystemMessageType::GroupCreated => {
+                self.created_at = Some(now);
+                self.updated_at = Some(now);
+                self.group_name = body_str(msg, "group_name");
+                self.group_description = body_str(msg, "group_description");
+                self.group_display_picture = body_str(msg, "group_display_picture");
+                self.join_policy = msg.join_policy().unwrap_or_default();
+                if let Some(actor) = &participants.actor {
+                    self.admins.push(actor.clone());
+                    self.members.push(actor.clone());
+                    self.membership.push(Membership::new(actor, message_id));
+                }
+            }
+            SystemMessageType::GroupRenamed => {
+                if let Some(new_name) = &msg.new_name {
+                    self.group_name = Some(new_name.clone());
+                    self.updated_at = Some(now);
+                }
+            }
+            SystemMessageType::DescriptionUpdated => {
+                if let Some(new_desc) = &msg.new_description {
+                    self.group_description = Some(new_desc.clone());
+                    self.updated_at = Some(now);
+                }
+            }
+            SystemMessageType::DpUpdated => {
+                if let Some(new_dp) = &msg.new_dp_url {
+                    self.group_display_picture = Some(new_dp.clone());
+                    self.updated_at = Some(now);
+                }
+            }
+            SystemMessageType::Joined => {
+                if let Some(actor) = &participants.actor {
+                    self.add_member(actor, message_id, now);
+                }
+            }
+            SystemMessageType::JoinApproved => {
+                if let Some(target) = &participants.target {
+                    self.add_member(target, message_id, now);
+                }
+            }
+            SystemMessageType::JoinRequested => {
+                if let Some(actor) = &participants.actor {
+                    self.pending.push(JoinRequest {
+                        identity: actor.clone(),
+                        message_id,
+                        requested_at: now,
+                    });
+                }
+            }
+            SystemMessageType::JoinRejected => {
+                if let Some(target) = &participants.target {
+                    self.pending.retain(|request| &request.identity != target);
+                }
+            }
+            SystemMessageType::JoinPolicyUpdated => {
+                if let Some(policy) = msg.join_policy() {
+                    self.join_policy = policy;
+                    self.updated_at = Some(now);
+                }
+            }
+            SystemMessageType::Left | SystemMessageType::Removed => {
+                if let Some(target) = participants.target.as_ref().or(participants.actor.as_ref()) {
+                    self.members.retain(|x| x != target);
+                    self.admins.retain(|x| x != target);
+                    for membership in &mut self.membership {
+                        if &membership.identity == target && membership.left.is_none() {
+                            membership.left = Some(message_id);
+                        }
+                    }
+                    self.updated_at = Some(now);
+                }
+            }
+            SystemMessageType::AdminAssigned => {
+                if let Some(target) = &participants.target
+                    && !self.admins.contains(target)
+                {
+                    self.admins.push(target.clone());
+                    self.updated_at = Some(now);
+                }
+            }
+            SystemMessageType::AdminRevoked => {
+                if let Some(target) = &participants.target {
+                    self.admins.retain(|x| x != target);
+                    self.updated_at = Some(now);
+                }
+            }
+            SystemMessageType::KeyCommit => {
+                if let Some(epoch) = msg.body.get("epoch").and_then(|v| v.as_u64()) {
+                    self.epoch = epoch;
+                    self.updated_at = Some(now);
+                }
+            }
+            SystemMessageType::InviteRevoked => {
+                if let Some((invite, _)) = msg.invite().and_then(decode_invite) {
+                    self.revoked_invites.retain(|revoked| revoked.expires > now);
+                    if !self.is_revoked(&invite) {
+                        self.revoked_invites.push(RevokedInvite {
+                            id: invite.id.clone(),
+                            sender: invite.sender_pubkey.clone(),
+                            expires: invite.invite_expires().unwrap_or_default(),
+                        });
+                    }
+                }
+            }
+            SystemMessageType::ProfileUpdated | SystemMessageType::Invited => {}
+        }
+    }
+
+    fn add_member(&mut self, identity: &str, message_id: u32, now: u64) {
+        // Joining settles any pending request of the identity
+        self.pending.retain(|request| request.identity != identity);
+        if !self.is_member(identity) {
+            self.members.push(identity.to_string());
+            self.membership.push(Membership::new(identity, message_id));
+            self.updated_at = Some(now);
+        }
+    }
+}
+
+impl JoinPolicy {
+    pub fn parse(value: &str) -> Option<Self> {
+        hashify::tiny_map!(value.as_bytes(),
+            "open" => Self::Open,
+            "invite_only" => Self::InviteOnly,
+            "approval_required" => Self::ApprovalRequired,
+        )
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            JoinPolicy::Open => "open",
+            JoinPolicy::InviteOnly => "invite_only",
+            JoinPolicy::ApprovalRequired => "approval_required",
+        }
+    }
+}
+
+impl Membership {
+    pub fn new(identity: &str, joined: u32) -> Self {
+        Membership {
+            identity: identity.to_string(),
+            joined,
+            left: None,
+        }
+    }
+
+    /// Members can read the messages that added and removed them, and every
+    /// message posted in between.
+    pub fn contains(&self, message_id: u32) -> bool {
+        message_id >= self.joined && self.left.is_none_or(|left| message_id <= left)
+    }
+}
+
+impl EsmpMessage {
+    /// Returns the join policy set by `group_created` and
+    /// `join_policy_updated` messages.
+    pub fn join_policy(&self) -> Option<JoinPolicy> {
+        self.body
+            .get("join_policy")
+            .and_then(|v| v.as_str())
+            .and_then(JoinPolicy::parse)
+    }
+}
+
+fn body_str(msg: &EsmpMessage, key: &str) -> Option<String> {
+    msg.body
+        .get(key)
+        .and_then(|v| v.as_str())
+        .map(|s| s.to_string())
+}
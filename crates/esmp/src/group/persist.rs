@@ -0,0 +1,666 @@
+/*
+ * SPDX-FileCopyrightText: 2020 Stalwart Labs LLC <hello@stalw.art>
+ *
+ * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
+ */
+
+use std::{future::Future, time::Duration};
+
+use common::{
+    KV_LOCK_ESMP_GROUP, Server,
+    ipc::{EsmpStateChange, EsmpStateType},
+    storage::index::ObjectIndexBuilder,
+};
+use jmap_proto::types::collection::Collection;
+use store::{
+    query::Filter,
+    rand::Rng,
+    write::{AlignedBytes, Archive, BatchBuilder, now},
+};
+use trc::AddContext;
+
+use crate::{
+    ESMP_ACCOUNT_ID, IDX_GROUP, IDX_GROUP_ID, IDX_MEMBER, IDX_MESSAGE_ID, MAX_RETRIES,
+    blob::{BlobShare, persist::share_blobs},
+    handler::EsmpMessage,
+    keyset::persist::KeySetStore,
+    push::notify_sessions,
+    reference::ReferenceType,
+    webpush::persist::EsmpPushStore,
+};
+
+use super::{
+    GroupHistoryPage, GroupMessage, GroupMetadata, GroupReaction, HistoryCursor, Participants,
+    invite::verify_invite, policy::authorize,
+};
+
+const GROUP_LOCK_EXPIRY: u64 = 30;
+
+type ReferenceUpdate = (u32, Archive<AlignedBytes>, GroupMessage);
+
+pub trait GroupStore: Sync + Send {
+    fn group_document_id(
+        &self,
+        group_id: &str,
+    ) -> impl Future<Output = trc::Result<Option<u32>>> + Send;
+
+    fn fetch_group(
+        &self,
+        group_id: &str,
+    ) -> impl Future<Output = trc::Result<Option<(u32, GroupMetadata)>>> + Send;
+
+    fn fetch_group_metadata(
+        &self,
+        group_id: &str,
+    ) -> impl Future<Output = trc::Result<Option<GroupMetadata>>> + Send;
+
+    fn persist_group_message(
+        &self,
+        group_id: &str,
+        msg: &EsmpMessage,
+        contents: String,
+    ) -> impl Future<Output = trc::Result<Result<u32, &'static str>>> + Send;
+
+    fn append_group_message(
+        &self,
+        group_id: &str,
+        msg: &EsmpMessage,
+        contents: String,
+        participants: Participants,
+    ) -> impl Future<Output = trc::Result<Result<u32, &'static str>>> + Send;
+
+    fn shares_group(
+        &self,
+        identity: &str,
+        other: &str,
+    ) -> impl Future<Output = trc::Result<bool>> + Send;
+
+    fn fetch_group_history(
+        &self,
+        group_document_id: u32,
+        metadata: &GroupMetadata,
+        readers: &[String],
+        cursor: HistoryCursor,
+        limit: usize,
+    ) -> impl Future<Output = trc::Result<GroupHistoryPage>> + Send;
+
+    fn fetch_group_message(
+        &self,
+        group_document_id: u32,
+        message_id: u32,
+    ) -> impl Future<Output = trc::Result<Option<GroupMessage>>> + Send;
+}
+
+impl GroupStore for Server {
+    async fn group_document_id(&self, group_id: &str) -> trc::Result<Option<u32>> {
+        self.store()
+            .filter(
+                ESMP_ACCOUNT_ID,
+                Collection::EsmpGroup,
+                vec![Filter::eq(IDX_GROUP_ID, group_id.as_bytes().to_vec())],
+            )
+            .await
+            .caused_by(trc::location!())
+            .map(|result| result.results.min())
+    }
+
+    async fn fetch_group(&self, group_id: &str) -> trc::Result<Option<(u32, GroupMetadata)>> {
+        if let Some(document_id) = self.group_document_id(group_id).await? {
+            self.get_archive(ESMP_ACCOUNT_ID, Collection::EsmpGroup, document_id)
+                .await?
+                .map(|archive| {
+                    archive
+                        .deserialize::<GroupMetadata>()
+                        .map(|metadata| (document_id, metadata))
+                })
+                .transpose()
+                .caused_by(trc::location!())
+        } else {
+            Ok(None)
+        }
+    }
+
+    async fn fetch_group_metadata(&self, group_id: &str) -> trc::Result<Option<GroupMetadata>> {
+        self.fetch_group(group_id)
+            .await
+            .map(|group| group.map(|(_, metadata)| metadata))
+    }
+
+    async fn persist_group_message(
+        &self,
+        group_id: &str,
+        msg: &EsmpMessage,
+        contents: String,
+    ) -> trc::Result<Result<u32, &'static str>> {
+        let participants = resolve_participants(self, msg).await?;
+        self.append_group_message(group_id, msg, contents, participants)
+            .await
+    }
+
+    async fn append_group_message(
+        &self,
+        group_id: &str,
+        msg: &EsmpMessage,
+        contents: String,
+        participants: Participants,
+    ) -> trc::Result<Result<u32, &'static str>> {
+        // Groups are created by their first message, concurrent attempts to
+        // create the same group are serialized so only one of them succeeds
+        if self.group_document_id(group_id).await?.is_some() {
+            return append_message(self, group_id, msg, contents, &participants).await;
+        }
+        let mut try_count = 0;
+        while !self
+            .in_memory_store()
+            .try_lock(KV_LOCK_ESMP_GROUP, group_id.as_bytes(), GROUP_LOCK_EXPIRY)
+            .await
+            .caused_by(trc::location!())?
+        {
+            if try_count >= MAX_RETRIES {
+                return Err(trc::StoreEvent::AssertValueFailed
+                    .into_err()
+                    .details("Group is being created by another request")
+                    .caused_by(trc::location!()));
+            }
+            let backoff = store::rand::rng().random_range(50..=300);
+            tokio::time::sleep(Duration::from_millis(backoff)).await;
+            try_count += 1;
+        }
+
+        let result = append_message(self, group_id, msg, contents, &participants).await;
+        if let Err(err) = self
+            .in_memory_store()
+            .remove_lock(KV_LOCK_ESMP_GROUP, group_id.as_bytes())
+            .await
+        {
+            trc::error!(err.details("Failed to release group lock."));
+        }
+        result
+    }
+
+    async fn shares_group(&self, identity: &str, other: &str) -> trc::Result<bool> {
+        self.store()
+            .filter(
+                ESMP_ACCOUNT_ID,
+                Collection::EsmpGroup,
+                vec![
+                    Filter::eq(IDX_MEMBER, identity.as_bytes().to_vec()),
+                    Filter::eq(IDX_MEMBER, other.as_bytes().to_vec()),
+                ],
+            )
+            .await
+            .caused_by(trc::location!())
+            .map(|result| !result.results.is_empty())
+    }
+
+    async fn fetch_group_history(
+        &self,
+        group_document_id: u32,
+        metadata: &GroupMetadata,
+        readers: &[String],
+        cursor: HistoryCursor,
+        limit: usize,
+    ) -> trc::Result<GroupHistoryPage> {
+        let message_ids = self
+            .store()
+            .filter(
+                ESMP_ACCOUNT_ID,
+                Collection::EsmpMessage,
+                vec![Filter::eq(
+                    IDX_GROUP,
+                    group_document_id.to_be_bytes().to_vec(),
+                )],
+            )
+            .await
+            .caused_by(trc::location!())?
+            .results;
+        let message_ids: Box<dyn Iterator<Item = u32> + Send> = match cursor {
+            HistoryCursor::Before(before) => {
+                Box::new(message_ids.into_iter().rev().filter(move |id| *id < before))
+            }
+            HistoryCursor::Since(since) => {
+                Box::new(message_ids.into_iter().filter(move |id| *id > since))
+            }
+        };
+
+        // Readers only see the messages posted while they were members
+        let mut page = GroupHistoryPage::default();
+        for message_id in
+            message_ids.filter(|id| readers.iter().any(|reader| metadata.can_read(reader, *id)))
+        {
+            if page.messages.len() == limit {
+                page.has_more = true;
+                break;
+            }
+
+            if let Some(archive) = self
+                .get_archive(ESMP_ACCOUNT_ID, Collection::EsmpMessage, message_id)
+                .await?
+            {
+                page.messages.push((
+                    message_id,
+                    archive
+                        .deserialize::<GroupMessage>()
+                        .caused_by(trc::location!())?,
+                ));
+            }
+        }
+
+        // Pages are always returned oldest first
+        if matches!(cursor, HistoryCursor::Before(_)) {
+            page.messages.reverse();
+        }
+
+        Ok(page)
+    }
+
+    async fn fetch_group_message(
+        &self,
+        group_document_id: u32,
+        message_id: u32,
+    ) -> trc::Result<Option<GroupMessage>> {
+        self.get_archive(ESMP_ACCOUNT_ID, Collection::EsmpMessage, message_id)
+            .await?
+            .map(|archive| archive.deserialize::<GroupMessage>())
+            .transpose()
+            .caused_by(trc::location!())
+            .map(|message| message.filter(|message| message.group_id == group_document_id))
+    }
+}
+
+/// Appends `msg` to the group thread, creating the group from its first
+/// message.
+/// Resolves the keys named by a message to the identities they act for.
+pub(crate) async fn resolve_participants(
+    server: &Server,
+    msg: &EsmpMessage,
+) -> trc::Result<Participants> {
+    let mut participants = Participants {
+        sender: server.resolve_identity(&msg.sender_pubkey).await?,
+        ..Default::default()
+    };
+    if let Some(actor) = &msg.actor {
+        participants.actor = Some(server.resolve_identity(actor).await?);
+    }
+    if let Some(target) = &msg.target {
+        participants.target = Some(server.resolve_identity(target).await?);
+    }
+    Ok(participants)
+}
+
+async fn append_message(
+    server: &Server,
+    group_id: &str,
+    msg: &EsmpMessage,
+    contents: String,
+    participants: &Participants,
+) -> trc::Result<Result<u32, &'static str>> {
+    let is_system = msg.r#type == "system";
+    let mut try_count = 0;
+
+    loop {
+        // Message ids are assigned in order and double as history cursors
+        let message_id = server
+            .store()
+            .assign_document_ids(ESMP_ACCOUNT_ID, Collection::EsmpMessage, 1)
+            .await
+            .caused_by(trc::location!())?;
+        let mut batch = BatchBuilder::new();
+        batch
+            .with_account_id(ESMP_ACCOUNT_ID)
+            .with_collection(Collection::EsmpGroup);
+
+        // Load group metadata
+        let document_id = server.group_document_id(group_id).await?;
+        let metadata_ = if let Some(document_id) = document_id {
+            Some(
+                server
+                    .get_archive(ESMP_ACCOUNT_ID, Collection::EsmpGroup, document_id)
+                    .await?
+                    .ok_or_else(|| {
+                        trc::StoreEvent::NotFound
+                            .into_err()
+                            .document_id(document_id)
+                            .caused_by(trc::location!())
+                    })?,
+            )
+        } else {
+            None
+        };
+        let metadata = metadata_
+            .as_ref()
+            .map(|metadata| metadata.to_unarchived::<GroupMetadata>())
+            .transpose()
+            .caused_by(trc::location!())?;
+        let new_metadata = metadata
+            .as_ref()
+            .map(|metadata| metadata.deserialize::<GroupMetadata>())
+            .transpose()
+            .caused_by(trc::location!())?;
+
+        // Invites are checked against the group before authorizing joins
+        let invited = match new_metadata.as_ref().zip(document_id).filter(|_| is_system) {
+            Some((group, document_id)) => {
+                match verify_invite(server, document_id, group, msg, participants).await? {
+                    Ok(invited) => invited,
+                    Err(reason) => return Ok(Err(reason)),
+                }
+            }
+            None => false,
+        };
+
+        // Reject unauthorized state transitions
+        if let Err(reason) = authorize(new_metadata.as_ref(), msg, participants, invited) {
+            return Ok(Err(reason));
+        }
+
+        // Edits, retractions and reactions change the messages they reference
+        let references = match new_metadata
+            .as_ref()
+            .zip(document_id)
+            .filter(|_| !is_system)
+        {
+            Some((group, document_id)) => {
+                match resolve_references(
+                    server,
+                    document_id,
+                    group,
+                    msg,
+                    &contents,
+                    participants,
+                    message_id,
+                )
+                .await?
+                {
+                    Ok(references) => references,
+                    Err(reason) => return Ok(Err(reason)),
+                }
+            }
+            None => vec![],
+        };
+
+        let (group_document_id, members) = match (document_id, metadata, new_metadata) {
+            (Some(document_id), Some(metadata), Some(mut new_metadata)) => {
+                // Posts are also asserted against the group, so they are
+                // retried if the membership changes concurrently
+                if is_system {
+                    new_metadata.apply(msg, participants, message_id, now());
+                }
+                let members = new_metadata.members.clone();
+                batch
+                    .update_document(document_id)
+                    .custom(
+                        ObjectIndexBuilder::new()
+                            .with_current(metadata)
+                            .with_changes(new_metadata),
+                    )
+                    .caused_by(trc::location!())?;
+
+                (document_id, members)
+            }
+            _ => {
+                let mut metadata = GroupMetadata::new(group_id);
+                metadata.apply(msg, participants, message_id, now());
+                let members = metadata.members.clone();
+                let document_id = server
+                    .store()
+                    .assign_document_ids(ESMP_ACCOUNT_ID, Collection::EsmpGroup, 1)
+                    .await
+                    .caused_by(trc::location!())?;
+                batch
+                    .create_document(document_id)
+                    .custom(ObjectIndexBuilder::<(), _>::new().with_changes(metadata))
+                    .caused_by(trc::location!())?;
+                (document_id, members)
+            }
+        };
+
+        // Attachments are readable by the members that can read the message
+        if !is_system {
+            share_blobs(
+                server,
+                &mut batch,
+                &msg.attachments().unwrap_or_default(),
+                BlobShare::Group {
+                    group_id,
+                    message_id,
+                },
+            )
+            .await?;
+        }
+
+        // Append message to the group thread
+        batch
+            .with_account_id(ESMP_ACCOUNT_ID)
+            .with_collection(Collection::EsmpMessage);
+        for (reference_id, current, changes) in references {
+            batch
+                .update_document(reference_id)
+                .custom(
+                    ObjectIndexBuilder::new()
+                        .with_current(
+                            current
+                                .to_unarchived::<GroupMessage>()
+                                .caused_by(trc::location!())?,
+                        )
+                        .with_changes(changes),
+                )
+                .caused_by(trc::location!())?;
+        }
+        batch
+            .create_document(message_id)
+            .custom(
+                ObjectIndexBuilder::<(), _>::new().with_changes(GroupMessage {
+                    group_id: group_document_id,
+                    message_id: msg.id.clone(),
+                    sender: msg.sender_pubkey.clone(),
+                    received_at: now(),
+                    contents: contents.clone(),
+                    ..Default::default()
+                }),
+            )
+            .caused_by(trc::location!())?
+            .commit_point();
+
+        match server.commit_batch(batch).await {
+            Ok(assigned) => {
+                if is_system {
+                    trc::event!(
+                        Esmp(trc::EsmpEvent::GroupStateChange),
+                        Id = group_id.to_string(),
+                        Type = msg.subtype.clone(),
+                        AccountName = participants.sender.clone(),
+                        Total = members.len(),
+                    );
+                }
+
+                // Wake up the sessions and the offline devices of the group members
+                let state_change = EsmpStateChange {
+                    change_id: assigned.last_change_id(ESMP_ACCOUNT_ID).unwrap_or_default(),
+                    state_type: EsmpStateType::Message,
+                };
+                if let Err(err) = notify_sessions(server, &members, state_change).await {
+                    trc::error!(err.details("Failed to notify sessions"));
+                }
+                if let Err(err) = server
+                    .notify_push_subscribers(&members, &msg.sender_pubkey, state_change)
+                    .await
+                {
+                    trc::error!(err.details("Failed to notify push subscribers"));
+                }
+
+                return Ok(Ok(message_id));
+            }
+            Err(err) if err.is_assertion_failure() && try_count < MAX_RETRIES => {
+                let backoff = store::rand::rng().random_range(50..=300);
+                tokio::time::sleep(Duration::from_millis(backoff)).await;
+                try_count += 1;
+            }
+            Err(err) => {
+                return Err(err.caused_by(trc::location!()));
+            }
+        }
+    }
+}
+
+/// Resolves the messages named by `reply_to` and `ref_id`, returning the
+/// referenced messages with the changes made by `msg`.
+async fn resolve_references(
+    server: &Server,
+    group_document_id: u32,
+    group: &GroupMetadata,
+    msg: &EsmpMessage,
+    contents: &str,
+    participants: &Participants,
+    message_id: u32,
+) -> trc::Result<Result<Vec<ReferenceUpdate>, &'static str>> {
+    let sender = participants.sender.as_str();
+
+    if let Some(reply_to) = &msg.reply_to
+        && group_messages(server, group_document_id, group, reply_to, sender)
+            .await?
+            .is_empty()
+    {
+        return Ok(Err("Replied message not found"));
+    }
+
+    let (Some(reference_type), Some(ref_id)) = (msg.reference_type(), &msg.ref_id) else {
+        return Ok(Ok(vec![]));
+    };
+    let mut candidates = group_messages(server, group_document_id, group, ref_id, sender).await?;
+    if candidates.is_empty() {
+        return Ok(Err("Referenced message not found"));
+    }
+
+    // Message ids are chosen by their senders, so edits and retractions
+    // refer to the one sent by the same identity
+    let mut position = Some(0);
+    if reference_type.requires_author() {
+        position = None;
+        for (idx, (_, _, message)) in candidates.iter().enumerate() {
+            if server.resolve_identity(&message.sender).await? == sender {
+                position = Some(idx);
+                break;
+            }
+        }
+    }
+    let Some(position) = position else {
+        return Ok(Err(
+            "Only the original author can edit or retract a message",
+        ));
+    };
+    let (reference_id, current, mut reference) = candidates.swap_remove(position);
+    let Ok(target) = serde_json::from_str::<EsmpMessage>(&reference.contents) else {
+        return Ok(Err("Referenced message not found"));
+    };
+
+    let mut updates = Vec::with_capacity(2);
+    match reference_type {
+        ReferenceType::Edit | ReferenceType::Reaction if !target.is_content() => {
+            return Ok(Err(
+                "Only messages with content can be edited or reacted to",
+            ));
+        }
+        ReferenceType::Edit => {
+            reference.edit = Some(contents.to_string());
+        }
+        ReferenceType::Reaction => {
+            let reaction = msg.reaction().unwrap_or_default();
+            if reference
+                .reactions
+                .iter()
+                .any(|item| item.identity == sender && item.reaction == reaction)
+            {
+                return Ok(Err("Reaction has already been added"));
+            }
+            reference.reactions.push(GroupReaction {
+                reaction_id: message_id,
+                identity: sender.to_string(),
+                reaction: reaction.to_string(),
+            });
+        }
+        ReferenceType::Retract => {
+            match target.reference_type() {
+                None if target.r#type != "system" => (),
+                Some(ReferenceType::Reaction) => {
+                    // Retracted reactions are removed from the message they react to
+                    if let Some(reacted_id) = &target.ref_id {
+                        for (document_id, archive, mut reacted) in
+                            group_messages(server, group_document_id, group, reacted_id, sender)
+                                .await?
+                        {
+                            if reacted
+                                .reactions
+                                .iter()
+                                .any(|item| item.reaction_id == reference_id)
+                            {
+                                reacted
+                                    .reactions
+                                    .retain(|item| item.reaction_id != reference_id);
+                                updates.push((document_id, archive, reacted));
+                            }
+                        }
+                    }
+                }
+                _ => {
+                    return Ok(Err(
+                        "Only messages with content and reactions can be retracted",
+                    ));
+                }
+            }
+
+            // Retracted messages are kept as tombstones in the group history
+            reference.contents = String::new();
+            reference.edit = None;
+            reference.reactions.clear();
+            reference.retracted = true;
+        }
+    }
+    updates.push((reference_id, current, reference));
+
+    Ok(Ok(updates))
+}
+
+/// Returns the messages posted to the group under the id chosen by their
+/// senders, skipping retracted messages and those `reader` cannot read.
+async fn group_messages(
+    server: &Server,
+    group_document_id: u32,
+    group: &GroupMetadata,
+    id: &str,
+    reader: &str,
+) -> trc::Result<Vec<ReferenceUpdate>> {
+    let message_ids = server
+        .store()
+        .filter(
+            ESMP_ACCOUNT_ID,
+            Collection::EsmpMessage,
+            vec![
+                Filter::eq(IDX_GROUP, group_document_id.to_be_bytes().to_vec()),
+                Filter::eq(IDX_MESSAGE_ID, id.as_bytes().to_vec()),
+            ],
+        )
+        .await
+        .caused_by(trc::location!())?
+        .results;
+
+    let mut messages = Vec::new();
+    for message_id in message_ids
+        .into_iter()
+        .filter(|message_id| group.can_read(reader, *message_id))
+    {
+        if let Some(archive) = server
+            .get_archive(ESMP_ACCOUNT_ID, Collection::EsmpMessage, message_id)
+            .await?
+        {
+            let message = archive
+                .deserialize::<GroupMessage>()
+                .caused_by(trc::location!())?;
+            if !message.retracted {
+                messages.push((message_id, archive, message));
+            }
+        }
+    }
+
+    Ok(messages)
+}
@@ -0,0 +1,184 @@
+/*
+ * SPDX-FileCopyrightText: 2020 Stalwart Labs LLC <hello@stalw.art>
+ *
+ * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
+ */
+
+use crate::{handler::EsmpMessage, system::SystemMessageType};
+
+use super::{GroupMetadata, JoinPolicy, Participants};
+
+/// Returns whether `msg` may be applied to `group`, which is `None` when the
+/// group does not exist yet. `invited` is set when the sender joins with a
+/// valid invite.
+pub fn authorize(
+    group: Option<&GroupMetadata>,
+    msg: &EsmpMessage,
+    participants: &Participants,
+    invited: bool,
+) -> Result<(), &'static str> {
+    let sender = participants.sender.as_str();
+
+    if msg.r#type != "system" {
+        return match group {
+            Some(group) if !group.is_member(sender) => {
+                Err("Only group members can post to the group")
+            }
+            // Encrypted messages must use the key of the current epoch
+            Some(group)
+                if msg.r#type == "encrypted"
+                    && msg.body.get("epoch").and_then(|v| v.as_u64()) != Some(group.epoch) =>
+            {
+                Err("Message is not encrypted for the current group epoch")
+            }
+            Some(_) => Ok(()),
+            None => Err("Group does not exist"),
+        };
+    }
+
+    let subtype = msg
+        .subtype
+        .as_deref()
+        .and_then(SystemMessageType::parse)
+        .ok_or("Invalid system message subtype")?;
+
+    // Keys can only act on behalf of their own identity
+    if participants.actor.as_deref() != Some(sender) {
+        return Err("Actor does not match the message signer");
+    }
+
+    let Some(group) = group else {
+        return if subtype == SystemMessageType::GroupCreated {
+            Ok(())
+        } else {
+            Err("Group does not exist")
+        };
+    };
+    let target = participants.target.as_deref();
+
+    match subtype {
+        SystemMessageType::GroupCreated => Err("Group already exists"),
+        SystemMessageType::Joined if group.is_member(sender) => {
+            Err("Sender is already a group member")
+        }
+        SystemMessageType::Joined => match group.join_policy {
+            JoinPolicy::Open => Ok(()),
+            _ if invited => Ok(()),
+            JoinPolicy::InviteOnly => Err("Joining this group requires an invite"),
+            JoinPolicy::ApprovalRequired => {
+                Err("Joining this group requires an invite or the approval of an admin")
+            }
+        },
+        SystemMessageType::JoinRequested if group.is_member(sender) => {
+            Err("Sender is already a group member")
+        }
+        SystemMessageType::JoinRequested if group.join_policy != JoinPolicy::ApprovalRequired => {
+            Err("Group does not accept join requests")
+        }
+        SystemMessageType::JoinRequested if group.is_pending(sender) => {
+            Err("Join request is already pending")
+        }
+        SystemMessageType::JoinRequested => Ok(()),
+        SystemMessageType::Left if !group.is_member(sender) => Err("Sender is not a group member"),
+        SystemMessageType::Left if target.is_some_and(|target| target != sender) => {
+            Err("Members can only leave on their own behalf")
+        }
+        SystemMessageType::Left if group.is_last_admin(sender) && group.members.len() > 1 => {
+            Err("The last admin cannot leave while the group has other members")
+        }
+        SystemMessageType::Left => Ok(()),
+        SystemMessageType::GroupRenamed
+        | SystemMessageType::DescriptionUpdated
+        | SystemMessageType::DpUpdated
+        | SystemMessageType::Removed
+        | SystemMessageType::AdminAssigned
+        | SystemMessageType::AdminRevoked
+        | SystemMessageType::Invited
+        | SystemMessageType::InviteRevoked
+        | SystemMessageType::JoinApproved
+        | SystemMessageType::JoinRejected
+        | SystemMessageType::JoinPolicyUpdated
+            if !group.is_admin(sender) =>
+        {
+            Err("Only group admins can perform this action")
+        }
+        SystemMessageType::GroupRenamed
+        | SystemMessageType::DescriptionUpdated
+        | SystemMessageType::DpUpdated
+        | SystemMessageType::Invited
+        | SystemMessageType::InviteRevoked
+        | SystemMessageType::JoinPolicyUpdated => Ok(()),
+        SystemMessageType::JoinApproved | SystemMessageType::JoinRejected
+            if !target.is_some_and(|target| group.is_pending(target)) =>
+        {
+            Err("Target has no pending join request")
+        }
+        SystemMessageType::JoinApproved | SystemMessageType::JoinRejected => Ok(()),
+        SystemMessageType::Removed | SystemMessageType::AdminAssigned
+            if !target.is_some_and(|target| group.is_member(target)) =>
+        {
+            Err("Target is not a group member")
+        }
+        SystemMessageType::Removed if target.is_some_and(|target| group.is_last_admin(target)) => {
+            Err("The last admin cannot be removed")
+        }
+        SystemMessageType::Removed => Ok(()),
+        SystemMessageType::AdminAssigned if target.is_some_and(|target| group.is_admin(target)) => {
+            Err("Target is already a group admin")
+        }
+        SystemMessageType::AdminAssigned => Ok(()),
+        SystemMessageType::AdminRevoked if !target.is_some_and(|target| group.is_admin(target)) => {
+            Err("Target is not a group admin")
+        }
+        SystemMessageType::AdminRevoked if group.admins.len() == 1 => {
+            Err("The last admin cannot be revoked")
+        }
+        SystemMessageType::AdminRevoked => Ok(()),
+        SystemMessageType::ProfileUpdated | SystemMessageType::KeyCommit
+            if !group.is_member(sender) =>
+        {
+            Err("Only group members can post to the group")
+        }
+        SystemMessageType::KeyCommit
+            if msg.body.get("epoch").and_then(|v| v.as_u64()) != Some(group.epoch + 1) =>
+        {
+            Err("Key commits must advance the group to the next epoch")
+        }
+        SystemMessageType::ProfileUpdated | SystemMessageType::KeyCommit => Ok(()),
+    }
+}
+
+impl GroupMetadata {
+    pub fn is_member(&self, pubkey: &str) -> bool {
+        self.members.iter().any(|member| member == pubkey)
+    }
+
+    /// Returns whether the identity is or has been a member of the group.
+    pub fn has_member(&self, identity: &str) -> bool {
+        self.membership
+            .iter()
+            .any(|membership| membership.identity == identity)
+    }
+
+    /// Returns whether the message was posted while the identity was a member.
+    pub fn can_read(&self, identity: &str, message_id: u32) -> bool {
+        self.membership
+            .iter()
+            .any(|membership| membership.identity == identity && membership.contains(message_id))
+    }
+
+    pub fn is_admin(&self, pubkey: &str) -> bool {
+        self.admins.iter().any(|admin| admin == pubkey)
+    }
+
+    /// Returns whether the identity has a join request waiting for an admin.
+    pub fn is_pending(&self, identity: &str) -> bool {
+        self.pending
+            .iter()
+            .any(|request| request.identity == identity)
+    }
+
+    fn is_last_admin(&self, pubkey: &str) -> bool {
+        self.admins.len() == 1 && self.is_admin(pubkey)
+    }
+}
@@ -0,0 +1,632 @@
+/*
+ * SPDX-FileCopyrightText: 2020 Stalwart Labs LLC <hello@stalw.art>
+ *
+ * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
+ */
+
+use std::borrow::Cow;
+
+use common::{
+    KV_ESMP_REPLAY, KV_RATE_LIMIT_ESMP, config::esmp::EsmpSpamAction, listener::SessionStream,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use store::write::now;
+
+use crate::{
+    MAX_ID_LENGTH, Session,
+    blob::persist::EsmpBlobStore,
+    canonical::{canonicalize, parse_json, signing_input},
+    crypto::{is_public_key, verify_signature},
+    gateway::is_email_address,
+    group::persist::GroupStore,
+    identity::{EsmpIdentity, address_to_email},
+    keyset::persist::KeySetStore,
+    request::{EsmpRequest, Hello},
+    response::{ErrorCode, Response},
+    spam::EsmpSpamFilter,
+    system::SystemMessageType,
+};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EsmpMessage {
+    pub version: u32,
+    pub id: String,
+    pub timestamp: u64,
+    pub from: Option<String>, // Sender address, must be bound to sender_pubkey
+    pub to: Vec<String>,
+    pub cc: Option<Vec<String>>,
+    pub group_id: Option<String>,
+    pub r#type: String,
+    pub subtype: Option<String>, // For system messages
+    pub actor: Option<String>,   // For system messages
+    pub target: Option<String>,  // For system messages
+    pub ref_id: Option<String>,  // Message edited, retracted or reacted to
+    pub reply_to: Option<String>,
+    pub body: Value,
+    pub signature: String,
+    pub sender_pubkey: String,
+    // Optional system message metadata
+    pub new_name: Option<String>,
+    pub new_description: Option<String>,
+    pub new_dp_url: Option<String>,
+}
+
+impl EsmpMessage {
+    /// Returns the unique recipients listed in `to` and `cc`.
+    pub fn recipients(&self) -> Vec<&str> {
+        let mut recipients: Vec<&str> = Vec::with_capacity(self.to.len());
+        for recipient in self
+            .to
+            .iter()
+            .chain(self.cc.iter().flatten())
+            .map(|recipient| recipient.trim())
+        {
+            if !recipient.is_empty() && !recipients.contains(&recipient) {
+                recipients.push(recipient);
+            }
+        }
+        recipients
+    }
+
+    /// Checks that every recipient is a key or an address, and that there
+    /// are no more than `max_recipients` of them.
+    pub fn validate_recipients(&self, max_recipients: usize) -> Result<(), &'static str> {
+        let recipients = self.recipients();
+        if recipients.len() > max_recipients {
+            Err("Message exceeds the maximum number of recipients")
+        } else if recipients.iter().any(|recipient| {
+            !is_public_key(recipient)
+                && address_to_email(recipient).is_none()
+                && !is_email_address(recipient)
+        }) {
+            Err("Recipients must be public keys or addresses")
+        } else {
+            Ok(())
+        }
+    }
+
+    pub fn validate_system_message(&self) -> Result<SystemMessageType, &'static str> {
+        // System messages must have a subtype
+        let sys_type = self
+            .subtype
+            .as_deref()
+            .ok_or("System messages must have a subtype")
+            .and_then(|subtype| {
+                SystemMessageType::parse(subtype).ok_or("Invalid system message subtype")
+            })?;
+
+        if sys_type.requires_group() && self.group_id.is_none() {
+            return Err("This system message type requires a group_id");
+        }
+        if sys_type.requires_actor() && self.actor.is_none() {
+            return Err("This system message type requires an actor");
+        }
+        if sys_type.requires_target() && self.target.is_none() {
+            return Err("This system message type requires a target");
+        }
+
+        // Validate metadata based on subtype
+        match sys_type {
+            SystemMessageType::GroupRenamed if self.new_name.is_none() => {
+                Err("group_renamed requires new_name")
+            }
+            SystemMessageType::DescriptionUpdated if self.new_description.is_none() => {
+                Err("description_updated requires new_description")
+            }
+            SystemMessageType::DpUpdated if self.new_dp_url.is_none() => {
+                Err("dp_updated requires new_dp_url")
+            }
+            SystemMessageType::Invited if self.invite_expires().is_none() => {
+                Err("invited requires expires")
+            }
+            SystemMessageType::InviteRevoked if self.invite().is_none() => {
+                Err("invite_revoked requires invite")
+            }
+            SystemMessageType::GroupCreated
+                if self.body.get("join_policy").is_some() && self.join_policy().is_none() =>
+            {
+                Err("Invalid join_policy")
+            }
+            SystemMessageType::JoinPolicyUpdated if self.join_policy().is_none() => {
+                Err("join_policy_updated requires a valid join_policy")
+            }
+            _ => Ok(sys_type),
+        }
+    }
+}
+
+/// Members shared by every signed object received from a client.
+pub trait SignedObject {
+    fn version(&self) -> u32;
+    fn id(&self) -> &str;
+    fn timestamp(&self) -> u64;
+    fn signature(&self) -> &str;
+    fn sender_pubkey(&self) -> &str;
+}
+
+impl SignedObject for EsmpMessage {
+    fn version(&self) -> u32 {
+        self.version
+    }
+
+    fn id(&self) -> &str {
+        &self.id
+    }
+
+    fn timestamp(&self) -> u64 {
+        self.timestamp
+    }
+
+    fn signature(&self) -> &str {
+        &self.signature
+    }
+
+    fn sender_pubkey(&self) -> &str {
+        &self.sender_pubkey
+    }
+}
+
+impl<T: SessionStream> Session<T> {
+    pub async fn handle_message(&mut self, json: &str) {
+        let value = match parse_json(json.as_bytes()) {
+            Ok(value) => value,
+            Err(err) => {
+                self.write_error(ErrorCode::Parse, None, err.to_string())
+                    .await;
+                return;
+            }
+        };
+
+        match value.get("command").and_then(Value::as_str) {
+            Some("hello") => {
+                match serde_json::from_value::<Hello>(value) {
+                    Ok(hello) => self.handle_hello(hello).await,
+                    Err(err) => {
+                        self.write_error(ErrorCode::Parse, None, err.to_string())
+                            .await
+                    }
+                }
+                return;
+            }
+            _ if self.version.is_none() => {
+                self.write_error(
+                    ErrorCode::BadSequence,
+                    value.get("id").and_then(Value::as_str),
+                    "Send hello before any other command",
+                )
+                .await;
+                return;
+            }
+            _ => (),
+        }
+
+        // Signatures are computed over the canonical form of the received object
+        let signed_bytes = signing_input(&value).unwrap_or_default();
+
+        if value.get("command").is_some() {
+            match serde_json::from_value::<EsmpRequest>(value) {
+                Ok(request) => {
+                    if self.is_session_request(&request).await
+                        && self.verify_signed_object(&request, &signed_bytes).await
+                        && self.is_new_message(&request).await
+                    {
+                        self.handle_request(request).await;
+                    }
+                }
+                Err(err) => {
+                    self.write_error(ErrorCode::Parse, None, err.to_string())
+                        .await;
+                }
+            }
+            return;
+        }
+
+        let contents = canonicalize(&value);
+        let msg = match serde_json::from_value::<EsmpMessage>(value) {
+            Ok(msg) => msg,
+            Err(err) => {
+                self.write_error(ErrorCode::Parse, None, err.to_string())
+                    .await;
+                return;
+            }
+        };
+
+        // Messages must be sent by the key bound to the session
+        let error = match &self.pubkey {
+            _ if self.peer.is_some() => Some((
+                ErrorCode::Forbidden,
+                "Federated sessions can only relay messages",
+            )),
+            Some(pubkey) if *pubkey == msg.sender_pubkey => None,
+            Some(_) => Some((
+                ErrorCode::Forbidden,
+                "Message is not signed by the session key",
+            )),
+            None => Some((ErrorCode::Unauthenticated, "Authentication required")),
+        };
+        if let Some((code, reason)) = error {
+            self.write_error(code, Some(&msg.id), reason).await;
+            return;
+        }
+
+        // Checked before the replay lock so that throttled messages can be resent
+        if !self.is_sender_allowed(&msg).await {
+            return;
+        }
+
+        if !self.verify_signed_object(&msg, &signed_bytes).await {
+            return;
+        }
+
+        let validation = msg
+            .validate_recipients(self.limits.max_recipients)
+            .and_then(|_| match msg.r#type.as_str() {
+                "system" => msg.validate_system_message().map(|_| ()),
+                "encrypted" => msg.validate_envelope().map(|_| ()),
+                _ => msg.attachments().map(|_| ()),
+            })
+            .and_then(|_| msg.validate_references())
+            .and_then(|_| {
+                if self.server.core.esmp.gateway.outbound
+                    && msg.group_id.is_none()
+                    && !msg.email_recipients().is_empty()
+                {
+                    msg.validate_email_delivery()
+                } else {
+                    Ok(())
+                }
+            });
+        if let Err(reason) = validation {
+            trc::event!(
+                Esmp(trc::EsmpEvent::MessageRejected),
+                SpanId = self.session_id,
+                AccountName = msg.sender_pubkey.clone(),
+                Id = msg.id.clone(),
+                Reason = reason,
+            );
+            self.write_error(ErrorCode::InvalidMessage, Some(&msg.id), reason)
+                .await;
+            return;
+        }
+
+        // Claimed sender addresses must list the signing key in the directory
+        if let Some(from) = &msg.from {
+            match self.server.is_address_key(from, &msg.sender_pubkey).await {
+                Ok(true) => (),
+                Ok(false) => {
+                    self.write_error(
+                        ErrorCode::Forbidden,
+                        Some(&msg.id),
+                        "Sender address is not bound to the signing key",
+                    )
+                    .await;
+                    return;
+                }
+                Err(err) => {
+                    trc::error!(err.span_id(self.session_id));
+                    self.write_error(
+                        ErrorCode::ServerFail,
+                        Some(&msg.id),
+                        "Internal server error",
+                    )
+                    .await;
+                    return;
+                }
+            }
+        }
+
+        // Group attachments have to be readable by the members of the group
+        if let Some(group_id) = msg
+            .group_id
+            .as_deref()
+            .filter(|_| !matches!(msg.r#type.as_str(), "system" | "encrypted"))
+        {
+            for hash in msg.attachments().unwrap_or_default() {
+                match self.server.is_group_blob(&hash, group_id).await {
+                    Ok(true) => (),
+                    Ok(false) => {
+                        self.write_error(
+                            ErrorCode::Forbidden,
+                            Some(&msg.id),
+                            "Attachments must be uploaded to the group",
+                        )
+                        .await;
+                        return;
+                    }
+                    Err(err) => {
+                        trc::error!(err.span_id(self.session_id));
+                        self.write_error(
+                            ErrorCode::ServerFail,
+                            Some(&msg.id),
+                            "Internal server error",
+                        )
+                        .await;
+                        return;
+                    }
+                }
+            }
+        }
+
+        // Direct messages are screened for spam, group senders are admitted by the group admins
+        let spam_score = if msg.group_id.is_none() {
+            self.server.esmp_spam_classify(&msg, self.session_id).await
+        } else {
+            None
+        };
+        if spam_score.is_some() && self.server.core.esmp.spam.action == EsmpSpamAction::Reject {
+            let reason = "Message was classified as spam";
+            trc::event!(
+                Esmp(trc::EsmpEvent::MessageRejected),
+                SpanId = self.session_id,
+                AccountName = msg.sender_pubkey.clone(),
+                Id = msg.id.clone(),
+                Reason = reason,
+            );
+            self.write_error(ErrorCode::Forbidden, Some(&msg.id), reason)
+                .await;
+            return;
+        }
+
+        // Ids are only remembered once the message is valid, and released
+        // again if it could not be stored
+        if !self.is_new_message(&msg).await {
+            return;
+        }
+
+        let size = contents.len();
+        let result = if let Some(group_id) = &msg.group_id {
+            self.server
+                .persist_group_message(group_id, &msg, contents)
+                .await
+                .map(|result| {
+                    result
+                        .map(|message_id| (vec![message_id], vec![]))
+                        .map_err(Cow::from)
+                })
+        } else {
+            self.route_direct_message(&msg, contents, spam_score).await
+        };
+
+        match result {
+            Ok(Err(reason)) => {
+                self.release_message_id(&msg).await;
+                trc::event!(
+                    Esmp(trc::EsmpEvent::MessageRejected),
+                    SpanId = self.session_id,
+                    AccountName = msg.sender_pubkey.clone(),
+                    Id = msg.id.clone(),
+                    Reason = reason.clone(),
+                );
+                self.write_error(ErrorCode::Forbidden, Some(&msg.id), reason)
+                    .await;
+            }
+            Ok(Ok((message_ids, queued))) => {
+                trc::event!(
+                    Esmp(trc::EsmpEvent::MessageAccepted),
+                    SpanId = self.session_id,
+                    AccountName = msg.sender_pubkey.clone(),
+                    Id = msg.id.clone(),
+                    Type = msg.r#type.clone(),
+                    Size = size,
+                    Total = message_ids.len() + queued.len(),
+                );
+                self.write_bytes(
+                    Response::Ack {
+                        id: &msg.id,
+                        message_ids,
+                        queued,
+                    }
+                    .serialize(),
+                )
+                .await
+                .ok();
+            }
+            Err(err) => {
+                self.release_message_id(&msg).await;
+                trc::error!(err.span_id(self.session_id));
+                self.write_error(
+                    ErrorCode::ServerFail,
+                    Some(&msg.id),
+                    "Internal server error",
+                )
+                .await;
+            }
+        }
+    }
+
+    async fn verify_signed_object(
+        &mut self,
+        object: &impl SignedObject,
+        signed_bytes: &[u8],
+    ) -> bool {
+        match self.check_signed_object(object, signed_bytes).await {
+            Ok(()) => true,
+            Err((code, reason)) => {
+                let id = Some(object.id()).filter(|_| code != ErrorCode::InvalidRequest);
+                self.write_error(code, id, reason).await;
+                false
+            }
+        }
+    }
+
+    /// Checks the version, signature, signing key and timestamp of a signed
+    /// object. Replays are detected separately by `lock_message_id`.
+    pub(crate) async fn check_signed_object(
+        &self,
+        object: &impl SignedObject,
+        signed_bytes: &[u8],
+    ) -> Result<(), (ErrorCode, &'static str)> {
+        if object.id().is_empty() || object.id().len() > MAX_ID_LENGTH {
+            return Err((ErrorCode::InvalidRequest, "Invalid message id"));
+        }
+
+        if Some(object.version()) != self.version {
+            return Err((
+                ErrorCode::UnsupportedVersion,
+                "Version does not match the negotiated protocol version",
+            ));
+        }
+
+        if !verify_signature(object.sender_pubkey(), object.signature(), signed_bytes) {
+            trc::event!(
+                Esmp(trc::EsmpEvent::SignatureFailure),
+                SpanId = self.session_id,
+                AccountName = object.sender_pubkey().to_string(),
+                Id = object.id().to_string(),
+            );
+            return Err((
+                ErrorCode::InvalidSignature,
+                "Rejected unsigned or tampered ESMP message",
+            ));
+        }
+
+        // Keys are no longer accepted once revoked
+        match self.server.key_set(object.sender_pubkey()).await {
+            Ok(Some(key_set)) if !key_set.is_active(object.sender_pubkey(), object.timestamp()) => {
+                return Err((ErrorCode::KeyRevoked, "Signing key has been revoked"));
+            }
+            Ok(_) => (),
+            Err(err) => {
+                trc::error!(err.span_id(self.session_id).caused_by(trc::location!()));
+                return Err((ErrorCode::ServerFail, "Internal server error"));
+            }
+        }
+
+        // Reject stale or future-dated messages
+        if now().abs_diff(object.timestamp()) > self.server.core.esmp.replay_window.as_secs() {
+            return Err((
+                ErrorCode::Expired,
+                "Message timestamp outside of acceptance window",
+            ));
+        }
+
+        Ok(())
+    }
+
+    async fn is_new_message(&mut self, object: &impl SignedObject) -> bool {
+        match self.lock_message_id(object).await {
+            Ok(()) => true,
+            Err((code, reason)) => {
+                self.write_error(code, Some(object.id()), reason).await;
+                false
+            }
+        }
+    }
+
+    /// Remembers the id of a signed object for as long as its timestamp is
+    /// acceptable, rejecting ids already received from the same sender.
+    pub(crate) async fn lock_message_id(
+        &self,
+        object: &impl SignedObject,
+    ) -> Result<(), (ErrorCode, &'static str)> {
+        let window = self.server.core.esmp.replay_window.as_secs();
+        match self
+            .server
+            .in_memory_store()
+            .try_lock(KV_ESMP_REPLAY, &replay_key(object), window * 2)
+            .await
+        {
+            Ok(true) => Ok(()),
+            Ok(false) => {
+                trc::event!(
+                    Esmp(trc::EsmpEvent::Replay),
+                    SpanId = self.session_id,
+                    AccountName = object.sender_pubkey().to_string(),
+                    Id = object.id().to_string(),
+                );
+                Err((ErrorCode::Replay, "Message has already been received"))
+            }
+            Err(err) => {
+                trc::error!(err.span_id(self.session_id).caused_by(trc::location!()));
+                Err((ErrorCode::ServerFail, "Internal server error"))
+            }
+        }
+    }
+
+    /// Forgets the id of a message that was not stored, so that it can be
+    /// sent again.
+    pub(crate) async fn release_message_id(&self, object: &impl SignedObject) {
+        if let Err(err) = self
+            .server
+            .in_memory_store()
+            .remove_lock(KV_ESMP_REPLAY, &replay_key(object))
+            .await
+        {
+            trc::error!(err.span_id(self.session_id).caused_by(trc::location!()));
+        }
+    }
+
+    async fn is_sender_allowed(&mut self, msg: &EsmpMessage) -> bool {
+        let Some(rate) = &self.limits.rate_sender else {
+            return true;
+        };
+
+        match self
+            .server
+            .in_memory_store()
+            .is_rate_allowed(
+                KV_RATE_LIMIT_ESMP,
+                msg.sender_pubkey.as_bytes(),
+                rate,
+                false,
+            )
+            .await
+        {
+            Ok(None) => true,
+            Ok(Some(retry_in)) => {
+                trc::event!(
+                    Limit(trc::LimitEvent::TooManyRequests),
+                    SpanId = self.session_id,
+                    AccountName = msg.sender_pubkey.clone(),
+                );
+                self.write_error(
+                    ErrorCode::RateLimited,
+                    Some(&msg.id),
+                    format!("Sending quota exceeded, retry in {retry_in} seconds"),
+                )
+                .await;
+                false
+            }
+            Err(err) => {
+                trc::error!(err.span_id(self.session_id).caused_by(trc::location!()));
+                self.write_error(
+                    ErrorCode::ServerFail,
+                    Some(&msg.id),
+                    "Internal server error",
+                )
+                .await;
+                false
+            }
+        }
+    }
+
+    pub async fn write_error(
+        &mut self,
+        code: ErrorCode,
+        id: Option<&str>,
+        reason: impl Into<Cow<'static, str>>,
+    ) {
+        let reason = reason.into();
+
+        trc::event!(
+            Esmp(trc::EsmpEvent::Error),
+            SpanId = self.session_id,
+            Code = code.as_str(),
+            Id = id.map(|id| id.to_string()),
+            Reason = reason.clone(),
+        );
+
+        self.write_bytes(Response::Error { code, id, reason }.serialize())
+            .await
+            .ok();
+    }
+}
+
+pub(crate) fn replay_key(object: &impl SignedObject) -> Vec<u8> {
+    let mut key = Vec::with_capacity(object.sender_pubkey().len() + object.id().len() + 1);
+    key.extend_from_slice(object.sender_pubkey().as_bytes());
+    key.push(0);
+    key.extend_from_slice(object.id().as_bytes());
+    key
+}
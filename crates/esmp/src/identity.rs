@@ -0,0 +1,83 @@
+/*
+ * SPDX-FileCopyrightText: 2020 Stalwart Labs LLC <hello@stalw.art>
+ *
+ * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
+ */
+
+use std::future::Future;
+
+use common::Server;
+use directory::{QueryBy, Type};
+use trc::AddContext;
+
+use crate::keyset::persist::KeySetStore;
+
+pub trait EsmpIdentity: Sync + Send {
+    fn address_keys(&self, address: &str) -> impl Future<Output = trc::Result<Vec<String>>> + Send;
+
+    fn account_keys(
+        &self,
+        account_id: u32,
+    ) -> impl Future<Output = trc::Result<Vec<String>>> + Send;
+
+    fn is_address_key(
+        &self,
+        address: &str,
+        pubkey: &str,
+    ) -> impl Future<Output = trc::Result<bool>> + Send;
+}
+
+impl EsmpIdentity for Server {
+    async fn address_keys(&self, address: &str) -> trc::Result<Vec<String>> {
+        let Some(email) = address_to_email(address) else {
+            return Ok(vec![]);
+        };
+        if let Some(account_id) = self
+            .directory()
+            .email_to_id(&email)
+            .await
+            .caused_by(trc::location!())?
+        {
+            self.account_keys(account_id).await
+        } else {
+            Ok(vec![])
+        }
+    }
+
+    async fn account_keys(&self, account_id: u32) -> trc::Result<Vec<String>> {
+        Ok(self
+            .directory()
+            .query(QueryBy::Id(account_id), false)
+            .await
+            .caused_by(trc::location!())?
+            .filter(|principal| principal.typ() == Type::Individual)
+            .map(|principal| principal.esmp_keys().to_vec())
+            .unwrap_or_default())
+    }
+
+    async fn is_address_key(&self, address: &str, pubkey: &str) -> trc::Result<bool> {
+        let keys = self.address_keys(address).await?;
+        if keys.iter().any(|key| key == pubkey) {
+            return Ok(true);
+        }
+
+        // Device keys act for the addresses bound to their identity
+        let identity = self.resolve_identity(pubkey).await?;
+        Ok(identity != pubkey && keys.contains(&identity))
+    }
+}
+
+/// Maps an ESMP address (`user#domain`) to the e-mail address of the
+/// directory principal that owns it.
+pub fn address_to_email(address: &str) -> Option<String> {
+    let (local, domain) = address.trim().split_once('#')?;
+    if !local.is_empty()
+        && !domain.is_empty()
+        && !local.contains('@')
+        && !domain.contains(['@', '#'])
+    {
+        Some(format!("{local}@{domain}").to_lowercase())
+    } else {
+        None
+    }
+}
@@ -0,0 +1,109 @@
+/*
+ * SPDX-FileCopyrightText: 2020 Stalwart Labs LLC <hello@stalw.art>
+ *
+ * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
+ */
+
+use common::storage::index::{IndexValue, IndexableAndSerializableObject, IndexableObject};
+use jmap_proto::types::collection::SyncCollection;
+use store::write::TagValue;
+
+use crate::{IDX_ACKED, IDX_INBOX, IDX_MESSAGE_ID, IDX_QUARANTINED, IDX_RECIPIENT};
+
+use super::{ArchivedInbox, ArchivedInboxMessage, Inbox, InboxMessage};
+
+impl IndexableObject for Inbox {
+    fn index_values(&self) -> impl Iterator<Item = IndexValue<'_>> {
+        [
+            IndexValue::Index {
+                field: IDX_RECIPIENT,
+                value: self.recipient.as_str().into(),
+            },
+            IndexValue::LogContainer {
+                sync_collection: SyncCollection::EsmpInbox.into(),
+            },
+        ]
+        .into_iter()
+    }
+}
+
+impl IndexableAndSerializableObject for Inbox {
+    fn is_versioned() -> bool {
+        false
+    }
+}
+
+impl IndexableObject for &ArchivedInbox {
+    fn index_values(&self) -> impl Iterator<Item = IndexValue<'_>> {
+        [
+            IndexValue::Index {
+                field: IDX_RECIPIENT,
+                value: self.recipient.as_str().into(),
+            },
+            IndexValue::LogContainer {
+                sync_collection: SyncCollection::EsmpInbox.into(),
+            },
+        ]
+        .into_iter()
+    }
+}
+
+// Inbox messages are logged in the change log of their inbox when persisted
+impl IndexableObject for InboxMessage {
+    fn index_values(&self) -> impl Iterator<Item = IndexValue<'_>> {
+        [
+            IndexValue::Index {
+                field: IDX_INBOX,
+                value: self.inbox_id.into(),
+            },
+            IndexValue::Index {
+                field: IDX_MESSAGE_ID,
+                value: self.message_id.as_str().into(),
+            },
+            IndexValue::Tag {
+                field: IDX_ACKED,
+                value: state_tag(self.acked),
+            },
+            IndexValue::Tag {
+                field: IDX_QUARANTINED,
+                value: state_tag(self.quarantined),
+            },
+        ]
+        .into_iter()
+    }
+}
+
+impl IndexableAndSerializableObject for InboxMessage {
+    fn is_versioned() -> bool {
+        false
+    }
+}
+
+impl IndexableObject for &ArchivedInboxMessage {
+    fn index_values(&self) -> impl Iterator<Item = IndexValue<'_>> {
+        [
+            IndexValue::Index {
+                field: IDX_INBOX,
+                value: self.inbox_id.into(),
+            },
+            IndexValue::Index {
+                field: IDX_MESSAGE_ID,
+                value: self.message_id.as_str().into(),
+            },
+            IndexValue::Tag {
+                field: IDX_ACKED,
+                value: state_tag(self.acked),
+            },
+            IndexValue::Tag {
+                field: IDX_QUARANTINED,
+                value: state_tag(self.quarantined),
+            },
+        ]
+        .into_iter()
+    }
+}
+
+// Unread and quarantined messages are looked up by these flags
+fn state_tag(is_set: bool) -> Vec<TagValue> {
+    if is_set { vec![().into()] } else { vec![] }
+}
@@ -0,0 +1,45 @@
+/*
+ * SPDX-FileCopyrightText: 2020 Stalwart Labs LLC <hello@stalw.art>
+ *
+ * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
+ */
+
+pub mod index;
+pub mod persist;
+
+#[derive(
+    rkyv::Archive, rkyv::Deserialize, rkyv::Serialize, Debug, Default, Clone, PartialEq, Eq,
+)]
+#[rkyv(derive(Debug))]
+pub struct Inbox {
+    pub recipient: String,
+    pub created_at: u64,
+}
+
+#[derive(rkyv::Archive, rkyv::Deserialize, rkyv::Serialize, Debug, Default, Clone, PartialEq)]
+#[rkyv(derive(Debug))]
+pub struct InboxMessage {
+    pub inbox_id: u32,
+    pub message_id: String,
+    pub sender: String,
+    pub received_at: u64,
+    pub acked: bool,
+    pub quarantined: bool,
+    pub spam_score: Option<f64>,
+    pub trained: Option<bool>,
+    pub contents: String,
+}
+
+#[derive(Debug, Default)]
+pub struct InboxPage {
+    pub messages: Vec<(u32, InboxMessage)>,
+    pub has_more: bool,
+}
+
+#[derive(Debug, Default)]
+pub struct InboxChanges {
+    pub state: u64,
+    pub created: Vec<u32>,
+    pub updated: Vec<u32>,
+    pub destroyed: Vec<u32>,
+}
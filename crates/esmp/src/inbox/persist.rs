@@ -0,0 +1,723 @@
+/*
+ * SPDX-FileCopyrightText: 2020 Stalwart Labs LLC <hello@stalw.art>
+ *
+ * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
+ */
+
+use std::{future::Future, time::Duration};
+
+use common::{
+    ESMP_MAX_INBOXES, KV_LOCK_ESMP_INBOX, Server,
+    config::esmp::EsmpSpamAction,
+    esmp_inbox_account_id,
+    ipc::{EsmpStateChange, EsmpStateType},
+    storage::index::ObjectIndexBuilder,
+};
+use jmap_proto::types::collection::{Collection, SyncCollection};
+use store::{
+    query::{
+        Filter,
+        log::{Change, Query},
+    },
+    rand::Rng,
+    write::{BatchBuilder, now},
+};
+use trc::AddContext;
+
+use crate::{
+    ESMP_ACCOUNT_ID, IDX_ACKED, IDX_INBOX, IDX_MESSAGE_ID, IDX_QUARANTINED, IDX_RECIPIENT,
+    MAX_RETRIES,
+    blob::{BlobShare, persist::share_blobs},
+    handler::EsmpMessage,
+    identity::{EsmpIdentity, address_to_email},
+    keyset::persist::KeySetStore,
+    reference::ReferenceType,
+    spam::EsmpSpamFilter,
+    webpush::persist::EsmpPushStore,
+};
+
+use super::{Inbox, InboxChanges, InboxMessage, InboxPage};
+
+const INBOX_LOCK_EXPIRY: u64 = 30;
+
+pub trait InboxStore: Sync + Send {
+    fn inbox_document_id(
+        &self,
+        recipient: &str,
+    ) -> impl Future<Output = trc::Result<Option<u32>>> + Send;
+
+    fn get_or_create_inbox(&self, recipient: &str)
+    -> impl Future<Output = trc::Result<u32>> + Send;
+
+    fn deliver_to_inboxes(
+        &self,
+        msg: &EsmpMessage,
+        contents: String,
+        spam_score: Option<f64>,
+    ) -> impl Future<Output = trc::Result<Vec<u32>>> + Send;
+
+    fn fetch_inbox(
+        &self,
+        recipient: &str,
+        after: Option<u32>,
+        limit: usize,
+        include_acked: bool,
+        quarantined: bool,
+    ) -> impl Future<Output = trc::Result<InboxPage>> + Send;
+
+    fn ack_inbox_messages(
+        &self,
+        recipient: &str,
+        ids: &[u32],
+    ) -> impl Future<Output = trc::Result<Vec<u32>>> + Send;
+
+    fn delete_inbox_messages(
+        &self,
+        recipient: &str,
+        ids: &[u32],
+    ) -> impl Future<Output = trc::Result<Vec<u32>>> + Send;
+
+    fn train_inbox_messages(
+        &self,
+        recipient: &str,
+        ids: &[u32],
+        is_spam: bool,
+        span_id: u64,
+    ) -> impl Future<Output = trc::Result<Vec<u32>>> + Send;
+
+    fn inbox_changes(
+        &self,
+        recipient: &str,
+        since: Option<u64>,
+    ) -> impl Future<Output = trc::Result<Option<InboxChanges>>> + Send;
+}
+
+impl InboxStore for Server {
+    async fn inbox_document_id(&self, recipient: &str) -> trc::Result<Option<u32>> {
+        self.store()
+            .filter(
+                ESMP_ACCOUNT_ID,
+                Collection::EsmpInbox,
+                vec![Filter::eq(IDX_RECIPIENT, recipient.as_bytes().to_vec())],
+            )
+            .await
+            .caused_by(trc::location!())
+            .map(|result| result.results.min())
+    }
+
+    async fn get_or_create_inbox(&self, recipient: &str) -> trc::Result<u32> {
+        if let Some(inbox_id) = self.inbox_document_id(recipient).await? {
+            return Ok(inbox_id);
+        }
+
+        // Inboxes are created on first use, concurrent attempts to create
+        // the same inbox are serialized so only one of them succeeds
+        let mut try_count = 0;
+        while !self
+            .in_memory_store()
+            .try_lock(KV_LOCK_ESMP_INBOX, recipient.as_bytes(), INBOX_LOCK_EXPIRY)
+            .await
+            .caused_by(trc::location!())?
+        {
+            if try_count >= MAX_RETRIES {
+                return Err(trc::StoreEvent::AssertValueFailed
+                    .into_err()
+                    .details("Inbox is being created by another request")
+                    .caused_by(trc::location!()));
+            }
+            let backoff = store::rand::rng().random_range(50..=300);
+            tokio::time::sleep(Duration::from_millis(backoff)).await;
+            try_count += 1;
+        }
+
+        let result = create_inbox(self, recipient).await;
+        if let Err(err) = self
+            .in_memory_store()
+            .remove_lock(KV_LOCK_ESMP_INBOX, recipient.as_bytes())
+            .await
+        {
+            trc::error!(err.details("Failed to release inbox lock."));
+        }
+        result
+    }
+
+    async fn deliver_to_inboxes(
+        &self,
+        msg: &EsmpMessage,
+        contents: String,
+        spam_score: Option<f64>,
+    ) -> trc::Result<Vec<u32>> {
+        let quarantined =
+            spam_score.is_some() && self.core.esmp.spam.action == EsmpSpamAction::Quarantine;
+
+        // Messages addressed to a device key or to a local address are
+        // delivered to the identities of the keys, where sessions are notified
+        let mut recipients: Vec<String> = Vec::with_capacity(msg.to.len());
+        for recipient in msg.recipients() {
+            let mut keys = if address_to_email(recipient).is_some() {
+                self.address_keys(recipient).await?
+            } else {
+                vec![]
+            };
+            if keys.is_empty() {
+                keys.push(recipient.to_string());
+            }
+            for key in keys {
+                let recipient = self.resolve_identity(&key).await?;
+                if !recipients.contains(&recipient) {
+                    recipients.push(recipient);
+                }
+            }
+        }
+        if recipients.is_empty() {
+            return Ok(vec![]);
+        }
+
+        // Retractions remove the retracted message from the inboxes it was
+        // delivered to, provided that it was sent by the same identity
+        let retraction = match (msg.reference_type(), &msg.ref_id) {
+            (Some(ReferenceType::Retract), Some(ref_id)) => {
+                Some((ref_id, self.resolve_identity(&msg.sender_pubkey).await?))
+            }
+            _ => None,
+        };
+
+        let mut inbox_ids = Vec::with_capacity(recipients.len());
+        for recipient in &recipients {
+            inbox_ids.push(self.get_or_create_inbox(recipient).await?);
+        }
+        let attachments = msg.attachments().unwrap_or_default();
+        let sender = if !attachments.is_empty() {
+            self.resolve_identity(&msg.sender_pubkey).await?
+        } else {
+            String::new()
+        };
+
+        let mut try_count = 0;
+        loop {
+            let mut batch = BatchBuilder::new();
+            let mut message_ids = Vec::with_capacity(inbox_ids.len());
+
+            for &inbox_id in &inbox_ids {
+                if let Some((ref_id, author)) = &retraction {
+                    let retracted_ids = self
+                        .store()
+                        .filter(
+                            ESMP_ACCOUNT_ID,
+                            Collection::EsmpInboxMessage,
+                            vec![
+                                Filter::eq(IDX_INBOX, inbox_id.to_be_bytes().to_vec()),
+                                Filter::eq(IDX_MESSAGE_ID, ref_id.as_bytes().to_vec()),
+                            ],
+                        )
+                        .await
+                        .caused_by(trc::location!())?
+                        .results;
+                    for retracted_id in retracted_ids {
+                        let Some(message_) = self
+                            .get_archive(
+                                ESMP_ACCOUNT_ID,
+                                Collection::EsmpInboxMessage,
+                                retracted_id,
+                            )
+                            .await?
+                        else {
+                            continue;
+                        };
+                        let message = message_
+                            .to_unarchived::<InboxMessage>()
+                            .caused_by(trc::location!())?;
+                        if self.resolve_identity(&message.inner.sender).await? == *author {
+                            batch
+                                .with_account_id(ESMP_ACCOUNT_ID)
+                                .with_collection(Collection::EsmpInboxMessage)
+                                .delete_document(retracted_id)
+                                .custom(ObjectIndexBuilder::<_, ()>::new().with_current(message))
+                                .caused_by(trc::location!())?;
+                            log_inbox_change(&mut batch, inbox_id, InboxChange::Delete)
+                                .commit_point();
+                        }
+                    }
+                }
+
+                let message_id = self
+                    .store()
+                    .assign_document_ids(ESMP_ACCOUNT_ID, Collection::EsmpInboxMessage, 1)
+                    .await
+                    .caused_by(trc::location!())?;
+                batch
+                    .with_account_id(ESMP_ACCOUNT_ID)
+                    .with_collection(Collection::EsmpInboxMessage)
+                    .create_document(message_id)
+                    .custom(
+                        ObjectIndexBuilder::<(), _>::new().with_changes(InboxMessage {
+                            inbox_id,
+                            message_id: msg.id.clone(),
+                            sender: msg.sender_pubkey.clone(),
+                            received_at: now(),
+                            acked: false,
+                            quarantined,
+                            spam_score,
+                            trained: None,
+                            contents: contents.clone(),
+                        }),
+                    )
+                    .caused_by(trc::location!())?;
+                log_inbox_change(&mut batch, inbox_id, InboxChange::Insert).commit_point();
+                message_ids.push(message_id);
+            }
+
+            // Attachments are readable by the recipients of the uploader
+            share_blobs(
+                self,
+                &mut batch,
+                &attachments,
+                BlobShare::Direct {
+                    sender: &sender,
+                    recipients: &recipients,
+                },
+            )
+            .await?;
+
+            match self.commit_batch(batch).await {
+                Ok(assigned) => {
+                    trc::event!(
+                        Esmp(trc::EsmpEvent::InboxDelivery),
+                        AccountName = msg.sender_pubkey.clone(),
+                        Id = msg.id.clone(),
+                        Total = recipients.len(),
+                    );
+
+                    // Wake up the sessions and the offline devices of the
+                    // recipients, unless the message is held in quarantine
+                    if !quarantined {
+                        let mut change_id = 0;
+                        for inbox_id in &inbox_ids {
+                            let account_id = esmp_inbox_account_id(*inbox_id);
+                            let state_change = EsmpStateChange {
+                                change_id: assigned.last_change_id(account_id).unwrap_or_default(),
+                                state_type: EsmpStateType::InboxMessage,
+                            };
+                            change_id = change_id.max(state_change.change_id);
+                            if let Err(err) = self
+                                .notify_esmp_state(account_id, Vec::new(), state_change)
+                                .await
+                            {
+                                trc::error!(err.details("Failed to notify sessions"));
+                            }
+                        }
+                        let state_change = EsmpStateChange {
+                            change_id,
+                            state_type: EsmpStateType::InboxMessage,
+                        };
+                        if let Err(err) = self
+                            .notify_push_subscribers(&recipients, &msg.sender_pubkey, state_change)
+                            .await
+                        {
+                            trc::error!(err.details("Failed to notify push subscribers"));
+                        }
+                    }
+
+                    return Ok(message_ids);
+                }
+                Err(err) if err.is_assertion_failure() && try_count < MAX_RETRIES => {
+                    let backoff = store::rand::rng().random_range(50..=300);
+                    tokio::time::sleep(Duration::from_millis(backoff)).await;
+                    try_count += 1;
+                }
+                Err(err) => {
+                    return Err(err.caused_by(trc::location!()));
+                }
+            }
+        }
+    }
+
+    async fn fetch_inbox(
+        &self,
+        recipient: &str,
+        after: Option<u32>,
+        limit: usize,
+        include_acked: bool,
+        quarantined: bool,
+    ) -> trc::Result<InboxPage> {
+        let Some(inbox_id) = self.inbox_document_id(recipient).await? else {
+            return Ok(InboxPage::default());
+        };
+
+        // Acknowledged and quarantined messages are looked up in their indexes
+        let mut filters = vec![Filter::eq(IDX_INBOX, inbox_id.to_be_bytes().to_vec())];
+        if !include_acked {
+            filters.extend([
+                Filter::Not,
+                Filter::is_in_bitmap(IDX_ACKED, ()),
+                Filter::End,
+            ]);
+        }
+        if quarantined {
+            filters.push(Filter::is_in_bitmap(IDX_QUARANTINED, ()));
+        } else {
+            filters.extend([
+                Filter::Not,
+                Filter::is_in_bitmap(IDX_QUARANTINED, ()),
+                Filter::End,
+            ]);
+        }
+        let message_ids = self
+            .store()
+            .filter(ESMP_ACCOUNT_ID, Collection::EsmpInboxMessage, filters)
+            .await
+            .caused_by(trc::location!())?
+            .results;
+
+        let mut page = InboxPage::default();
+        for message_id in message_ids
+            .into_iter()
+            .filter(|id| after.is_none_or(|after| *id > after))
+        {
+            if page.messages.len() == limit {
+                page.has_more = true;
+                break;
+            }
+
+            if let Some(archive) = self
+                .get_archive(ESMP_ACCOUNT_ID, Collection::EsmpInboxMessage, message_id)
+                .await?
+            {
+                page.messages.push((
+                    message_id,
+                    archive
+                        .deserialize::<InboxMessage>()
+                        .caused_by(trc::location!())?,
+                ));
+            }
+        }
+
+        Ok(page)
+    }
+
+    async fn ack_inbox_messages(&self, recipient: &str, ids: &[u32]) -> trc::Result<Vec<u32>> {
+        let Some(inbox_id) = self.inbox_document_id(recipient).await? else {
+            return Ok(vec![]);
+        };
+        let mut try_count = 0;
+
+        loop {
+            let mut batch = BatchBuilder::new();
+            let mut acked = Vec::with_capacity(ids.len());
+            batch
+                .with_account_id(ESMP_ACCOUNT_ID)
+                .with_collection(Collection::EsmpInboxMessage);
+
+            for &message_id in ids {
+                let Some(message_) = self
+                    .get_archive(ESMP_ACCOUNT_ID, Collection::EsmpInboxMessage, message_id)
+                    .await?
+                else {
+                    continue;
+                };
+                let message = message_
+                    .to_unarchived::<InboxMessage>()
+                    .caused_by(trc::location!())?;
+                if message.inner.inbox_id.to_native() != inbox_id {
+                    continue;
+                }
+
+                if !message.inner.acked {
+                    let mut new_message = message
+                        .deserialize::<InboxMessage>()
+                        .caused_by(trc::location!())?;
+                    new_message.acked = true;
+                    batch
+                        .with_account_id(ESMP_ACCOUNT_ID)
+                        .update_document(message_id)
+                        .custom(
+                            ObjectIndexBuilder::new()
+                                .with_current(message)
+                                .with_changes(new_message),
+                        )
+                        .caused_by(trc::location!())?;
+                    log_inbox_change(&mut batch, inbox_id, InboxChange::Update).commit_point();
+                }
+                acked.push(message_id);
+            }
+
+            if batch.is_empty() {
+                return Ok(acked);
+            }
+
+            match self.commit_batch(batch).await {
+                Ok(_) => return Ok(acked),
+                Err(err) if err.is_assertion_failure() && try_count < MAX_RETRIES => {
+                    let backoff = store::rand::rng().random_range(50..=300);
+                    tokio::time::sleep(Duration::from_millis(backoff)).await;
+                    try_count += 1;
+                }
+                Err(err) => {
+                    return Err(err.caused_by(trc::location!()));
+                }
+            }
+        }
+    }
+
+    async fn delete_inbox_messages(&self, recipient: &str, ids: &[u32]) -> trc::Result<Vec<u32>> {
+        let Some(inbox_id) = self.inbox_document_id(recipient).await? else {
+            return Ok(vec![]);
+        };
+        let mut try_count = 0;
+
+        loop {
+            let mut batch = BatchBuilder::new();
+            let mut deleted = Vec::with_capacity(ids.len());
+            batch
+                .with_account_id(ESMP_ACCOUNT_ID)
+                .with_collection(Collection::EsmpInboxMessage);
+
+            for &message_id in ids {
+                let Some(message_) = self
+                    .get_archive(ESMP_ACCOUNT_ID, Collection::EsmpInboxMessage, message_id)
+                    .await?
+                else {
+                    continue;
+                };
+                let message = message_
+                    .to_unarchived::<InboxMessage>()
+                    .caused_by(trc::location!())?;
+                if message.inner.inbox_id.to_native() != inbox_id {
+                    continue;
+                }
+
+                batch
+                    .with_account_id(ESMP_ACCOUNT_ID)
+                    .delete_document(message_id)
+                    .custom(ObjectIndexBuilder::<_, ()>::new().with_current(message))
+                    .caused_by(trc::location!())?;
+                log_inbox_change(&mut batch, inbox_id, InboxChange::Delete).commit_point();
+                deleted.push(message_id);
+            }
+
+            if batch.is_empty() {
+                return Ok(deleted);
+            }
+
+            match self.commit_batch(batch).await {
+                Ok(_) => return Ok(deleted),
+                Err(err) if err.is_assertion_failure() && try_count < MAX_RETRIES => {
+                    let backoff = store::rand::rng().random_range(50..=300);
+                    tokio::time::sleep(Duration::from_millis(backoff)).await;
+                    try_count += 1;
+                }
+                Err(err) => {
+                    return Err(err.caused_by(trc::location!()));
+                }
+            }
+        }
+    }
+
+    async fn train_inbox_messages(
+        &self,
+        recipient: &str,
+        ids: &[u32],
+        is_spam: bool,
+        span_id: u64,
+    ) -> trc::Result<Vec<u32>> {
+        let Some(inbox_id) = self.inbox_document_id(recipient).await? else {
+            return Ok(vec![]);
+        };
+        let mut try_count = 0;
+
+        loop {
+            let mut batch = BatchBuilder::new();
+            let mut reported = Vec::with_capacity(ids.len());
+            let mut trained = Vec::with_capacity(ids.len());
+            batch
+                .with_account_id(ESMP_ACCOUNT_ID)
+                .with_collection(Collection::EsmpInboxMessage);
+
+            for &message_id in ids {
+                let Some(message_) = self
+                    .get_archive(ESMP_ACCOUNT_ID, Collection::EsmpInboxMessage, message_id)
+                    .await?
+                else {
+                    continue;
+                };
+                let message = message_
+                    .to_unarchived::<InboxMessage>()
+                    .caused_by(trc::location!())?;
+                if message.inner.inbox_id.to_native() != inbox_id {
+                    continue;
+                }
+
+                // Messages are only trained again when reported as the other class
+                reported.push(message_id);
+                if message.inner.trained.as_ref().copied() == Some(is_spam) {
+                    continue;
+                }
+
+                // Spam is moved to the quarantine and ham is released from it
+                let mut new_message = message
+                    .deserialize::<InboxMessage>()
+                    .caused_by(trc::location!())?;
+                trained.push(new_message.contents.clone());
+                let is_moved = new_message.quarantined != is_spam;
+                new_message.trained = Some(is_spam);
+                if is_moved {
+                    new_message.quarantined = is_spam;
+                    if !is_spam {
+                        new_message.spam_score = None;
+                    }
+                }
+                batch
+                    .with_account_id(ESMP_ACCOUNT_ID)
+                    .update_document(message_id)
+                    .custom(
+                        ObjectIndexBuilder::new()
+                            .with_current(message)
+                            .with_changes(new_message),
+                    )
+                    .caused_by(trc::location!())?;
+                if is_moved {
+                    log_inbox_change(&mut batch, inbox_id, InboxChange::Update);
+                }
+                batch.commit_point();
+            }
+
+            if !batch.is_empty() {
+                match self.commit_batch(batch).await {
+                    Ok(_) => (),
+                    Err(err) if err.is_assertion_failure() && try_count < MAX_RETRIES => {
+                        let backoff = store::rand::rng().random_range(50..=300);
+                        tokio::time::sleep(Duration::from_millis(backoff)).await;
+                        try_count += 1;
+                        continue;
+                    }
+                    Err(err) => {
+                        return Err(err.caused_by(trc::location!()));
+                    }
+                }
+            }
+
+            for contents in trained {
+                if let Ok(msg) = serde_json::from_str::<EsmpMessage>(&contents) {
+                    self.esmp_spam_train(&msg, is_spam, span_id).await?;
+                }
+            }
+
+            return Ok(reported);
+        }
+    }
+
+    async fn inbox_changes(
+        &self,
+        recipient: &str,
+        since: Option<u64>,
+    ) -> trc::Result<Option<InboxChanges>> {
+        let Some(inbox_id) = self.inbox_document_id(recipient).await? else {
+            return Ok(Some(InboxChanges::default()));
+        };
+        let changelog = self
+            .store()
+            .changes(
+                esmp_inbox_account_id(inbox_id),
+                SyncCollection::EsmpInbox,
+                since.map_or(Query::All, Query::Since),
+            )
+            .await
+            .caused_by(trc::location!())?;
+
+        // Purged change log entries require a full resync
+        if changelog.is_truncated {
+            return Ok(None);
+        }
+
+        let mut changes = InboxChanges {
+            state: changelog.to_change_id.max(since.unwrap_or_default()),
+            ..Default::default()
+        };
+        for change in changelog.changes {
+            match change {
+                Change::InsertItem(id) => {
+                    changes.created.push(id as u32);
+                }
+                Change::UpdateItem(id) => {
+                    let id = id as u32;
+                    if !changes.created.contains(&id) && !changes.updated.contains(&id) {
+                        changes.updated.push(id);
+                    }
+                }
+                Change::DeleteItem(id) => {
+                    let id = id as u32;
+                    if let Some(pos) = changes.created.iter().position(|item| *item == id) {
+                        // Created and destroyed within the same range
+                        changes.created.swap_remove(pos);
+                    } else {
+                        changes.updated.retain(|item| *item != id);
+                        changes.destroyed.push(id);
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        Ok(Some(changes))
+    }
+}
+
+enum InboxChange {
+    Insert,
+    Update,
+    Delete,
+}
+
+/// Logs a change to the current inbox message in the change log of its
+/// inbox, so each inbox is synchronized without reading the changes of others.
+/// The global log only records that the inbox has changed.
+fn log_inbox_change(
+    batch: &mut BatchBuilder,
+    inbox_id: u32,
+    change: InboxChange,
+) -> &mut BatchBuilder {
+    batch.with_account_id(esmp_inbox_account_id(inbox_id));
+    match change {
+        InboxChange::Insert => batch.log_item_insert(SyncCollection::EsmpInbox, None),
+        InboxChange::Update => batch.log_item_update(SyncCollection::EsmpInbox, None),
+        InboxChange::Delete => batch.log_item_delete(SyncCollection::EsmpInbox, None),
+    };
+    batch
+        .with_account_id(ESMP_ACCOUNT_ID)
+        .log_container_property_change(SyncCollection::EsmpInbox, inbox_id)
+}
+
+async fn create_inbox(server: &Server, recipient: &str) -> trc::Result<u32> {
+    // The inbox may have been created while waiting for the lock
+    if let Some(inbox_id) = server.inbox_document_id(recipient).await? {
+        return Ok(inbox_id);
+    }
+
+    let inbox_id = server
+        .store()
+        .assign_document_ids(ESMP_ACCOUNT_ID, Collection::EsmpInbox, 1)
+        .await
+        .caused_by(trc::location!())?;
+    if inbox_id >= ESMP_MAX_INBOXES {
+        // Change logs are kept under a limited range of reserved accounts
+        return Err(trc::LimitEvent::Quota
+            .into_err()
+            .ctx(trc::Key::Total, ESMP_MAX_INBOXES)
+            .id(recipient.to_string()));
+    }
+    let mut batch = BatchBuilder::new();
+    batch
+        .with_account_id(ESMP_ACCOUNT_ID)
+        .with_collection(Collection::EsmpInbox)
+        .create_document(inbox_id)
+        .custom(ObjectIndexBuilder::<(), _>::new().with_changes(Inbox {
+            recipient: recipient.to_string(),
+            created_at: now(),
+        }))
+        .caused_by(trc::location!())?;
+    server
+        .commit_batch(batch)
+        .await
+        .caused_by(trc::location!())
+        .map(|_| inbox_id)
+}
@@ -0,0 +1,45 @@
+/*
+ * SPDX-FileCopyrightText: 2020 Stalwart Labs LLC <hello@stalw.art>
+ *
+ * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
+ */
+
+use common::storage::index::{IndexValue, IndexableAndSerializableObject, IndexableObject};
+
+use crate::IDX_PUBKEY;
+
+use super::{ArchivedKeySet, KeySet};
+
+impl IndexableObject for KeySet {
+    fn index_values(&self) -> impl Iterator<Item = IndexValue<'_>> {
+        [IndexValue::IndexList {
+            field: IDX_PUBKEY,
+            value: self
+                .keys
+                .iter()
+                .map(|key| key.pubkey.as_str().into())
+                .collect(),
+        }]
+        .into_iter()
+    }
+}
+
+impl IndexableAndSerializableObject for KeySet {
+    fn is_versioned() -> bool {
+        true
+    }
+}
+
+impl IndexableObject for &ArchivedKeySet {
+    fn index_values(&self) -> impl Iterator<Item = IndexValue<'_>> {
+        [IndexValue::IndexList {
+            field: IDX_PUBKEY,
+            value: self
+                .keys
+                .iter()
+                .map(|key| key.pubkey.as_str().into())
+                .collect(),
+        }]
+        .into_iter()
+    }
+}
@@ -0,0 +1,160 @@
+/*
+ * SPDX-FileCopyrightText: 2020 Stalwart Labs LLC <hello@stalw.art>
+ *
+ * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
+ */
+
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use crate::canonical::signing_input;
+
+pub mod index;
+pub mod persist;
+
+pub const MAX_DEVICE_KEYS: usize = 32;
+
+const MAX_DEVICE_NAME_LENGTH: usize = 64;
+
+/// Device keys that act on behalf of a single identity. The identity is named
+/// after the key that created the set and remains stable across rotations.
+#[derive(
+    rkyv::Archive,
+    rkyv::Deserialize,
+    rkyv::Serialize,
+    Debug,
+    Default,
+    Clone,
+    PartialEq,
+    Eq,
+    Serialize,
+    Deserialize,
+)]
+#[rkyv(derive(Debug))]
+pub struct KeySet {
+    pub identity: String,
+    pub keys: Vec<DeviceKey>,
+    pub updated_at: u64,
+}
+
+#[derive(
+    rkyv::Archive,
+    rkyv::Deserialize,
+    rkyv::Serialize,
+    Debug,
+    Default,
+    Clone,
+    PartialEq,
+    Eq,
+    Serialize,
+    Deserialize,
+)]
+#[rkyv(derive(Debug))]
+pub struct DeviceKey {
+    pub pubkey: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub device: Option<String>,
+    pub added_at: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub revoked_at: Option<u64>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum KeyChange {
+    Add {
+        pubkey: String,
+        device: Option<String>,
+    },
+    Revoke {
+        pubkey: String,
+        revoked_at: u64,
+    },
+}
+
+impl KeySet {
+    pub fn new(identity: impl Into<String>, now: u64) -> Self {
+        let identity = identity.into();
+        KeySet {
+            keys: vec![DeviceKey {
+                pubkey: identity.clone(),
+                device: None,
+                added_at: now,
+                revoked_at: None,
+            }],
+            identity,
+            updated_at: now,
+        }
+    }
+
+    pub fn key(&self, pubkey: &str) -> Option<&DeviceKey> {
+        self.keys.iter().find(|key| key.pubkey == pubkey)
+    }
+
+    /// Returns whether `pubkey` belongs to this identity and was not revoked
+    /// at `timestamp`.
+    pub fn is_active(&self, pubkey: &str, timestamp: u64) -> bool {
+        self.key(pubkey)
+            .is_some_and(|key| !key.is_revoked_at(timestamp))
+    }
+
+    pub fn apply(&mut self, change: KeyChange, now: u64) -> Result<(), &'static str> {
+        match change {
+            KeyChange::Add { pubkey, device } => {
+                if self.key(&pubkey).is_some() {
+                    return Err("Key already belongs to the identity");
+                }
+                if self.keys.len() >= MAX_DEVICE_KEYS {
+                    return Err("Too many device keys");
+                }
+                if device
+                    .as_ref()
+                    .is_some_and(|device| device.len() > MAX_DEVICE_NAME_LENGTH)
+                {
+                    return Err("Device name too long");
+                }
+                self.keys.push(DeviceKey {
+                    pubkey,
+                    device,
+                    added_at: now,
+                    revoked_at: None,
+                });
+            }
+            KeyChange::Revoke { pubkey, revoked_at } => {
+                let key = self
+                    .keys
+                    .iter_mut()
+                    .find(|key| key.pubkey == pubkey)
+                    .ok_or("Key does not belong to the identity")?;
+                if key.revoked_at.is_some() {
+                    return Err("Key is already revoked");
+                }
+                key.revoked_at = Some(revoked_at.max(key.added_at));
+
+                // Identities always keep a key that can sign new statements
+                if self.keys.iter().all(|key| key.revoked_at.is_some()) {
+                    return Err("The last active key cannot be revoked, rotate it instead");
+                }
+            }
+        }
+
+        self.updated_at = now;
+        Ok(())
+    }
+}
+
+impl DeviceKey {
+    pub fn is_revoked_at(&self, timestamp: u64) -> bool {
+        self.revoked_at
+            .is_some_and(|revoked_at| timestamp >= revoked_at)
+    }
+}
+
+/// Returns the input a new device key signs to consent to joining `identity`.
+pub fn key_proof_input(identity: &str, pubkey: &str, timestamp: u64) -> Vec<u8> {
+    signing_input(&json!({
+        "identity": identity,
+        "pubkey": pubkey,
+        "timestamp": timestamp,
+    }))
+    .unwrap_or_default()
+}
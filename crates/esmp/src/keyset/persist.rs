@@ -0,0 +1,229 @@
+/*
+ * SPDX-FileCopyrightText: 2020 Stalwart Labs LLC <hello@stalw.art>
+ *
+ * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
+ */
+
+use std::{future::Future, time::Duration};
+
+use common::{KV_LOCK_ESMP_KEY, Server, storage::index::ObjectIndexBuilder};
+use jmap_proto::types::collection::Collection;
+use store::{
+    query::Filter,
+    rand::Rng,
+    write::{BatchBuilder, now},
+};
+use trc::AddContext;
+
+use crate::{ESMP_ACCOUNT_ID, IDX_PUBKEY, MAX_RETRIES};
+
+use super::{KeyChange, KeySet};
+
+const KEY_LOCK_EXPIRY: u64 = 30;
+
+pub trait KeySetStore: Sync + Send {
+    fn key_set_document_id(
+        &self,
+        pubkey: &str,
+    ) -> impl Future<Output = trc::Result<Option<u32>>> + Send;
+
+    fn key_set(&self, pubkey: &str) -> impl Future<Output = trc::Result<Option<KeySet>>> + Send;
+
+    fn resolve_identity(&self, pubkey: &str) -> impl Future<Output = trc::Result<String>> + Send;
+
+    fn update_key_set(
+        &self,
+        sender: &str,
+        changes: Vec<KeyChange>,
+    ) -> impl Future<Output = trc::Result<Result<KeySet, &'static str>>> + Send;
+}
+
+impl KeySetStore for Server {
+    async fn key_set_document_id(&self, pubkey: &str) -> trc::Result<Option<u32>> {
+        self.store()
+            .filter(
+                ESMP_ACCOUNT_ID,
+                Collection::EsmpKeySet,
+                vec![Filter::eq(IDX_PUBKEY, pubkey.as_bytes().to_vec())],
+            )
+            .await
+            .caused_by(trc::location!())
+            .map(|result| result.results.min())
+    }
+
+    async fn key_set(&self, pubkey: &str) -> trc::Result<Option<KeySet>> {
+        if let Some(document_id) = self.key_set_document_id(pubkey).await? {
+            self.get_archive(ESMP_ACCOUNT_ID, Collection::EsmpKeySet, document_id)
+                .await?
+                .map(|archive| archive.deserialize::<KeySet>())
+                .transpose()
+                .caused_by(trc::location!())
+        } else {
+            Ok(None)
+        }
+    }
+
+    async fn resolve_identity(&self, pubkey: &str) -> trc::Result<String> {
+        // Keys without a key set are identities of their own
+        Ok(self
+            .key_set(pubkey)
+            .await?
+            .map(|key_set| key_set.identity)
+            .unwrap_or_else(|| pubkey.to_string()))
+    }
+
+    async fn update_key_set(
+        &self,
+        sender: &str,
+        changes: Vec<KeyChange>,
+    ) -> trc::Result<Result<KeySet, &'static str>> {
+        // Keys can only belong to a single key set, which is looked up through
+        // the key index, so the keys being added are locked until the key set
+        // is written, along with the sender whose key set may be created
+        let mut pubkeys = vec![sender];
+        for change in &changes {
+            if let KeyChange::Add { pubkey, .. } = change
+                && !pubkeys.contains(&pubkey.as_str())
+            {
+                pubkeys.push(pubkey);
+            }
+        }
+        let mut try_count = 0;
+        while !lock_keys(self, &pubkeys).await? {
+            if try_count >= MAX_RETRIES {
+                return Err(trc::StoreEvent::AssertValueFailed
+                    .into_err()
+                    .details("Key set is being updated by another request")
+                    .caused_by(trc::location!()));
+            }
+            let backoff = store::rand::rng().random_range(50..=300);
+            tokio::time::sleep(Duration::from_millis(backoff)).await;
+            try_count += 1;
+        }
+
+        let result = apply_key_changes(self, sender, &changes).await;
+        unlock_keys(self, &pubkeys).await;
+        result
+    }
+}
+
+async fn apply_key_changes(
+    server: &Server,
+    sender: &str,
+    changes: &[KeyChange],
+) -> trc::Result<Result<KeySet, &'static str>> {
+    let mut try_count = 0;
+
+    loop {
+        let mut batch = BatchBuilder::new();
+        batch
+            .with_account_id(ESMP_ACCOUNT_ID)
+            .with_collection(Collection::EsmpKeySet);
+
+        let document_id = server.key_set_document_id(sender).await?;
+        let current_ = if let Some(document_id) = document_id {
+            Some(
+                server
+                    .get_archive(ESMP_ACCOUNT_ID, Collection::EsmpKeySet, document_id)
+                    .await?
+                    .ok_or_else(|| {
+                        trc::StoreEvent::NotFound
+                            .into_err()
+                            .document_id(document_id)
+                            .caused_by(trc::location!())
+                    })?,
+            )
+        } else {
+            None
+        };
+        let current = current_
+            .as_ref()
+            .map(|current| current.to_unarchived::<KeySet>())
+            .transpose()
+            .caused_by(trc::location!())?;
+        let mut key_set = if let Some(current) = &current {
+            current
+                .deserialize::<KeySet>()
+                .caused_by(trc::location!())?
+        } else {
+            KeySet::new(sender, now())
+        };
+
+        for change in changes.iter().cloned() {
+            // Keys can only belong to a single identity
+            if let KeyChange::Add { pubkey, .. } = &change
+                && server
+                    .key_set_document_id(pubkey)
+                    .await?
+                    .is_some_and(|other_id| Some(other_id) != document_id)
+            {
+                return Ok(Err("Key already belongs to another identity"));
+            }
+            if let Err(reason) = key_set.apply(change, now()) {
+                return Ok(Err(reason));
+            }
+        }
+
+        if let (Some(document_id), Some(current)) = (document_id, current) {
+            batch
+                .update_document(document_id)
+                .custom(
+                    ObjectIndexBuilder::new()
+                        .with_current(current)
+                        .with_changes(key_set.clone()),
+                )
+                .caused_by(trc::location!())?;
+        } else {
+            let document_id = server
+                .store()
+                .assign_document_ids(ESMP_ACCOUNT_ID, Collection::EsmpKeySet, 1)
+                .await
+                .caused_by(trc::location!())?;
+            batch
+                .create_document(document_id)
+                .custom(ObjectIndexBuilder::<(), _>::new().with_changes(key_set.clone()))
+                .caused_by(trc::location!())?;
+        }
+
+        match server.commit_batch(batch).await {
+            Ok(_) => return Ok(Ok(key_set)),
+            Err(err) if err.is_assertion_failure() && try_count < MAX_RETRIES => {
+                let backoff = store::rand::rng().random_range(50..=300);
+                tokio::time::sleep(Duration::from_millis(backoff)).await;
+                try_count += 1;
+            }
+            Err(err) => {
+                return Err(err.caused_by(trc::location!()));
+            }
+        }
+    }
+}
+
+/// Locks `pubkeys`, returning whether all of them were locked.
+async fn lock_keys(server: &Server, pubkeys: &[&str]) -> trc::Result<bool> {
+    for (pos, pubkey) in pubkeys.iter().enumerate() {
+        if !server
+            .in_memory_store()
+            .try_lock(KV_LOCK_ESMP_KEY, pubkey.as_bytes(), KEY_LOCK_EXPIRY)
+            .await
+            .caused_by(trc::location!())?
+        {
+            unlock_keys(server, &pubkeys[..pos]).await;
+            return Ok(false);
+        }
+    }
+
+    Ok(true)
+}
+
+async fn unlock_keys(server: &Server, pubkeys: &[&str]) {
+    for pubkey in pubkeys {
+        if let Err(err) = server
+            .in_memory_store()
+            .remove_lock(KV_LOCK_ESMP_KEY, pubkey.as_bytes())
+            .await
+        {
+            trc::error!(err.details("Failed to release key lock."));
+        }
+    }
+}
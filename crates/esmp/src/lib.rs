@@ -0,0 +1,135 @@
+/*
+ * SPDX-FileCopyrightText: 2020 Stalwart Labs LLC <hello@stalw.art>
+ *
+ * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
+ */
+
+use std::{net::IpAddr, sync::Arc, time::Instant};
+
+use common::{
+    Inner, Server,
+    config::esmp::EsmpLimits,
+    core::BuildServer,
+    listener::{ServerInstance, SessionStream, limiter::InFlight},
+    manager::boot::{BootManager, IpcReceivers},
+};
+use federation::queue::spawn_federation_queue;
+use gateway::inbound::spawn_email_gateway;
+use push::PushState;
+use webpush::persist::EsmpPushStore;
+
+pub use common::ESMP_ACCOUNT_ID;
+
+pub mod api;
+pub mod blob;
+pub mod canonical;
+pub mod crypto;
+pub mod e2ee;
+pub mod federation;
+pub mod gateway;
+pub mod group;
+pub mod handler;
+pub mod identity;
+pub mod inbox;
+pub mod keyset;
+pub mod profile;
+pub mod push;
+pub mod reference;
+pub mod request;
+pub mod response;
+pub mod session;
+pub mod spam;
+pub mod system;
+pub mod webpush;
+
+pub const ESMP_VERSION: u32 = 1;
+pub const ESMP_CAPABILITIES: &[&str] = &[
+    "auth",
+    "inbox",
+    "sync",
+    "push",
+    "e2ee",
+    "identity",
+    "keys",
+    "webpush",
+    "references",
+    "invites",
+    "blobs",
+    "spam",
+];
+
+pub const IDX_GROUP_ID: u8 = 0;
+pub const IDX_GROUP: u8 = 1;
+pub const IDX_PUBKEY: u8 = 2;
+pub const IDX_RECIPIENT: u8 = 3;
+pub const IDX_INBOX: u8 = 4;
+pub const IDX_MEMBER: u8 = 5;
+pub const IDX_BLOB: u8 = 6;
+pub const IDX_MESSAGE_ID: u8 = 7;
+pub const IDX_ACKED: u8 = 8;
+pub const IDX_QUARANTINED: u8 = 9;
+
+pub(crate) const MAX_RETRIES: u32 = 10;
+pub(crate) const MAX_ID_LENGTH: usize = 128;
+
+pub trait StartEsmpServices {
+    fn start_esmp_services(&mut self);
+}
+
+pub trait SpawnEsmpServices {
+    fn spawn_esmp_services(&mut self, inner: Arc<Inner>);
+}
+
+impl StartEsmpServices for BootManager {
+    fn start_esmp_services(&mut self) {
+        self.ipc_rxs.spawn_esmp_services(self.inner.clone());
+    }
+}
+
+impl SpawnEsmpServices for IpcReceivers {
+    fn spawn_esmp_services(&mut self, inner: Arc<Inner>) {
+        // Spawn federation queue
+        spawn_federation_queue(inner.clone());
+
+        // Spawn e-mail gateway
+        spawn_email_gateway(inner.clone(), self.esmp_gateway_rx.take().unwrap());
+
+        // Register the push subscriptions of device keys
+        tokio::spawn(async move {
+            if let Err(err) = inner.build_server().update_esmp_push_subscriptions().await {
+                trc::error!(err.details("Failed to load ESMP push subscriptions"));
+            }
+        });
+    }
+}
+
+#[derive(Clone)]
+pub struct EsmpSessionManager {
+    pub inner: Arc<Inner>,
+}
+
+impl EsmpSessionManager {
+    pub fn new(inner: Arc<Inner>) -> Self {
+        Self { inner }
+    }
+}
+
+pub struct Session<T: SessionStream> {
+    pub server: Server,
+    pub instance: Arc<ServerInstance>,
+    pub stream: T,
+    pub in_flight: InFlight,
+    pub remote_addr: IpAddr,
+    pub session_id: u64,
+    pub buf: Vec<u8>,
+    pub limits: EsmpLimits,
+    pub started: Instant,
+    pub rate_start: Instant,
+    pub rate_count: u64,
+    pub nonce: String,
+    pub version: Option<u32>,
+    pub pubkey: Option<String>,
+    pub identity: Option<String>,
+    pub peer: Option<String>,
+    pub push: Option<PushState>,
+}
@@ -0,0 +1,399 @@
+/*
+ * SPDX-FileCopyrightText: 2020 Stalwart Labs LLC <hello@stalw.art>
+ *
+ * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
+ */
+
+use common::config::esmp::EsmpEncryption;
+use serde::{Deserialize, Serialize};
+use store::write::now;
+use thiserror::Error;
+use url::Url;
+
+use crate::crypto::{decrypt_aes, encrypt_aes, generate_key};
+
+pub mod index;
+pub mod persist;
+
+#[derive(Debug, Error)]
+pub enum ProfileError {
+    #[error("Invalid display picture URL: {0}")]
+    InvalidDisplayPictureUrl(String),
+    #[error("Name too long: {0}")]
+    NameTooLong(String),
+    #[error("Address too long")]
+    AddressTooLong,
+    #[error("Invalid character in name: {0}")]
+    InvalidNameCharacter(char),
+    #[error("Encryption error: {0}")]
+    EncryptionError(String),
+    #[error("Decryption error: {0}")]
+    DecryptionError(String),
+    #[error("No master key is configured in esmp.encryption.key")]
+    MissingMasterKey,
+    #[error("Unknown master key: {0}")]
+    UnknownMasterKey(String),
+    #[error("Unauthorized access to private field")]
+    UnauthorizedAccess,
+    #[error("Too many entries in {0}")]
+    TooManyEntries(String),
+}
+
+const MAX_NAME_LENGTH: usize = 50;
+const MAX_ADDRESS_LENGTH: usize = 200;
+const MAX_CONTACTS: usize = 1000;
+const MAX_ALLOWED: usize = 100;
+
+#[derive(
+    rkyv::Archive,
+    rkyv::Deserialize,
+    rkyv::Serialize,
+    Debug,
+    Default,
+    Clone,
+    Copy,
+    PartialEq,
+    Eq,
+    Serialize,
+    Deserialize,
+)]
+#[rkyv(derive(Debug))]
+pub enum Visibility {
+    #[serde(rename = "public")]
+    Public,
+    #[serde(rename = "contacts")]
+    Contacts,
+    #[serde(rename = "groups")]
+    Groups,
+    #[serde(rename = "custom")]
+    Custom,
+    #[default]
+    #[serde(rename = "private")]
+    Private,
+}
+
+#[derive(
+    rkyv::Archive,
+    rkyv::Deserialize,
+    rkyv::Serialize,
+    Debug,
+    Clone,
+    PartialEq,
+    Eq,
+    Serialize,
+    Deserialize,
+)]
+pub struct ProfileField<T> {
+    pub value: Option<T>,
+    pub visibility: Visibility,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub allowed: Vec<String>, // Identities that can see a custom field
+}
+
+impl<T> Default for ProfileField<T> {
+    fn default() -> Self {
+        Self {
+            value: None,
+            visibility: Visibility::default(),
+            allowed: Vec::new(),
+        }
+    }
+}
+
+/// The relationship of a requester to the owner of a profile, which decides
+/// the fields it can see.
+#[derive(Debug, Default, Clone)]
+pub struct Audience {
+    pub identities: Vec<String>,
+    pub is_owner: bool,
+    pub is_contact: bool,
+    pub shares_group: bool,
+}
+
+#[derive(
+    rkyv::Archive,
+    rkyv::Deserialize,
+    rkyv::Serialize,
+    Debug,
+    Default,
+    Clone,
+    PartialEq,
+    Eq,
+    Serialize,
+    Deserialize,
+)]
+pub struct UserProfile {
+    pub pubkey: String, // Ed25519 public key as base64
+    pub first_name: ProfileField<String>,
+    pub middle_name: ProfileField<String>,
+    pub last_name: ProfileField<String>,
+    pub display_picture: ProfileField<String>,
+    pub address: ProfileField<Envelope>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub contacts: Vec<String>, // Identities that can see fields shared with contacts
+    pub updated_at: Option<u64>,
+}
+
+/// A value encrypted with its own data key. The data key is stored wrapped
+/// by the server master key named by `key_id`, so rotating the master key
+/// only requires wrapping the data key again.
+#[derive(
+    rkyv::Archive,
+    rkyv::Deserialize,
+    rkyv::Serialize,
+    Debug,
+    Default,
+    Clone,
+    PartialEq,
+    Eq,
+    Serialize,
+    Deserialize,
+)]
+#[rkyv(derive(Debug))]
+pub struct Envelope {
+    pub key_id: String,
+    pub wrapped_key: Vec<u8>,
+    pub ciphertext: Vec<u8>,
+}
+
+impl UserProfile {
+    pub fn new(pubkey: String) -> Self {
+        Self {
+            pubkey,
+            updated_at: Some(now()),
+            ..Default::default()
+        }
+    }
+
+    /// Set a profile field (except address)
+    pub fn set_field(
+        &mut self,
+        field: &str,
+        value: Option<String>,
+        visibility: Option<Visibility>,
+    ) {
+        let field_ = match field {
+            "first_name" => &mut self.first_name,
+            "middle_name" => &mut self.middle_name,
+            "last_name" => &mut self.last_name,
+            "display_picture" => &mut self.display_picture,
+            _ => return,
+        };
+        *field_ = ProfileField {
+            value,
+            visibility: visibility.unwrap_or_default(),
+            allowed: std::mem::take(&mut field_.allowed),
+        };
+    }
+
+    /// Set the identities that can see a field with custom visibility
+    pub fn set_allowed(&mut self, field: &str, allowed: Vec<String>) {
+        match field {
+            "first_name" => self.first_name.allowed = allowed,
+            "middle_name" => self.middle_name.allowed = allowed,
+            "last_name" => self.last_name.allowed = allowed,
+            "display_picture" => self.display_picture.allowed = allowed,
+            "address" => self.address.allowed = allowed,
+            _ => {}
+        }
+    }
+
+    /// Set the address (encrypted, visibility can be set)
+    pub fn set_address(
+        &mut self,
+        address: Option<String>,
+        keys: &EsmpEncryption,
+        visibility: Option<Visibility>,
+    ) -> Result<(), ProfileError> {
+        let value = if let Some(address) = address {
+            if address.len() > MAX_ADDRESS_LENGTH {
+                return Err(ProfileError::AddressTooLong);
+            }
+            Some(Envelope::seal(
+                keys,
+                address.as_bytes(),
+                self.pubkey.as_bytes(),
+            )?)
+        } else {
+            None
+        };
+        self.address = ProfileField {
+            value,
+            visibility: visibility.unwrap_or_default(),
+            allowed: std::mem::take(&mut self.address.allowed),
+        };
+        Ok(())
+    }
+
+    /// Decrypt the stored address
+    pub fn address(&self, keys: &EsmpEncryption) -> Result<Option<String>, ProfileError> {
+        self.address
+            .value
+            .as_ref()
+            .map(|envelope| {
+                envelope
+                    .open(keys, self.pubkey.as_bytes())
+                    .and_then(|decrypted| {
+                        String::from_utf8(decrypted).map_err(|_| {
+                            ProfileError::DecryptionError("Invalid UTF-8 in address".into())
+                        })
+                    })
+            })
+            .transpose()
+    }
+
+    /// Wrap the data keys of encrypted fields with the active master key,
+    /// returning whether any of them changed
+    pub fn rewrap(&mut self, keys: &EsmpEncryption) -> Result<bool, ProfileError> {
+        match &mut self.address.value {
+            Some(envelope) => envelope.rewrap(keys, self.pubkey.as_bytes()),
+            None => Ok(false),
+        }
+    }
+
+    pub fn validate(&self) -> Result<(), ProfileError> {
+        // Validate display picture URL if present
+        if let Some(url) = &self.display_picture.value {
+            Url::parse(url).map_err(|_| ProfileError::InvalidDisplayPictureUrl(url.clone()))?;
+        }
+
+        // Validate name fields
+        for (name, field) in [
+            ("first_name", &self.first_name),
+            ("middle_name", &self.middle_name),
+            ("last_name", &self.last_name),
+        ] {
+            if let Some(value) = &field.value {
+                if value.len() > MAX_NAME_LENGTH {
+                    return Err(ProfileError::NameTooLong(name.to_string()));
+                }
+                // Only allow letters, spaces, hyphens and apostrophes in names
+                if let Some(invalid_char) = value
+                    .chars()
+                    .find(|c| !c.is_alphabetic() && *c != ' ' && *c != '-' && *c != '\'')
+                {
+                    return Err(ProfileError::InvalidNameCharacter(invalid_char));
+                }
+            }
+        }
+
+        // Audiences are bounded
+        if self.contacts.len() > MAX_CONTACTS {
+            return Err(ProfileError::TooManyEntries("contacts".to_string()));
+        }
+        for (name, allowed) in [
+            ("first_name", &self.first_name.allowed),
+            ("middle_name", &self.middle_name.allowed),
+            ("last_name", &self.last_name.allowed),
+            ("display_picture", &self.display_picture.allowed),
+            ("address", &self.address.allowed),
+        ] {
+            if allowed.len() > MAX_ALLOWED {
+                return Err(ProfileError::TooManyEntries(name.to_string()));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Returns whether any field is shared with the given audience level
+    pub fn has_visibility(&self, visibility: Visibility) -> bool {
+        [
+            self.first_name.visibility,
+            self.middle_name.visibility,
+            self.last_name.visibility,
+            self.display_picture.visibility,
+            self.address.visibility,
+        ]
+        .contains(&visibility)
+    }
+
+    /// Returns the fields the audience is allowed to see. Audience lists are
+    /// only disclosed to the owner.
+    pub fn to_view(&self, audience: &Audience) -> Self {
+        if audience.is_owner {
+            return self.clone();
+        }
+
+        let mut view = self.clone();
+        view.contacts.clear();
+        view.first_name.restrict(audience);
+        view.middle_name.restrict(audience);
+        view.last_name.restrict(audience);
+        view.display_picture.restrict(audience);
+        view.address.restrict(audience);
+        view
+    }
+}
+
+impl<T> ProfileField<T> {
+    pub fn is_visible_to(&self, audience: &Audience) -> bool {
+        audience.is_owner
+            || match self.visibility {
+                Visibility::Public => true,
+                Visibility::Contacts => audience.is_contact,
+                Visibility::Groups => audience.shares_group,
+                Visibility::Custom => self
+                    .allowed
+                    .iter()
+                    .any(|identity| audience.identities.contains(identity)),
+                Visibility::Private => false,
+            }
+    }
+
+    fn restrict(&mut self, audience: &Audience) {
+        if !self.is_visible_to(audience) {
+            self.value = None;
+        }
+        self.allowed.clear();
+    }
+}
+
+impl Envelope {
+    pub fn seal(keys: &EsmpEncryption, plaintext: &[u8], aad: &[u8]) -> Result<Self, ProfileError> {
+        let (key_id, master_key) = keys.active_key().ok_or(ProfileError::MissingMasterKey)?;
+        let data_key = generate_key();
+
+        Ok(Envelope {
+            key_id: key_id.to_string(),
+            wrapped_key: encrypt_aes(master_key, &data_key, aad)
+                .map_err(ProfileError::EncryptionError)?,
+            ciphertext: encrypt_aes(&data_key, plaintext, &[])
+                .map_err(ProfileError::EncryptionError)?,
+        })
+    }
+
+    pub fn open(&self, keys: &EsmpEncryption, aad: &[u8]) -> Result<Vec<u8>, ProfileError> {
+        decrypt_aes(&self.data_key(keys, aad)?, &self.ciphertext, &[])
+            .map_err(ProfileError::DecryptionError)
+    }
+
+    pub fn rewrap(&mut self, keys: &EsmpEncryption, aad: &[u8]) -> Result<bool, ProfileError> {
+        let Some((key_id, master_key)) = keys.active_key() else {
+            return Err(ProfileError::MissingMasterKey);
+        };
+        if self.key_id == key_id {
+            return Ok(false);
+        }
+
+        let data_key = self.data_key(keys, aad)?;
+        self.wrapped_key =
+            encrypt_aes(master_key, &data_key, aad).map_err(ProfileError::EncryptionError)?;
+        self.key_id = key_id.to_string();
+        Ok(true)
+    }
+
+    fn data_key(&self, keys: &EsmpEncryption, aad: &[u8]) -> Result<[u8; 32], ProfileError> {
+        // Wrapped keys are bound to the profile they were issued for
+        let master_key = keys
+            .keys
+            .get(&self.key_id)
+            .ok_or_else(|| ProfileError::UnknownMasterKey(self.key_id.clone()))?;
+        decrypt_aes(master_key, &self.wrapped_key, aad)
+            .map_err(ProfileError::DecryptionError)
+            .and_then(|data_key| {
+                <[u8; 32]>::try_from(data_key)
+                    .map_err(|_| ProfileError::DecryptionError("Invalid data key".into()))
+            })
+    }
+}
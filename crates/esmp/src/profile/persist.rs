@@ -0,0 +1,232 @@
+/*
+ * SPDX-FileCopyrightText: 2020 Stalwart Labs LLC <hello@stalw.art>
+ *
+ * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
+ */
+
+use std::future::Future;
+
+use common::{KV_LOCK_HOUSEKEEPER, Server, storage::index::ObjectIndexBuilder};
+use jmap_proto::types::collection::Collection;
+use store::{query::Filter, write::BatchBuilder};
+use trc::AddContext;
+
+use crate::{
+    ESMP_ACCOUNT_ID, IDX_PUBKEY, group::persist::GroupStore, keyset::persist::KeySetStore,
+};
+
+use super::{Audience, UserProfile, Visibility};
+
+const REENCRYPT_LOCK: &[u8] = b"esmp-reencrypt";
+const REENCRYPT_LOCK_EXPIRY: u64 = 3600;
+
+pub trait ProfileStore: Sync + Send {
+    fn profile_document_id(
+        &self,
+        pubkey: &str,
+    ) -> impl Future<Output = trc::Result<Option<u32>>> + Send;
+
+    fn get_profile(
+        &self,
+        pubkey: &str,
+    ) -> impl Future<Output = trc::Result<Option<UserProfile>>> + Send;
+
+    fn save_profile(&self, profile: UserProfile) -> impl Future<Output = trc::Result<()>> + Send;
+
+    fn reencrypt_profiles(&self) -> impl Future<Output = trc::Result<usize>> + Send;
+
+    fn profile_audience(
+        &self,
+        profile: &UserProfile,
+        identities: Vec<String>,
+    ) -> impl Future<Output = trc::Result<Audience>> + Send;
+}
+
+impl ProfileStore for Server {
+    async fn profile_document_id(&self, pubkey: &str) -> trc::Result<Option<u32>> {
+        self.store()
+            .filter(
+                ESMP_ACCOUNT_ID,
+                Collection::EsmpProfile,
+                vec![Filter::eq(IDX_PUBKEY, pubkey.as_bytes().to_vec())],
+            )
+            .await
+            .caused_by(trc::location!())
+            .map(|result| result.results.min())
+    }
+
+    async fn get_profile(&self, pubkey: &str) -> trc::Result<Option<UserProfile>> {
+        // Profiles belong to the identity rather than to a device key
+        let identity = self.resolve_identity(pubkey).await?;
+        if let Some(document_id) = self.profile_document_id(&identity).await? {
+            self.get_archive(ESMP_ACCOUNT_ID, Collection::EsmpProfile, document_id)
+                .await?
+                .map(|archive| archive.deserialize::<UserProfile>())
+                .transpose()
+                .caused_by(trc::location!())
+        } else {
+            Ok(None)
+        }
+    }
+
+    async fn save_profile(&self, profile: UserProfile) -> trc::Result<()> {
+        // Validate profile before saving
+        profile.validate().map_err(|err| {
+            trc::EsmpEvent::Error
+                .into_err()
+                .reason(err)
+                .caused_by(trc::location!())
+        })?;
+
+        let mut batch = BatchBuilder::new();
+        batch
+            .with_account_id(ESMP_ACCOUNT_ID)
+            .with_collection(Collection::EsmpProfile);
+
+        if let Some(document_id) = self.profile_document_id(&profile.pubkey).await? {
+            let current_ = self
+                .get_archive(ESMP_ACCOUNT_ID, Collection::EsmpProfile, document_id)
+                .await?
+                .ok_or_else(|| {
+                    trc::StoreEvent::NotFound
+                        .into_err()
+                        .document_id(document_id)
+                        .caused_by(trc::location!())
+                })?;
+            let current = current_
+                .to_unarchived::<UserProfile>()
+                .caused_by(trc::location!())?;
+            batch
+                .update_document(document_id)
+                .custom(
+                    ObjectIndexBuilder::new()
+                        .with_current(current)
+                        .with_changes(profile),
+                )
+                .caused_by(trc::location!())?;
+        } else {
+            let document_id = self
+                .store()
+                .assign_document_ids(ESMP_ACCOUNT_ID, Collection::EsmpProfile, 1)
+                .await
+                .caused_by(trc::location!())?;
+            batch
+                .create_document(document_id)
+                .custom(ObjectIndexBuilder::<(), _>::new().with_changes(profile))
+                .caused_by(trc::location!())?;
+        }
+
+        self.commit_batch(batch)
+            .await
+            .caused_by(trc::location!())
+            .map(|_| ())
+    }
+
+    async fn reencrypt_profiles(&self) -> trc::Result<usize> {
+        if self.core.esmp.encryption.active.is_none() {
+            return Ok(0);
+        }
+
+        // Only one node re-encrypts profiles at a time
+        if !self
+            .in_memory_store()
+            .try_lock(KV_LOCK_HOUSEKEEPER, REENCRYPT_LOCK, REENCRYPT_LOCK_EXPIRY)
+            .await?
+        {
+            return Ok(0);
+        }
+        let result = rewrap_profiles(self).await;
+        self.in_memory_store()
+            .remove_lock(KV_LOCK_HOUSEKEEPER, REENCRYPT_LOCK)
+            .await?;
+
+        result
+    }
+
+    async fn profile_audience(
+        &self,
+        profile: &UserProfile,
+        identities: Vec<String>,
+    ) -> trc::Result<Audience> {
+        let mut audience = Audience {
+            is_owner: identities.contains(&profile.pubkey),
+            is_contact: profile
+                .contacts
+                .iter()
+                .any(|contact| identities.contains(contact)),
+            ..Default::default()
+        };
+
+        // Shared groups are only looked up when a field is visible to them
+        if !audience.is_owner && profile.has_visibility(Visibility::Groups) {
+            for identity in &identities {
+                if self.shares_group(&profile.pubkey, identity).await? {
+                    audience.shares_group = true;
+                    break;
+                }
+            }
+        }
+        audience.identities = identities;
+
+        Ok(audience)
+    }
+}
+
+async fn rewrap_profiles(server: &Server) -> trc::Result<usize> {
+    let keys = &server.core.esmp.encryption;
+    let mut count = 0;
+
+    for document_id in server
+        .get_document_ids(ESMP_ACCOUNT_ID, Collection::EsmpProfile)
+        .await?
+        .unwrap_or_default()
+    {
+        let Some(current_) = server
+            .get_archive(ESMP_ACCOUNT_ID, Collection::EsmpProfile, document_id)
+            .await?
+        else {
+            continue;
+        };
+        let current = current_
+            .to_unarchived::<UserProfile>()
+            .caused_by(trc::location!())?;
+        let mut profile = current
+            .deserialize::<UserProfile>()
+            .caused_by(trc::location!())?;
+        match profile.rewrap(keys) {
+            Ok(true) => {}
+            Ok(false) => continue,
+            Err(err) => {
+                trc::error!(
+                    trc::EsmpEvent::Error
+                        .into_err()
+                        .reason(err)
+                        .document_id(document_id)
+                        .details("Failed to re-encrypt profile")
+                        .caused_by(trc::location!())
+                );
+                continue;
+            }
+        }
+
+        let mut batch = BatchBuilder::new();
+        batch
+            .with_account_id(ESMP_ACCOUNT_ID)
+            .with_collection(Collection::EsmpProfile)
+            .update_document(document_id)
+            .custom(
+                ObjectIndexBuilder::new()
+                    .with_current(current)
+                    .with_changes(profile),
+            )
+            .caused_by(trc::location!())?;
+        match server.commit_batch(batch).await {
+            Ok(_) => count += 1,
+            // Profiles saved in the meantime are already encrypted with the active key
+            Err(err) if err.is_assertion_failure() => {}
+            Err(err) => return Err(err.caused_by(trc::location!())),
+        }
+    }
+
+    Ok(count)
+}
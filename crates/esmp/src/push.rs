@@ -0,0 +1,243 @@
+/*
+ * SPDX-FileCopyrightText: 2020 Stalwart Labs LLC <hello@stalw.art>
+ *
+ * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
+ */
+
+use common::{
+    Server, esmp_inbox_account_id,
+    ipc::{EsmpStateChange, EsmpStateType},
+    listener::SessionStream,
+};
+use jmap_proto::types::collection::{Collection, SyncCollection};
+use serde_json::value::RawValue;
+use store::{
+    ahash::AHashMap,
+    query::log::{Change, Query},
+};
+use tokio::sync::mpsc;
+use trc::AddContext;
+
+use crate::{
+    ESMP_ACCOUNT_ID, Session,
+    group::{GroupMessage, GroupMetadata},
+    inbox::{InboxMessage, persist::InboxStore},
+    response::Response,
+};
+
+pub struct PushState {
+    pub rx: mpsc::Receiver<EsmpStateChange>,
+    pub account_id: u32,
+    pub inbox_state: u64,
+    pub group_state: u64,
+}
+
+impl<T: SessionStream> Session<T> {
+    pub async fn subscribe_push(&mut self, identity: &str) -> trc::Result<()> {
+        // Sessions are only woken up by the changes to the inbox and the
+        // groups of their identity
+        let inbox_id = self.server.get_or_create_inbox(identity).await?;
+        let account_id = esmp_inbox_account_id(inbox_id);
+        let rx = self.server.subscribe_esmp_state(account_id).await?;
+
+        // Only messages delivered after authentication are pushed
+        let inbox_state = self
+            .server
+            .store()
+            .get_last_change_id(account_id, SyncCollection::EsmpInbox)
+            .await
+            .caused_by(trc::location!())?
+            .unwrap_or_default();
+        let group_state = self
+            .server
+            .store()
+            .get_last_change_id(ESMP_ACCOUNT_ID, SyncCollection::Esmp)
+            .await
+            .caused_by(trc::location!())?
+            .unwrap_or_default();
+
+        self.push = Some(PushState {
+            rx,
+            account_id,
+            inbox_state,
+            group_state,
+        });
+
+        Ok(())
+    }
+
+    pub async fn push_changes(&mut self, state_change: EsmpStateChange) {
+        let Some(pubkey) = self.pubkey.clone() else {
+            return;
+        };
+        let identity = self.identity.clone().unwrap_or_else(|| pubkey.clone());
+
+        let result = match state_change.state_type {
+            EsmpStateType::InboxMessage => self.push_inbox_messages(&identity).await,
+            EsmpStateType::Message => self.push_group_messages(&pubkey, &identity).await,
+        };
+        if let Err(err) = result {
+            trc::error!(err.span_id(self.session_id));
+        }
+    }
+
+    async fn push_inbox_messages(&mut self, identity: &str) -> trc::Result<()> {
+        let Some((account_id, since)) = self
+            .push
+            .as_ref()
+            .map(|push| (push.account_id, push.inbox_state))
+        else {
+            return Ok(());
+        };
+        let Some(changes) = self.server.inbox_changes(identity, Some(since)).await? else {
+            // Purged changes are not pushed, clients recover them with a fetch
+            if let Some(push) = &mut self.push {
+                push.inbox_state = self
+                    .server
+                    .store()
+                    .get_last_change_id(account_id, SyncCollection::EsmpInbox)
+                    .await
+                    .caused_by(trc::location!())?
+                    .unwrap_or(since);
+            }
+            return Ok(());
+        };
+
+        for message_id in changes.created {
+            let Some(archive) = self
+                .server
+                .get_archive(ESMP_ACCOUNT_ID, Collection::EsmpInboxMessage, message_id)
+                .await?
+            else {
+                continue;
+            };
+            let message = archive
+                .deserialize::<InboxMessage>()
+                .caused_by(trc::location!())?;
+            // Quarantined messages are only returned by an explicit fetch
+            if message.quarantined {
+                continue;
+            }
+            if let Ok(contents) = RawValue::from_string(message.contents) {
+                self.write_bytes(
+                    Response::Push {
+                        id: message_id,
+                        group_id: None,
+                        spam_score: message.spam_score,
+                        message: contents,
+                    }
+                    .serialize(),
+                )
+                .await?;
+            }
+        }
+
+        if let Some(push) = &mut self.push {
+            push.inbox_state = changes.state;
+        }
+
+        Ok(())
+    }
+
+    async fn push_group_messages(&mut self, pubkey: &str, identity: &str) -> trc::Result<()> {
+        let Some(since) = self.push.as_ref().map(|push| push.group_state) else {
+            return Ok(());
+        };
+        let changelog = self
+            .server
+            .store()
+            .changes(ESMP_ACCOUNT_ID, SyncCollection::Esmp, Query::Since(since))
+            .await
+            .caused_by(trc::location!())?;
+
+        // Group membership is resolved once per group
+        let mut groups: AHashMap<u32, Option<GroupMetadata>> = AHashMap::new();
+
+        for change in &changelog.changes {
+            let Change::InsertItem(id) = change else {
+                continue;
+            };
+            let group_document_id = (id >> 32) as u32;
+            let message_id = *id as u32;
+
+            if !groups.contains_key(&group_document_id) {
+                let group = self
+                    .server
+                    .get_archive(ESMP_ACCOUNT_ID, Collection::EsmpGroup, group_document_id)
+                    .await?
+                    .map(|archive| archive.deserialize::<GroupMetadata>())
+                    .transpose()
+                    .caused_by(trc::location!())?;
+                groups.insert(group_document_id, group);
+            }
+            // Sessions are not woken up by the groups they are not a member of,
+            // so only the messages posted during their membership are pushed
+            let Some(group_id) = groups
+                .get(&group_document_id)
+                .and_then(|group| group.as_ref())
+                .filter(|group| group.is_member(identity) && group.can_read(identity, message_id))
+                .map(|group| group.group_id.clone())
+            else {
+                continue;
+            };
+
+            let Some(archive) = self
+                .server
+                .get_archive(ESMP_ACCOUNT_ID, Collection::EsmpMessage, message_id)
+                .await?
+            else {
+                continue;
+            };
+            let message = archive
+                .deserialize::<GroupMessage>()
+                .caused_by(trc::location!())?;
+            // Messages are not echoed to the device that sent them
+            if message.sender == pubkey {
+                continue;
+            }
+            if let Ok(contents) = RawValue::from_string(message.contents) {
+                self.write_bytes(
+                    Response::Push {
+                        id: message_id,
+                        group_id: Some(&group_id),
+                        spam_score: None,
+                        message: contents,
+                    }
+                    .serialize(),
+                )
+                .await?;
+            }
+        }
+
+        if let Some(push) = &mut self.push {
+            push.group_state = changelog.to_change_id.max(since);
+        }
+
+        Ok(())
+    }
+}
+
+/// Wakes up the sessions of `identities`, which are subscribed to the change
+/// log of their inbox.
+pub(crate) async fn notify_sessions(
+    server: &Server,
+    identities: &[String],
+    state_change: EsmpStateChange,
+) -> trc::Result<()> {
+    for identity in identities {
+        if let Some(inbox_id) = server.inbox_document_id(identity).await? {
+            server
+                .notify_esmp_state(esmp_inbox_account_id(inbox_id), Vec::new(), state_change)
+                .await?;
+        }
+    }
+
+    Ok(())
+}
+
+pub async fn next_state_change(push: &mut Option<PushState>) -> Option<EsmpStateChange> {
+    match push {
+        Some(push) => push.rx.recv().await,
+        None => std::future::pending().await,
+    }
+}
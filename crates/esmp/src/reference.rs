@@ -0,0 +1,89 @@
+/*
+ * SPDX-FileCopyrightText: 2020 Stalwart Labs LLC <hello@stalw.art>
+ *
+ * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
+ */
+
+use serde_json::Value;
+
+use crate::{MAX_ID_LENGTH, handler::EsmpMessage};
+
+const MAX_REACTION_LENGTH: usize = 64;
+
+/// Message types that act on an earlier message, named by its `ref_id`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReferenceType {
+    Edit,
+    Retract,
+    Reaction,
+}
+
+impl ReferenceType {
+    pub fn parse(value: &str) -> Option<Self> {
+        hashify::tiny_map!(value.as_bytes(),
+            "edit" => Self::Edit,
+            "retract" => Self::Retract,
+            "reaction" => Self::Reaction,
+        )
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ReferenceType::Edit => "edit",
+            ReferenceType::Retract => "retract",
+            ReferenceType::Reaction => "reaction",
+        }
+    }
+
+    /// Edits and retractions can only be sent by the author of the
+    /// referenced message.
+    pub fn requires_author(&self) -> bool {
+        matches!(self, ReferenceType::Edit | ReferenceType::Retract)
+    }
+}
+
+impl EsmpMessage {
+    pub fn reference_type(&self) -> Option<ReferenceType> {
+        ReferenceType::parse(&self.r#type)
+    }
+
+    /// Returns whether the message carries content of its own, and can be
+    /// replied to, edited or reacted to.
+    pub fn is_content(&self) -> bool {
+        self.r#type != "system" && self.reference_type().is_none()
+    }
+
+    /// Returns the reaction of a `reaction` message.
+    pub fn reaction(&self) -> Option<&str> {
+        self.body.get("reaction").and_then(Value::as_str)
+    }
+
+    pub fn validate_references(&self) -> Result<(), &'static str> {
+        for id in [&self.ref_id, &self.reply_to].into_iter().flatten() {
+            if id.is_empty() || id.len() > MAX_ID_LENGTH {
+                return Err("Invalid referenced message id");
+            }
+        }
+
+        match self.reference_type() {
+            Some(_) if self.ref_id.is_none() => Err("This message type requires a ref_id"),
+            Some(ReferenceType::Retract | ReferenceType::Reaction) if self.reply_to.is_some() => {
+                Err("Retractions and reactions cannot be replies")
+            }
+            Some(ReferenceType::Reaction)
+                if !self.reaction().is_some_and(|reaction| {
+                    !reaction.is_empty() && reaction.len() <= MAX_REACTION_LENGTH
+                }) =>
+            {
+                Err("Reactions require a reaction in the body")
+            }
+            None if self.ref_id.is_some() => {
+                Err("Only edit, retract and reaction messages can have a ref_id")
+            }
+            None if self.r#type == "system" && self.reply_to.is_some() => {
+                Err("System messages cannot be replies")
+            }
+            _ => Ok(()),
+        }
+    }
+}
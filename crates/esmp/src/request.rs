@@ -0,0 +1,756 @@
+/*
+ * SPDX-FileCopyrightText: 2020 Stalwart Labs LLC <hello@stalw.art>
+ *
+ * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
+ */
+
+use common::{KV_RATE_LIMIT_ESMP, listener::SessionStream};
+use serde::Deserialize;
+use serde_json::{Value, value::RawValue};
+use store::write::now;
+
+use crate::{
+    ESMP_CAPABILITIES, ESMP_VERSION, Session,
+    canonical::signing_input,
+    crypto::verify_signature,
+    e2ee::{KeyPackage, MAX_KEY_PACKAGES, persist::KeyPackageStore},
+    handler::SignedObject,
+    identity::{EsmpIdentity, address_to_email},
+    inbox::persist::InboxStore,
+    keyset::{KeyChange, KeySet, key_proof_input, persist::KeySetStore},
+    response::{ErrorCode, InboxEntry, Response},
+    spam::TrainClass,
+    webpush::{PushKeysRequest, PushSubscription, persist::EsmpPushStore, validate_subscription},
+};
+
+const MAX_FETCH_LIMIT: usize = 100;
+const MAX_REQUEST_IDS: usize = 256;
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Hello {
+    pub versions: Vec<u32>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct EsmpRequest {
+    pub version: u32,
+    pub id: String,
+    pub timestamp: u64,
+    #[serde(default)]
+    pub inbox: Option<String>,
+    #[serde(flatten)]
+    pub command: Command,
+    pub signature: String,
+    pub sender_pubkey: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "command", rename_all = "snake_case")]
+pub enum Command {
+    Authenticate {
+        nonce: String,
+    },
+    Fetch {
+        #[serde(default)]
+        after: Option<u32>,
+        #[serde(default)]
+        limit: Option<usize>,
+        #[serde(default)]
+        include_acked: bool,
+        #[serde(default)]
+        quarantined: bool,
+    },
+    Ack {
+        ids: Vec<u32>,
+    },
+    Delete {
+        ids: Vec<u32>,
+    },
+    Train {
+        class: TrainClass,
+        ids: Vec<u32>,
+    },
+    Sync {
+        #[serde(default)]
+        since: Option<u64>,
+    },
+    PublishKeyPackages {
+        packages: Vec<Value>,
+    },
+    FetchKeyPackage {
+        pubkey: String,
+    },
+    CountKeyPackages,
+    LookupKeys {
+        address: String,
+    },
+    AddKey {
+        pubkey: String,
+        #[serde(default)]
+        device: Option<String>,
+        key_signature: String,
+    },
+    RotateKey {
+        pubkey: String,
+        #[serde(default)]
+        device: Option<String>,
+        key_signature: String,
+    },
+    RevokeKey {
+        pubkey: String,
+        #[serde(default)]
+        revoked_at: Option<u64>,
+    },
+    FetchKeySet {
+        pubkey: String,
+    },
+    Federate {
+        domain: String,
+        nonce: String,
+    },
+    Relay {
+        message: Value,
+    },
+    RegisterPush {
+        url: String,
+        #[serde(default)]
+        keys: Option<PushKeysRequest>,
+        #[serde(default)]
+        expires: Option<u64>,
+    },
+    UnregisterPush,
+}
+
+impl SignedObject for EsmpRequest {
+    fn version(&self) -> u32 {
+        self.version
+    }
+
+    fn id(&self) -> &str {
+        &self.id
+    }
+
+    fn timestamp(&self) -> u64 {
+        self.timestamp
+    }
+
+    fn signature(&self) -> &str {
+        &self.signature
+    }
+
+    fn sender_pubkey(&self) -> &str {
+        &self.sender_pubkey
+    }
+}
+
+impl<T: SessionStream> Session<T> {
+    pub async fn handle_hello(&mut self, hello: Hello) {
+        if self.version.is_some() {
+            self.write_error(
+                ErrorCode::BadSequence,
+                None,
+                "Protocol version already negotiated",
+            )
+            .await;
+            return;
+        }
+
+        if hello.versions.contains(&ESMP_VERSION) {
+            self.version = Some(ESMP_VERSION);
+            self.write_bytes(
+                Response::Hello {
+                    version: ESMP_VERSION,
+                    capabilities: ESMP_CAPABILITIES,
+                }
+                .serialize(),
+            )
+            .await
+            .ok();
+        } else {
+            self.write_error(
+                ErrorCode::UnsupportedVersion,
+                None,
+                format!("Supported protocol versions: {ESMP_VERSION}"),
+            )
+            .await;
+        }
+    }
+
+    /// Checks that the request is signed by the key bound to the session,
+    /// before its signature is verified and its id remembered.
+    pub(crate) async fn is_session_request(&mut self, request: &EsmpRequest) -> bool {
+        let error = match &self.pubkey {
+            _ if matches!(
+                request.command,
+                Command::Authenticate { .. } | Command::Federate { .. }
+            ) =>
+            {
+                None
+            }
+            _ if self.peer.is_some() && !matches!(request.command, Command::Relay { .. }) => {
+                Some((
+                    ErrorCode::Forbidden,
+                    "Federated sessions can only relay messages",
+                ))
+            }
+            Some(pubkey) if *pubkey == request.sender_pubkey => None,
+            Some(_) => Some((
+                ErrorCode::Forbidden,
+                "Request is not signed by the session key",
+            )),
+            None => Some((ErrorCode::Unauthenticated, "Authentication required")),
+        };
+        if let Some((code, reason)) = error {
+            self.write_error(code, Some(&request.id), reason).await;
+            false
+        } else {
+            true
+        }
+    }
+
+    pub async fn handle_request(&mut self, request: EsmpRequest) {
+        // Inboxes can only be accessed by the identity they are addressed to, or
+        // by the keys bound to the inbox address in the directory
+        let identity = self
+            .identity
+            .clone()
+            .unwrap_or_else(|| request.sender_pubkey.clone());
+        let inbox = request.inbox.as_deref().unwrap_or(&identity);
+        if inbox != request.sender_pubkey && inbox != identity {
+            let error = match self
+                .server
+                .is_address_key(inbox, &request.sender_pubkey)
+                .await
+            {
+                Ok(true) => None,
+                Ok(false) => Some((
+                    ErrorCode::Forbidden,
+                    "Inbox does not belong to the requesting key",
+                )),
+                Err(err) => {
+                    trc::error!(err.span_id(self.session_id));
+                    Some((ErrorCode::ServerFail, "Internal server error"))
+                }
+            };
+            if let Some((code, reason)) = error {
+                self.write_error(code, Some(&request.id), reason).await;
+                return;
+            }
+        }
+
+        let result = match &request.command {
+            Command::Fetch {
+                after,
+                limit,
+                include_acked,
+                quarantined,
+            } => self
+                .server
+                .fetch_inbox(
+                    inbox,
+                    *after,
+                    limit.unwrap_or(MAX_FETCH_LIMIT).clamp(1, MAX_FETCH_LIMIT),
+                    *include_acked,
+                    *quarantined,
+                )
+                .await
+                .map(|page| {
+                    let cursor = page.messages.last().map(|(id, _)| *id);
+                    Response::Messages {
+                        id: &request.id,
+                        messages: page
+                            .messages
+                            .into_iter()
+                            .filter_map(|(id, message)| {
+                                Some(InboxEntry {
+                                    id,
+                                    received_at: message.received_at,
+                                    acked: message.acked,
+                                    spam_score: message.spam_score,
+                                    message: RawValue::from_string(message.contents).ok()?,
+                                })
+                            })
+                            .collect(),
+                        cursor,
+                        has_more: page.has_more,
+                    }
+                }),
+            Command::Ack { ids } | Command::Delete { ids } | Command::Train { ids, .. }
+                if ids.len() > MAX_REQUEST_IDS =>
+            {
+                Ok(Response::Error {
+                    code: ErrorCode::InvalidRequest,
+                    id: request.id.as_str().into(),
+                    reason: "Too many ids in request".into(),
+                })
+            }
+            Command::Ack { ids } => {
+                self.server
+                    .ack_inbox_messages(inbox, ids)
+                    .await
+                    .map(|ids| Response::Ok {
+                        id: &request.id,
+                        ids,
+                    })
+            }
+            Command::Delete { ids } => {
+                self.server
+                    .delete_inbox_messages(inbox, ids)
+                    .await
+                    .map(|ids| Response::Ok {
+                        id: &request.id,
+                        ids,
+                    })
+            }
+            Command::Train { class, ids } => self
+                .server
+                .train_inbox_messages(inbox, ids, *class == TrainClass::Spam, self.session_id)
+                .await
+                .map(|ids| Response::Ok {
+                    id: &request.id,
+                    ids,
+                }),
+            Command::Authenticate { nonce } => Ok(self.handle_authenticate(&request, nonce).await),
+            Command::Federate { domain, nonce } => {
+                Ok(self.handle_federate(&request, domain, nonce).await)
+            }
+            Command::Relay { message } => self.handle_relay(&request, message).await,
+            Command::PublishKeyPackages { packages } => {
+                match parse_key_packages(&request.sender_pubkey, packages) {
+                    Ok(packages) => self
+                        .server
+                        .publish_key_packages(&request.sender_pubkey, packages)
+                        .await
+                        .map(|result| match result {
+                            Ok(count) => Response::KeyPackageCount {
+                                id: &request.id,
+                                count,
+                            },
+                            Err(reason) => Response::Error {
+                                code: ErrorCode::InvalidRequest,
+                                id: request.id.as_str().into(),
+                                reason: reason.into(),
+                            },
+                        }),
+                    Err((code, reason)) => Ok(Response::Error {
+                        code,
+                        id: request.id.as_str().into(),
+                        reason: reason.into(),
+                    }),
+                }
+            }
+            Command::FetchKeyPackage { pubkey } => {
+                self.handle_fetch_key_package(&request, pubkey).await
+            }
+            Command::CountKeyPackages => self
+                .server
+                .key_packages(&request.sender_pubkey)
+                .await
+                .map(|packages| Response::KeyPackageCount {
+                    id: &request.id,
+                    count: packages
+                        .iter()
+                        .filter(|(_, package)| !package.last_resort)
+                        .count(),
+                }),
+            Command::LookupKeys { address } if address_to_email(address).is_none() => {
+                Ok(Response::Error {
+                    code: ErrorCode::InvalidRequest,
+                    id: request.id.as_str().into(),
+                    reason: "Invalid ESMP address".into(),
+                })
+            }
+            Command::LookupKeys { address } => {
+                self.server
+                    .address_keys(address)
+                    .await
+                    .map(|keys| Response::Keys {
+                        id: &request.id,
+                        address,
+                        keys,
+                    })
+            }
+            Command::AddKey {
+                pubkey,
+                device,
+                key_signature,
+            }
+            | Command::RotateKey {
+                pubkey,
+                device,
+                key_signature,
+            } => {
+                self.handle_add_key(&request, pubkey, device.clone(), key_signature)
+                    .await
+            }
+            Command::RevokeKey {
+                revoked_at: Some(revoked_at),
+                ..
+            } if *revoked_at > request.timestamp => Ok(Response::Error {
+                code: ErrorCode::InvalidRequest,
+                id: request.id.as_str().into(),
+                reason: "Revocations cannot be dated after the statement".into(),
+            }),
+            Command::RevokeKey { pubkey, revoked_at } => {
+                self.handle_revoke_key(&request, pubkey, *revoked_at).await
+            }
+            Command::FetchKeySet { pubkey } => {
+                self.server
+                    .key_set(pubkey)
+                    .await
+                    .map(|key_set| Response::KeySet {
+                        id: &request.id,
+                        pubkey,
+                        key_set,
+                    })
+            }
+            Command::RegisterPush { url, keys, expires } => {
+                self.handle_register_push(&request, &identity, url, keys.as_ref(), *expires)
+                    .await
+            }
+            Command::UnregisterPush => self
+                .server
+                .unregister_push_subscription(&request.sender_pubkey)
+                .await
+                .map(|removed| {
+                    if removed {
+                        Response::Ok {
+                            id: &request.id,
+                            ids: vec![],
+                        }
+                    } else {
+                        Response::Error {
+                            code: ErrorCode::InvalidRequest,
+                            id: request.id.as_str().into(),
+                            reason: "No push subscription registered for this key".into(),
+                        }
+                    }
+                }),
+            Command::Sync { since } => {
+                self.server
+                    .inbox_changes(inbox, *since)
+                    .await
+                    .map(|changes| match changes {
+                        Some(changes) => Response::Changes {
+                            id: &request.id,
+                            state: changes.state,
+                            created: changes.created,
+                            updated: changes.updated,
+                            destroyed: changes.destroyed,
+                        },
+                        None => Response::Error {
+                            code: ErrorCode::CannotCalculateChanges,
+                            id: request.id.as_str().into(),
+                            reason: "Changes are no longer available, fetch the inbox again".into(),
+                        },
+                    })
+            }
+        };
+
+        match result {
+            Ok(Response::Error { code, id, reason }) => {
+                self.write_error(code, id, reason).await;
+            }
+            Ok(response) => {
+                self.write_bytes(response.serialize()).await.ok();
+            }
+            Err(err) => {
+                trc::error!(err.span_id(self.session_id));
+                self.write_error(
+                    ErrorCode::ServerFail,
+                    Some(&request.id),
+                    "Internal server error",
+                )
+                .await;
+            }
+        }
+    }
+
+    async fn handle_authenticate<'x>(
+        &mut self,
+        request: &'x EsmpRequest,
+        nonce: &str,
+    ) -> Response<'x> {
+        if self.pubkey.is_some() {
+            return Response::Error {
+                code: ErrorCode::InvalidRequest,
+                id: request.id.as_str().into(),
+                reason: "Session is already authenticated".into(),
+            };
+        }
+
+        // The signed request proves possession of the key for this session only
+        if nonce != self.nonce {
+            trc::event!(
+                Auth(trc::AuthEvent::Failed),
+                SpanId = self.session_id,
+                AccountName = request.sender_pubkey.clone(),
+                Reason = "Invalid challenge nonce",
+            );
+            return Response::Error {
+                code: ErrorCode::Forbidden,
+                id: request.id.as_str().into(),
+                reason: "Invalid challenge nonce".into(),
+            };
+        }
+
+        let identity = match self.server.resolve_identity(&request.sender_pubkey).await {
+            Ok(identity) => identity,
+            Err(err) => {
+                trc::error!(err.span_id(self.session_id));
+                return Response::Error {
+                    code: ErrorCode::ServerFail,
+                    id: request.id.as_str().into(),
+                    reason: "Internal server error".into(),
+                };
+            }
+        };
+
+        if let Err(err) = self.subscribe_push(&identity).await {
+            trc::error!(err.span_id(self.session_id));
+            return Response::Error {
+                code: ErrorCode::ServerFail,
+                id: request.id.as_str().into(),
+                reason: "Internal server error".into(),
+            };
+        }
+
+        trc::event!(
+            Auth(trc::AuthEvent::Success),
+            SpanId = self.session_id,
+            AccountName = request.sender_pubkey.clone(),
+        );
+
+        self.pubkey = Some(request.sender_pubkey.clone());
+        self.identity = Some(identity);
+
+        Response::Authenticated {
+            id: &request.id,
+            pubkey: &request.sender_pubkey,
+        }
+    }
+}
+
+impl<T: SessionStream> Session<T> {
+    async fn handle_add_key<'x>(
+        &self,
+        request: &'x EsmpRequest,
+        pubkey: &str,
+        device: Option<String>,
+        key_signature: &str,
+    ) -> trc::Result<Response<'x>> {
+        // New keys consent to joining the identity by signing it
+        let identity = self.server.resolve_identity(&request.sender_pubkey).await?;
+        if !verify_signature(
+            pubkey,
+            key_signature,
+            &key_proof_input(&identity, pubkey, request.timestamp),
+        ) {
+            return Ok(Response::Error {
+                code: ErrorCode::InvalidSignature,
+                id: request.id.as_str().into(),
+                reason: "Key proof is not signed by the new key".into(),
+            });
+        }
+
+        // Rotations retire the signing key in the same statement
+        let mut changes = vec![KeyChange::Add {
+            pubkey: pubkey.to_string(),
+            device,
+        }];
+        if matches!(request.command, Command::RotateKey { .. }) {
+            changes.push(KeyChange::Revoke {
+                pubkey: request.sender_pubkey.clone(),
+                revoked_at: request.timestamp,
+            });
+        }
+
+        self.server
+            .update_key_set(&request.sender_pubkey, changes)
+            .await
+            .map(|result| key_set_response(request, result))
+    }
+
+    async fn handle_revoke_key<'x>(
+        &self,
+        request: &'x EsmpRequest,
+        pubkey: &str,
+        revoked_at: Option<u64>,
+    ) -> trc::Result<Response<'x>> {
+        // The key that names the identity can only be retired by rotating it
+        if self.server.resolve_identity(&request.sender_pubkey).await? == pubkey {
+            return Ok(Response::Error {
+                code: ErrorCode::Forbidden,
+                id: request.id.as_str().into(),
+                reason: "The identity key cannot be revoked, rotate it instead".into(),
+            });
+        }
+
+        // Revocations cannot be backdated past the replay window, as messages
+        // signed before it may already have been delivered
+        let min_revoked_at = now().saturating_sub(self.server.core.esmp.replay_window.as_secs());
+        self.server
+            .update_key_set(
+                &request.sender_pubkey,
+                vec![KeyChange::Revoke {
+                    pubkey: pubkey.to_string(),
+                    revoked_at: revoked_at.unwrap_or(request.timestamp).max(min_revoked_at),
+                }],
+            )
+            .await
+            .map(|result| key_set_response(request, result))
+    }
+
+    async fn handle_fetch_key_package<'x>(
+        &self,
+        request: &'x EsmpRequest,
+        pubkey: &'x str,
+    ) -> trc::Result<Response<'x>> {
+        // Claims consume one-time packages, so they are limited both for the
+        // requester and for the key whose packages are being claimed
+        for (rate, key) in [
+            (
+                &self.limits.rate_key_package_requester,
+                format!("kp.requester.{}", request.sender_pubkey),
+            ),
+            (
+                &self.limits.rate_key_package_target,
+                format!("kp.target.{pubkey}"),
+            ),
+        ] {
+            let Some(rate) = rate else {
+                continue;
+            };
+            if let Some(retry_in) = self
+                .server
+                .in_memory_store()
+                .is_rate_allowed(KV_RATE_LIMIT_ESMP, key.as_bytes(), rate, false)
+                .await?
+            {
+                trc::event!(
+                    Limit(trc::LimitEvent::TooManyRequests),
+                    SpanId = self.session_id,
+                    AccountName = request.sender_pubkey.clone(),
+                );
+                return Ok(Response::Error {
+                    code: ErrorCode::RateLimited,
+                    id: request.id.as_str().into(),
+                    reason: format!("Key package quota exceeded, retry in {retry_in} seconds")
+                        .into(),
+                });
+            }
+        }
+
+        self.server
+            .claim_key_package(pubkey)
+            .await
+            .map(|package| Response::KeyPackage {
+                id: &request.id,
+                pubkey,
+                package,
+            })
+    }
+}
+
+impl<T: SessionStream> Session<T> {
+    async fn handle_register_push<'x>(
+        &self,
+        request: &'x EsmpRequest,
+        identity: &str,
+        url: &str,
+        keys: Option<&PushKeysRequest>,
+        expires: Option<u64>,
+    ) -> trc::Result<Response<'x>> {
+        let expires = match validate_subscription(url, expires) {
+            Ok(expires) => expires,
+            Err(reason) => {
+                return Ok(Response::Error {
+                    code: ErrorCode::InvalidRequest,
+                    id: request.id.as_str().into(),
+                    reason: reason.into(),
+                });
+            }
+        };
+        let keys = match keys.map(PushKeysRequest::parse).transpose() {
+            Ok(keys) => keys,
+            Err(reason) => {
+                return Ok(Response::Error {
+                    code: ErrorCode::InvalidRequest,
+                    id: request.id.as_str().into(),
+                    reason: reason.into(),
+                });
+            }
+        };
+
+        // Subscriptions are bound to the device key that signed the request
+        self.server
+            .register_push_subscription(PushSubscription {
+                identity: identity.to_string(),
+                pubkey: request.sender_pubkey.clone(),
+                url: url.to_string(),
+                keys,
+                expires,
+                created_at: now(),
+            })
+            .await
+            .map(|_| Response::PushRegistered {
+                id: &request.id,
+                pubkey: &request.sender_pubkey,
+                expires,
+            })
+    }
+}
+
+fn key_set_response<'x>(
+    request: &'x EsmpRequest,
+    result: Result<KeySet, &'static str>,
+) -> Response<'x> {
+    match result {
+        Ok(key_set) => Response::KeySet {
+            id: &request.id,
+            pubkey: &request.sender_pubkey,
+            key_set: Some(key_set),
+        },
+        Err(reason) => Response::Error {
+            code: ErrorCode::Forbidden,
+            id: request.id.as_str().into(),
+            reason: reason.into(),
+        },
+    }
+}
+
+/// Key packages are signed by the key that publishes them so that peers can
+/// verify them independently of the server.
+fn parse_key_packages(
+    pubkey: &str,
+    packages: &[Value],
+) -> Result<Vec<KeyPackage>, (ErrorCode, &'static str)> {
+    if packages.len() > MAX_KEY_PACKAGES {
+        return Err((
+            ErrorCode::InvalidRequest,
+            "Too many key packages in request",
+        ));
+    }
+
+    let mut result = Vec::with_capacity(packages.len());
+    for value in packages {
+        let mut package = serde_json::from_value::<KeyPackage>(value.clone())
+            .map_err(|_| (ErrorCode::InvalidRequest, "Invalid key package"))?;
+        package
+            .validate()
+            .map_err(|reason| (ErrorCode::InvalidRequest, reason))?;
+        if !signing_input(value)
+            .is_some_and(|input| verify_signature(pubkey, &package.signature, &input))
+        {
+            return Err((
+                ErrorCode::InvalidSignature,
+                "Key package is not signed by the publishing key",
+            ));
+        }
+        package.pubkey = pubkey.to_string();
+        package.created_at = now();
+        result.push(package);
+    }
+
+    Ok(result)
+}
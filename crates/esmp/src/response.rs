@@ -0,0 +1,161 @@
+/*
+ * SPDX-FileCopyrightText: 2020 Stalwart Labs LLC <hello@stalw.art>
+ *
+ * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
+ */
+
+use std::borrow::Cow;
+
+use serde::{Serialize, Serializer};
+use serde_json::value::RawValue;
+
+use crate::{e2ee::KeyPackage, keyset::KeySet};
+
+#[derive(Debug, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Response<'x> {
+    Greeting {
+        nonce: &'x str,
+    },
+    Hello {
+        version: u32,
+        capabilities: &'static [&'static str],
+    },
+    Authenticated {
+        id: &'x str,
+        pubkey: &'x str,
+    },
+    Push {
+        id: u32,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        group_id: Option<&'x str>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        spam_score: Option<f64>,
+        message: Box<RawValue>,
+    },
+    Ack {
+        id: &'x str,
+        message_ids: Vec<u32>,
+        #[serde(skip_serializing_if = "Vec::is_empty")]
+        queued: Vec<String>,
+    },
+    Error {
+        code: ErrorCode,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        id: Option<&'x str>,
+        reason: Cow<'static, str>,
+    },
+    Ok {
+        id: &'x str,
+        ids: Vec<u32>,
+    },
+    Messages {
+        id: &'x str,
+        messages: Vec<InboxEntry>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        cursor: Option<u32>,
+        has_more: bool,
+    },
+    Changes {
+        id: &'x str,
+        state: u64,
+        created: Vec<u32>,
+        updated: Vec<u32>,
+        destroyed: Vec<u32>,
+    },
+    KeyPackage {
+        id: &'x str,
+        pubkey: &'x str,
+        package: Option<KeyPackage>,
+    },
+    KeyPackageCount {
+        id: &'x str,
+        count: usize,
+    },
+    Keys {
+        id: &'x str,
+        address: &'x str,
+        keys: Vec<String>,
+    },
+    KeySet {
+        id: &'x str,
+        pubkey: &'x str,
+        key_set: Option<KeySet>,
+    },
+    Federated {
+        id: &'x str,
+        domain: String,
+    },
+    PushRegistered {
+        id: &'x str,
+        pubkey: &'x str,
+        expires: u64,
+    },
+}
+
+#[derive(Debug, Serialize)]
+pub struct InboxEntry {
+    pub id: u32,
+    pub received_at: u64,
+    pub acked: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub spam_score: Option<f64>,
+    pub message: Box<RawValue>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorCode {
+    Parse,
+    BadSequence,
+    UnsupportedVersion,
+    InvalidSignature,
+    InvalidRequest,
+    InvalidMessage,
+    Replay,
+    Expired,
+    Unauthenticated,
+    Forbidden,
+    CannotCalculateChanges,
+    FrameTooLarge,
+    RateLimited,
+    Timeout,
+    KeyRevoked,
+    ServerFail,
+}
+
+impl ErrorCode {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ErrorCode::Parse => "parse",
+            ErrorCode::BadSequence => "bad_sequence",
+            ErrorCode::UnsupportedVersion => "unsupported_version",
+            ErrorCode::InvalidSignature => "invalid_signature",
+            ErrorCode::InvalidRequest => "invalid_request",
+            ErrorCode::InvalidMessage => "invalid_message",
+            ErrorCode::Replay => "replay",
+            ErrorCode::Expired => "expired",
+            ErrorCode::Unauthenticated => "unauthenticated",
+            ErrorCode::Forbidden => "forbidden",
+            ErrorCode::CannotCalculateChanges => "cannot_calculate_changes",
+            ErrorCode::FrameTooLarge => "frame_too_large",
+            ErrorCode::RateLimited => "rate_limited",
+            ErrorCode::Timeout => "timeout",
+            ErrorCode::KeyRevoked => "key_revoked",
+            ErrorCode::ServerFail => "server_fail",
+        }
+    }
+}
+
+impl Serialize for ErrorCode {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.as_str())
+    }
+}
+
+impl Response<'_> {
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut bytes = serde_json::to_vec(self).unwrap_or_default();
+        bytes.push(b'\n');
+        bytes
+    }
+}
@@ -0,0 +1,290 @@
+/*
+ * SPDX-FileCopyrightText: 2020 Stalwart Labs LLC <hello@stalw.art>
+ *
+ * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
+ */
+
+use common::{
+    Server,
+    core::BuildServer,
+    listener::{
+        ServerInstance, SessionData, SessionManager, SessionResult, SessionStream,
+        limiter::InFlight,
+    },
+};
+use std::{net::IpAddr, sync::Arc, time::Instant};
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+use crate::{
+    EsmpSessionManager, Session,
+    crypto::generate_nonce,
+    push::next_state_change,
+    response::{ErrorCode, Response},
+};
+
+impl SessionManager for EsmpSessionManager {
+    #[allow(clippy::manual_async_fn)]
+    fn handle<T: SessionStream>(
+        self,
+        session: SessionData<T>,
+    ) -> impl std::future::Future<Output = ()> + Send {
+        async move {
+            Session::new(
+                self.inner.build_server(),
+                session.instance,
+                session.stream,
+                session.in_flight,
+                session.remote_ip,
+                session.session_id,
+            )
+            .handle_conn()
+            .await;
+        }
+    }
+
+    #[allow(clippy::manual_async_fn)]
+    fn shutdown(&self) -> impl std::future::Future<Output = ()> + Send {
+        async {}
+    }
+}
+
+impl<T: SessionStream> Session<T> {
+    pub fn new(
+        server: Server,
+        instance: Arc<ServerInstance>,
+        stream: T,
+        in_flight: InFlight,
+        remote_addr: IpAddr,
+        session_id: u64,
+    ) -> Self {
+        let limits = server.core.esmp.limits(&instance.id).clone();
+        Session {
+            server,
+            instance,
+            stream,
+            in_flight,
+            remote_addr,
+            session_id,
+            buf: Vec::new(),
+            limits,
+            started: Instant::now(),
+            rate_start: Instant::now(),
+            rate_count: 0,
+            nonce: generate_nonce(),
+            version: None,
+            pubkey: None,
+            identity: None,
+            peer: None,
+            push: None,
+        }
+    }
+
+    pub async fn handle_conn(&mut self) {
+        let mut buf = vec![0; 8192];
+        let mut shutdown_rx = self.instance.shutdown_rx.clone();
+
+        if let Err(err) = self.write_greeting().await {
+            trc::error!(err.span_id(self.session_id));
+            return;
+        }
+
+        let session_deadline = self.started + self.limits.timeout_session;
+        let mut idle_deadline = Instant::now() + self.limits.timeout_idle;
+
+        loop {
+            tokio::select! {
+                result = self.stream.read(&mut buf) => {
+                    match result {
+                        Ok(bytes_read) => {
+                            if bytes_read > 0 {
+                                idle_deadline = Instant::now() + self.limits.timeout_idle;
+                                match self.ingest(&buf[..bytes_read]).await {
+                                    SessionResult::Continue => (),
+                                    SessionResult::UpgradeTls | SessionResult::Close => {
+                                        break;
+                                    }
+                                }
+                            } else {
+                                trc::event!(
+                                    Network(trc::NetworkEvent::Closed),
+                                    SpanId = self.session_id,
+                                    CausedBy = trc::location!()
+                                );
+                                break;
+                            }
+                        },
+                        Err(err) => {
+                            trc::event!(
+                                Network(trc::NetworkEvent::ReadError),
+                                SpanId = self.session_id,
+                                Reason = err.to_string(),
+                                CausedBy = trc::location!()
+                            );
+                            break;
+                        },
+                    }
+                },
+                _ = tokio::time::sleep_until(idle_deadline.min(session_deadline).into()) => {
+                    let reason = if idle_deadline < session_deadline {
+                        "Connection idle for too long"
+                    } else {
+                        "Maximum session duration exceeded"
+                    };
+                    trc::event!(
+                        Network(trc::NetworkEvent::Timeout),
+                        SpanId = self.session_id,
+                        Reason = reason,
+                        CausedBy = trc::location!()
+                    );
+                    self.write_error(ErrorCode::Timeout, None, reason).await;
+                    break;
+                },
+                state_change = next_state_change(&mut self.push) => {
+                    if let Some(state_change) = state_change {
+                        self.push_changes(state_change).await;
+                    } else {
+                        trc::event!(
+                            Esmp(trc::EsmpEvent::Error),
+                            SpanId = self.session_id,
+                            Reason = "State manager channel closed",
+                            CausedBy = trc::location!()
+                        );
+                        self.push = None;
+                    }
+                },
+                _ = shutdown_rx.changed() => {
+                    trc::event!(
+                        Network(trc::NetworkEvent::Closed),
+                        SpanId = self.session_id,
+                        Reason = "Server shutting down",
+                        CausedBy = trc::location!()
+                    );
+                    break;
+                }
+            };
+        }
+    }
+
+    /// Clients prove possession of their key by signing the greeting nonce.
+    pub async fn write_greeting(&mut self) -> trc::Result<()> {
+        let greeting = Response::Greeting { nonce: &self.nonce }.serialize();
+        self.write_bytes(greeting).await
+    }
+
+    pub async fn ingest(&mut self, bytes: &[u8]) -> SessionResult {
+        trc::event!(
+            Esmp(trc::EsmpEvent::RawInput),
+            SpanId = self.session_id,
+            Size = bytes.len(),
+            Contents = trc::Value::from_maybe_string(bytes),
+        );
+
+        // Buffered bytes never contain a complete frame, only the new ones are scanned
+        let mut scan_from = self.buf.len();
+        let mut frame_start = 0;
+        self.buf.extend_from_slice(bytes);
+        while let Some(pos) = self.buf[scan_from..]
+            .iter()
+            .position(|&ch| ch == b'\n')
+            .map(|pos| scan_from + pos)
+        {
+            if pos - frame_start > self.limits.max_frame_size {
+                return self.frame_too_large().await;
+            }
+
+            let line = self.buf[frame_start..=pos].to_vec();
+            frame_start = pos + 1;
+            scan_from = frame_start;
+            match std::str::from_utf8(&line) {
+                Ok(line) => {
+                    let line = line.trim();
+                    if !line.is_empty() {
+                        if self.is_frame_allowed() {
+                            self.handle_message(line).await;
+                        } else {
+                            self.write_error(
+                                ErrorCode::RateLimited,
+                                None,
+                                "Too many frames, slow down",
+                            )
+                            .await;
+                        }
+                    }
+                }
+                Err(_) => {
+                    self.write_error(
+                        ErrorCode::Parse,
+                        None,
+                        "Invalid UTF-8 sequence in ESMP frame",
+                    )
+                    .await;
+                }
+            }
+        }
+        self.buf.drain(..frame_start);
+
+        // Incomplete frames cannot grow past the maximum frame size
+        if self.buf.len() > self.limits.max_frame_size {
+            return self.frame_too_large().await;
+        }
+
+        SessionResult::Continue
+    }
+
+    async fn frame_too_large(&mut self) -> SessionResult {
+        trc::event!(
+            Limit(trc::LimitEvent::SizeRequest),
+            SpanId = self.session_id,
+            Size = self.buf.len(),
+            Limit = self.limits.max_frame_size,
+        );
+        self.write_error(
+            ErrorCode::FrameTooLarge,
+            None,
+            format!(
+                "Frame exceeds the maximum size of {} bytes",
+                self.limits.max_frame_size
+            ),
+        )
+        .await;
+        SessionResult::Close
+    }
+
+    fn is_frame_allowed(&mut self) -> bool {
+        let Some(rate) = &self.limits.rate_connection else {
+            return true;
+        };
+
+        if self.rate_start.elapsed() >= rate.period {
+            self.rate_start = Instant::now();
+            self.rate_count = 0;
+        }
+        self.rate_count += 1;
+        self.rate_count <= rate.requests
+    }
+
+    pub async fn write_bytes(&mut self, bytes: impl AsRef<[u8]>) -> trc::Result<()> {
+        let bytes = bytes.as_ref();
+
+        trc::event!(
+            Esmp(trc::EsmpEvent::RawOutput),
+            SpanId = self.session_id,
+            Size = bytes.len(),
+            Contents = trc::Value::from_maybe_string(bytes),
+        );
+
+        self.stream.write_all(bytes).await.map_err(|err| {
+            trc::NetworkEvent::WriteError
+                .into_err()
+                .reason(err)
+                .caused_by(trc::location!())
+        })?;
+        self.stream.flush().await.map_err(|err| {
+            trc::NetworkEvent::WriteError
+                .into_err()
+                .reason(err)
+                .caused_by(trc::location!())
+        })
+    }
+}
@@ -0,0 +1,205 @@
+/*
+ * SPDX-FileCopyrightText: 2020 Stalwart Labs LLC <hello@stalw.art>
+ *
+ * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
+ */
+
+use std::future::Future;
+
+use common::{KV_REPUTATION_ESMP, Server, config::spamfilter::SpamFilterAction};
+use mail_builder::{
+    MessageBuilder,
+    headers::address::{Address, EmailAddress},
+};
+use mail_parser::MessageParser;
+use serde::Deserialize;
+use spam_filter::{
+    SpamFilterInput,
+    analysis::{
+        bayes::SpamFilterAnalyzeBayes, init::SpamFilterInit,
+        reputation::SpamFilterAnalyzeReputation, score::SpamFilterAnalyzeScore,
+        url::SpamFilterAnalyzeUrl,
+    },
+    modules::bayes::BayesClassifier,
+};
+
+#[cfg(feature = "enterprise")]
+use spam_filter::analysis::llm::SpamFilterAnalyzeLlm;
+
+use crate::{gateway::outbound::body_parts, handler::EsmpMessage, identity::address_to_email};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TrainClass {
+    Spam,
+    Ham,
+}
+
+pub trait EsmpSpamFilter: Sync + Send {
+    /// Returns the score of messages classified as spam.
+    fn esmp_spam_classify(
+        &self,
+        msg: &EsmpMessage,
+        span_id: u64,
+    ) -> impl Future<Output = Option<f64>> + Send;
+
+    fn esmp_spam_train(
+        &self,
+        msg: &EsmpMessage,
+        is_spam: bool,
+        span_id: u64,
+    ) -> impl Future<Output = trc::Result<()>> + Send;
+}
+
+impl EsmpSpamFilter for Server {
+    async fn esmp_spam_classify(&self, msg: &EsmpMessage, span_id: u64) -> Option<f64> {
+        let config = &self.core.esmp.spam;
+        if !config.enable || !msg.is_spam_filtered() {
+            return None;
+        }
+
+        let raw_message = build_spam_message(msg);
+        let message = MessageParser::new().parse(&raw_message)?;
+        let mut ctx = self.spam_filter_init(SpamFilterInput::from_message(&message, span_id));
+
+        if config.check_url {
+            self.spam_filter_analyze_url(&mut ctx).await;
+        }
+        #[cfg(feature = "enterprise")]
+        if config.check_llm {
+            self.spam_filter_analyze_llm(&mut ctx).await;
+        }
+        if config.check_bayes {
+            self.spam_filter_analyze_bayes_classify(&mut ctx).await;
+        }
+        let is_blocked = !matches!(
+            self.spam_filter_score(&mut ctx).await,
+            SpamFilterAction::Allow(_)
+        );
+
+        // Senders are also judged by the previous messages signed with their key
+        let mut score = ctx.result.score;
+        if let Some(reputation_config) = self
+            .core
+            .spam
+            .reputation
+            .as_ref()
+            .filter(|_| config.reputation_weight > 0.0)
+            && let Some(reputation) = self
+                .spam_filter_reputation(
+                    span_id,
+                    KV_REPUTATION_ESMP,
+                    msg.sender_pubkey.as_bytes(),
+                    score,
+                    false,
+                )
+                .await
+        {
+            let reputation = reputation * config.reputation_weight;
+            if reputation > 0.0 {
+                score += (reputation - score) * reputation_config.factor;
+            }
+        }
+
+        if is_blocked || score >= config.threshold {
+            trc::event!(
+                Esmp(trc::EsmpEvent::SpamDetected),
+                SpanId = span_id,
+                AccountName = msg.sender_pubkey.clone(),
+                Id = msg.id.clone(),
+                Value = score,
+                Details = config.action.as_str(),
+                Result = ctx
+                    .result
+                    .tags
+                    .iter()
+                    .map(|tag| trc::Value::from(tag.to_string()))
+                    .collect::<Vec<_>>(),
+            );
+
+            Some(score)
+        } else {
+            None
+        }
+    }
+
+    async fn esmp_spam_train(
+        &self,
+        msg: &EsmpMessage,
+        is_spam: bool,
+        span_id: u64,
+    ) -> trc::Result<()> {
+        let config = &self.core.esmp.spam;
+        if !msg.is_spam_filtered() {
+            return Ok(());
+        }
+
+        if config.check_bayes && self.core.spam.bayes.is_some() {
+            let raw_message = build_spam_message(msg);
+            if let Some(message) = MessageParser::new().parse(&raw_message) {
+                self.bayes_train(
+                    &self.spam_filter_init(SpamFilterInput::from_message(&message, span_id)),
+                    is_spam,
+                    true,
+                )
+                .await?;
+            }
+        }
+
+        // Reported messages count towards the reputation of the sender key
+        if config.reputation_weight > 0.0 {
+            self.spam_filter_reputation(
+                span_id,
+                KV_REPUTATION_ESMP,
+                msg.sender_pubkey.as_bytes(),
+                if is_spam {
+                    config.threshold
+                } else {
+                    -config.threshold
+                },
+                false,
+            )
+            .await;
+        }
+
+        Ok(())
+    }
+}
+
+impl EsmpMessage {
+    /// Returns whether the message has a readable body that can be screened
+    /// for spam. System messages are generated by clients and servers,
+    /// encrypted bodies cannot be analyzed and retractions and reactions
+    /// carry no content.
+    pub fn is_spam_filtered(&self) -> bool {
+        !matches!(
+            self.r#type.as_str(),
+            "system" | "encrypted" | "retract" | "reaction"
+        )
+    }
+}
+
+/// Builds the MIME message analyzed by the spam filter from the body of an
+/// ESMP message.
+fn build_spam_message(msg: &EsmpMessage) -> Vec<u8> {
+    let (subject, text, html) = body_parts(&msg.body);
+
+    let mut builder = MessageBuilder::new();
+    if let Some(from) = msg.from.as_deref().and_then(address_to_email) {
+        builder = builder.from(Address::Address(EmailAddress {
+            name: None,
+            email: from.into(),
+        }));
+    }
+    if let Some(subject) = subject {
+        builder = builder.subject(subject);
+    }
+    if let Some(text) = text {
+        builder = builder.text_body(text);
+    }
+    if let Some(html) = html {
+        builder = builder.html_body(html);
+    }
+
+    builder.write_to_vec().unwrap_or_default()
+}
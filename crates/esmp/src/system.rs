@@ -0,0 +1,105 @@
+/*
+ * SPDX-FileCopyrightText: 2020 Stalwart Labs LLC <hello@stalw.art>
+ *
+ * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
+ */
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SystemMessageType {
+    Joined,
+    Left,
+    Removed,
+    AdminAssigned,
+    AdminRevoked,
+    GroupCreated,
+    GroupRenamed,
+    DescriptionUpdated,
+    DpUpdated,
+    ProfileUpdated,
+    KeyCommit,
+    Invited,
+    JoinRequested,
+    JoinApproved,
+    JoinRejected,
+    JoinPolicyUpdated,
+    InviteRevoked,
+}
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct ProfileChanges {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub first_name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub middle_name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub display_picture: Option<String>,
+}
+
+impl SystemMessageType {
+    pub fn parse(value: &str) -> Option<Self> {
+        hashify::tiny_map!(value.as_bytes(),
+            "joined" => Self::Joined,
+            "left" => Self::Left,
+            "removed" => Self::Removed,
+            "admin_assigned" => Self::AdminAssigned,
+            "admin_revoked" => Self::AdminRevoked,
+            "group_created" => Self::GroupCreated,
+            "group_renamed" => Self::GroupRenamed,
+            "description_updated" => Self::DescriptionUpdated,
+            "dp_updated" => Self::DpUpdated,
+            "profile_updated" => Self::ProfileUpdated,
+            "key_commit" => Self::KeyCommit,
+            "invited" => Self::Invited,
+            "join_requested" => Self::JoinRequested,
+            "join_approved" => Self::JoinApproved,
+            "join_rejected" => Self::JoinRejected,
+            "join_policy_updated" => Self::JoinPolicyUpdated,
+            "invite_revoked" => Self::InviteRevoked,
+        )
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SystemMessageType::Joined => "joined",
+            SystemMessageType::Left => "left",
+            SystemMessageType::Removed => "removed",
+            SystemMessageType::AdminAssigned => "admin_assigned",
+            SystemMessageType::AdminRevoked => "admin_revoked",
+            SystemMessageType::GroupCreated => "group_created",
+            SystemMessageType::GroupRenamed => "group_renamed",
+            SystemMessageType::DescriptionUpdated => "description_updated",
+            SystemMessageType::DpUpdated => "dp_updated",
+            SystemMessageType::ProfileUpdated => "profile_updated",
+            SystemMessageType::KeyCommit => "key_commit",
+            SystemMessageType::Invited => "invited",
+            SystemMessageType::JoinRequested => "join_requested",
+            SystemMessageType::JoinApproved => "join_approved",
+            SystemMessageType::JoinRejected => "join_rejected",
+            SystemMessageType::JoinPolicyUpdated => "join_policy_updated",
+            SystemMessageType::InviteRevoked => "invite_revoked",
+        }
+    }
+
+    pub fn requires_group(&self) -> bool {
+        !matches!(self, SystemMessageType::ProfileUpdated)
+    }
+
+    pub fn requires_actor(&self) -> bool {
+        true
+    }
+
+    pub fn requires_target(&self) -> bool {
+        matches!(
+            self,
+            SystemMessageType::Removed
+                | SystemMessageType::AdminAssigned
+                | SystemMessageType::AdminRevoked
+                | SystemMessageType::JoinApproved
+                | SystemMessageType::JoinRejected
+        )
+    }
+}
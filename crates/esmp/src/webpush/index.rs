@@ -0,0 +1,49 @@
+/*
+ * SPDX-FileCopyrightText: 2020 Stalwart Labs LLC <hello@stalw.art>
+ *
+ * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
+ */
+
+use common::storage::index::{IndexValue, IndexableAndSerializableObject, IndexableObject};
+
+use crate::{IDX_PUBKEY, IDX_RECIPIENT};
+
+use super::{ArchivedPushSubscription, PushSubscription};
+
+impl IndexableObject for PushSubscription {
+    fn index_values(&self) -> impl Iterator<Item = IndexValue<'_>> {
+        [
+            IndexValue::Index {
+                field: IDX_PUBKEY,
+                value: self.pubkey.as_str().into(),
+            },
+            IndexValue::Index {
+                field: IDX_RECIPIENT,
+                value: self.identity.as_str().into(),
+            },
+        ]
+        .into_iter()
+    }
+}
+
+impl IndexableAndSerializableObject for PushSubscription {
+    fn is_versioned() -> bool {
+        false
+    }
+}
+
+impl IndexableObject for &ArchivedPushSubscription {
+    fn index_values(&self) -> impl Iterator<Item = IndexValue<'_>> {
+        [
+            IndexValue::Index {
+                field: IDX_PUBKEY,
+                value: self.pubkey.as_str().into(),
+            },
+            IndexValue::Index {
+                field: IDX_RECIPIENT,
+                value: self.identity.as_str().into(),
+            },
+        ]
+        .into_iter()
+    }
+}
@@ -0,0 +1,75 @@
+/*
+ * SPDX-FileCopyrightText: 2020 Stalwart Labs LLC <hello@stalw.art>
+ *
+ * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
+ */
+
+use base64::{Engine, engine::general_purpose};
+use serde::Deserialize;
+use store::write::now;
+
+pub mod index;
+pub mod persist;
+
+pub const EXPIRES_MAX: u64 = 7 * 24 * 3600; // 7 days
+
+const MAX_URL_LENGTH: usize = 512;
+const P256DH_LEN: usize = 65;
+const AUTH_LEN: usize = 16;
+
+/// A Web Push endpoint woken up when messages are delivered to the identity
+/// of a device key. Each device key registers at most one subscription.
+#[derive(rkyv::Archive, rkyv::Deserialize, rkyv::Serialize, Debug, Clone, PartialEq, Eq)]
+#[rkyv(derive(Debug))]
+pub struct PushSubscription {
+    pub identity: String,
+    pub pubkey: String,
+    pub url: String,
+    pub keys: Option<PushKeys>,
+    pub expires: u64,
+    pub created_at: u64,
+}
+
+#[derive(rkyv::Archive, rkyv::Deserialize, rkyv::Serialize, Debug, Clone, PartialEq, Eq)]
+#[rkyv(derive(Debug))]
+pub struct PushKeys {
+    pub p256dh: Vec<u8>,
+    pub auth: Vec<u8>,
+}
+
+/// RFC 8291 keys as published by the browser, base64url encoded.
+#[derive(Debug, Clone, Deserialize)]
+pub struct PushKeysRequest {
+    pub p256dh: String,
+    pub auth: String,
+}
+
+impl PushKeysRequest {
+    pub fn parse(&self) -> Result<PushKeys, &'static str> {
+        let decode = |value: &str| {
+            general_purpose::URL_SAFE_NO_PAD
+                .decode(value.trim_end_matches('='))
+                .ok()
+        };
+        match (decode(&self.p256dh), decode(&self.auth)) {
+            (Some(p256dh), Some(auth)) if p256dh.len() == P256DH_LEN && auth.len() == AUTH_LEN => {
+                Ok(PushKeys { p256dh, auth })
+            }
+            _ => Err("Invalid push subscription keys"),
+        }
+    }
+}
+
+/// Validates the endpoint of a new subscription and caps its expiration.
+pub fn validate_subscription(url: &str, expires: Option<u64>) -> Result<u64, &'static str> {
+    if url.len() > MAX_URL_LENGTH || !url.starts_with("https://") {
+        return Err("Push endpoints must be HTTPS URLs");
+    }
+
+    let max_expires = now() + EXPIRES_MAX;
+    match expires {
+        Some(expires) if expires <= now() => Err("Push subscription has already expired"),
+        Some(expires) => Ok(expires.min(max_expires)),
+        None => Ok(max_expires),
+    }
+}
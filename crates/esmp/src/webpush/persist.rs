@@ -0,0 +1,250 @@
+/*
+ * SPDX-FileCopyrightText: 2020 Stalwart Labs LLC <hello@stalw.art>
+ *
+ * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
+ */
+
+use std::future::Future;
+
+use common::{
+    Server,
+    ipc::{self, EncryptionKeys, EsmpStateChange, StateEvent, UpdateSubscription},
+    storage::index::ObjectIndexBuilder,
+};
+use jmap_proto::types::collection::Collection;
+use store::{
+    query::Filter,
+    write::{BatchBuilder, now},
+};
+use trc::AddContext;
+use utils::map::bitmap::Bitmap;
+
+use crate::{ESMP_ACCOUNT_ID, IDX_PUBKEY, IDX_RECIPIENT, keyset::persist::KeySetStore};
+
+use super::{PushKeys, PushSubscription};
+
+pub trait EsmpPushStore: Sync + Send {
+    fn register_push_subscription(
+        &self,
+        subscription: PushSubscription,
+    ) -> impl Future<Output = trc::Result<u32>> + Send;
+
+    fn unregister_push_subscription(
+        &self,
+        pubkey: &str,
+    ) -> impl Future<Output = trc::Result<bool>> + Send;
+
+    fn update_esmp_push_subscriptions(&self) -> impl Future<Output = trc::Result<()>> + Send;
+
+    fn notify_push_subscribers(
+        &self,
+        identities: &[String],
+        sender_pubkey: &str,
+        state_change: EsmpStateChange,
+    ) -> impl Future<Output = trc::Result<()>> + Send;
+}
+
+impl EsmpPushStore for Server {
+    async fn register_push_subscription(&self, subscription: PushSubscription) -> trc::Result<u32> {
+        let document_id = self
+            .store()
+            .assign_document_ids(ESMP_ACCOUNT_ID, Collection::EsmpPushSubscription, 1)
+            .await
+            .caused_by(trc::location!())?;
+
+        // Registering a new endpoint replaces the previous one of the device key
+        let mut batch = BatchBuilder::new();
+        batch
+            .with_account_id(ESMP_ACCOUNT_ID)
+            .with_collection(Collection::EsmpPushSubscription);
+        delete_subscriptions(self, &mut batch, &subscription.pubkey).await?;
+        batch
+            .create_document(document_id)
+            .custom(ObjectIndexBuilder::<(), _>::new().with_changes(subscription))
+            .caused_by(trc::location!())?;
+        self.commit_batch(batch).await.caused_by(trc::location!())?;
+
+        self.update_esmp_push_subscriptions()
+            .await
+            .map(|_| document_id)
+    }
+
+    async fn unregister_push_subscription(&self, pubkey: &str) -> trc::Result<bool> {
+        let mut batch = BatchBuilder::new();
+        batch
+            .with_account_id(ESMP_ACCOUNT_ID)
+            .with_collection(Collection::EsmpPushSubscription);
+        if !delete_subscriptions(self, &mut batch, pubkey).await? {
+            return Ok(false);
+        }
+        self.commit_batch(batch).await.caused_by(trc::location!())?;
+
+        self.update_esmp_push_subscriptions().await.map(|_| true)
+    }
+
+    async fn update_esmp_push_subscriptions(&self) -> trc::Result<()> {
+        let document_ids = self
+            .get_document_ids(ESMP_ACCOUNT_ID, Collection::EsmpPushSubscription)
+            .await?
+            .unwrap_or_default();
+        let current_time = now();
+        let mut subscriptions = Vec::with_capacity(document_ids.len() as usize);
+
+        for document_id in document_ids {
+            let Some(subscription) = self
+                .get_archive(
+                    ESMP_ACCOUNT_ID,
+                    Collection::EsmpPushSubscription,
+                    document_id,
+                )
+                .await?
+                .map(|archive| archive.deserialize::<PushSubscription>())
+                .transpose()
+                .caused_by(trc::location!())?
+            else {
+                continue;
+            };
+
+            // Subscriptions are registered without types so that they are
+            // only woken up by the deliveries addressed to their identity
+            if subscription.expires > current_time {
+                subscriptions.push(UpdateSubscription::Verified(ipc::PushSubscription {
+                    id: document_id,
+                    url: subscription.url,
+                    expires: subscription.expires,
+                    types: Bitmap::new(),
+                    keys: subscription.keys.map(Into::into),
+                }));
+            }
+        }
+
+        self.inner
+            .ipc
+            .state_tx
+            .send(StateEvent::UpdateSubscriptions {
+                account_id: ESMP_ACCOUNT_ID,
+                subscriptions,
+            })
+            .await
+            .map_err(|err| {
+                trc::EventType::Server(trc::ServerEvent::ThreadError)
+                    .reason(err)
+                    .caused_by(trc::location!())
+            })
+    }
+
+    async fn notify_push_subscribers(
+        &self,
+        identities: &[String],
+        sender_pubkey: &str,
+        state_change: EsmpStateChange,
+    ) -> trc::Result<()> {
+        let current_time = now();
+        let mut subscription_ids = Vec::new();
+
+        for identity in identities {
+            let document_ids = self
+                .store()
+                .filter(
+                    ESMP_ACCOUNT_ID,
+                    Collection::EsmpPushSubscription,
+                    vec![Filter::eq(IDX_RECIPIENT, identity.as_bytes().to_vec())],
+                )
+                .await
+                .caused_by(trc::location!())?
+                .results;
+
+            for document_id in document_ids {
+                let Some(subscription) = self
+                    .get_archive(
+                        ESMP_ACCOUNT_ID,
+                        Collection::EsmpPushSubscription,
+                        document_id,
+                    )
+                    .await?
+                    .map(|archive| archive.deserialize::<PushSubscription>())
+                    .transpose()
+                    .caused_by(trc::location!())?
+                else {
+                    continue;
+                };
+
+                // The sending device and revoked keys are not woken up
+                if subscription.pubkey != sender_pubkey
+                    && subscription.expires > current_time
+                    && self
+                        .key_set(&subscription.pubkey)
+                        .await?
+                        .is_none_or(|key_set| key_set.is_active(&subscription.pubkey, current_time))
+                {
+                    subscription_ids.push(document_id);
+                }
+            }
+        }
+
+        if !subscription_ids.is_empty() {
+            trc::event!(
+                Esmp(trc::EsmpEvent::PushNotify),
+                AccountName = sender_pubkey.to_string(),
+                Total = subscription_ids.len(),
+            );
+
+            self.notify_esmp_state(ESMP_ACCOUNT_ID, subscription_ids, state_change)
+                .await?;
+        }
+
+        Ok(())
+    }
+}
+
+async fn delete_subscriptions(
+    server: &Server,
+    batch: &mut BatchBuilder,
+    pubkey: &str,
+) -> trc::Result<bool> {
+    let document_ids = server
+        .store()
+        .filter(
+            ESMP_ACCOUNT_ID,
+            Collection::EsmpPushSubscription,
+            vec![Filter::eq(IDX_PUBKEY, pubkey.as_bytes().to_vec())],
+        )
+        .await
+        .caused_by(trc::location!())?
+        .results;
+    let mut deleted = false;
+
+    for document_id in document_ids {
+        if let Some(archive) = server
+            .get_archive(
+                ESMP_ACCOUNT_ID,
+                Collection::EsmpPushSubscription,
+                document_id,
+            )
+            .await?
+        {
+            batch
+                .delete_document(document_id)
+                .custom(
+                    ObjectIndexBuilder::<_, ()>::new().with_current(
+                        archive
+                            .to_unarchived::<PushSubscription>()
+                            .caused_by(trc::location!())?,
+                    ),
+                )
+                .caused_by(trc::location!())?;
+            deleted = true;
+        }
+    }
+
+    Ok(deleted)
+}
+
+impl From<PushKeys> for EncryptionKeys {
+    fn from(keys: PushKeys) -> Self {
+        EncryptionKeys {
+            p256dh: keys.p256dh,
+            auth: keys.auth,
+        }
+    }
+}
@@ -32,7 +32,7 @@ pub enum HttpResponseBody {
     Text(String),
     Binary(Vec<u8>),
     Stream(http_body_util::combinators::BoxBody<hyper::body::Bytes, hyper::Error>),
-    WebsocketUpgrade(String),
+    WebsocketUpgrade(String, Option<&'static str>),
     Empty,
 }
 
@@ -108,8 +108,16 @@ impl HttpResponse {
         self
     }
 
-    pub fn with_websocket_upgrade(mut self, derived_key: String) -> Self {
-        self.body = HttpResponseBody::WebsocketUpgrade(derived_key);
+    pub fn with_websocket_upgrade(self, derived_key: String) -> Self {
+        self.with_websocket_protocol_upgrade(derived_key, Some("jmap"))
+    }
+
+    pub fn with_websocket_protocol_upgrade(
+        mut self,
+        derived_key: String,
+        protocol: Option<&'static str>,
+    ) -> Self {
+        self.body = HttpResponseBody::WebsocketUpgrade(derived_key, protocol);
         self
     }
 
@@ -183,17 +191,21 @@ impl HttpResponse {
                     .boxed(),
             ),
             HttpResponseBody::Stream(stream) => self.builder.body(stream),
-            HttpResponseBody::WebsocketUpgrade(derived_key) => self
-                .builder
-                .header(header::CONNECTION, "upgrade")
-                .header(header::UPGRADE, "websocket")
-                .header("Sec-WebSocket-Accept", &derived_key)
-                .header("Sec-WebSocket-Protocol", "jmap")
-                .body(
+            HttpResponseBody::WebsocketUpgrade(derived_key, protocol) => {
+                let mut builder = self
+                    .builder
+                    .header(header::CONNECTION, "upgrade")
+                    .header(header::UPGRADE, "websocket")
+                    .header("Sec-WebSocket-Accept", &derived_key);
+                if let Some(protocol) = protocol {
+                    builder = builder.header("Sec-WebSocket-Protocol", protocol);
+                }
+                builder.body(
                     Full::new(Bytes::from("Switching to WebSocket protocol"))
                         .map_err(|never| match never {})
                         .boxed(),
-                ),
+                )
+            }
         }
         .unwrap()
     }
@@ -0,0 +1,275 @@
+/*
+ * SPDX-FileCopyrightText: 2020 Stalwart Labs LLC <hello@stalw.art>
+ *
+ * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
+ */
+
+use std::future::Future;
+
+use common::{
+    Server,
+    scripts::functions::image::{image_type, thumbnail},
+};
+use esmp::{
+    blob::{BlobKind, BlobUpload, StoredBlob, parse_blob_id, persist::EsmpBlobStore},
+    group::persist::GroupStore,
+    profile::persist::ProfileStore,
+};
+use http_proto::*;
+use hyper::StatusCode;
+use serde::Serialize;
+use serde_json::json;
+use utils::url_params::UrlParams;
+
+use super::{EsmpRequester, forbidden};
+
+#[derive(Debug, Serialize)]
+pub struct BlobView {
+    pub blob_id: String,
+    pub kind: BlobKind,
+    pub content_type: String,
+    pub size: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub group_id: Option<String>,
+    pub url: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub thumbnail_url: Option<String>,
+}
+
+pub trait EsmpBlobApi: Sync + Send {
+    fn handle_upload_blob(
+        &self,
+        requester: EsmpRequester,
+        params: &UrlParams<'_>,
+        content_type: Option<&str>,
+        data: &[u8],
+        base_url: &str,
+    ) -> impl Future<Output = trc::Result<HttpResponse>> + Send;
+
+    fn handle_download_blob(
+        &self,
+        blob_id: &str,
+        requester: Option<EsmpRequester>,
+        is_thumbnail: bool,
+    ) -> impl Future<Output = trc::Result<HttpResponse>> + Send;
+
+    fn handle_delete_blob(
+        &self,
+        blob_id: &str,
+        requester: EsmpRequester,
+    ) -> impl Future<Output = trc::Result<HttpResponse>> + Send;
+}
+
+impl EsmpBlobApi for Server {
+    async fn handle_upload_blob(
+        &self,
+        requester: EsmpRequester,
+        params: &UrlParams<'_>,
+        content_type: Option<&str>,
+        data: &[u8],
+        base_url: &str,
+    ) -> trc::Result<HttpResponse> {
+        let kind = match params.get("kind").unwrap_or("attachment") {
+            "picture" => BlobKind::Picture,
+            "attachment" => BlobKind::Attachment,
+            _ => {
+                return Err(trc::ResourceEvent::BadParameters
+                    .into_err()
+                    .details("Blob kind must be picture or attachment"));
+            }
+        };
+        if data.is_empty() {
+            return Err(trc::ResourceEvent::BadParameters
+                .into_err()
+                .details("Uploads cannot be empty"));
+        }
+
+        // Group uploads are owned by the identity that is a member of the group
+        let group_id = params.get("group_id").map(|group_id| group_id.to_string());
+        let owner = if let Some(group_id) = &group_id {
+            let Some(metadata) = self.fetch_group_metadata(group_id).await? else {
+                return Err(trc::ResourceEvent::NotFound.into_err());
+            };
+            requester
+                .identities
+                .iter()
+                .find(|identity| metadata.is_member(identity))
+                .ok_or_else(|| forbidden("Only group members can upload to the group"))?
+        } else {
+            requester
+                .identities
+                .first()
+                .ok_or_else(|| forbidden("Uploads require an ESMP identity"))?
+        };
+
+        // Pictures are served inline, so their content type is taken from the image itself
+        let (content_type, thumbnail) = match kind {
+            BlobKind::Picture => {
+                let Some((format, preview)) =
+                    image_type(data).zip(thumbnail(data, self.core.esmp.blob.thumbnail_size))
+                else {
+                    return Err(trc::ResourceEvent::BadParameters
+                        .into_err()
+                        .details("Pictures must be PNG, JPEG, GIF or WebP images"));
+                };
+                (format!("image/{format}"), Some(preview))
+            }
+            BlobKind::Attachment => (
+                content_type
+                    .unwrap_or("application/octet-stream")
+                    .to_string(),
+                None,
+            ),
+        };
+
+        let blob = self
+            .store_blob(BlobUpload {
+                owner: owner.clone(),
+                kind,
+                content_type,
+                group_id,
+                data,
+                thumbnail,
+            })
+            .await?;
+
+        Ok(JsonResponse::with_status(
+            StatusCode::CREATED,
+            json!({
+                "data": BlobView::new(blob, base_url),
+            }),
+        )
+        .into_http_response())
+    }
+
+    async fn handle_download_blob(
+        &self,
+        blob_id: &str,
+        requester: Option<EsmpRequester>,
+        is_thumbnail: bool,
+    ) -> trc::Result<HttpResponse> {
+        let Some(hash) = parse_blob_id(blob_id) else {
+            return Err(trc::ResourceEvent::NotFound.into_err());
+        };
+
+        // Blobs are readable as long as any of their records grants access
+        let mut readable = None;
+        for blob in self.blob_records(&hash).await? {
+            if can_read_blob(self, &blob, requester.as_ref()).await? {
+                readable = Some(blob);
+                break;
+            }
+        }
+        let Some(blob) = readable else {
+            return Err(trc::ResourceEvent::NotFound.into_err());
+        };
+
+        let (hash, content_type) = if is_thumbnail {
+            match blob.thumbnail {
+                Some(thumbnail) => (thumbnail.hash, "image/png".to_string()),
+                None => return Err(trc::ResourceEvent::NotFound.into_err()),
+            }
+        } else {
+            (blob.hash, blob.content_type)
+        };
+        let Some(contents) = self.blob_contents(&hash).await? else {
+            return Err(trc::ResourceEvent::NotFound.into_err());
+        };
+
+        // Attachments are never rendered by the browser
+        Ok(match blob.kind {
+            BlobKind::Picture => HttpResponse::new(StatusCode::OK)
+                .with_content_type(content_type)
+                .with_cache_control("private, immutable, max-age=31536000")
+                .with_binary_body(contents),
+            BlobKind::Attachment => DownloadResponse {
+                filename: blob_id.to_string(),
+                content_type,
+                blob: contents,
+            }
+            .into_http_response(),
+        })
+    }
+
+    async fn handle_delete_blob(
+        &self,
+        blob_id: &str,
+        requester: EsmpRequester,
+    ) -> trc::Result<HttpResponse> {
+        let Some(hash) = parse_blob_id(blob_id) else {
+            return Err(trc::ResourceEvent::NotFound.into_err());
+        };
+
+        let mut deleted = false;
+        for identity in &requester.identities {
+            deleted |= self.delete_blob(&hash, identity).await?;
+        }
+
+        if deleted {
+            Ok(JsonResponse::new(json!({
+                "data": (),
+            }))
+            .into_http_response())
+        } else {
+            Err(trc::ResourceEvent::NotFound.into_err())
+        }
+    }
+}
+
+impl BlobView {
+    fn new(blob: StoredBlob, base_url: &str) -> Self {
+        let url = format!("{base_url}/api/esmp/blobs/{}", blob.blob_id());
+
+        BlobView {
+            blob_id: blob.blob_id(),
+            kind: blob.kind,
+            content_type: blob.content_type,
+            size: blob.size,
+            group_id: blob.group_id,
+            thumbnail_url: blob.thumbnail.as_ref().map(|_| format!("{url}/thumbnail")),
+            url,
+        }
+    }
+}
+
+async fn can_read_blob(
+    server: &Server,
+    blob: &StoredBlob,
+    requester: Option<&EsmpRequester>,
+) -> trc::Result<bool> {
+    if requester.is_some_and(|requester| requester.is(&blob.owner)) {
+        return Ok(true);
+    }
+
+    match (&blob.group_id, blob.kind) {
+        // Group files follow access to the messages that attach them
+        (Some(group_id), _) => match (requester, server.fetch_group_metadata(group_id).await?) {
+            (Some(requester), Some(metadata)) => Ok(blob
+                .posted
+                .iter()
+                .any(|message_id| requester.can_read(&metadata, *message_id))),
+            _ => Ok(false),
+        },
+        // Profile pictures follow the visibility of the display picture
+        (None, BlobKind::Picture) => {
+            let Some(profile) = server.get_profile(&blob.owner).await? else {
+                return Ok(false);
+            };
+            let audience = server
+                .profile_audience(
+                    &profile,
+                    requester
+                        .map(|requester| requester.identities.clone())
+                        .unwrap_or_default(),
+                )
+                .await?;
+            Ok(profile.display_picture.is_visible_to(&audience))
+        }
+        // Direct attachments are shared with the recipients of their uploader
+        (None, BlobKind::Attachment) => Ok(requester.is_some_and(|requester| {
+            blob.recipients
+                .iter()
+                .any(|recipient| requester.is(recipient))
+        })),
+    }
+}
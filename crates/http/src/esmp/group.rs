@@ -0,0 +1,463 @@
+/*
+ * SPDX-FileCopyrightText: 2020 Stalwart Labs LLC <hello@stalw.art>
+ *
+ * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
+ */
+
+use std::future::Future;
+
+use common::Server;
+use esmp::{
+    api::EsmpApi,
+    group::{
+        GroupMessage, GroupMetadata, HistoryCursor,
+        invite::{encode_invite, invite_link},
+        persist::GroupStore,
+    },
+    handler::EsmpMessage,
+    keyset::persist::KeySetStore,
+    system::SystemMessageType,
+};
+use http_proto::*;
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+use utils::url_params::UrlParams;
+
+use super::{EsmpRequester, forbidden};
+
+const MAX_HISTORY_LIMIT: usize = 100;
+
+const UPDATE_SUBTYPES: &[SystemMessageType] = &[
+    SystemMessageType::GroupRenamed,
+    SystemMessageType::DescriptionUpdated,
+    SystemMessageType::DpUpdated,
+    SystemMessageType::JoinPolicyUpdated,
+];
+
+/// Group updates are submitted as system messages signed by an admin.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct GroupUpdate {
+    pub messages: Vec<Value>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct HistoryEntry {
+    pub id: u32,
+    pub received_at: u64,
+    pub message: Value,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub edit: Option<Value>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub reactions: Vec<ReactionSummary>,
+    pub retracted: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ReactionSummary {
+    pub reaction: String,
+    pub count: usize,
+    pub identities: Vec<String>,
+}
+
+pub trait EsmpGroupApi: Sync + Send {
+    fn handle_get_group(
+        &self,
+        group_id: &str,
+        requester: EsmpRequester,
+    ) -> impl Future<Output = trc::Result<HttpResponse>> + Send;
+
+    fn handle_update_group(
+        &self,
+        group_id: &str,
+        requester: EsmpRequester,
+        body: Value,
+    ) -> impl Future<Output = trc::Result<HttpResponse>> + Send;
+
+    fn handle_create_group_invite(
+        &self,
+        group_id: &str,
+        requester: EsmpRequester,
+        body: Value,
+    ) -> impl Future<Output = trc::Result<HttpResponse>> + Send;
+
+    fn handle_revoke_group_invite(
+        &self,
+        group_id: &str,
+        token: &str,
+        requester: EsmpRequester,
+        body: Value,
+    ) -> impl Future<Output = trc::Result<HttpResponse>> + Send;
+
+    fn handle_get_join_requests(
+        &self,
+        group_id: &str,
+        requester: EsmpRequester,
+    ) -> impl Future<Output = trc::Result<HttpResponse>> + Send;
+
+    fn handle_decide_join_request(
+        &self,
+        group_id: &str,
+        pubkey: &str,
+        requester: EsmpRequester,
+        body: Value,
+        approve: bool,
+    ) -> impl Future<Output = trc::Result<HttpResponse>> + Send;
+
+    fn handle_get_group_history(
+        &self,
+        group_id: &str,
+        requester: EsmpRequester,
+        params: &UrlParams<'_>,
+    ) -> impl Future<Output = trc::Result<HttpResponse>> + Send;
+
+    fn handle_get_group_message(
+        &self,
+        group_id: &str,
+        message_id: u32,
+        requester: EsmpRequester,
+    ) -> impl Future<Output = trc::Result<HttpResponse>> + Send;
+}
+
+impl EsmpGroupApi for Server {
+    async fn handle_get_group(
+        &self,
+        group_id: &str,
+        requester: EsmpRequester,
+    ) -> trc::Result<HttpResponse> {
+        let metadata = member_group(self, group_id, &requester).await?;
+
+        Ok(JsonResponse::new(json!({
+            "data": metadata,
+        }))
+        .into_http_response())
+    }
+
+    async fn handle_update_group(
+        &self,
+        group_id: &str,
+        requester: EsmpRequester,
+        body: Value,
+    ) -> trc::Result<HttpResponse> {
+        let update = serde_json::from_value::<GroupUpdate>(body)
+            .map_err(|err| trc::ResourceEvent::BadParameters.into_err().reason(err))?;
+        let messages = update
+            .messages
+            .iter()
+            .map(|message| system_message(group_id, message, UPDATE_SUBTYPES))
+            .collect::<trc::Result<Vec<_>>>()?;
+        let metadata = member_group(self, group_id, &requester).await?;
+        admin_identity(&metadata, &requester)?;
+
+        // Every message is checked before any is applied, so that a rejected
+        // message leaves the group unchanged. Only a concurrent change to the
+        // group can reject a later message once earlier ones were applied.
+        for (index, msg) in messages.iter().enumerate() {
+            if messages[..index].iter().any(|other| other.id == msg.id) {
+                return Err(trc::ResourceEvent::BadParameters
+                    .into_err()
+                    .details("Duplicate message id"));
+            }
+        }
+        for (message, msg) in update.messages.iter().zip(&messages) {
+            self.check_group_message(&requester.identities, message, msg, requester.session_id)
+                .await?
+                .map_err(forbidden)?;
+        }
+        for (message, msg) in update.messages.iter().zip(&messages) {
+            self.submit_group_message(&requester.identities, message, msg, requester.session_id)
+                .await?
+                .map_err(forbidden)?;
+        }
+
+        self.handle_get_group(group_id, requester).await
+    }
+
+    async fn handle_create_group_invite(
+        &self,
+        group_id: &str,
+        requester: EsmpRequester,
+        body: Value,
+    ) -> trc::Result<HttpResponse> {
+        let msg = system_message(group_id, &body, &[SystemMessageType::Invited])?;
+        if !msg.is_invite_lifetime_valid(self.core.esmp.invite.max_expiry.as_secs()) {
+            return Err(trc::ResourceEvent::BadParameters
+                .into_err()
+                .details("Invites must expire within the maximum invite lifetime"));
+        }
+        let metadata = member_group(self, group_id, &requester).await?;
+        admin_identity(&metadata, &requester)?;
+
+        let token = self
+            .submit_group_message(&requester.identities, &body, &msg, requester.session_id)
+            .await?
+            .map(|contents| encode_invite(&contents))
+            .map_err(forbidden)?;
+
+        Ok(JsonResponse::new(json!({
+            "data": {
+                "link": invite_link(&self.core.esmp.federation.domain, &token),
+                "token": token,
+                "expires": msg.invite_expires(),
+            },
+        }))
+        .into_http_response())
+    }
+
+    async fn handle_revoke_group_invite(
+        &self,
+        group_id: &str,
+        token: &str,
+        requester: EsmpRequester,
+        body: Value,
+    ) -> trc::Result<HttpResponse> {
+        let msg = system_message(group_id, &body, &[SystemMessageType::InviteRevoked])?;
+        if msg.invite() != Some(token) {
+            return Err(trc::ResourceEvent::BadParameters
+                .into_err()
+                .details("Message does not revoke this invite"));
+        }
+        let metadata = member_group(self, group_id, &requester).await?;
+        admin_identity(&metadata, &requester)?;
+
+        self.submit_group_message(&requester.identities, &body, &msg, requester.session_id)
+            .await?
+            .map_err(forbidden)?;
+
+        Ok(JsonResponse::new(json!({
+            "data": (),
+        }))
+        .into_http_response())
+    }
+
+    async fn handle_get_join_requests(
+        &self,
+        group_id: &str,
+        requester: EsmpRequester,
+    ) -> trc::Result<HttpResponse> {
+        let metadata = member_group(self, group_id, &requester).await?;
+        admin_identity(&metadata, &requester)?;
+
+        Ok(JsonResponse::new(json!({
+            "data": metadata
+                .pending
+                .iter()
+                .map(|request| json!({
+                    "identity": &request.identity,
+                    "requested_at": request.requested_at,
+                }))
+                .collect::<Vec<_>>(),
+        }))
+        .into_http_response())
+    }
+
+    async fn handle_decide_join_request(
+        &self,
+        group_id: &str,
+        pubkey: &str,
+        requester: EsmpRequester,
+        body: Value,
+        approve: bool,
+    ) -> trc::Result<HttpResponse> {
+        let subtype = if approve {
+            SystemMessageType::JoinApproved
+        } else {
+            SystemMessageType::JoinRejected
+        };
+        let msg = system_message(group_id, &body, &[subtype])?;
+        let metadata = member_group(self, group_id, &requester).await?;
+        admin_identity(&metadata, &requester)?;
+
+        // The decision has to be about the request named in the path
+        let target = msg.target.as_deref().unwrap_or_default();
+        if self.resolve_identity(target).await? != self.resolve_identity(pubkey).await? {
+            return Err(trc::ResourceEvent::BadParameters
+                .into_err()
+                .details("Message target does not match the join request"));
+        }
+
+        self.submit_group_message(&requester.identities, &body, &msg, requester.session_id)
+            .await?
+            .map_err(forbidden)?;
+
+        self.handle_get_join_requests(group_id, requester).await
+    }
+
+    async fn handle_get_group_history(
+        &self,
+        group_id: &str,
+        requester: EsmpRequester,
+        params: &UrlParams<'_>,
+    ) -> trc::Result<HttpResponse> {
+        // Pages are read backwards from the most recent message by default
+        let cursor = match (params.parse::<u32>("before"), params.parse::<u32>("since")) {
+            (Some(_), Some(_)) => {
+                return Err(trc::ResourceEvent::BadParameters
+                    .into_err()
+                    .details("Only one of before and since can be specified"));
+            }
+            (Some(before), None) => HistoryCursor::Before(before),
+            (None, Some(since)) => HistoryCursor::Since(since),
+            (None, None) => HistoryCursor::Before(u32::MAX),
+        };
+        let limit = params
+            .parse::<usize>("limit")
+            .unwrap_or(MAX_HISTORY_LIMIT)
+            .clamp(1, MAX_HISTORY_LIMIT);
+
+        let Some((group_document_id, metadata)) = self.fetch_group(group_id).await? else {
+            return Err(trc::ResourceEvent::NotFound.into_err());
+        };
+        if !requester.has_been_member(&metadata) {
+            return Err(forbidden("Only group members can read the group"));
+        }
+        let page = self
+            .fetch_group_history(
+                group_document_id,
+                &metadata,
+                &requester.identities,
+                cursor,
+                limit,
+            )
+            .await?;
+
+        // The cursor continues the page in the direction it was read
+        let cursor = match cursor {
+            HistoryCursor::Before(_) => page.messages.first(),
+            HistoryCursor::Since(_) => page.messages.last(),
+        }
+        .map(|(id, _)| *id);
+
+        Ok(JsonResponse::new(json!({
+            "data": {
+                "messages": page
+                    .messages
+                    .into_iter()
+                    .filter_map(|(id, message)| HistoryEntry::new(id, message))
+                    .collect::<Vec<_>>(),
+                "cursor": cursor,
+                "has_more": page.has_more,
+            },
+        }))
+        .into_http_response())
+    }
+
+    async fn handle_get_group_message(
+        &self,
+        group_id: &str,
+        message_id: u32,
+        requester: EsmpRequester,
+    ) -> trc::Result<HttpResponse> {
+        let Some((group_document_id, metadata)) = self.fetch_group(group_id).await? else {
+            return Err(trc::ResourceEvent::NotFound.into_err());
+        };
+        if !requester.has_been_member(&metadata) {
+            return Err(forbidden("Only group members can read the group"));
+        }
+
+        // Messages posted outside of the requester's membership are not disclosed
+        if !requester.can_read(&metadata, message_id) {
+            return Err(trc::ResourceEvent::NotFound.into_err());
+        }
+        match self
+            .fetch_group_message(group_document_id, message_id)
+            .await?
+            .and_then(|message| HistoryEntry::new(message_id, message))
+        {
+            Some(entry) => Ok(JsonResponse::new(json!({
+                "data": entry,
+            }))
+            .into_http_response()),
+            None => Err(trc::ResourceEvent::NotFound.into_err()),
+        }
+    }
+}
+
+impl HistoryEntry {
+    /// Builds the aggregated view of a message, with its latest edit and its
+    /// reactions grouped by reaction. Retracted messages have no contents.
+    fn new(id: u32, message: GroupMessage) -> Option<Self> {
+        let mut reactions: Vec<ReactionSummary> = Vec::new();
+        for item in message.reactions {
+            if let Some(summary) = reactions
+                .iter_mut()
+                .find(|summary| summary.reaction == item.reaction)
+            {
+                summary.count += 1;
+                summary.identities.push(item.identity);
+            } else {
+                reactions.push(ReactionSummary {
+                    reaction: item.reaction,
+                    count: 1,
+                    identities: vec![item.identity],
+                });
+            }
+        }
+
+        Some(HistoryEntry {
+            id,
+            received_at: message.received_at,
+            message: if !message.retracted {
+                serde_json::from_str(&message.contents).ok()?
+            } else {
+                Value::Null
+            },
+            edit: message
+                .edit
+                .and_then(|edit| serde_json::from_str(&edit).ok()),
+            reactions,
+            retracted: message.retracted,
+        })
+    }
+}
+
+async fn member_group(
+    server: &Server,
+    group_id: &str,
+    requester: &EsmpRequester,
+) -> trc::Result<GroupMetadata> {
+    let Some(metadata) = server.fetch_group_metadata(group_id).await? else {
+        return Err(trc::ResourceEvent::NotFound.into_err());
+    };
+    if requester.is_member(&metadata) {
+        Ok(metadata)
+    } else {
+        Err(forbidden("Only group members can read the group"))
+    }
+}
+
+/// Parses a system message signed by the requester, which has to be one of
+/// `subtypes` and be addressed to the group.
+fn system_message(
+    group_id: &str,
+    message: &Value,
+    subtypes: &[SystemMessageType],
+) -> trc::Result<EsmpMessage> {
+    let msg = serde_json::from_value::<EsmpMessage>(message.clone())
+        .map_err(|err| trc::ResourceEvent::BadParameters.into_err().reason(err))?;
+    let reason = if msg.r#type != "system" {
+        "Only system messages can be submitted"
+    } else if msg.group_id.as_deref() != Some(group_id) {
+        "Message is not addressed to this group"
+    } else {
+        match msg.validate_system_message() {
+            Ok(subtype) if subtypes.contains(&subtype) => return Ok(msg),
+            Ok(_) => "System message subtype is not accepted by this endpoint",
+            Err(reason) => reason,
+        }
+    };
+
+    Err(trc::ResourceEvent::BadParameters.into_err().details(reason))
+}
+
+fn admin_identity<'x>(
+    metadata: &GroupMetadata,
+    requester: &'x EsmpRequester,
+) -> trc::Result<&'x str> {
+    requester
+        .identities
+        .iter()
+        .find(|identity| metadata.is_admin(identity))
+        .map(String::as_str)
+        .ok_or_else(|| forbidden("Only group admins can perform this action"))
+}
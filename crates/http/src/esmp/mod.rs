@@ -0,0 +1,308 @@
+/*
+ * SPDX-FileCopyrightText: 2020 Stalwart Labs LLC <hello@stalw.art>
+ *
+ * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
+ */
+
+pub mod blob;
+pub mod group;
+pub mod profile;
+pub mod websocket;
+
+use std::future::Future;
+
+use blob::EsmpBlobApi;
+use common::Server;
+use esmp::{
+    api::{EsmpApi, SignedHttpRequest},
+    canonical::parse_json,
+    group::GroupMetadata,
+    identity::EsmpIdentity,
+    keyset::persist::KeySetStore,
+};
+use group::EsmpGroupApi;
+use http_proto::{
+    request::{decode_path_element, fetch_body},
+    *,
+};
+use hyper::{Method, header};
+use profile::EsmpProfileApi;
+use serde_json::Value;
+use utils::{BlobHash, url_params::UrlParams};
+use websocket::EsmpWebSocket;
+
+use crate::auth::authenticate::Authenticator;
+
+const MAX_BODY_SIZE: usize = 1024 * 1024;
+
+pub const HEADER_KEY: &str = "X-ESMP-Key";
+pub const HEADER_ID: &str = "X-ESMP-Id";
+pub const HEADER_TIMESTAMP: &str = "X-ESMP-Timestamp";
+pub const HEADER_SIGNATURE: &str = "X-ESMP-Signature";
+
+/// The ESMP identities an API request acts for.
+#[derive(Debug, Clone, Default)]
+pub struct EsmpRequester {
+    pub identities: Vec<String>,
+    pub session_id: u64,
+}
+
+impl EsmpRequester {
+    pub fn is(&self, identity: &str) -> bool {
+        self.identities.iter().any(|item| item == identity)
+    }
+
+    pub fn is_member(&self, group: &GroupMetadata) -> bool {
+        self.identities
+            .iter()
+            .any(|identity| group.is_member(identity))
+    }
+
+    pub fn has_been_member(&self, group: &GroupMetadata) -> bool {
+        self.identities
+            .iter()
+            .any(|identity| group.has_member(identity))
+    }
+
+    pub fn can_read(&self, group: &GroupMetadata, message_id: u32) -> bool {
+        self.identities
+            .iter()
+            .any(|identity| group.can_read(identity, message_id))
+    }
+}
+
+pub trait EsmpApiHandler: Sync + Send {
+    fn handle_esmp_api_request(
+        &self,
+        req: &mut HttpRequest,
+        session: &HttpSessionData,
+    ) -> impl Future<Output = trc::Result<HttpResponse>> + Send;
+
+    fn authenticate_esmp_request(
+        &self,
+        req: &HttpRequest,
+        session: &HttpSessionData,
+        body: &Value,
+    ) -> impl Future<Output = trc::Result<Option<EsmpRequester>>> + Send;
+}
+
+impl EsmpApiHandler for Server {
+    async fn handle_esmp_api_request(
+        &self,
+        req: &mut HttpRequest,
+        session: &HttpSessionData,
+    ) -> trc::Result<HttpResponse> {
+        // WebSocket sessions authenticate with the same handshake as the TCP transport
+        if req.method() == Method::GET && req.uri().path() == "/api/esmp/ws" {
+            return self.upgrade_esmp_websocket(req, session);
+        }
+
+        let is_upload = req.method() == Method::POST && req.uri().path() == "/api/esmp/blobs";
+        let (bytes, body) = if is_upload {
+            // Uploads are signed over the id of their contents
+            let bytes = fetch_body(req, self.core.esmp.blob.max_size, session.session_id)
+                .await
+                .ok_or_else(|| trc::LimitEvent::SizeUpload.into_err())?;
+            let blob_id = BlobHash::generate(&bytes).to_hex();
+            (bytes, Value::String(blob_id))
+        } else {
+            let bytes = fetch_body(req, MAX_BODY_SIZE, session.session_id)
+                .await
+                .ok_or_else(|| trc::LimitEvent::SizeRequest.into_err())?;
+            let body = if !bytes.is_empty() {
+                parse_json(&bytes)
+                    .map_err(|err| trc::ResourceEvent::BadParameters.into_err().reason(err))?
+            } else {
+                Value::Null
+            };
+            (bytes, body)
+        };
+        let requester = self.authenticate_esmp_request(req, session, &body).await?;
+        let path = req
+            .uri()
+            .path()
+            .split('/')
+            .skip(3)
+            .map(decode_path_element)
+            .collect::<Vec<_>>();
+
+        match (
+            path.first().map(AsRef::as_ref).unwrap_or_default(),
+            path.get(1).map(AsRef::as_ref),
+            path.get(2).map(AsRef::as_ref),
+            path.get(3).map(AsRef::as_ref),
+            req.method(),
+        ) {
+            ("groups", Some(group_id), None, None, &Method::GET) => {
+                self.handle_get_group(group_id, require_requester(requester)?)
+                    .await
+            }
+            ("groups", Some(group_id), None, None, &Method::PUT) => {
+                self.handle_update_group(group_id, require_requester(requester)?, body)
+                    .await
+            }
+            ("groups", Some(group_id), Some("invites"), None, &Method::POST) => {
+                self.handle_create_group_invite(group_id, require_requester(requester)?, body)
+                    .await
+            }
+            ("groups", Some(group_id), Some("invites"), Some(token), &Method::DELETE) => {
+                self.handle_revoke_group_invite(
+                    group_id,
+                    token,
+                    require_requester(requester)?,
+                    body,
+                )
+                .await
+            }
+            ("groups", Some(group_id), Some("requests"), None, &Method::GET) => {
+                self.handle_get_join_requests(group_id, require_requester(requester)?)
+                    .await
+            }
+            ("groups", Some(group_id), Some("requests"), Some(pubkey), &Method::POST) => {
+                self.handle_decide_join_request(
+                    group_id,
+                    pubkey,
+                    require_requester(requester)?,
+                    body,
+                    true,
+                )
+                .await
+            }
+            ("groups", Some(group_id), Some("requests"), Some(pubkey), &Method::DELETE) => {
+                self.handle_decide_join_request(
+                    group_id,
+                    pubkey,
+                    require_requester(requester)?,
+                    body,
+                    false,
+                )
+                .await
+            }
+            ("groups", Some(group_id), Some("messages"), None, &Method::GET) => {
+                self.handle_get_group_history(
+                    group_id,
+                    require_requester(requester)?,
+                    &UrlParams::new(req.uri().query()),
+                )
+                .await
+            }
+            ("groups", Some(group_id), Some("messages"), Some(message_id), &Method::GET) => {
+                self.handle_get_group_message(
+                    group_id,
+                    message_id
+                        .parse()
+                        .map_err(|_| trc::ResourceEvent::NotFound.into_err())?,
+                    require_requester(requester)?,
+                )
+                .await
+            }
+            ("blobs", None, None, None, &Method::POST) => {
+                let base_url = HttpContext::new(session, req)
+                    .resolve_response_url(self)
+                    .await;
+                self.handle_upload_blob(
+                    require_requester(requester)?,
+                    &UrlParams::new(req.uri().query()),
+                    header_value(req, header::CONTENT_TYPE.as_str()),
+                    &bytes,
+                    &base_url,
+                )
+                .await
+            }
+            ("blobs", Some(blob_id), None, None, &Method::GET) => {
+                self.handle_download_blob(blob_id, requester, false).await
+            }
+            ("blobs", Some(blob_id), Some("thumbnail"), None, &Method::GET) => {
+                self.handle_download_blob(blob_id, requester, true).await
+            }
+            ("blobs", Some(blob_id), None, None, &Method::DELETE) => {
+                self.handle_delete_blob(blob_id, require_requester(requester)?)
+                    .await
+            }
+            ("users", Some(pubkey), Some("profile"), None, &Method::GET) => {
+                self.handle_get_profile(pubkey, requester).await
+            }
+            ("users", Some(pubkey), Some("profile"), None, &Method::PUT) => {
+                self.handle_update_profile(pubkey, require_requester(requester)?, body)
+                    .await
+            }
+            _ => Err(trc::ResourceEvent::NotFound.into_err()),
+        }
+    }
+
+    async fn authenticate_esmp_request(
+        &self,
+        req: &HttpRequest,
+        session: &HttpSessionData,
+        body: &Value,
+    ) -> trc::Result<Option<EsmpRequester>> {
+        if let Some(signature) = header_value(req, HEADER_SIGNATURE) {
+            // Enforce anonymous rate limit
+            self.is_http_anonymous_request_allowed(&session.remote_ip)
+                .await?;
+
+            let request = SignedHttpRequest {
+                pubkey: header_value(req, HEADER_KEY).unwrap_or_default(),
+                id: header_value(req, HEADER_ID).unwrap_or_default(),
+                timestamp: header_value(req, HEADER_TIMESTAMP)
+                    .and_then(|timestamp| timestamp.parse().ok())
+                    .unwrap_or_default(),
+                signature,
+                method: req.method().as_str(),
+                path: req.uri().path_and_query().map_or("/", |path| path.as_str()),
+                body,
+            };
+
+            match self.verify_signed_request(&request).await? {
+                Ok(identity) => Ok(Some(EsmpRequester {
+                    identities: vec![identity],
+                    session_id: session.session_id,
+                })),
+                Err(reason) => Err(trc::AuthEvent::Failed
+                    .into_err()
+                    .details(reason)
+                    .id(request.pubkey.to_string())
+                    .caused_by(trc::location!())),
+            }
+        } else if req.headers().contains_key(header::AUTHORIZATION) {
+            // Accounts act for the keys bound to them in the directory
+            let (_in_flight, access_token) = self.authenticate_headers(req, session, false).await?;
+            let mut identities = Vec::new();
+            for pubkey in self.account_keys(access_token.primary_id()).await? {
+                let identity = self.resolve_identity(&pubkey).await?;
+                if !identities.contains(&identity) {
+                    identities.push(identity);
+                }
+            }
+
+            Ok(Some(EsmpRequester {
+                identities,
+                session_id: session.session_id,
+            }))
+        } else {
+            // Enforce anonymous rate limit
+            self.is_http_anonymous_request_allowed(&session.remote_ip)
+                .await?;
+
+            Ok(None)
+        }
+    }
+}
+
+fn header_value<'x>(req: &'x HttpRequest, name: &str) -> Option<&'x str> {
+    req.headers()
+        .get(name)
+        .and_then(|value| value.to_str().ok())
+}
+
+fn require_requester(requester: Option<EsmpRequester>) -> trc::Result<EsmpRequester> {
+    requester.ok_or_else(|| {
+        trc::AuthEvent::Failed
+            .into_err()
+            .details("You have to authenticate first.")
+    })
+}
+
+pub(crate) fn forbidden(reason: &'static str) -> trc::Error {
+    trc::SecurityEvent::Unauthorized.into_err().details(reason)
+}
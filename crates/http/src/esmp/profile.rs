@@ -0,0 +1,244 @@
+/*
+ * SPDX-FileCopyrightText: 2020 Stalwart Labs LLC <hello@stalw.art>
+ *
+ * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
+ */
+
+use std::future::Future;
+
+use common::Server;
+use common::config::esmp::EsmpEncryption;
+use directory::backend::internal::manage;
+use esmp::{
+    keyset::persist::KeySetStore,
+    profile::{ProfileError, ProfileField, UserProfile, Visibility, persist::ProfileStore},
+};
+use http_proto::*;
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+use store::write::now;
+
+use super::{EsmpRequester, forbidden};
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ProfileUpdate {
+    #[serde(default)]
+    pub first_name: Option<FieldUpdate>,
+    #[serde(default)]
+    pub middle_name: Option<FieldUpdate>,
+    #[serde(default)]
+    pub last_name: Option<FieldUpdate>,
+    #[serde(default)]
+    pub display_picture: Option<FieldUpdate>,
+    #[serde(default)]
+    pub address: Option<FieldUpdate>,
+    #[serde(default)]
+    pub contacts: Option<Vec<String>>,
+}
+
+/// Fields keep their value when `value` is absent, and are cleared when it
+/// is null.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FieldUpdate {
+    #[serde(default, deserialize_with = "deserialize_value")]
+    pub value: Option<Option<String>>,
+    #[serde(default)]
+    pub visibility: Option<Visibility>,
+    #[serde(default)]
+    pub allowed: Option<Vec<String>>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ProfileView {
+    pub pubkey: String,
+    pub first_name: ProfileField<String>,
+    pub middle_name: ProfileField<String>,
+    pub last_name: ProfileField<String>,
+    pub display_picture: ProfileField<String>,
+    pub address: ProfileField<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub contacts: Vec<String>,
+    pub updated_at: Option<u64>,
+}
+
+pub trait EsmpProfileApi: Sync + Send {
+    fn handle_get_profile(
+        &self,
+        pubkey: &str,
+        requester: Option<EsmpRequester>,
+    ) -> impl Future<Output = trc::Result<HttpResponse>> + Send;
+
+    fn handle_update_profile(
+        &self,
+        pubkey: &str,
+        requester: EsmpRequester,
+        body: Value,
+    ) -> impl Future<Output = trc::Result<HttpResponse>> + Send;
+}
+
+impl EsmpProfileApi for Server {
+    async fn handle_get_profile(
+        &self,
+        pubkey: &str,
+        requester: Option<EsmpRequester>,
+    ) -> trc::Result<HttpResponse> {
+        let identity = self.resolve_identity(pubkey).await?;
+        let Some(profile) = self.get_profile(&identity).await? else {
+            return Err(trc::ResourceEvent::NotFound.into_err());
+        };
+
+        // Requesters only see the fields shared with their audience
+        let audience = self
+            .profile_audience(
+                &profile,
+                requester
+                    .map(|requester| requester.identities)
+                    .unwrap_or_default(),
+            )
+            .await?;
+        let profile = profile.to_view(&audience);
+
+        Ok(JsonResponse::new(json!({
+            "data": ProfileView::new(profile, &self.core.esmp.encryption)?,
+        }))
+        .into_http_response())
+    }
+
+    async fn handle_update_profile(
+        &self,
+        pubkey: &str,
+        requester: EsmpRequester,
+        body: Value,
+    ) -> trc::Result<HttpResponse> {
+        let update = serde_json::from_value::<ProfileUpdate>(body)
+            .map_err(|err| trc::ResourceEvent::BadParameters.into_err().reason(err))?;
+        let identity = self.resolve_identity(pubkey).await?;
+        if !requester.is(&identity) {
+            return Err(forbidden("Profiles can only be updated by their owner"));
+        }
+
+        let mut profile = self
+            .get_profile(&identity)
+            .await?
+            .unwrap_or_else(|| UserProfile::new(identity.clone()));
+        for (field, update) in [
+            ("first_name", update.first_name),
+            ("middle_name", update.middle_name),
+            ("last_name", update.last_name),
+            ("display_picture", update.display_picture),
+        ] {
+            if let Some(update) = update {
+                let current = profile_field(&profile, field);
+                let visibility = update
+                    .visibility
+                    .or_else(|| current.map(|field| field.visibility));
+                let value = update
+                    .value
+                    .unwrap_or_else(|| current.and_then(|field| field.value.clone()));
+                profile.set_field(field, value, visibility);
+                if let Some(allowed) = update.allowed {
+                    profile.set_allowed(field, resolve_identities(self, allowed).await?);
+                }
+            }
+        }
+        if let Some(update) = update.address {
+            if update.visibility == Some(Visibility::Public) {
+                return Err(trc::ResourceEvent::BadParameters
+                    .into_err()
+                    .details("Address field cannot be marked as public"));
+            }
+            let visibility = update.visibility.unwrap_or(profile.address.visibility);
+            if let Some(allowed) = update.allowed {
+                profile.set_allowed("address", resolve_identities(self, allowed).await?);
+            }
+            if let Some(address) = update.value {
+                profile
+                    .set_address(address, &self.core.esmp.encryption, Some(visibility))
+                    .map_err(|err| match err {
+                        ProfileError::MissingMasterKey => manage::unsupported(
+                            "Addresses require a master key in esmp.encryption.key",
+                        ),
+                        err => trc::ResourceEvent::BadParameters
+                            .into_err()
+                            .details(err.to_string()),
+                    })?;
+            } else {
+                // The sealed address is kept when only its visibility changes
+                profile.address.visibility = visibility;
+            }
+        }
+        if let Some(contacts) = update.contacts {
+            profile.contacts = resolve_identities(self, contacts).await?;
+        }
+        profile.updated_at = Some(now());
+        profile.validate().map_err(|err| {
+            trc::ResourceEvent::BadParameters
+                .into_err()
+                .details(err.to_string())
+        })?;
+        self.save_profile(profile.clone()).await?;
+
+        Ok(JsonResponse::new(json!({
+            "data": ProfileView::new(profile, &self.core.esmp.encryption)?,
+        }))
+        .into_http_response())
+    }
+}
+
+impl ProfileView {
+    fn new(profile: UserProfile, keys: &EsmpEncryption) -> trc::Result<Self> {
+        let address = profile.address(keys).map_err(|err| {
+            trc::EsmpEvent::Error
+                .into_err()
+                .reason(err)
+                .caused_by(trc::location!())
+        })?;
+
+        Ok(ProfileView {
+            first_name: profile.first_name,
+            middle_name: profile.middle_name,
+            last_name: profile.last_name,
+            display_picture: profile.display_picture,
+            address: ProfileField {
+                value: address,
+                visibility: profile.address.visibility,
+                allowed: profile.address.allowed,
+            },
+            contacts: profile.contacts,
+            updated_at: profile.updated_at,
+            pubkey: profile.pubkey,
+        })
+    }
+}
+
+/// Audiences are stored as identities so that they survive key rotations.
+async fn resolve_identities(server: &Server, pubkeys: Vec<String>) -> trc::Result<Vec<String>> {
+    let mut identities = Vec::with_capacity(pubkeys.len());
+    for pubkey in pubkeys {
+        let identity = server.resolve_identity(&pubkey).await?;
+        if !identities.contains(&identity) {
+            identities.push(identity);
+        }
+    }
+    Ok(identities)
+}
+
+fn profile_field<'x>(profile: &'x UserProfile, field: &str) -> Option<&'x ProfileField<String>> {
+    match field {
+        "first_name" => Some(&profile.first_name),
+        "middle_name" => Some(&profile.middle_name),
+        "last_name" => Some(&profile.last_name),
+        "display_picture" => Some(&profile.display_picture),
+        _ => None,
+    }
+}
+
+/// Tells a null value apart from an absent one, which deserializes to `None`.
+fn deserialize_value<'de, D>(deserializer: D) -> Result<Option<Option<String>>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    Option::<String>::deserialize(deserializer).map(Some)
+}
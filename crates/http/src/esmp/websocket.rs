@@ -0,0 +1,257 @@
+/*
+ * SPDX-FileCopyrightText: 2020 Stalwart Labs LLC <hello@stalw.art>
+ *
+ * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
+ */
+
+use std::{future::Future, time::Instant};
+
+use common::{
+    Server,
+    listener::{SessionResult, limiter::InFlight, stream::NullIo},
+};
+use esmp::{Session, push::next_state_change, response::ErrorCode};
+use futures_util::{SinkExt, StreamExt};
+use http_proto::*;
+use hyper::{StatusCode, upgrade::Upgraded};
+use hyper_util::rt::TokioIo;
+use tokio_tungstenite::WebSocketStream;
+use tungstenite::{Message, handshake::derive_accept_key, protocol::Role};
+
+pub trait EsmpWebSocket: Sync + Send {
+    fn upgrade_esmp_websocket(
+        &self,
+        req: &mut HttpRequest,
+        session: &HttpSessionData,
+    ) -> trc::Result<HttpResponse>;
+
+    fn handle_esmp_websocket(
+        &self,
+        stream: WebSocketStream<TokioIo<Upgraded>>,
+        session: Session<NullIo>,
+    ) -> impl Future<Output = ()> + Send;
+}
+
+impl EsmpWebSocket for Server {
+    fn upgrade_esmp_websocket(
+        &self,
+        req: &mut HttpRequest,
+        session: &HttpSessionData,
+    ) -> trc::Result<HttpResponse> {
+        let headers = req.headers();
+        let is_upgrade = headers
+            .get(hyper::header::CONNECTION)
+            .and_then(|h| h.to_str().ok())
+            .is_some_and(|h| {
+                h.split(',')
+                    .any(|value| value.trim().eq_ignore_ascii_case("upgrade"))
+            })
+            && headers
+                .get(hyper::header::UPGRADE)
+                .and_then(|h| h.to_str().ok())
+                .is_some_and(|h| h.eq_ignore_ascii_case("websocket"));
+        let derived_key = match (
+            headers
+                .get("Sec-WebSocket-Key")
+                .and_then(|h| h.to_str().ok()),
+            headers
+                .get("Sec-WebSocket-Version")
+                .and_then(|h| h.to_str().ok()),
+        ) {
+            (Some(key), Some("13")) if is_upgrade => derive_accept_key(key.as_bytes()),
+            _ => {
+                return Err(trc::ResourceEvent::BadParameters
+                    .into_err()
+                    .details("WebSocket upgrade failed")
+                    .ctx(
+                        trc::Key::Reason,
+                        "Missing or Invalid Connection, Upgrade or Sec-WebSocket headers.",
+                    ));
+            }
+        };
+
+        // Each text message carries one or more frames of the line protocol
+        let server = self.clone();
+        let session = Session::new(
+            self.clone(),
+            session.instance.clone(),
+            NullIo::default(),
+            InFlight::default(),
+            session.remote_ip,
+            session.session_id,
+        );
+        let on_upgrade = hyper::upgrade::on(req);
+        tokio::spawn(async move {
+            let session_id = session.session_id;
+            match on_upgrade.await {
+                Ok(upgraded) => {
+                    Box::pin(
+                        server.handle_esmp_websocket(
+                            WebSocketStream::from_raw_socket(
+                                TokioIo::new(upgraded),
+                                Role::Server,
+                                None,
+                            )
+                            .await,
+                            session,
+                        ),
+                    )
+                    .await;
+                }
+                Err(err) => {
+                    trc::event!(
+                        Esmp(trc::EsmpEvent::Error),
+                        Details = "Websocket upgrade failed",
+                        SpanId = session_id,
+                        Reason = err.to_string()
+                    );
+                }
+            }
+        });
+
+        Ok(HttpResponse::new(StatusCode::SWITCHING_PROTOCOLS)
+            .with_websocket_protocol_upgrade(derived_key, None))
+    }
+
+    async fn handle_esmp_websocket(
+        &self,
+        mut stream: WebSocketStream<TokioIo<Upgraded>>,
+        mut session: Session<NullIo>,
+    ) {
+        let mut shutdown_rx = session.instance.shutdown_rx.clone();
+
+        if let Err(err) = session.write_greeting().await {
+            trc::error!(err.span_id(session.session_id));
+            return;
+        }
+        if !flush_frames(&mut session, &mut stream).await {
+            return;
+        }
+
+        let session_deadline = session.started + session.limits.timeout_session;
+        let mut idle_deadline = Instant::now() + session.limits.timeout_idle;
+
+        loop {
+            let result = tokio::select! {
+                event = stream.next() => {
+                    match event {
+                        Some(Ok(Message::Text(text))) => {
+                            idle_deadline = Instant::now() + session.limits.timeout_idle;
+                            let mut bytes = text.as_bytes().to_vec();
+                            if !bytes.ends_with(b"\n") {
+                                bytes.push(b'\n');
+                            }
+                            session.ingest(&bytes).await
+                        }
+                        Some(Ok(Message::Binary(_))) => {
+                            session
+                                .write_error(
+                                    ErrorCode::Parse,
+                                    None,
+                                    "ESMP frames must be sent as text messages",
+                                )
+                                .await;
+                            SessionResult::Continue
+                        }
+                        Some(Ok(Message::Ping(bytes))) => {
+                            if stream.send(Message::Pong(bytes)).await.is_err() {
+                                break;
+                            }
+                            SessionResult::Continue
+                        }
+                        Some(Ok(Message::Close(frame))) => {
+                            let _ = stream.close(frame).await;
+                            break;
+                        }
+                        Some(Ok(_)) => SessionResult::Continue,
+                        Some(Err(err)) => {
+                            trc::event!(
+                                Network(trc::NetworkEvent::ReadError),
+                                SpanId = session.session_id,
+                                Reason = err.to_string(),
+                                CausedBy = trc::location!()
+                            );
+                            break;
+                        }
+                        None => {
+                            trc::event!(
+                                Network(trc::NetworkEvent::Closed),
+                                SpanId = session.session_id,
+                                CausedBy = trc::location!()
+                            );
+                            break;
+                        }
+                    }
+                },
+                _ = tokio::time::sleep_until(idle_deadline.min(session_deadline).into()) => {
+                    let reason = if idle_deadline < session_deadline {
+                        "Connection idle for too long"
+                    } else {
+                        "Maximum session duration exceeded"
+                    };
+                    trc::event!(
+                        Network(trc::NetworkEvent::Timeout),
+                        SpanId = session.session_id,
+                        Reason = reason,
+                        CausedBy = trc::location!()
+                    );
+                    session.write_error(ErrorCode::Timeout, None, reason).await;
+                    SessionResult::Close
+                },
+                state_change = next_state_change(&mut session.push) => {
+                    if let Some(state_change) = state_change {
+                        session.push_changes(state_change).await;
+                    } else {
+                        trc::event!(
+                            Esmp(trc::EsmpEvent::Error),
+                            SpanId = session.session_id,
+                            Reason = "State manager channel closed",
+                            CausedBy = trc::location!()
+                        );
+                        session.push = None;
+                    }
+                    SessionResult::Continue
+                },
+                _ = shutdown_rx.changed() => {
+                    trc::event!(
+                        Network(trc::NetworkEvent::Closed),
+                        SpanId = session.session_id,
+                        Reason = "Server shutting down",
+                        CausedBy = trc::location!()
+                    );
+                    break;
+                }
+            };
+
+            if !flush_frames(&mut session, &mut stream).await || result != SessionResult::Continue {
+                let _ = stream.close(None).await;
+                break;
+            }
+        }
+    }
+}
+
+/// Sends the frames written by the session, one per text message.
+async fn flush_frames(
+    session: &mut Session<NullIo>,
+    stream: &mut WebSocketStream<TokioIo<Upgraded>>,
+) -> bool {
+    let tx_buf = std::mem::take(&mut session.stream.tx_buf);
+    for frame in tx_buf
+        .split(|&ch| ch == b'\n')
+        .filter(|frame| !frame.is_empty())
+    {
+        let frame = String::from_utf8_lossy(frame).into_owned();
+        if let Err(err) = stream.send(Message::Text(frame.into())).await {
+            trc::event!(
+                Network(trc::NetworkEvent::WriteError),
+                SpanId = session.session_id,
+                Reason = err.to_string(),
+                CausedBy = trc::location!()
+            );
+            return false;
+        }
+    }
+
+    true
+}
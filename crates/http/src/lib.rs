@@ -6,6 +6,7 @@
 
 pub mod auth;
 pub mod autoconfig;
+pub mod esmp;
 pub mod form;
 pub mod management;
 pub mod request;
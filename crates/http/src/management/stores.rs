@@ -151,6 +151,7 @@ impl ManageStore for Server {
                     }
                     Some("rate-http-anonymous") => vec![KV_RATE_LIMIT_HTTP_ANONYMOUS].into(),
                     Some("rate-imap") => vec![KV_RATE_LIMIT_IMAP].into(),
+                    Some("rate-esmp") => vec![KV_RATE_LIMIT_ESMP].into(),
                     Some("reputation-ip") => vec![KV_REPUTATION_IP].into(),
                     Some("reputation-from") => vec![KV_REPUTATION_FROM].into(),
                     Some("reputation-domain") => vec![KV_REPUTATION_DOMAIN].into(),
@@ -54,6 +54,7 @@ use crate::{
         },
     },
     autoconfig::Autoconfig,
+    esmp::EsmpApiHandler,
     form::FormHandler,
     management::{ManagementApi, ToManageHttpResponse, troubleshoot::TroubleshootApi},
 };
@@ -391,6 +392,11 @@ impl ParseHttp for Server {
                     return Ok(JsonProblemResponse(StatusCode::NO_CONTENT).into_http_response());
                 }
 
+                // ESMP clients may also authenticate with signed requests
+                if path.next() == Some("esmp") {
+                    return self.handle_esmp_api_request(&mut req, &session).await;
+                }
+
                 // Authenticate user
                 match self.authenticate_headers(&req, &session, true).await {
                     Ok((_, access_token)) => {
@@ -32,6 +32,17 @@ pub enum Collection {
     FileNode = 12,
     #[default]
     None = 13,
+    // ESMP collections follow the sentinel to keep the JMAP values stable
+    EsmpGroup = 14,
+    EsmpMessage = 15,
+    EsmpProfile = 16,
+    EsmpInbox = 17,
+    EsmpInboxMessage = 18,
+    EsmpKeyPackage = 19,
+    EsmpKeySet = 20,
+    EsmpQueueMessage = 21,
+    EsmpBlob = 22,
+    EsmpPushSubscription = 23,
 }
 
 #[derive(Debug, Clone, Copy, Hash, PartialEq, Eq, Default)]
@@ -47,6 +58,9 @@ pub enum SyncCollection {
     SieveScript = 7,
     #[default]
     None = 8,
+    // ESMP collections follow the sentinel to keep the JMAP values stable
+    Esmp = 9,
+    EsmpInbox = 10,
 }
 
 #[derive(Debug, Clone, Copy, Hash, PartialEq, Eq)]
@@ -128,6 +142,20 @@ impl SyncCollection {
             SyncCollection::Identity => Collection::Identity,
             SyncCollection::EmailSubmission => Collection::EmailSubmission,
             SyncCollection::SieveScript => Collection::SieveScript,
+            SyncCollection::Esmp => {
+                if is_container {
+                    Collection::EsmpGroup
+                } else {
+                    Collection::EsmpMessage
+                }
+            }
+            SyncCollection::EsmpInbox => {
+                if is_container {
+                    Collection::EsmpInbox
+                } else {
+                    Collection::EsmpInboxMessage
+                }
+            }
             SyncCollection::None => Collection::None,
         }
     }
@@ -159,6 +187,10 @@ impl From<Collection> for SyncCollection {
             Collection::AddressBook => SyncCollection::AddressBook,
             Collection::ContactCard => SyncCollection::AddressBook,
             Collection::FileNode => SyncCollection::FileNode,
+            Collection::EsmpGroup => SyncCollection::Esmp,
+            Collection::EsmpMessage => SyncCollection::Esmp,
+            Collection::EsmpInbox => SyncCollection::EsmpInbox,
+            Collection::EsmpInboxMessage => SyncCollection::EsmpInbox,
             _ => SyncCollection::None,
         }
     }
@@ -180,6 +212,16 @@ impl From<u8> for Collection {
             10 => Collection::AddressBook,
             11 => Collection::ContactCard,
             12 => Collection::FileNode,
+            14 => Collection::EsmpGroup,
+            15 => Collection::EsmpMessage,
+            16 => Collection::EsmpProfile,
+            17 => Collection::EsmpInbox,
+            18 => Collection::EsmpInboxMessage,
+            19 => Collection::EsmpKeyPackage,
+            20 => Collection::EsmpKeySet,
+            21 => Collection::EsmpQueueMessage,
+            22 => Collection::EsmpBlob,
+            23 => Collection::EsmpPushSubscription,
             _ => Collection::None,
         }
     }
@@ -196,6 +238,8 @@ impl From<u8> for SyncCollection {
             5 => SyncCollection::Identity,
             6 => SyncCollection::EmailSubmission,
             7 => SyncCollection::SieveScript,
+            9 => SyncCollection::Esmp,
+            10 => SyncCollection::EsmpInbox,
             _ => SyncCollection::None,
         }
     }
@@ -217,6 +261,16 @@ impl From<u64> for Collection {
             10 => Collection::AddressBook,
             11 => Collection::ContactCard,
             12 => Collection::FileNode,
+            14 => Collection::EsmpGroup,
+            15 => Collection::EsmpMessage,
+            16 => Collection::EsmpProfile,
+            17 => Collection::EsmpInbox,
+            18 => Collection::EsmpInboxMessage,
+            19 => Collection::EsmpKeyPackage,
+            20 => Collection::EsmpKeySet,
+            21 => Collection::EsmpQueueMessage,
+            22 => Collection::EsmpBlob,
+            23 => Collection::EsmpPushSubscription,
             _ => Collection::None,
         }
     }
@@ -285,6 +339,16 @@ impl Collection {
             Collection::AddressBook => "addressBook",
             Collection::ContactCard => "contactCard",
             Collection::FileNode => "fileNode",
+            Collection::EsmpGroup => "esmpGroup",
+            Collection::EsmpMessage => "esmpMessage",
+            Collection::EsmpProfile => "esmpProfile",
+            Collection::EsmpInbox => "esmpInbox",
+            Collection::EsmpInboxMessage => "esmpInboxMessage",
+            Collection::EsmpKeyPackage => "esmpKeyPackage",
+            Collection::EsmpKeySet => "esmpKeySet",
+            Collection::EsmpQueueMessage => "esmpQueueMessage",
+            Collection::EsmpBlob => "esmpBlob",
+            Collection::EsmpPushSubscription => "esmpPushSubscription",
             Collection::None => "",
         }
     }
@@ -308,6 +372,16 @@ impl FromStr for Collection {
             "addressBook" => Collection::AddressBook,
             "contactCard" => Collection::ContactCard,
             "fileNode" => Collection::FileNode,
+            "esmpGroup" => Collection::EsmpGroup,
+            "esmpMessage" => Collection::EsmpMessage,
+            "esmpProfile" => Collection::EsmpProfile,
+            "esmpInbox" => Collection::EsmpInbox,
+            "esmpInboxMessage" => Collection::EsmpInboxMessage,
+            "esmpKeyPackage" => Collection::EsmpKeyPackage,
+            "esmpKeySet" => Collection::EsmpKeySet,
+            "esmpQueueMessage" => Collection::EsmpQueueMessage,
+            "esmpBlob" => Collection::EsmpBlob,
+            "esmpPushSubscription" => Collection::EsmpPushSubscription,
         )
         .ok_or(())
     }
@@ -340,6 +414,8 @@ impl SyncCollection {
             SyncCollection::Identity => "identity",
             SyncCollection::EmailSubmission => "emailSubmission",
             SyncCollection::SieveScript => "sieveScript",
+            SyncCollection::Esmp => "esmp",
+            SyncCollection::EsmpInbox => "esmpInbox",
             SyncCollection::None => "",
         }
     }
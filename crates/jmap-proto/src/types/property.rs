@@ -19,7 +19,8 @@ pub enum Property {
     Acl,
     Aliases,
     Attachments,
-        BlobId,
+    Bcc,
+    BlobId,
     BodyStructure,
     BodyValues,
     Capabilities,
@@ -75,7 +76,8 @@ pub enum Property {
     SentAt,
     Size,
     SortOrder,
-        SubParts,
+    Subject,
+    SubParts,
     TextBody,
     TextSignature,
     ThreadId,
@@ -336,6 +338,7 @@ fn parse_property(first_char: u8, hash: u128) -> Option<Property> {
             _ => return None,
         },
         b'b' => match hash {
+            0x6363 => Property::Bcc,
             0x0064_4962_6f6c => Property::BlobId,
             0x6572_7574_6375_7274_5379_646f => Property::BodyStructure,
             0x0073_6575_6c61_5679_646f => Property::BodyValues,
@@ -434,7 +437,8 @@ fn parse_property(first_char: u8, hash: u128) -> Option<Property> {
             0x0074_4174_6e65 => Property::SentAt,
             0x0065_7a69 => Property::Size,
             0x7265_6472_4f74_726f => Property::SortOrder,
-            0x7463_656a_6275 => Property::            0x7374_7261_5062_7573 => Property::SubParts,
+            0x7463_656a_6275 => Property::Subject,
+            0x7374_7261_5062_7573 => Property::SubParts,
             _ => return None,
         },
         b't' => match hash {
@@ -754,8 +758,10 @@ impl Property {
             Property::From => HeaderName::From,
             Property::To => HeaderName::To,
             Property::Cc => HeaderName::Cc,
-            Property::Bcc => HeaderName::            Property::ReplyTo => HeaderName::ReplyTo,
-            Property::Subject => HeaderName::            Property::SentAt => HeaderName::Date,
+            Property::Bcc => HeaderName::Bcc,
+            Property::ReplyTo => HeaderName::ReplyTo,
+            Property::Subject => HeaderName::Subject,
+            Property::SentAt => HeaderName::Date,
             _ => unreachable!(),
         }
     }
@@ -767,7 +773,8 @@ impl Display for Property {
             Property::Acl => write!(f, "acl"),
             Property::Aliases => write!(f, "aliases"),
             Property::Attachments => write!(f, "attachments"),
-                        Property::BlobId => write!(f, "blobId"),
+            Property::Bcc => write!(f, "bcc"),
+            Property::BlobId => write!(f, "blobId"),
             Property::BodyStructure => write!(f, "bodyStructure"),
             Property::BodyValues => write!(f, "bodyValues"),
             Property::Capabilities => write!(f, "capabilities"),
@@ -823,7 +830,8 @@ impl Display for Property {
             Property::SentAt => write!(f, "sentAt"),
             Property::Size => write!(f, "size"),
             Property::SortOrder => write!(f, "sortOrder"),
-                        Property::SubParts => write!(f, "subParts"),
+            Property::Subject => write!(f, "subject"),
+            Property::SubParts => write!(f, "subParts"),
             Property::TextBody => write!(f, "textBody"),
             Property::TextSignature => write!(f, "textSignature"),
             Property::ThreadId => write!(f, "threadId"),
@@ -886,7 +894,8 @@ impl Property {
             Property::Acl => "acl",
             Property::Aliases => "aliases",
             Property::Attachments => "attachments",
-                        Property::BlobId => "blobId",
+            Property::Bcc => "bcc",
+            Property::BlobId => "blobId",
             Property::BodyStructure => "bodyStructure",
             Property::BodyValues => "bodyValues",
             Property::Capabilities => "capabilities",
@@ -942,7 +951,8 @@ impl Property {
             Property::SentAt => "sentAt",
             Property::Size => "size",
             Property::SortOrder => "sortOrder",
-                        Property::SubParts => "subParts",
+            Property::Subject => "subject",
+            Property::SubParts => "subParts",
             Property::TextBody => "textBody",
             Property::TextSignature => "textSignature",
             Property::ThreadId => "threadId",
@@ -1095,7 +1105,8 @@ impl From<&Property> for u8 {
             Property::SentAt => 26,
             Property::Size => 27,
             Property::SortOrder => 28,
-                        Property::SubParts => 30,
+            Property::Subject => 29,
+            Property::SubParts => 30,
             Property::TextBody => 31,
             Property::TextSignature => 32,
             Property::ThreadId => 33,
@@ -1134,7 +1145,8 @@ impl From<&Property> for u8 {
             Property::Acl => 66,
             Property::Aliases => 67,
             Property::Attachments => 68,
-                        Property::BlobId => 70,
+            Property::Bcc => 69,
+            Property::BlobId => 70,
             Property::BodyStructure => 71,
             Property::BodyValues => 72,
             Property::Capabilities => 73,
@@ -1182,11 +1194,13 @@ impl From<Property> for u8 {
 impl Property {
     pub fn from_header(header: &HeaderName) -> Self {
         match header {
-            HeaderName::Subject => Property::            HeaderName::From => Property::From,
+            HeaderName::Subject => Property::Subject,
+            HeaderName::From => Property::From,
             HeaderName::To => Property::To,
             HeaderName::Cc => Property::Cc,
             HeaderName::Date => Property::SentAt,
-            HeaderName::Bcc => Property::            HeaderName::ReplyTo => Property::ReplyTo,
+            HeaderName::Bcc => Property::Bcc,
+            HeaderName::ReplyTo => Property::ReplyTo,
             HeaderName::Sender => Property::Sender,
             HeaderName::InReplyTo => Property::InReplyTo,
             HeaderName::MessageId => Property::MessageId,
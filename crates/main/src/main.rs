@@ -7,6 +7,7 @@
 #![warn(clippy::large_futures)]
 
 use common::{config::server::ServerProtocol, core::BuildServer, manager::boot::BootManager};
+use esmp::{EsmpSessionManager, StartEsmpServices};
 use http::HttpSessionManager;
 use imap::core::ImapSessionManager;
 use managesieve::core::ManageSieveSessionManager;
@@ -42,6 +43,7 @@ async fn main() -> std::io::Result<()> {
     // Init services
     init.start_services().await;
     init.start_queue_manager();
+    init.start_esmp_services();
 
     // Log configuration errors
     init.config.log_errors();
@@ -84,6 +86,12 @@ async fn main() -> std::io::Result<()> {
                 acceptor,
                 shutdown_rx,
             ),
+            ServerProtocol::Esmp => server.spawn(
+                EsmpSessionManager::new(init.inner.clone()),
+                init.inner.clone(),
+                acceptor,
+                shutdown_rx,
+            ),
         };
     });
 
@@ -25,6 +25,7 @@ use common::telemetry::{
 };
 
 use email::message::delete::EmailDeletion;
+use esmp::profile::persist::ProfileStore;
 use smtp::reporting::SmtpReporting;
 use store::{PurgeStore, write::now};
 use tokio::sync::mpsc;
@@ -41,6 +42,7 @@ enum ActionClass {
     Account,
     Store(usize),
     Acme(String),
+    EsmpReencrypt,
     OtelMetrics,
     #[cfg(feature = "enterprise")]
     InternalMetrics,
@@ -87,6 +89,11 @@ pub fn spawn_housekeeper(inner: Arc<Inner>, mut rx: mpsc::Receiver<HousekeeperEv
                 }
             }
 
+            // ESMP profile re-encryption, runs on startup to pick up rotated master keys
+            if server.core.esmp.encryption.active.is_some() {
+                queue.schedule(Instant::now(), ActionClass::EsmpReencrypt);
+            }
+
             // OTEL Push Metrics
             if server.core.network.roles.push_metrics {
                 if let Some(otel) = &server.core.metrics.otel {
@@ -200,6 +207,12 @@ pub fn spawn_housekeeper(inner: Arc<Inner>, mut rx: mpsc::Receiver<HousekeeperEv
                             }
                             // SPDX-SnippetEnd
 
+                            // Re-encrypt ESMP profiles in case the master key was rotated
+                            queue.remove_action(&ActionClass::EsmpReencrypt);
+                            if server.core.esmp.encryption.active.is_some() {
+                                queue.schedule(Instant::now(), ActionClass::EsmpReencrypt);
+                            }
+
                             // Reload ACME certificates
                             tokio::spawn(async move {
                                 for provider in server.core.acme.providers.values() {
@@ -360,6 +373,32 @@ pub fn spawn_housekeeper(inner: Arc<Inner>, mut rx: mpsc::Receiver<HousekeeperEv
                                     });
                                 }
                             }
+                            ActionClass::EsmpReencrypt => {
+                                trc::event!(
+                                    Housekeeper(trc::HousekeeperEvent::Run),
+                                    Type = "esmp_reencrypt"
+                                );
+
+                                queue.schedule(
+                                    Instant::now()
+                                        + server
+                                            .core
+                                            .esmp
+                                            .encryption
+                                            .reencrypt_frequency
+                                            .time_to_next(),
+                                    ActionClass::EsmpReencrypt,
+                                );
+
+                                let server = server.clone();
+                                tokio::spawn(async move {
+                                    if let Err(err) = server.reencrypt_profiles().await {
+                                        trc::error!(
+                                            err.details("Failed to re-encrypt ESMP profiles.")
+                                        );
+                                    }
+                                });
+                            }
                             ActionClass::OtelMetrics => {
                                 if let Some(otel) = &server.core.metrics.otel {
                                     trc::event!(
@@ -9,12 +9,16 @@ use std::time::{Duration, Instant};
 use base64::Engine;
 use common::ipc::EncryptionKeys;
 
-use jmap_proto::{response::status::StateChangeResponse, types::id::Id};
+use jmap_proto::{
+    response::status::{StateChangeResponse, StateChangeType},
+    types::{id::Id, state::State},
+};
 use reqwest::header::{CONTENT_ENCODING, CONTENT_TYPE};
 use tokio::sync::mpsc;
 use trc::PushSubscriptionEvent;
+use utils::map::vec_map::VecMap;
 
-use super::{Event, PushServer, ece::ece_encrypt};
+use super::{Event, PushChange, PushServer, ece::ece_encrypt};
 
 impl PushServer {
     pub fn send(&mut self, id: Id, push_tx: mpsc::Sender<Event>, push_timeout: Duration) {
@@ -27,30 +31,47 @@ impl PushServer {
 
         tokio::spawn(async move {
             let mut response = StateChangeResponse::new();
+            let mut esmp_changed: VecMap<Id, VecMap<&str, State>> = VecMap::new();
             for state_change in &state_changes {
-                for type_state in state_change.types {
-                    response
-                        .changed
-                        .get_mut_or_insert(state_change.account_id.into())
-                        .set(type_state, (state_change.change_id).into());
+                match state_change {
+                    PushChange::Jmap(state_change) => {
+                        for type_state in state_change.types {
+                            response
+                                .changed
+                                .get_mut_or_insert(state_change.account_id.into())
+                                .set(type_state, (state_change.change_id).into());
+                        }
+                    }
+                    PushChange::Esmp {
+                        account_id,
+                        state_change,
+                    } => {
+                        esmp_changed.get_mut_or_insert((*account_id).into()).set(
+                            state_change.state_type.as_str(),
+                            (state_change.change_id).into(),
+                        );
+                    }
                 }
             }
 
+            // ESMP subscriptions are only notified of ESMP changes, which are
+            // not JMAP data types
+            let body = if esmp_changed.is_empty() {
+                serde_json::to_string(&response).unwrap()
+            } else {
+                serde_json::json!({
+                    "@type": StateChangeType::StateChange,
+                    "changed": esmp_changed,
+                })
+                .to_string()
+            };
+
             push_tx
-                .send(
-                    if http_request(
-                        url,
-                        serde_json::to_string(&response).unwrap(),
-                        keys,
-                        push_timeout,
-                    )
-                    .await
-                    {
-                        Event::DeliverySuccess { id }
-                    } else {
-                        Event::DeliveryFailure { id, state_changes }
-                    },
-                )
+                .send(if http_request(url, body, keys, push_timeout).await {
+                    Event::DeliverySuccess { id }
+                } else {
+                    Event::DeliveryFailure { id, state_changes }
+                })
                 .await
                 .ok();
         });
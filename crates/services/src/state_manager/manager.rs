@@ -21,8 +21,8 @@ use trc::ServerEvent;
 use utils::map::bitmap::Bitmap;
 
 use super::{
-    Event, PURGE_EVERY, PushUpdate, SEND_TIMEOUT, Subscriber, SubscriberId, SubscriberType,
-    push::spawn_push_manager,
+    Event, PURGE_EVERY, PushChange, PushUpdate, SEND_TIMEOUT, Subscriber, SubscriberId,
+    SubscriberType, push::spawn_push_manager,
 };
 
 #[allow(clippy::unwrap_or_default)]
@@ -135,6 +135,18 @@ pub fn spawn_state_manager(inner: Arc<Inner>, mut change_rx: mpsc::Receiver<Stat
                             },
                         );
                 }
+                StateEvent::SubscribeEsmp { account_id, tx } => {
+                    subscribers
+                        .entry(account_id)
+                        .or_insert_with(AHashMap::default)
+                        .insert(
+                            SubscriberId::Ipc(rand::random()),
+                            Subscriber {
+                                types: Bitmap::new(),
+                                subscription: SubscriberType::Esmp { tx },
+                            },
+                        );
+                }
                 StateEvent::Publish {
                     state_change,
                     broadcast,
@@ -223,7 +235,7 @@ pub fn spawn_state_manager(inner: Arc<Inner>, mut change_rx: mpsc::Receiver<Stat
                             && push_tx
                                 .send(Event::Push {
                                     ids: push_ids,
-                                    state_change,
+                                    state_change: PushChange::Jmap(state_change),
                                 })
                                 .await
                                 .is_err()
@@ -236,6 +248,75 @@ pub fn spawn_state_manager(inner: Arc<Inner>, mut change_rx: mpsc::Receiver<Stat
                         }
                     }
                 }
+                StateEvent::Notify {
+                    account_id,
+                    subscription_ids,
+                    state_change,
+                } => {
+                    // ESMP changes wake up the ESMP sessions of the account and
+                    // the push subscriptions they are addressed to, regardless
+                    // of the JMAP types they are subscribed to
+                    let current_time = SystemTime::now()
+                        .duration_since(SystemTime::UNIX_EPOCH)
+                        .map(|d| d.as_secs())
+                        .unwrap_or(0);
+                    let mut push_ids = Vec::new();
+                    if let Some(subscribers) = subscribers.get(&account_id) {
+                        for subscriber in subscribers.values() {
+                            if let SubscriberType::Esmp { tx } = &subscriber.subscription
+                                && !tx.is_closed()
+                            {
+                                let subscriber_tx = tx.clone();
+
+                                tokio::spawn(async move {
+                                    // Timeout after 500ms in case there is a blocked client
+                                    if subscriber_tx
+                                        .send_timeout(state_change, SEND_TIMEOUT)
+                                        .await
+                                        .is_err()
+                                    {
+                                        trc::event!(
+                                            Server(ServerEvent::ThreadError),
+                                            Details = "Error sending state change to subscriber.",
+                                            CausedBy = trc::location!()
+                                        );
+                                    }
+                                });
+                            }
+                        }
+
+                        for id in subscription_ids {
+                            if matches!(
+                                subscribers.get(&SubscriberId::Push(id)),
+                                Some(Subscriber {
+                                    subscription: SubscriberType::Push { expires },
+                                    ..
+                                }) if *expires > current_time
+                            ) {
+                                push_ids.push(Id::from_parts(account_id, id));
+                            }
+                        }
+                    }
+
+                    if !push_ids.is_empty()
+                        && push_tx
+                            .send(Event::Push {
+                                ids: push_ids,
+                                state_change: PushChange::Esmp {
+                                    account_id,
+                                    state_change,
+                                },
+                            })
+                            .await
+                            .is_err()
+                    {
+                        trc::event!(
+                            Server(ServerEvent::ThreadError),
+                            Details = "Error sending push updates.",
+                            CausedBy = trc::location!()
+                        );
+                    }
+                }
                 StateEvent::UpdateSubscriptions {
                     account_id,
                     subscriptions,
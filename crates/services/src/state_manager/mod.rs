@@ -11,7 +11,7 @@ pub mod push;
 
 use std::time::{Duration, Instant};
 
-use common::ipc::EncryptionKeys;
+use common::ipc::{EncryptionKeys, EsmpStateChange};
 
 use jmap_proto::types::{id::Id, state::StateChange, type_state::DataType};
 use tokio::sync::mpsc;
@@ -26,6 +26,7 @@ struct Subscriber {
 #[derive(Debug)]
 pub enum SubscriberType {
     Ipc { tx: mpsc::Sender<StateChange> },
+    Esmp { tx: mpsc::Sender<EsmpStateChange> },
     Push { expires: u64 },
 }
 
@@ -35,10 +36,19 @@ pub struct PushServer {
     keys: Option<EncryptionKeys>,
     num_attempts: u32,
     last_request: Instant,
-    state_changes: Vec<StateChange>,
+    state_changes: Vec<PushChange>,
     in_flight: bool,
 }
 
+#[derive(Debug, Clone, Copy)]
+pub enum PushChange {
+    Jmap(StateChange),
+    Esmp {
+        account_id: u32,
+        state_change: EsmpStateChange,
+    },
+}
+
 #[derive(Debug)]
 pub enum Event {
     Update {
@@ -46,14 +56,14 @@ pub enum Event {
     },
     Push {
         ids: Vec<Id>,
-        state_change: StateChange,
+        state_change: PushChange,
     },
     DeliverySuccess {
         id: Id,
     },
     DeliveryFailure {
         id: Id,
-        state_changes: Vec<StateChange>,
+        state_changes: Vec<PushChange>,
     },
     Reset,
 }
@@ -81,6 +91,7 @@ impl Subscriber {
     fn is_valid(&self, current_time: u64) -> bool {
         match &self.subscription {
             SubscriberType::Ipc { tx } => !tx.is_closed(),
+            SubscriberType::Esmp { tx } => !tx.is_closed(),
             SubscriberType::Push { expires } => expires > &current_time,
         }
     }
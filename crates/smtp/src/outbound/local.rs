@@ -4,9 +4,13 @@
  * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
  */
 
-use common::Server;
+use common::{
+    Server,
+    ipc::{EsmpGatewayEvent, EsmpIngestStatus},
+};
 use email::message::delivery::{IngestMessage, LocalDeliveryStatus, MailDelivery};
 use smtp_proto::Response;
+use tokio::sync::oneshot;
 use trc::SieveEvent;
 
 use crate::{
@@ -41,6 +45,61 @@ impl Message {
             pending_recipients.push(rcpt);
         }
 
+        // Accounts using ESMP receive the message in their ESMP inbox
+        if server.core.esmp.gateway.inbound && !pending_recipients.is_empty() {
+            let esmp_result = self.deliver_esmp(&recipient_addresses, server).await;
+            let mut mailbox_recipients = Vec::with_capacity(pending_recipients.len());
+            let mut mailbox_addresses = Vec::with_capacity(pending_recipients.len());
+            for ((rcpt, address), result) in pending_recipients
+                .into_iter()
+                .zip(recipient_addresses)
+                .zip(esmp_result)
+            {
+                match result {
+                    EsmpIngestStatus::Delivered => {
+                        rcpt.flags |= RCPT_STATUS_CHANGED;
+                        rcpt.status = Status::Completed(HostResponse {
+                            hostname: "localhost".into(),
+                            response: Response {
+                                code: 250,
+                                esc: [2, 1, 5],
+                                message: "OK".into(),
+                            },
+                        });
+                        total_completed += 1;
+                    }
+                    EsmpIngestStatus::TemporaryFailure { reason } => {
+                        rcpt.flags |= RCPT_STATUS_CHANGED;
+                        rcpt.status = Status::TemporaryFailure(HostResponse {
+                            hostname: ErrorDetails {
+                                entity: "localhost".into(),
+                                details: format!("RCPT TO:<{}>", rcpt.address),
+                            },
+                            response: Response {
+                                code: 451,
+                                esc: [4, 3, 0],
+                                message: reason.into(),
+                            },
+                        });
+                    }
+                    EsmpIngestStatus::Skipped => {
+                        mailbox_recipients.push(rcpt);
+                        mailbox_addresses.push(address);
+                    }
+                }
+            }
+            pending_recipients = mailbox_recipients;
+            recipient_addresses = mailbox_addresses;
+
+            if pending_recipients.is_empty() {
+                return if total_completed == total_rcpt {
+                    Status::Completed(())
+                } else {
+                    Status::Scheduled
+                };
+            }
+        }
+
         // Deliver message
         let delivery_result = server
             .deliver_message(IngestMessage {
@@ -154,4 +213,38 @@ impl Message {
             Status::Scheduled
         }
     }
+
+    async fn deliver_esmp(&self, recipients: &[String], server: &Server) -> Vec<EsmpIngestStatus> {
+        let (result_tx, result_rx) = oneshot::channel();
+        // A failed send drops the result sender, so the receiver errors out below
+        let _ = server
+            .inner
+            .ipc
+            .esmp_gateway_tx
+            .send(EsmpGatewayEvent::Ingest {
+                sender_address: self.return_path_lcase.clone(),
+                recipients: recipients.to_vec(),
+                message_blob: self.blob_hash.clone(),
+                session_id: self.span_id,
+                result_tx,
+            })
+            .await;
+        if let Ok(result) = result_rx.await {
+            return result;
+        }
+
+        trc::event!(
+            Server(trc::ServerEvent::ThreadError),
+            CausedBy = trc::location!(),
+            SpanId = self.span_id,
+            Details = "Failed to deliver message to the ESMP gateway"
+        );
+
+        recipients
+            .iter()
+            .map(|_| EsmpIngestStatus::TemporaryFailure {
+                reason: "ESMP gateway unavailable.".into(),
+            })
+            .collect()
+    }
 }
@@ -23,6 +23,17 @@ pub trait SpamFilterAnalyzeReputation: Sync + Send {
         &self,
         ctx: &mut SpamFilterContext<'_>,
     ) -> impl Future<Output = ()> + Send;
+
+    /// Adds the score of a message to the reputation stored under `key`,
+    /// returning the average score of the previous messages.
+    fn spam_filter_reputation(
+        &self,
+        span_id: u64,
+        prefix: u8,
+        key: &[u8],
+        score: f64,
+        is_test: bool,
+    ) -> impl Future<Output = Option<f64>> + Send;
 }
 
 #[derive(Debug)]
@@ -85,60 +96,18 @@ impl SpamFilterAnalyzeReputation for Server {
             let mut reputation = 0.0;
 
             for (rep_type, key) in types {
-                let token = match key_get::<Reputation>(
-                    self,
-                    ctx.input.span_id,
-                    KeyValue::<()>::build_key(rep_type.prefix(), key.as_ref()),
-                )
-                .await
-                {
-                    Ok(Some(token)) => token,
-                    Ok(None) if !ctx.input.is_test => {
-                        key_set(
-                            self,
-                            ctx.input.span_id,
-                            KeyValue::with_prefix(
-                                rep_type.prefix(),
-                                key.as_ref(),
-                                Reputation {
-                                    count: 1,
-                                    score: ctx.result.score,
-                                }
-                                .serialize()
-                                .unwrap(),
-                            )
-                            .expires(config.expiry),
-                        )
-                        .await;
-                        continue;
-                    }
-                    Ok(None) | Err(_) => continue,
-                };
-
-                // Update reputation
-                let updated_score = (token.count + 1) as f64
-                    * (ctx.result.score + config.token_score * token.score)
-                    / (config.token_score * token.count as f64 + 1.0);
-                let updated_count = token.count + 1;
-
-                if !ctx.input.is_test {
-                    key_set(
-                        self,
+                let Some(score) = self
+                    .spam_filter_reputation(
                         ctx.input.span_id,
-                        KeyValue::with_prefix(
-                            rep_type.prefix(),
-                            key.as_ref(),
-                            Reputation {
-                                count: updated_count,
-                                score: updated_score,
-                            }
-                            .serialize()
-                            .unwrap(),
-                        )
-                        .expires(config.expiry),
+                        rep_type.prefix(),
+                        key.as_ref(),
+                        ctx.result.score,
+                        ctx.input.is_test,
                     )
-                    .await;
-                }
+                    .await
+                else {
+                    continue;
+                };
 
                 // Assign weight
                 let weight = match rep_type {
@@ -148,7 +117,7 @@ impl SpamFilterAnalyzeReputation for Server {
                     Type::Asn => config.asn_weight,
                 };
 
-                reputation += token.score / token.count as f64 * weight;
+                reputation += score * weight;
             }
 
             // Adjust score
@@ -157,6 +126,67 @@ impl SpamFilterAnalyzeReputation for Server {
             }
         }
     }
+
+    async fn spam_filter_reputation(
+        &self,
+        span_id: u64,
+        prefix: u8,
+        key: &[u8],
+        score: f64,
+        is_test: bool,
+    ) -> Option<f64> {
+        let config = self.core.spam.reputation.as_ref()?;
+        let token = match key_get::<Reputation>(
+            self,
+            span_id,
+            KeyValue::<()>::build_key(prefix, key),
+        )
+        .await
+        {
+            Ok(Some(token)) => token,
+            Ok(None) if !is_test => {
+                key_set(
+                    self,
+                    span_id,
+                    KeyValue::with_prefix(
+                        prefix,
+                        key,
+                        Reputation { count: 1, score }.serialize().unwrap(),
+                    )
+                    .expires(config.expiry),
+                )
+                .await;
+                return None;
+            }
+            Ok(None) | Err(_) => return None,
+        };
+
+        // Update reputation
+        let updated_score = (token.count + 1) as f64 * (score + config.token_score * token.score)
+            / (config.token_score * token.count as f64 + 1.0);
+        let updated_count = token.count + 1;
+
+        if !is_test {
+            key_set(
+                self,
+                span_id,
+                KeyValue::with_prefix(
+                    prefix,
+                    key,
+                    Reputation {
+                        count: updated_count,
+                        score: updated_score,
+                    }
+                    .serialize()
+                    .unwrap(),
+                )
+                .expires(config.expiry),
+            )
+            .await;
+        }
+
+        Some(token.score / token.count as f64)
+    }
 }
 
 impl Type {
@@ -54,6 +54,7 @@ impl EventType {
             EventType::Ai(event) => event.description(),
             EventType::WebDav(event) => event.description(),
             EventType::Calendar(event) => event.description(),
+            EventType::Esmp(event) => event.description(),
         }
     }
 
@@ -104,6 +105,7 @@ impl EventType {
             EventType::Ai(event) => event.explain(),
             EventType::WebDav(event) => event.explain(),
             EventType::Calendar(event) => event.explain(),
+            EventType::Esmp(event) => event.explain(),
         }
     }
 }
@@ -1910,3 +1912,71 @@ impl CalendarEvent {
         }
     }
 }
+
+impl EsmpEvent {
+    pub fn description(&self) -> &'static str {
+        match self {
+            EsmpEvent::ConnectionStart => "ESMP connection started",
+            EsmpEvent::ConnectionEnd => "ESMP connection ended",
+            EsmpEvent::MessageAccepted => "ESMP message accepted",
+            EsmpEvent::MessageRejected => "ESMP message rejected",
+            EsmpEvent::SignatureFailure => "ESMP signature verification failed",
+            EsmpEvent::Replay => "ESMP replayed message rejected",
+            EsmpEvent::InboxDelivery => "ESMP message delivered to inboxes",
+            EsmpEvent::GroupStateChange => "ESMP group state changed",
+            EsmpEvent::PushNotify => "ESMP push notification sent",
+            EsmpEvent::SpamDetected => "ESMP message classified as spam",
+            EsmpEvent::FederationDelivery => "ESMP message relayed to remote server",
+            EsmpEvent::FederationDeferred => "ESMP federation delivery deferred",
+            EsmpEvent::FederationFailed => "ESMP federation delivery failed",
+            EsmpEvent::FederationRelay => "ESMP message received from remote server",
+            EsmpEvent::GatewayOutbound => "ESMP message sent as e-mail",
+            EsmpEvent::GatewayInbound => "E-mail delivered as ESMP message",
+            EsmpEvent::Error => "ESMP error occurred",
+            EsmpEvent::RawInput => "Raw ESMP input received",
+            EsmpEvent::RawOutput => "Raw ESMP output sent",
+        }
+    }
+
+    pub fn explain(&self) -> &'static str {
+        match self {
+            EsmpEvent::ConnectionStart => "A new ESMP connection was started",
+            EsmpEvent::ConnectionEnd => "An ESMP connection was ended",
+            EsmpEvent::MessageAccepted => "A signed ESMP message was accepted for delivery",
+            EsmpEvent::MessageRejected => {
+                "An ESMP message was rejected by validation or group policy"
+            }
+            EsmpEvent::SignatureFailure => {
+                "The signature of an ESMP message or command could not be verified"
+            }
+            EsmpEvent::Replay => "An ESMP message id was received more than once",
+            EsmpEvent::InboxDelivery => "An ESMP message was delivered to the recipient inboxes",
+            EsmpEvent::GroupStateChange => {
+                "A system message changed the membership or metadata of an ESMP group"
+            }
+            EsmpEvent::PushNotify => "Offline ESMP devices were woken up by a push notification",
+            EsmpEvent::SpamDetected => "The spam filter classified a direct ESMP message as spam",
+            EsmpEvent::FederationDelivery => {
+                "A queued ESMP message was relayed to the server of the recipient domain"
+            }
+            EsmpEvent::FederationDeferred => {
+                "Relaying a queued ESMP message failed temporarily and will be retried"
+            }
+            EsmpEvent::FederationFailed => {
+                "Relaying a queued ESMP message failed permanently and the sender was notified"
+            }
+            EsmpEvent::FederationRelay => {
+                "A federated server relayed an ESMP message to local users"
+            }
+            EsmpEvent::GatewayOutbound => {
+                "An ESMP message was submitted as e-mail to non-ESMP recipients"
+            }
+            EsmpEvent::GatewayInbound => {
+                "An incoming e-mail was converted into an ESMP message for local users"
+            }
+            EsmpEvent::Error => "An error occurred while processing an ESMP frame",
+            EsmpEvent::RawInput => "Raw ESMP input was received",
+            EsmpEvent::RawOutput => "Raw ESMP output was sent",
+        }
+    }
+}
@@ -543,6 +543,26 @@ impl EventType {
                 | CalendarEvent::AlarmSkipped
                 | CalendarEvent::AlarmRecipientOverride => Level::Debug,
             },
+            EventType::Esmp(event) => match event {
+                EsmpEvent::MessageAccepted
+                | EsmpEvent::FederationDelivery
+                | EsmpEvent::FederationRelay
+                | EsmpEvent::GatewayOutbound
+                | EsmpEvent::GatewayInbound
+                | EsmpEvent::SignatureFailure
+                | EsmpEvent::Replay
+                | EsmpEvent::SpamDetected
+                | EsmpEvent::FederationDeferred => Level::Info,
+                EsmpEvent::FederationFailed => Level::Warn,
+                EsmpEvent::ConnectionStart
+                | EsmpEvent::ConnectionEnd
+                | EsmpEvent::MessageRejected
+                | EsmpEvent::InboxDelivery
+                | EsmpEvent::GroupStateChange
+                | EsmpEvent::PushNotify
+                | EsmpEvent::Error => Level::Debug,
+                EsmpEvent::RawInput | EsmpEvent::RawOutput => Level::Trace,
+            },
         }
     }
 }
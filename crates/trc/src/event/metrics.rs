@@ -32,6 +32,10 @@ impl MetricType {
             Self::SmtpActiveConnections => "smtp.active-connections",
             Self::SieveActiveConnections => "sieve.active-connections",
             Self::DeliveryActiveConnections => "delivery.active-connections",
+            Self::EsmpActiveConnections => "esmp.active-connections",
+            Self::EsmpRequestTime => "esmp.request-time",
+            Self::EsmpMessageSize => "esmp.message-size",
+            Self::EsmpFederationTime => "esmp.federation-time",
             Self::ServerMemory => "server.memory",
             Self::QueueCount => "queue.count",
             Self::UserCount => "user.count",
@@ -64,6 +68,10 @@ impl MetricType {
             Self::SmtpActiveConnections => "Active SMTP connections",
             Self::SieveActiveConnections => "Active ManageSieve connections",
             Self::DeliveryActiveConnections => "Active delivery connections",
+            Self::EsmpActiveConnections => "Active ESMP connections",
+            Self::EsmpRequestTime => "ESMP session duration",
+            Self::EsmpMessageSize => "Accepted ESMP message size",
+            Self::EsmpFederationTime => "ESMP federation relay time",
             Self::ServerMemory => "Server memory usage",
             Self::QueueCount => "Total number of messages in the queue",
             Self::UserCount => "Total number of users",
@@ -86,17 +94,21 @@ impl MetricType {
             | Self::ImapRequestTime
             | Self::Pop3RequestTime
             | Self::SmtpRequestTime
-            | Self::SieveRequestTime => "milliseconds",
+            | Self::SieveRequestTime
+            | Self::EsmpRequestTime
+            | Self::EsmpFederationTime => "milliseconds",
             Self::MessageSize
             | Self::MessageAuthSize
             | Self::ReportOutgoingSize
+            | Self::EsmpMessageSize
             | Self::ServerMemory => "bytes",
             Self::HttpActiveConnections
             | Self::ImapActiveConnections
             | Self::Pop3ActiveConnections
             | Self::SmtpActiveConnections
             | Self::SieveActiveConnections
-            | Self::DeliveryActiveConnections => "connections",
+            | Self::DeliveryActiveConnections
+            | Self::EsmpActiveConnections => "connections",
             Self::QueueCount => "messages",
             Self::UserCount => "users",
             Self::DomainCount => "domains",
@@ -132,6 +144,10 @@ impl MetricType {
             Self::QueueCount => 24,
             Self::UserCount => 25,
             Self::DomainCount => 26,
+            Self::EsmpActiveConnections => 27,
+            Self::EsmpRequestTime => 28,
+            Self::EsmpMessageSize => 29,
+            Self::EsmpFederationTime => 30,
         }
     }
 
@@ -164,6 +180,10 @@ impl MetricType {
             24 => Some(Self::QueueCount),
             25 => Some(Self::UserCount),
             26 => Some(Self::DomainCount),
+            27 => Some(Self::EsmpActiveConnections),
+            28 => Some(Self::EsmpRequestTime),
+            29 => Some(Self::EsmpMessageSize),
+            30 => Some(Self::EsmpFederationTime),
             _ => None,
         }
     }
@@ -193,6 +213,10 @@ impl MetricType {
             "smtp.active-connections" => Some(Self::SmtpActiveConnections),
             "sieve.active-connections" => Some(Self::SieveActiveConnections),
             "delivery.active-connections" => Some(Self::DeliveryActiveConnections),
+            "esmp.active-connections" => Some(Self::EsmpActiveConnections),
+            "esmp.request-time" => Some(Self::EsmpRequestTime),
+            "esmp.message-size" => Some(Self::EsmpMessageSize),
+            "esmp.federation-time" => Some(Self::EsmpFederationTime),
             "server.memory" => Some(Self::ServerMemory),
             "queue.count" => Some(Self::QueueCount),
             "user.count" => Some(Self::UserCount),
@@ -226,6 +250,10 @@ impl MetricType {
             Self::SmtpActiveConnections,
             Self::SieveActiveConnections,
             Self::DeliveryActiveConnections,
+            Self::EsmpActiveConnections,
+            Self::EsmpRequestTime,
+            Self::EsmpMessageSize,
+            Self::EsmpFederationTime,
             Self::ServerMemory,
             Self::QueueCount,
             Self::UserCount,
@@ -249,6 +249,7 @@ impl EventType {
                 | EventType::Imap(ImapEvent::ConnectionStart)
                 | EventType::ManageSieve(ManageSieveEvent::ConnectionStart)
                 | EventType::Pop3(Pop3Event::ConnectionStart)
+                | EventType::Esmp(EsmpEvent::ConnectionStart)
                 | EventType::Http(HttpEvent::ConnectionStart)
                 | EventType::Delivery(DeliveryEvent::AttemptStart)
         )
@@ -262,6 +263,7 @@ impl EventType {
                 | EventType::Imap(ImapEvent::ConnectionEnd)
                 | EventType::ManageSieve(ManageSieveEvent::ConnectionEnd)
                 | EventType::Pop3(Pop3Event::ConnectionEnd)
+                | EventType::Esmp(EsmpEvent::ConnectionEnd)
                 | EventType::Http(HttpEvent::ConnectionEnd)
                 | EventType::Delivery(DeliveryEvent::AttemptEnd)
         )
@@ -273,6 +275,7 @@ impl EventType {
             EventType::Imap(ImapEvent::RawInput | ImapEvent::RawOutput)
                 | EventType::Smtp(SmtpEvent::RawInput | SmtpEvent::RawOutput)
                 | EventType::Pop3(Pop3Event::RawInput | Pop3Event::RawOutput)
+                | EventType::Esmp(EsmpEvent::RawInput | EsmpEvent::RawOutput)
                 | EventType::ManageSieve(ManageSieveEvent::RawInput | ManageSieveEvent::RawOutput)
                 | EventType::Delivery(DeliveryEvent::RawInput | DeliveryEvent::RawOutput)
                 | EventType::Milter(MilterEvent::Read | MilterEvent::Write)
@@ -306,6 +309,7 @@ impl EventType {
             EventType::Imap(_) => "IMAP error",
             EventType::ManageSieve(_) => "ManageSieve error",
             EventType::Pop3(_) => "POP3 error",
+            EventType::Esmp(_) => "ESMP error",
             EventType::Smtp(_) => "SMTP error",
             EventType::Network(_) => "Network error",
             EventType::Limit(cause) => cause.message(),
@@ -627,6 +631,18 @@ impl Pop3Event {
     }
 }
 
+impl EsmpEvent {
+    #[inline(always)]
+    pub fn ctx(self, key: Key, value: impl Into<Value>) -> Error {
+        self.into_err().ctx(key, value)
+    }
+
+    #[inline(always)]
+    pub fn into_err(self) -> Error {
+        Error::new(EventType::Esmp(self))
+    }
+}
+
 impl ManageSieveEvent {
     #[inline(always)]
     pub fn ctx(self, key: Key, value: impl Into<Value>) -> Error {
@@ -70,6 +70,8 @@ const SMTP_CONN_END: usize = EventType::Smtp(SmtpEvent::ConnectionEnd).id();
 const MANAGE_SIEVE_CONN_START: usize =
     EventType::ManageSieve(ManageSieveEvent::ConnectionStart).id();
 const MANAGE_SIEVE_CONN_END: usize = EventType::ManageSieve(ManageSieveEvent::ConnectionEnd).id();
+const ESMP_CONN_START: usize = EventType::Esmp(EsmpEvent::ConnectionStart).id();
+const ESMP_CONN_END: usize = EventType::Esmp(EsmpEvent::ConnectionEnd).id();
 const EV_ATTEMPT_START: usize = EventType::Delivery(DeliveryEvent::AttemptStart).id();
 const EV_ATTEMPT_END: usize = EventType::Delivery(DeliveryEvent::AttemptEnd).id();
 
@@ -134,6 +136,7 @@ impl Collector {
                                 | POP3_CONN_START
                                 | SMTP_CONN_START
                                 | MANAGE_SIEVE_CONN_START
+                                | ESMP_CONN_START
                                 | EV_ATTEMPT_START => {
                                     let event = Arc::new(event);
                                     self.active_spans.insert(
@@ -157,6 +160,7 @@ impl Collector {
                                 | POP3_CONN_END
                                 | SMTP_CONN_END
                                 | MANAGE_SIEVE_CONN_END
+                                | ESMP_CONN_END
                                 | EV_ATTEMPT_END => {
                                     if let Some(span) = self
                                         .active_spans
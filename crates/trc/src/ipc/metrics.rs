@@ -45,6 +45,11 @@ static STORE_BLOB_WRITE_TIME: AtomicHistogram<12> =
 static DNS_LOOKUP_TIME: AtomicHistogram<12> =
     AtomicHistogram::<10>::new_short_durations(MetricType::DnsLookupTime);
 
+static ESMP_MESSAGE_SIZE: AtomicHistogram<12> =
+    AtomicHistogram::<12>::new_message_sizes(MetricType::EsmpMessageSize);
+static ESMP_FEDERATION_TIME: AtomicHistogram<12> =
+    AtomicHistogram::<18>::new_medium_durations(MetricType::EsmpFederationTime);
+
 static SERVER_MEMORY: AtomicGauge = AtomicGauge::new(MetricType::ServerMemory);
 static QUEUE_COUNT: AtomicGauge = AtomicGauge::new(MetricType::QueueCount);
 static USER_COUNT: AtomicGauge = AtomicGauge::new(MetricType::UserCount);
@@ -56,7 +61,8 @@ const CONN_IMAP: usize = 2;
 const CONN_POP3: usize = 3;
 const CONN_HTTP: usize = 4;
 const CONN_SIEVE: usize = 5;
-const TOTAL_CONN_TYPES: usize = 6;
+const CONN_ESMP: usize = 6;
+const TOTAL_CONN_TYPES: usize = 7;
 
 pub struct ConnectionMetrics {
     pub active_connections: AtomicGauge,
@@ -132,6 +138,21 @@ impl Collector {
                 conn.active_connections.decrement();
                 conn.elapsed.observe(elapsed);
             }
+            EventType::Esmp(EsmpEvent::ConnectionStart) => {
+                let conn = &CONNECTION_METRICS[CONN_ESMP];
+                conn.active_connections.increment();
+            }
+            EventType::Esmp(EsmpEvent::ConnectionEnd) => {
+                let conn = &CONNECTION_METRICS[CONN_ESMP];
+                conn.active_connections.decrement();
+                conn.elapsed.observe(elapsed);
+            }
+            EventType::Esmp(EsmpEvent::MessageAccepted) => {
+                ESMP_MESSAGE_SIZE.observe(size);
+            }
+            EventType::Esmp(EsmpEvent::FederationDelivery) => {
+                ESMP_FEDERATION_TIME.observe(elapsed);
+            }
             EventType::Delivery(DeliveryEvent::AttemptStart) => {
                 let conn = &CONNECTION_METRICS[CONN_SMTP_OUT];
                 conn.active_connections.increment();
@@ -251,11 +272,15 @@ impl Collector {
             &STORE_BLOB_READ_TIME,
             &STORE_BLOB_WRITE_TIME,
             &DNS_LOOKUP_TIME,
+            &ESMP_MESSAGE_SIZE,
+            &ESMP_FEDERATION_TIME,
         ];
         static C_HISTOGRAMS: &[&AtomicHistogram<12>] = &[
             &MESSAGE_DELIVERY_TIME,
             &MESSAGE_INCOMING_SIZE,
             &MESSAGE_SUBMISSION_SIZE,
+            &ESMP_MESSAGE_SIZE,
+            &ESMP_FEDERATION_TIME,
         ];
 
         if is_enterprise {
@@ -313,6 +338,12 @@ impl Collector {
                 CONNECTION_METRICS[CONN_SIEVE].active_connections.get() as f64
             }
             MetricType::SieveRequestTime => CONNECTION_METRICS[CONN_SIEVE].elapsed.average(),
+            MetricType::EsmpActiveConnections => {
+                CONNECTION_METRICS[CONN_ESMP].active_connections.get() as f64
+            }
+            MetricType::EsmpRequestTime => CONNECTION_METRICS[CONN_ESMP].elapsed.average(),
+            MetricType::EsmpMessageSize => ESMP_MESSAGE_SIZE.average(),
+            MetricType::EsmpFederationTime => ESMP_FEDERATION_TIME.average(),
             MetricType::UserCount => USER_COUNT.get() as f64,
             MetricType::DomainCount => DOMAIN_COUNT.get() as f64,
         }
@@ -339,6 +370,8 @@ impl Collector {
             MetricType::DeliveryTotalTime => MESSAGE_DELIVERY_TIME.observe(value),
             MetricType::DeliveryTime => CONNECTION_METRICS[CONN_SMTP_OUT].elapsed.observe(value),
             MetricType::DnsLookupTime => DNS_LOOKUP_TIME.observe(value),
+            MetricType::EsmpMessageSize => ESMP_MESSAGE_SIZE.observe(value),
+            MetricType::EsmpFederationTime => ESMP_FEDERATION_TIME.observe(value),
             _ => {}
         }
     }
@@ -395,6 +428,10 @@ const fn init_conn_metrics() -> [ConnectionMetrics; TOTAL_CONN_TYPES] {
                 MetricType::SieveRequestTime,
                 MetricType::SieveActiveConnections,
             ],
+            CONN_ESMP => &[
+                MetricType::EsmpRequestTime,
+                MetricType::EsmpActiveConnections,
+            ],
             _ => &[MetricType::BlobReadTime, MetricType::BlobReadTime],
         };
 
@@ -189,6 +189,7 @@ pub enum EventType {
     Ai(AiEvent),
     WebDav(WebDavEvent),
     Calendar(CalendarEvent),
+    Esmp(EsmpEvent),
 }
 
 #[event_type]
@@ -990,6 +991,39 @@ pub enum CalendarEvent {
     AlarmFailed,
 }
 
+#[event_type]
+pub enum EsmpEvent {
+    ConnectionStart,
+    ConnectionEnd,
+
+    // Messages
+    MessageAccepted,
+    MessageRejected,
+    SignatureFailure,
+    Replay,
+    InboxDelivery,
+    GroupStateChange,
+    PushNotify,
+    SpamDetected,
+
+    // Federation
+    FederationDelivery,
+    FederationDeferred,
+    FederationFailed,
+    FederationRelay,
+
+    // E-mail gateway
+    GatewayOutbound,
+    GatewayInbound,
+
+    // Errors
+    Error,
+
+    // Debugging
+    RawInput,
+    RawOutput,
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum MetricType {
     ServerMemory,
@@ -1017,6 +1051,10 @@ pub enum MetricType {
     SmtpRequestTime,
     SieveActiveConnections,
     SieveRequestTime,
+    EsmpActiveConnections,
+    EsmpRequestTime,
+    EsmpMessageSize,
+    EsmpFederationTime,
     UserCount,
     DomainCount,
 }
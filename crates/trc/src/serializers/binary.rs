@@ -892,6 +892,25 @@ impl EventType {
             EventType::Calendar(CalendarEvent::AlarmSkipped) => 580,
             EventType::Calendar(CalendarEvent::AlarmRecipientOverride) => 581,
             EventType::Calendar(CalendarEvent::AlarmFailed) => 582,
+            EventType::Esmp(EsmpEvent::ConnectionStart) => 583,
+            EventType::Esmp(EsmpEvent::ConnectionEnd) => 584,
+            EventType::Esmp(EsmpEvent::Error) => 585,
+            EventType::Esmp(EsmpEvent::RawInput) => 586,
+            EventType::Esmp(EsmpEvent::RawOutput) => 587,
+            EventType::Esmp(EsmpEvent::MessageAccepted) => 588,
+            EventType::Esmp(EsmpEvent::MessageRejected) => 589,
+            EventType::Esmp(EsmpEvent::SignatureFailure) => 590,
+            EventType::Esmp(EsmpEvent::Replay) => 591,
+            EventType::Esmp(EsmpEvent::InboxDelivery) => 592,
+            EventType::Esmp(EsmpEvent::GroupStateChange) => 593,
+            EventType::Esmp(EsmpEvent::PushNotify) => 594,
+            EventType::Esmp(EsmpEvent::FederationDelivery) => 595,
+            EventType::Esmp(EsmpEvent::FederationDeferred) => 596,
+            EventType::Esmp(EsmpEvent::FederationFailed) => 597,
+            EventType::Esmp(EsmpEvent::FederationRelay) => 598,
+            EventType::Esmp(EsmpEvent::GatewayOutbound) => 599,
+            EventType::Esmp(EsmpEvent::GatewayInbound) => 600,
+            EventType::Esmp(EsmpEvent::SpamDetected) => 601,
         }
     }
 
@@ -1520,6 +1539,25 @@ impl EventType {
             580 => Some(EventType::Calendar(CalendarEvent::AlarmSkipped)),
             581 => Some(EventType::Calendar(CalendarEvent::AlarmRecipientOverride)),
             582 => Some(EventType::Calendar(CalendarEvent::AlarmFailed)),
+            583 => Some(EventType::Esmp(EsmpEvent::ConnectionStart)),
+            584 => Some(EventType::Esmp(EsmpEvent::ConnectionEnd)),
+            585 => Some(EventType::Esmp(EsmpEvent::Error)),
+            586 => Some(EventType::Esmp(EsmpEvent::RawInput)),
+            587 => Some(EventType::Esmp(EsmpEvent::RawOutput)),
+            588 => Some(EventType::Esmp(EsmpEvent::MessageAccepted)),
+            589 => Some(EventType::Esmp(EsmpEvent::MessageRejected)),
+            590 => Some(EventType::Esmp(EsmpEvent::SignatureFailure)),
+            591 => Some(EventType::Esmp(EsmpEvent::Replay)),
+            592 => Some(EventType::Esmp(EsmpEvent::InboxDelivery)),
+            593 => Some(EventType::Esmp(EsmpEvent::GroupStateChange)),
+            594 => Some(EventType::Esmp(EsmpEvent::PushNotify)),
+            595 => Some(EventType::Esmp(EsmpEvent::FederationDelivery)),
+            596 => Some(EventType::Esmp(EsmpEvent::FederationDeferred)),
+            597 => Some(EventType::Esmp(EsmpEvent::FederationFailed)),
+            598 => Some(EventType::Esmp(EsmpEvent::FederationRelay)),
+            599 => Some(EventType::Esmp(EsmpEvent::GatewayOutbound)),
+            600 => Some(EventType::Esmp(EsmpEvent::GatewayInbound)),
+            601 => Some(EventType::Esmp(EsmpEvent::SpamDetected)),
             _ => None,
         }
     }
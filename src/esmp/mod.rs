@@ -1,7 +0,0 @@
-pub mod api;
-pub mod crypto;
-pub mod group;
-pub mod handler;
-pub mod listener;
-pub mod profile; // Add the new profile module
-pub mod system;
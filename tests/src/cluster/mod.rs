@@ -18,6 +18,7 @@ use common::{
         config::{ConfigManager, Patterns},
     },
 };
+use esmp::EsmpSessionManager;
 use http::HttpSessionManager;
 use imap::core::ImapSessionManager;
 use imap_proto::ResponseType;
@@ -228,6 +229,12 @@ async fn build_server(mut config: Config, stores: Stores) -> (Server, watch::Sen
                 acceptor,
                 shutdown_rx,
             ),
+            ServerProtocol::Esmp => server.spawn(
+                EsmpSessionManager::new(inner.clone()),
+                inner.clone(),
+                acceptor,
+                shutdown_rx,
+            ),
         };
     });
 
@@ -0,0 +1,571 @@
+/*
+ * SPDX-FileCopyrightText: 2020 Stalwart Labs LLC <hello@stalw.art>
+ *
+ * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
+ */
+
+use std::time::Duration;
+
+use esmp::{canonical::signing_input, crypto::verify_signature};
+use reqwest::{Method, StatusCode, header::AUTHORIZATION};
+use serde_json::{Value, json};
+use store::write::now;
+
+use super::EsmpConnection;
+
+const GROUP_ID: &str = "esmp-api-group";
+const ALICE_CREDENTIALS: &str = "Basic YWxpY2U6c2VjcmV0";
+
+pub async fn test() {
+    println!("Running HTTP API tests...");
+
+    let mut owner = EsmpConnection::connect(76).await;
+    let mut alice = EsmpConnection::connect(50).await;
+    let outsider = EsmpConnection::connect(77).await;
+    owner.login().await;
+    alice.login().await;
+    let owner_pubkey = owner.pubkey.clone();
+    let alice_pubkey = alice.pubkey.clone();
+    let group_path = format!("/api/esmp/groups/{GROUP_ID}");
+
+    // Create a group with alice as a member
+    owner
+        .send(json!({"to": [], "group_id": GROUP_ID, "type": "system", "subtype": "group_created", "actor": &owner_pubkey, "body": {"group_name": "API group"}}))
+        .await;
+    owner.assert_read("ack").await;
+    alice
+        .send(json!({"to": [], "group_id": GROUP_ID, "type": "system", "subtype": "joined", "actor": &alice_pubkey, "body": ""}))
+        .await;
+    alice.assert_read("ack").await;
+    owner.assert_read("push").await;
+
+    // Group metadata is available to members authenticated by signature or account
+    let (status, response) = signed(&owner, Method::GET, &group_path, None).await;
+    assert_eq!(status, StatusCode::OK, "{response}");
+    assert_eq!(response["data"]["group_name"], "API group");
+    assert_eq!(
+        response["data"]["members"],
+        json!([&owner_pubkey, &alice_pubkey])
+    );
+    let (status, response) = request(Method::GET, &group_path, None, &[]).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED, "{response}");
+    let (status, response) = signed(&outsider, Method::GET, &group_path, None).await;
+    assert_eq!(status, StatusCode::FORBIDDEN, "{response}");
+    let (status, response) = request(
+        Method::GET,
+        &group_path,
+        None,
+        &[(AUTHORIZATION.as_str(), ALICE_CREDENTIALS.to_string())],
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK, "{response}");
+    assert_eq!(response["data"]["group_id"], GROUP_ID);
+
+    // Only admins can update the group, with system messages signed by themselves
+    let rename = alice.sign(json!({"to": [], "group_id": GROUP_ID, "type": "system", "subtype": "group_renamed", "actor": &alice_pubkey, "new_name": "Alice's group", "body": {}}));
+    let (status, response) = request(
+        Method::PUT,
+        &group_path,
+        Some(json!({"messages": [&rename]})),
+        &[(AUTHORIZATION.as_str(), ALICE_CREDENTIALS.to_string())],
+    )
+    .await;
+    assert_eq!(status, StatusCode::FORBIDDEN, "{response}");
+    let (status, response) = signed(
+        &owner,
+        Method::PUT,
+        &group_path,
+        Some(json!({"messages": [&rename]})),
+    )
+    .await;
+    assert_eq!(status, StatusCode::FORBIDDEN, "{response}");
+    for message in [
+        json!({"group_name": "Renamed"}),
+        owner.sign(json!({"to": [], "group_id": GROUP_ID, "type": "text", "body": "Not a system message"})),
+        owner.sign(json!({"to": [], "group_id": "other-group", "type": "system", "subtype": "group_renamed", "actor": &owner_pubkey, "new_name": "Renamed", "body": {}})),
+        owner.sign(json!({"to": [], "group_id": GROUP_ID, "type": "system", "subtype": "left", "actor": &owner_pubkey, "body": {}})),
+    ] {
+        let (status, response) = signed(
+            &owner,
+            Method::PUT,
+            &group_path,
+            Some(json!({"messages": [message]})),
+        )
+        .await;
+        assert_eq!(status, StatusCode::BAD_REQUEST, "{response}");
+    }
+    let rename = owner.sign(json!({"to": [], "group_id": GROUP_ID, "type": "system", "subtype": "group_renamed", "actor": &owner_pubkey, "new_name": "Renamed", "body": {}}));
+    let describe = owner.sign(json!({"to": [], "group_id": GROUP_ID, "type": "system", "subtype": "description_updated", "actor": &owner_pubkey, "new_description": "Updated over HTTP", "body": {}}));
+    let (status, response) = signed(
+        &owner,
+        Method::PUT,
+        &group_path,
+        Some(json!({"messages": [&rename, &describe]})),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK, "{response}");
+    assert_eq!(response["data"]["group_name"], "Renamed");
+    assert_eq!(response["data"]["group_description"], "Updated over HTTP");
+
+    // Updates are posted to the group unchanged, and cannot be replayed
+    for (expected, subtype) in [
+        (&rename, "group_renamed"),
+        (&describe, "description_updated"),
+    ] {
+        let message = alice.assert_read("push").await["message"].clone();
+        assert_eq!(message["subtype"], subtype);
+        assert_eq!(&message, expected);
+        assert!(verify_signature(
+            &owner_pubkey,
+            message["signature"].as_str().unwrap(),
+            &signing_input(&message).unwrap()
+        ));
+    }
+    let (status, response) = signed(
+        &owner,
+        Method::PUT,
+        &group_path,
+        Some(json!({"messages": [&rename]})),
+    )
+    .await;
+    assert_eq!(status, StatusCode::FORBIDDEN, "{response}");
+
+    // Updates are applied only if every message is accepted
+    let rename = owner.sign(json!({"to": [], "group_id": GROUP_ID, "type": "system", "subtype": "group_renamed", "actor": &owner_pubkey, "new_name": "Partially renamed", "body": {}}));
+    let mut tampered = owner.sign(json!({"to": [], "group_id": GROUP_ID, "type": "system", "subtype": "description_updated", "actor": &owner_pubkey, "new_description": "Signed", "body": {}}));
+    tampered["new_description"] = json!("Tampered");
+    let (status, response) = signed(
+        &owner,
+        Method::PUT,
+        &group_path,
+        Some(json!({"messages": [&rename, &tampered]})),
+    )
+    .await;
+    assert_eq!(status, StatusCode::FORBIDDEN, "{response}");
+    let (status, response) = signed(&owner, Method::GET, &group_path, None).await;
+    assert_eq!(status, StatusCode::OK, "{response}");
+    assert_eq!(response["data"]["group_name"], "Renamed");
+    assert_eq!(response["data"]["group_description"], "Updated over HTTP");
+    alice.assert_no_frames().await;
+
+    let (status, response) =
+        signed(&owner, Method::GET, &format!("{group_path}/messages"), None).await;
+    assert_eq!(status, StatusCode::OK, "{response}");
+    let messages = response["data"]["messages"].as_array().unwrap();
+    assert_eq!(messages.len(), 4);
+    assert_eq!(messages[0]["message"]["subtype"], "group_created");
+    assert_eq!(
+        messages[3]["message"]["new_description"],
+        "Updated over HTTP"
+    );
+    let (status, response) = signed(
+        &outsider,
+        Method::GET,
+        &format!("{group_path}/messages"),
+        None,
+    )
+    .await;
+    assert_eq!(status, StatusCode::FORBIDDEN, "{response}");
+
+    // History is paged backwards from the most recent message
+    let created_id = messages[0]["id"].as_u64().unwrap();
+    let (status, response) = signed(
+        &owner,
+        Method::GET,
+        &format!("{group_path}/messages?limit=2"),
+        None,
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK, "{response}");
+    assert_eq!(
+        subtypes(&response),
+        ["group_renamed", "description_updated"]
+    );
+    assert_eq!(response["data"]["has_more"], true);
+    let (status, response) = signed(
+        &owner,
+        Method::GET,
+        &format!(
+            "{group_path}/messages?limit=2&before={}",
+            response["data"]["cursor"]
+        ),
+        None,
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK, "{response}");
+    assert_eq!(subtypes(&response), ["group_created", "joined"]);
+    assert_eq!(response["data"]["has_more"], false);
+    assert_eq!(response["data"]["cursor"], created_id);
+    let (status, response) = signed(
+        &owner,
+        Method::GET,
+        &format!("{group_path}/messages?since={created_id}"),
+        None,
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK, "{response}");
+    assert_eq!(
+        subtypes(&response),
+        ["joined", "group_renamed", "description_updated"]
+    );
+    assert_eq!(response["data"]["has_more"], false);
+    let (status, response) = signed(
+        &owner,
+        Method::GET,
+        &format!("{group_path}/messages?since=1&before=10"),
+        None,
+    )
+    .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST, "{response}");
+
+    // Single messages can be fetched by id
+    let (status, response) = signed(
+        &owner,
+        Method::GET,
+        &format!("{group_path}/messages/{created_id}"),
+        None,
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK, "{response}");
+    assert_eq!(response["data"]["message"]["subtype"], "group_created");
+    let (status, response) = signed(
+        &owner,
+        Method::GET,
+        &format!("{group_path}/messages/99999999"),
+        None,
+    )
+    .await;
+    assert_eq!(status, StatusCode::NOT_FOUND, "{response}");
+    let (status, response) = signed(
+        &outsider,
+        Method::GET,
+        &format!("{group_path}/messages/{created_id}"),
+        None,
+    )
+    .await;
+    assert_eq!(status, StatusCode::FORBIDDEN, "{response}");
+
+    // Former members can read the messages posted while they were members
+    let mut carol = EsmpConnection::connect(78).await;
+    carol.login().await;
+    let carol_pubkey = carol.pubkey.clone();
+    carol
+        .send(json!({"to": [], "group_id": GROUP_ID, "type": "system", "subtype": "joined", "actor": &carol_pubkey, "body": ""}))
+        .await;
+    let joined_id = carol.assert_read("ack").await["message_ids"][0].clone();
+    owner.assert_read("push").await;
+    alice.assert_read("push").await;
+    owner
+        .send(json!({"to": [], "group_id": GROUP_ID, "type": "text", "body": "Welcome"}))
+        .await;
+    owner.assert_read("ack").await;
+    alice.assert_read("push").await;
+    carol.assert_read("push").await;
+    carol
+        .send(json!({"to": [], "group_id": GROUP_ID, "type": "system", "subtype": "left", "actor": &carol_pubkey, "body": ""}))
+        .await;
+    carol.assert_read("ack").await;
+    owner.assert_read("push").await;
+    alice.assert_read("push").await;
+    owner
+        .send(json!({"to": [], "group_id": GROUP_ID, "type": "text", "body": "Goodbye"}))
+        .await;
+    owner.assert_read("ack").await;
+    alice.assert_read("push").await;
+
+    let (status, response) =
+        signed(&carol, Method::GET, &format!("{group_path}/messages"), None).await;
+    assert_eq!(status, StatusCode::OK, "{response}");
+    let messages = response["data"]["messages"].as_array().unwrap();
+    assert_eq!(messages.len(), 3);
+    assert_eq!(messages[0]["id"], joined_id);
+    assert_eq!(messages[1]["message"]["body"], "Welcome");
+    assert_eq!(messages[2]["message"]["subtype"], "left");
+    let (status, response) = signed(
+        &carol,
+        Method::GET,
+        &format!("{group_path}/messages/{created_id}"),
+        None,
+    )
+    .await;
+    assert_eq!(status, StatusCode::NOT_FOUND, "{response}");
+    let (status, response) = signed(
+        &carol,
+        Method::GET,
+        &format!("{group_path}/messages/{joined_id}"),
+        None,
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK, "{response}");
+    let (status, response) = signed(&carol, Method::GET, &group_path, None).await;
+    assert_eq!(status, StatusCode::FORBIDDEN, "{response}");
+    carol.assert_no_frames().await;
+
+    // Signed requests cannot be replayed or tampered with
+    let headers = signature_headers(&owner, Method::GET, &group_path, None, "api-replay");
+    let (status, response) = request(Method::GET, &group_path, None, &headers).await;
+    assert_eq!(status, StatusCode::OK, "{response}");
+    let (status, response) = request(Method::GET, &group_path, None, &headers).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED, "{response}");
+    let headers = signature_headers(
+        &owner,
+        Method::PUT,
+        &group_path,
+        Some(&json!({"messages": []})),
+        "api-tampered",
+    );
+    let (status, response) = request(
+        Method::PUT,
+        &group_path,
+        Some(json!({"messages": [&rename]})),
+        &headers,
+    )
+    .await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED, "{response}");
+
+    // Profiles are updated by their owner
+    let profile_path = format!("/api/esmp/users/{}/profile", encode(&owner_pubkey));
+    let (status, response) = request(Method::GET, &profile_path, None, &[]).await;
+    assert_eq!(status, StatusCode::NOT_FOUND, "{response}");
+    let (status, response) = signed(
+        &owner,
+        Method::PUT,
+        &profile_path,
+        Some(json!({
+            "first_name": {"value": "Owen", "visibility": "public"},
+            "last_name": {"value": "Smith"},
+            "address": {"value": "1 Main Street"}
+        })),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK, "{response}");
+    assert_eq!(response["data"]["pubkey"], owner_pubkey.as_str());
+    assert_eq!(response["data"]["last_name"]["value"], "Smith");
+    assert_eq!(response["data"]["address"]["value"], "1 Main Street");
+    let (status, response) = request(
+        Method::PUT,
+        &profile_path,
+        Some(json!({"first_name": {"value": "Mallory"}})),
+        &[(AUTHORIZATION.as_str(), ALICE_CREDENTIALS.to_string())],
+    )
+    .await;
+    assert_eq!(status, StatusCode::FORBIDDEN, "{response}");
+    let (status, response) = signed(
+        &owner,
+        Method::PUT,
+        &profile_path,
+        Some(json!({"address": {"value": "1 Main Street", "visibility": "public"}})),
+    )
+    .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST, "{response}");
+    let (status, response) = signed(
+        &owner,
+        Method::PUT,
+        &profile_path,
+        Some(json!({"first_name": {"value": "Owen1"}})),
+    )
+    .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST, "{response}");
+
+    // Changing only the visibility keeps the value of a field
+    let (status, response) = signed(
+        &owner,
+        Method::PUT,
+        &profile_path,
+        Some(json!({
+            "last_name": {"visibility": "private"},
+            "address": {"visibility": "private"}
+        })),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK, "{response}");
+    assert_eq!(
+        response["data"]["last_name"],
+        json!({"value": "Smith", "visibility": "private"})
+    );
+    assert_eq!(
+        response["data"]["address"],
+        json!({"value": "1 Main Street", "visibility": "private"})
+    );
+    let (status, response) = signed(
+        &owner,
+        Method::PUT,
+        &profile_path,
+        Some(json!({"middle_name": {"value": "Lee"}})),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK, "{response}");
+    assert_eq!(response["data"]["middle_name"]["value"], "Lee");
+    let (status, response) = signed(
+        &owner,
+        Method::PUT,
+        &profile_path,
+        Some(json!({"middle_name": {"value": null}})),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK, "{response}");
+    assert_eq!(response["data"]["middle_name"]["value"], Value::Null);
+
+    // Other requesters only see public fields
+    let (status, response) = request(Method::GET, &profile_path, None, &[]).await;
+    assert_eq!(status, StatusCode::OK, "{response}");
+    assert_eq!(
+        response["data"]["first_name"],
+        json!({"value": "Owen", "visibility": "public"})
+    );
+    assert_eq!(response["data"]["last_name"]["value"], Value::Null);
+    assert_eq!(response["data"]["address"]["value"], Value::Null);
+    let (status, response) = signed(&owner, Method::GET, &profile_path, None).await;
+    assert_eq!(status, StatusCode::OK, "{response}");
+    assert_eq!(response["data"]["last_name"]["value"], "Smith");
+    assert_eq!(response["data"]["address"]["value"], "1 Main Street");
+
+    // Fields can be shared with contacts, group peers or a custom audience
+    let contact = EsmpConnection::connect(81).await;
+    let (status, response) = signed(
+        &owner,
+        Method::PUT,
+        &profile_path,
+        Some(json!({
+            "middle_name": {"value": "Lee", "visibility": "contacts"},
+            "last_name": {"value": "Smith", "visibility": "groups"},
+            "display_picture": {"value": "https://example.org/owen.png", "visibility": "custom", "allowed": [&contact.pubkey]},
+            "contacts": [&contact.pubkey, &contact.pubkey]
+        })),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK, "{response}");
+    assert_eq!(response["data"]["contacts"], json!([&contact.pubkey]));
+    assert_eq!(
+        response["data"]["display_picture"]["allowed"],
+        json!([&contact.pubkey])
+    );
+    assert_eq!(response["data"]["address"]["value"], "1 Main Street");
+    for (conn, visible) in [
+        (&contact, &["middle_name", "display_picture"][..]),
+        (&alice, &["last_name"][..]),
+    ] {
+        let (status, response) = signed(conn, Method::GET, &profile_path, None).await;
+        assert_eq!(status, StatusCode::OK, "{response}");
+        for field in ["middle_name", "last_name", "display_picture", "address"] {
+            assert_eq!(
+                response["data"][field]["value"].is_null(),
+                !visible.contains(&field),
+                "{field}: {response}"
+            );
+        }
+        assert_eq!(response["data"]["first_name"]["value"], "Owen");
+        assert!(response["data"]["contacts"].is_null(), "{response}");
+        assert!(
+            response["data"]["display_picture"]["allowed"].is_null(),
+            "{response}"
+        );
+    }
+    for headers in [
+        vec![],
+        signature_headers(&outsider, Method::GET, &profile_path, None, "api-outsider"),
+    ] {
+        let (status, response) = request(Method::GET, &profile_path, None, &headers).await;
+        assert_eq!(status, StatusCode::OK, "{response}");
+        assert_eq!(response["data"]["first_name"]["value"], "Owen");
+        for field in ["middle_name", "last_name", "display_picture", "address"] {
+            assert_eq!(response["data"][field]["value"], Value::Null, "{response}");
+        }
+    }
+
+    // Accounts can update the profiles of their bound keys
+    let (status, response) = request(
+        Method::PUT,
+        &format!("/api/esmp/users/{}/profile", encode(&alice_pubkey)),
+        Some(json!({"display_picture": {"value": "https://example.org/alice.png", "visibility": "public"}})),
+        &[(AUTHORIZATION.as_str(), ALICE_CREDENTIALS.to_string())],
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK, "{response}");
+    assert_eq!(
+        response["data"]["display_picture"]["value"],
+        "https://example.org/alice.png"
+    );
+
+    owner.assert_no_frames().await;
+    alice.assert_no_frames().await;
+}
+
+pub(super) async fn signed(
+    conn: &EsmpConnection,
+    method: Method,
+    path: &str,
+    body: Option<Value>,
+) -> (StatusCode, Value) {
+    let id = format!("api-{}", store::rand::random::<u64>());
+    let headers = signature_headers(conn, method.clone(), path, body.as_ref(), &id);
+    request(method, path, body, &headers).await
+}
+
+pub(super) fn signature_headers(
+    conn: &EsmpConnection,
+    method: Method,
+    path: &str,
+    body: Option<&Value>,
+    id: &str,
+) -> Vec<(&'static str, String)> {
+    let timestamp = now();
+    let signature = conn.signature(&json!({
+        "version": 1,
+        "id": id,
+        "timestamp": timestamp,
+        "method": method.as_str(),
+        "path": path,
+        "body": body,
+    }));
+
+    vec![
+        ("X-ESMP-Key", conn.pubkey.clone()),
+        ("X-ESMP-Id", id.to_string()),
+        ("X-ESMP-Timestamp", timestamp.to_string()),
+        ("X-ESMP-Signature", signature),
+    ]
+}
+
+pub(super) async fn request(
+    method: Method,
+    path: &str,
+    body: Option<Value>,
+    headers: &[(&str, String)],
+) -> (StatusCode, Value) {
+    let mut request = reqwest::Client::builder()
+        .timeout(Duration::from_secs(5))
+        .build()
+        .unwrap()
+        .request(method, format!("http://127.0.0.1:5880{path}"));
+    for (name, value) in headers {
+        request = request.header(*name, value);
+    }
+    if let Some(body) = body {
+        request = request.body(body.to_string());
+    }
+    let response = request.send().await.unwrap();
+    let status = response.status();
+    let bytes = response.bytes().await.unwrap();
+
+    (
+        status,
+        serde_json::from_slice(&bytes).unwrap_or(Value::Null),
+    )
+}
+
+fn subtypes(response: &Value) -> Vec<&str> {
+    response["data"]["messages"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|entry| entry["message"]["subtype"].as_str().unwrap())
+        .collect()
+}
+
+pub(super) fn encode(pubkey: &str) -> String {
+    pubkey
+        .replace('+', "%2B")
+        .replace('/', "%2F")
+        .replace('=', "%3D")
+}
@@ -0,0 +1,154 @@
+/*
+ * SPDX-FileCopyrightText: 2020 Stalwart Labs LLC <hello@stalw.art>
+ *
+ * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
+ */
+
+use serde_json::json;
+use store::write::now;
+
+use super::EsmpConnection;
+
+pub async fn test() {
+    println!("Running basic protocol tests...");
+
+    let mut alice = EsmpConnection::connect(1).await;
+    let bob = EsmpConnection::connect(2).await;
+    let alice_pubkey = alice.pubkey.clone();
+    let bob_pubkey = bob.pubkey.clone();
+
+    // Commands before hello are rejected
+    alice.send(json!({"command": "fetch", "id": "early"})).await;
+    assert_eq!(alice.assert_error("bad_sequence").await["id"], "early");
+
+    // Malformed frames
+    alice.send_raw("{not json").await;
+    alice.assert_error("parse").await;
+    alice
+        .send_raw(r#"{"command":"hello","versions":[1],"versions":[1]}"#)
+        .await;
+    alice.assert_error("parse").await;
+    alice
+        .send_raw(r#"{"command":"hello","versions":[9007199254740993]}"#)
+        .await;
+    alice.assert_error("parse").await;
+
+    // Version negotiation
+    alice
+        .send_unsigned(json!({"command": "hello", "versions": [0, 99]}))
+        .await;
+    alice.assert_error("unsupported_version").await;
+    alice
+        .send_unsigned(json!({"command": "hello", "versions": [1]}))
+        .await;
+    let hello = alice.assert_read("hello").await;
+    assert_eq!(hello["version"], 1);
+    for capability in ["inbox", "invites", "blobs", "spam"] {
+        assert!(
+            hello["capabilities"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .any(|item| item == capability),
+            "{capability}"
+        );
+    }
+    alice
+        .send_unsigned(json!({"command": "hello", "versions": [1]}))
+        .await;
+    alice.assert_error("bad_sequence").await;
+
+    // Messages and commands require authentication
+    alice
+        .send(json!({"to": [&bob_pubkey], "type": "text", "body": "Hi"}))
+        .await;
+    alice.assert_error("unauthenticated").await;
+    let fetch = alice.sign(json!({"command": "fetch"})).to_string();
+    alice.send_raw(&fetch).await;
+    alice.assert_error("unauthenticated").await;
+
+    // Authentication requires the session nonce
+    alice
+        .send(json!({"command": "authenticate", "nonce": "bogus"}))
+        .await;
+    alice.assert_error("forbidden").await;
+    let nonce = alice.nonce.clone();
+    alice
+        .send(json!({"command": "authenticate", "nonce": nonce}))
+        .await;
+    alice.assert_read("authenticated").await;
+    alice
+        .send(json!({"command": "authenticate", "nonce": nonce}))
+        .await;
+    alice.assert_error("invalid_request").await;
+
+    // Requests rejected before authentication can be sent again
+    alice.send_raw(&fetch).await;
+    alice.assert_read("messages").await;
+
+    // Tampered messages
+    let mut message = alice.sign(json!({"to": [&bob_pubkey], "type": "text", "body": "Hi"}));
+    message["body"] = "Bye".into();
+    alice.send_raw(&message.to_string()).await;
+    alice.assert_error("invalid_signature").await;
+
+    // Version mismatch
+    alice
+        .send(json!({"version": 2, "to": [&bob_pubkey], "type": "text", "body": "Hi"}))
+        .await;
+    alice.assert_error("unsupported_version").await;
+
+    // Stale messages
+    alice
+        .send(json!({"timestamp": now() - 3600, "to": [&bob_pubkey], "type": "text", "body": "Hi"}))
+        .await;
+    alice.assert_error("expired").await;
+
+    // Replayed messages
+    let message = alice
+        .sign(json!({"to": [&bob_pubkey], "type": "text", "body": "Hi"}))
+        .to_string();
+    alice.send_raw(&message).await;
+    assert_eq!(
+        alice.assert_read("ack").await["message_ids"]
+            .as_array()
+            .unwrap()
+            .len(),
+        1
+    );
+    alice.send_raw(&message).await;
+    alice.assert_error("replay").await;
+
+    // Invalid system messages
+    alice
+        .send(json!({"to": [], "group_id": "g1", "type": "system", "subtype": "group_renamed", "actor": &alice_pubkey, "body": ""}))
+        .await;
+    alice.assert_error("invalid_message").await;
+
+    // Ids are only consumed once a message is accepted
+    let retry_id = format!("retry-{}", alice.nonce);
+    alice
+        .send(json!({"id": &retry_id, "to": [], "group_id": "g1", "type": "system", "subtype": "group_renamed", "actor": &alice_pubkey, "body": ""}))
+        .await;
+    alice.assert_error("invalid_message").await;
+    alice
+        .send(json!({"id": &retry_id, "to": [], "group_id": "no-such-group", "type": "text", "body": "Hi"}))
+        .await;
+    alice.assert_error("forbidden").await;
+    alice
+        .send(json!({"id": &retry_id, "to": [&bob_pubkey], "type": "text", "body": "Hi"}))
+        .await;
+    alice.assert_read("ack").await;
+    alice
+        .send(json!({"id": &retry_id, "to": [&bob_pubkey], "type": "text", "body": "Hi"}))
+        .await;
+    alice.assert_error("replay").await;
+
+    // Messages signed by a different key than the session key
+    let mut mallory = EsmpConnection::connect(3).await;
+    mallory.login().await;
+    let mut message = alice.sign(json!({"to": [&bob_pubkey], "type": "text", "body": "Hi"}));
+    message["id"] = "forged".into();
+    mallory.send_raw(&message.to_string()).await;
+    mallory.assert_error("forbidden").await;
+}
@@ -0,0 +1,291 @@
+/*
+ * SPDX-FileCopyrightText: 2020 Stalwart Labs LLC <hello@stalw.art>
+ *
+ * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
+ */
+
+use std::time::Duration;
+
+use base64::{Engine, engine::general_purpose};
+use reqwest::{
+    Method, StatusCode,
+    header::{CONTENT_DISPOSITION, CONTENT_TYPE},
+};
+use serde_json::{Value, json};
+use utils::BlobHash;
+
+use super::{
+    EsmpConnection,
+    api::{encode, signature_headers, signed},
+};
+
+const GROUP_ID: &str = "esmp-blob-group";
+const PNG: &str =
+    "iVBORw0KGgoAAAANSUhEUgAAAAEAAAABCAQAAAC1HAwCAAAAC0lEQVR42mNkYAAAAAYAAjCB0C8AAAAASUVORK5CYII=";
+
+pub async fn test() {
+    println!("Running blob tests...");
+
+    let mut owner = EsmpConnection::connect(82).await;
+    let mut member = EsmpConnection::connect(83).await;
+    let outsider = EsmpConnection::connect(84).await;
+    owner.login().await;
+    member.login().await;
+    let owner_pubkey = owner.pubkey.clone();
+    let member_pubkey = member.pubkey.clone();
+
+    owner
+        .send(json!({"to": [], "group_id": GROUP_ID, "type": "system", "subtype": "group_created", "actor": &owner_pubkey, "body": {"group_name": "Blob group"}}))
+        .await;
+    owner.assert_read("ack").await;
+    member
+        .send(json!({"to": [], "group_id": GROUP_ID, "type": "system", "subtype": "joined", "actor": &member_pubkey, "body": ""}))
+        .await;
+    member.assert_read("ack").await;
+    owner.assert_read("push").await;
+
+    // Group attachments are only readable by group members
+    let (status, response) = upload(
+        &outsider,
+        &format!("?group_id={GROUP_ID}"),
+        "text/plain",
+        b"Minutes",
+    )
+    .await;
+    assert_eq!(status, StatusCode::FORBIDDEN, "{response}");
+    let (status, response) = upload(
+        &owner,
+        &format!("?group_id={GROUP_ID}"),
+        "text/html",
+        b"<p>Minutes</p>",
+    )
+    .await;
+    assert_eq!(status, StatusCode::CREATED, "{response}");
+    let attachment_id = response["data"]["blob_id"].as_str().unwrap().to_string();
+    assert_eq!(
+        attachment_id,
+        BlobHash::generate(b"<p>Minutes</p>").to_hex()
+    );
+    assert_eq!(response["data"]["kind"], "attachment");
+    assert!(
+        response["data"]["url"]
+            .as_str()
+            .unwrap()
+            .ends_with(&format!("/api/esmp/blobs/{attachment_id}")),
+        "{response}"
+    );
+    let attachment_path = format!("/api/esmp/blobs/{attachment_id}");
+    for conn in [Some(&member), Some(&outsider), None] {
+        let (status, _, _) = download(conn, &attachment_path).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+
+    // Members can read group attachments once a message posts them
+    owner
+        .send(json!({"to": [], "group_id": GROUP_ID, "type": "text", "body": {"text": "Minutes", "attachments": [{"blob_id": &attachment_id}]}}))
+        .await;
+    owner.assert_read("ack").await;
+    let message = member.assert_read("push").await["message"].clone();
+    assert_eq!(message["body"]["attachments"][0]["blob_id"], attachment_id);
+    let (status, contents, headers) = download(Some(&member), &attachment_path).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(contents, b"<p>Minutes</p>");
+    assert!(
+        headers[CONTENT_DISPOSITION]
+            .to_str()
+            .unwrap()
+            .starts_with("attachment")
+    );
+    for conn in [Some(&outsider), None] {
+        let (status, _, _) = download(conn, &attachment_path).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+
+    // Messages can only reference attachments uploaded to their group
+    let (status, response) = upload(&owner, "", "text/plain", b"Private notes").await;
+    assert_eq!(status, StatusCode::CREATED, "{response}");
+    let notes_id = response["data"]["blob_id"].as_str().unwrap().to_string();
+    let notes_path = format!("/api/esmp/blobs/{notes_id}");
+    owner
+        .send(json!({"to": [], "group_id": GROUP_ID, "type": "text", "body": {"attachments": [{"blob_id": &notes_id}]}}))
+        .await;
+    owner.assert_error("forbidden").await;
+
+    // Direct attachments are only readable by the recipients of the uploader
+    for conn in [&member, &outsider] {
+        let (status, _, _) = download(Some(conn), &notes_path).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+    owner
+        .send(json!({"to": [&member_pubkey], "type": "text", "body": {"attachments": [{"blob_id": &notes_id}]}}))
+        .await;
+    owner.assert_read("ack").await;
+    member.assert_read("push").await;
+    for (conn, expected) in [
+        (&owner, StatusCode::OK),
+        (&member, StatusCode::OK),
+        (&outsider, StatusCode::NOT_FOUND),
+    ] {
+        let (status, _, _) = download(Some(conn), &notes_path).await;
+        assert_eq!(status, expected);
+    }
+
+    // Former members keep the files posted while they were in the group
+    member
+        .send(json!({"to": [], "group_id": GROUP_ID, "type": "system", "subtype": "left", "actor": &member_pubkey, "body": ""}))
+        .await;
+    member.assert_read("ack").await;
+    owner.assert_read("push").await;
+    let (status, response) = upload(
+        &owner,
+        &format!("?group_id={GROUP_ID}"),
+        "text/plain",
+        b"Later minutes",
+    )
+    .await;
+    assert_eq!(status, StatusCode::CREATED, "{response}");
+    let later_path = format!(
+        "/api/esmp/blobs/{}",
+        response["data"]["blob_id"].as_str().unwrap()
+    );
+    owner
+        .send(json!({"to": [], "group_id": GROUP_ID, "type": "text", "body": {"attachments": [{"blob_id": response["data"]["blob_id"]}]}}))
+        .await;
+    owner.assert_read("ack").await;
+    let (status, _, _) = download(Some(&owner), &later_path).await;
+    assert_eq!(status, StatusCode::OK);
+    for (path, expected) in [
+        (&attachment_path, StatusCode::OK),
+        (&later_path, StatusCode::NOT_FOUND),
+    ] {
+        let (status, _, _) = download(Some(&member), path).await;
+        assert_eq!(status, expected);
+    }
+    owner
+        .send(json!({"to": [], "group_id": GROUP_ID, "type": "text", "body": {"attachments": [{"blob_id": "not-a-blob"}]}}))
+        .await;
+    owner.assert_error("invalid_message").await;
+
+    // Pictures are thumbnailed and follow the visibility of the display picture
+    let (status, response) = upload(&owner, "?kind=picture", "image/png", b"Not an image").await;
+    assert_eq!(status, StatusCode::BAD_REQUEST, "{response}");
+    let png = general_purpose::STANDARD.decode(PNG).unwrap();
+    let (status, response) = upload(&owner, "?kind=picture", "text/html", &png).await;
+    assert_eq!(status, StatusCode::CREATED, "{response}");
+    assert_eq!(response["data"]["content_type"], "image/png");
+    let picture_url = response["data"]["url"].as_str().unwrap().to_string();
+    let picture_path = format!(
+        "/api/esmp/blobs/{}",
+        response["data"]["blob_id"].as_str().unwrap()
+    );
+    let (status, _, _) = download(None, &picture_path).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    let (status, response) = signed(
+        &owner,
+        Method::PUT,
+        &format!("/api/esmp/users/{}/profile", encode(&owner_pubkey)),
+        Some(json!({"display_picture": {"value": &picture_url, "visibility": "public"}})),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK, "{response}");
+    let (status, contents, headers) = download(None, &picture_path).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(contents, png);
+    assert_eq!(headers[CONTENT_TYPE], "image/png");
+    let (status, contents, headers) = download(None, &format!("{picture_path}/thumbnail")).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(headers[CONTENT_TYPE], "image/png");
+    assert!(contents.starts_with(b"\x89PNG"));
+
+    // Uploads are limited in size and by the quota of their owner
+    let (status, response) = upload(&member, "", "text/plain", &[b'a'; 4096]).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST, "{response}");
+    for fill in [b'a', b'b'] {
+        let (status, response) = upload(&member, "", "text/plain", &[fill; 1800]).await;
+        assert_eq!(status, StatusCode::CREATED, "{response}");
+    }
+    let (status, response) = upload(&member, "", "text/plain", &[b'a'; 1800]).await;
+    assert_eq!(status, StatusCode::CREATED, "{response}");
+    let (status, response) = upload(&member, "", "text/plain", &[b'c'; 1800]).await;
+    assert_eq!(status, StatusCode::FORBIDDEN, "{response}");
+
+    // Deleting uploads releases quota
+    let full_path = format!(
+        "/api/esmp/blobs/{}",
+        BlobHash::generate([b'a'; 1800]).to_hex()
+    );
+    let (status, response) = signed(&outsider, Method::DELETE, &full_path, None).await;
+    assert_eq!(status, StatusCode::NOT_FOUND, "{response}");
+    let (status, response) = signed(&member, Method::DELETE, &full_path, None).await;
+    assert_eq!(status, StatusCode::OK, "{response}");
+    let (status, _, _) = download(Some(&member), &full_path).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    let (status, response) = upload(&member, "", "text/plain", &[b'c'; 1800]).await;
+    assert_eq!(status, StatusCode::CREATED, "{response}");
+
+    owner.assert_no_frames().await;
+    member.assert_no_frames().await;
+}
+
+pub async fn upload(
+    conn: &EsmpConnection,
+    query: &str,
+    content_type: &str,
+    data: &[u8],
+) -> (StatusCode, Value) {
+    let path = format!("/api/esmp/blobs{query}");
+    let mut headers = signature_headers(
+        conn,
+        Method::POST,
+        &path,
+        Some(&json!(BlobHash::generate(data).to_hex())),
+        &format!("blob-{}", store::rand::random::<u64>()),
+    );
+    headers.push(("Content-Type", content_type.to_string()));
+    let mut request = client()
+        .post(format!("http://127.0.0.1:5880{path}"))
+        .body(data.to_vec());
+    for (name, value) in headers {
+        request = request.header(name, value);
+    }
+    let response = request.send().await.unwrap();
+    let status = response.status();
+
+    (
+        status,
+        serde_json::from_slice(&response.bytes().await.unwrap()).unwrap_or(Value::Null),
+    )
+}
+
+pub async fn download(
+    conn: Option<&EsmpConnection>,
+    path: &str,
+) -> (StatusCode, Vec<u8>, reqwest::header::HeaderMap) {
+    let headers = conn
+        .map(|conn| {
+            signature_headers(
+                conn,
+                Method::GET,
+                path,
+                None,
+                &format!("blob-{}", store::rand::random::<u64>()),
+            )
+        })
+        .unwrap_or_default();
+    let mut request = client().get(format!("http://127.0.0.1:5880{path}"));
+    for (name, value) in headers {
+        request = request.header(name, value);
+    }
+    let response = request.send().await.unwrap();
+    let status = response.status();
+    let headers = response.headers().clone();
+
+    (status, response.bytes().await.unwrap().to_vec(), headers)
+}
+
+fn client() -> reqwest::Client {
+    reqwest::Client::builder()
+        .timeout(Duration::from_secs(5))
+        .build()
+        .unwrap()
+}
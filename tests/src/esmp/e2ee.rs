@@ -0,0 +1,150 @@
+/*
+ * SPDX-FileCopyrightText: 2020 Stalwart Labs LLC <hello@stalw.art>
+ *
+ * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
+ */
+
+use base64::{Engine, engine::general_purpose};
+use serde_json::{Value, json};
+
+use super::EsmpConnection;
+
+const GROUP_ID: &str = "esmp-e2ee-group";
+
+pub async fn test() {
+    println!("Running end-to-end encryption tests...");
+
+    let mut alice = EsmpConnection::connect(40).await;
+    let mut bob = EsmpConnection::connect(41).await;
+    alice.login().await;
+    bob.login().await;
+    let alice_pubkey = alice.pubkey.clone();
+    let bob_pubkey = bob.pubkey.clone();
+
+    // Publish key packages
+    let packages = vec![
+        key_package(&bob, "kp-1", 1, false),
+        key_package(&bob, "kp-2", 2, false),
+        key_package(&bob, "kp-last", 3, true),
+    ];
+    bob.send(json!({"command": "publish_key_packages", "packages": packages}))
+        .await;
+    assert_eq!(bob.assert_read("key_package_count").await["count"], 2);
+    bob.send(json!({"command": "publish_key_packages", "packages": [key_package(&bob, "kp-1", 1, false)]}))
+        .await;
+    bob.assert_error("invalid_request").await;
+
+    // Packages must be signed by the publishing key
+    let mut package = key_package(&bob, "kp-3", 4, false);
+    package["key"] = encode(&[5; 32]).into();
+    bob.send(json!({"command": "publish_key_packages", "packages": [package]}))
+        .await;
+    bob.assert_error("invalid_signature").await;
+    let package = key_package(&alice, "kp-3", 4, false);
+    bob.send(json!({"command": "publish_key_packages", "packages": [package]}))
+        .await;
+    bob.assert_error("invalid_signature").await;
+
+    // One-time packages are consumed, then the last resort package is served
+    for expected_id in ["kp-2", "kp-1", "kp-last", "kp-last"] {
+        alice
+            .send(json!({"command": "fetch_key_package", "pubkey": &bob_pubkey}))
+            .await;
+        let response = alice.assert_read("key_package").await;
+        assert_eq!(response["pubkey"], bob_pubkey.as_str());
+        assert_eq!(response["package"]["id"], expected_id);
+        assert!(response["package"]["signature"].is_string());
+    }
+    bob.send(json!({"command": "count_key_packages"})).await;
+    assert_eq!(bob.assert_read("key_package_count").await["count"], 0);
+    alice
+        .send(json!({"command": "fetch_key_package", "pubkey": "unknown-key"}))
+        .await;
+    assert_eq!(
+        alice.assert_read("key_package").await["package"],
+        Value::Null
+    );
+
+    // Direct envelopes need a wrapped key for every recipient
+    alice
+        .send(json!({"to": [&bob_pubkey], "type": "encrypted", "body": envelope(&[])}))
+        .await;
+    alice.assert_error("invalid_message").await;
+    alice
+        .send(json!({"to": [&bob_pubkey], "type": "encrypted", "body": {"ciphertext": "opaque"}}))
+        .await;
+    alice.assert_error("invalid_message").await;
+    let message_id = alice
+        .send(json!({"to": [&bob_pubkey], "type": "encrypted", "body": envelope(&[&bob_pubkey, &alice_pubkey])}))
+        .await;
+    alice.assert_read("ack").await;
+    let push = bob.assert_read("push").await;
+    assert_eq!(push["message"]["id"], message_id.as_str());
+    assert_eq!(
+        push["message"]["body"]["ciphertext"],
+        encode(b"opaque ciphertext")
+    );
+
+    // Group envelopes must use the current epoch
+    alice
+        .send(system("group_created", &alice_pubkey, json!("")))
+        .await;
+    alice.assert_read("ack").await;
+    bob.send(system("joined", &bob_pubkey, json!(""))).await;
+    bob.assert_read("ack").await;
+    alice.assert_read("push").await;
+    alice
+        .send(system("key_commit", &alice_pubkey, json!({"epoch": 2})))
+        .await;
+    alice.assert_error("forbidden").await;
+    alice
+        .send(system(
+            "key_commit",
+            &alice_pubkey,
+            json!({"epoch": 1, "commit": "opaque"}),
+        ))
+        .await;
+    alice.assert_read("ack").await;
+    bob.assert_read("push").await;
+    bob.send(group_envelope(0)).await;
+    bob.assert_error("forbidden").await;
+    bob.send(group_envelope(1)).await;
+    bob.assert_read("ack").await;
+    alice.assert_read("push").await;
+}
+
+fn key_package(conn: &EsmpConnection, id: &str, seed: u8, last_resort: bool) -> Value {
+    let mut package = json!({"id": id, "key": encode(&[seed; 32]), "last_resort": last_resort});
+    package["signature"] = conn.signature(&package).into();
+    package
+}
+
+fn envelope(recipients: &[&str]) -> Value {
+    json!({
+        "alg": "x25519-aes256gcm",
+        "nonce": encode(&[0; 12]),
+        "ciphertext": encode(b"opaque ciphertext"),
+        "recipients": recipients.iter().map(|pubkey| json!({
+            "pubkey": pubkey,
+            "ephemeral_key": encode(&[1; 32]),
+            "wrapped_key": encode(&[2; 48]),
+        })).collect::<Vec<_>>(),
+    })
+}
+
+fn group_envelope(epoch: u64) -> Value {
+    json!({"to": [], "group_id": GROUP_ID, "type": "encrypted", "body": {
+        "alg": "mls-aes256gcm",
+        "epoch": epoch,
+        "nonce": encode(&[0; 12]),
+        "ciphertext": encode(b"opaque ciphertext"),
+    }})
+}
+
+fn system(subtype: &str, actor: &str, body: Value) -> Value {
+    json!({"to": [], "group_id": GROUP_ID, "type": "system", "subtype": subtype, "actor": actor, "body": body})
+}
+
+fn encode(bytes: &[u8]) -> String {
+    general_purpose::STANDARD.encode(bytes)
+}
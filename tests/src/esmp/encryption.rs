@@ -0,0 +1,82 @@
+/*
+ * SPDX-FileCopyrightText: 2020 Stalwart Labs LLC <hello@stalw.art>
+ *
+ * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
+ */
+
+use common::{Server, config::esmp::EsmpEncryption};
+use esmp::profile::{ProfileError, UserProfile, persist::ProfileStore};
+
+use super::pubkey_from_seed;
+
+pub async fn test(server: &Server) {
+    println!("Running profile encryption tests...");
+
+    let keys = &server.core.esmp.encryption;
+    let pubkey = pubkey_from_seed(79);
+    assert_eq!(keys.active.as_deref(), Some("current"));
+
+    // Addresses cannot be stored without a master key
+    let mut profile = UserProfile::new(pubkey.clone());
+    assert!(matches!(
+        profile.set_address(
+            Some("1 Main Street".into()),
+            &EsmpEncryption::default(),
+            None
+        ),
+        Err(ProfileError::MissingMasterKey)
+    ));
+
+    // Store a profile encrypted with the previous master key
+    let previous = EsmpEncryption {
+        active: Some("previous".into()),
+        keys: keys.keys.clone(),
+        ..Default::default()
+    };
+    profile
+        .set_address(Some("1 Main Street".into()), &previous, None)
+        .unwrap();
+    server.save_profile(profile).await.unwrap();
+    let profile = server.get_profile(&pubkey).await.unwrap().unwrap();
+    let envelope = profile.address.value.as_ref().unwrap();
+    assert_eq!(envelope.key_id, "previous");
+    assert!(
+        !envelope
+            .ciphertext
+            .windows(b"Main Street".len())
+            .any(|window| window == b"Main Street")
+    );
+    assert_eq!(
+        profile.address(keys).unwrap().as_deref(),
+        Some("1 Main Street")
+    );
+
+    // Wrapped keys are bound to the profile they were issued for
+    let mut other = UserProfile::new(pubkey_from_seed(80));
+    other.address = profile.address.clone();
+    assert!(matches!(
+        other.address(keys),
+        Err(ProfileError::DecryptionError(_))
+    ));
+
+    // Re-encryption wraps the data key with the active master key
+    assert_eq!(server.reencrypt_profiles().await.unwrap(), 1);
+    let rotated = server.get_profile(&pubkey).await.unwrap().unwrap();
+    let rotated_envelope = rotated.address.value.as_ref().unwrap();
+    assert_eq!(rotated_envelope.key_id, "current");
+    assert_eq!(rotated_envelope.ciphertext, envelope.ciphertext);
+    assert_ne!(rotated_envelope.wrapped_key, envelope.wrapped_key);
+    assert_eq!(server.reencrypt_profiles().await.unwrap(), 0);
+
+    // The previous master key can then be retired
+    let mut current = keys.clone();
+    current.keys.remove("previous");
+    assert_eq!(
+        rotated.address(&current).unwrap().as_deref(),
+        Some("1 Main Street")
+    );
+    assert!(matches!(
+        profile.address(&current),
+        Err(ProfileError::UnknownMasterKey(_))
+    ));
+}
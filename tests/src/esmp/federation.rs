@@ -0,0 +1,334 @@
+/*
+ * SPDX-FileCopyrightText: 2020 Stalwart Labs LLC <hello@stalw.art>
+ *
+ * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
+ */
+
+use std::time::Duration;
+
+use esmp::{canonical::signing_input, crypto::verify_signature};
+use serde_json::{Value, json};
+use store::write::now;
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader, Lines, ReadHalf, WriteHalf},
+    net::{TcpListener, TcpStream},
+};
+
+use super::{EsmpConnection, SERVER_KEY_SEED, pubkey_from_seed};
+
+pub async fn test() {
+    println!("Running federation tests...");
+
+    let server_pubkey = pubkey_from_seed(SERVER_KEY_SEED);
+    let peer = TcpListener::bind("127.0.0.1:5890").await.unwrap();
+    let mut sender = EsmpConnection::connect(50).await;
+    let mut local = EsmpConnection::connect(75).await;
+    sender.login().await;
+    local.login().await;
+
+    // Messages to remote recipients need a sender address on this domain
+    sender
+        .send(json!({"to": ["carol#remote.example.org"], "type": "text", "body": "Hi"}))
+        .await;
+    sender.assert_error("forbidden").await;
+
+    // Remote recipients are queued, local ones are delivered immediately
+    let message_id = sender
+        .send(json!({"from": "alice#esmp.example.org", "to": ["carol#remote.example.org", &local.pubkey], "type": "text", "body": "Hi"}))
+        .await;
+    let ack = sender.assert_read("ack").await;
+    assert_eq!(ack["message_ids"].as_array().unwrap().len(), 1);
+    assert_eq!(ack["queued"], json!(["carol#remote.example.org"]));
+    local.assert_read("push").await;
+    let relayed = accept_relay(&peer, &server_pubkey, ack_frame).await;
+    assert_eq!(relayed["id"], message_id.as_str());
+    assert_eq!(relayed["sender_pubkey"], sender.pubkey.as_str());
+    assert!(verify_signature(
+        &sender.pubkey,
+        relayed["signature"].as_str().unwrap(),
+        &signing_input(&relayed).unwrap()
+    ));
+
+    // Temporary failures are retried
+    let message_id = sender
+        .send(json!({"from": "alice#esmp.example.org", "to": ["dave#remote.example.org"], "type": "text", "body": "Hi"}))
+        .await;
+    assert_eq!(
+        sender.assert_read("ack").await["queued"],
+        json!(["dave#remote.example.org"])
+    );
+    accept_relay(&peer, &server_pubkey, |relay| {
+        error_frame(relay, "server_fail")
+    })
+    .await;
+    let relayed = accept_relay(&peer, &server_pubkey, ack_frame).await;
+    assert_eq!(relayed["id"], message_id.as_str());
+
+    // Permanent failures are reported to the sender
+    let message_id = sender
+        .send(json!({"from": "alice#esmp.example.org", "to": ["eve#remote.example.org"], "type": "text", "body": "Hi"}))
+        .await;
+    sender.assert_read("ack").await;
+    accept_relay(&peer, &server_pubkey, |relay| {
+        error_frame(relay, "forbidden")
+    })
+    .await;
+    let notice = sender.assert_read("push").await["message"].clone();
+    assert_eq!(notice["type"], "delivery_failure");
+    assert_eq!(notice["sender_pubkey"], server_pubkey.as_str());
+    assert_eq!(notice["body"]["message_id"], message_id.as_str());
+    assert_eq!(
+        notice["body"]["recipients"],
+        json!(["eve#remote.example.org"])
+    );
+    assert!(verify_signature(
+        &server_pubkey,
+        notice["signature"].as_str().unwrap(),
+        &signing_input(&notice).unwrap()
+    ));
+
+    // Messages already stored by the peer are delivered, expired ones are bounced
+    let message_id = sender
+        .send(json!({"from": "alice#esmp.example.org", "to": ["hank#remote.example.org"], "type": "text", "body": "Hi"}))
+        .await;
+    sender.assert_read("ack").await;
+    let relayed = accept_relay(&peer, &server_pubkey, |relay| error_frame(relay, "replay")).await;
+    assert_eq!(relayed["id"], message_id.as_str());
+    let message_id = sender
+        .send(json!({"from": "alice#esmp.example.org", "to": ["ivan#remote.example.org"], "type": "text", "body": "Hi"}))
+        .await;
+    sender.assert_read("ack").await;
+    let relayed = accept_relay(&peer, &server_pubkey, |relay| error_frame(relay, "expired")).await;
+    assert_eq!(relayed["id"], message_id.as_str());
+    let notice = sender.assert_read("push").await["message"].clone();
+    assert_eq!(notice["type"], "delivery_failure");
+    assert_eq!(
+        notice["body"]["recipients"],
+        json!(["ivan#remote.example.org"])
+    );
+
+    // Messages that cannot be delivered before expiring are bounced
+    sender
+        .send(json!({"from": "alice#esmp.example.org", "to": ["gina#offline.example.org"], "type": "text", "body": "Hi"}))
+        .await;
+    sender.assert_read("ack").await;
+    tokio::time::sleep(Duration::from_secs(5)).await;
+    let notice = sender.assert_read("push").await["message"].clone();
+    assert_eq!(notice["type"], "delivery_failure");
+    assert_eq!(
+        notice["body"]["recipients"],
+        json!(["gina#offline.example.org"])
+    );
+
+    // Remote servers authenticate with the key published for their domain
+    let mut remote_user = EsmpConnection::connect(72).await;
+    let mut impostor = EsmpConnection::connect(73).await;
+    impostor
+        .send_unsigned(json!({"command": "hello", "versions": [1]}))
+        .await;
+    impostor.assert_read("hello").await;
+    let nonce = impostor.nonce.clone();
+    impostor
+        .send(json!({"command": "federate", "domain": "remote.example.org", "nonce": nonce}))
+        .await;
+    impostor.assert_error("forbidden").await;
+    sender
+        .send(json!({"command": "relay", "message": {}}))
+        .await;
+    sender.assert_error("forbidden").await;
+
+    let mut remote = EsmpConnection::connect(71).await;
+    remote
+        .send_unsigned(json!({"command": "hello", "versions": [1]}))
+        .await;
+    remote.assert_read("hello").await;
+    let nonce = remote.nonce.clone();
+    remote
+        .send(json!({"command": "federate", "domain": "Remote.example.org", "nonce": nonce}))
+        .await;
+    assert_eq!(
+        remote.assert_read("federated").await["domain"],
+        "remote.example.org"
+    );
+
+    // Relayed messages are delivered to local addresses
+    let message = remote_user.sign(json!({
+        "from": "frank#remote.example.org",
+        "to": ["alice#esmp.example.org", "carol#remote.example.org"],
+        "type": "text",
+        "body": "Hello from afar"
+    }));
+    remote
+        .send(json!({"command": "relay", "message": &message}))
+        .await;
+    assert_eq!(
+        remote.assert_read("ack").await["message_ids"]
+            .as_array()
+            .unwrap()
+            .len(),
+        1
+    );
+    assert_eq!(
+        sender.assert_read("push").await["message"]["id"],
+        message["id"]
+    );
+    sender.send(json!({"command": "fetch"})).await;
+    let messages = sender.assert_read("messages").await;
+    assert_eq!(
+        messages["messages"].as_array().unwrap().last().unwrap()["message"]["id"],
+        message["id"]
+    );
+
+    // Relayed messages cannot be replayed
+    remote
+        .send(json!({"command": "relay", "message": &message}))
+        .await;
+    remote.assert_error("replay").await;
+
+    // Relays must carry authentic messages from the relaying domain
+    let mut tampered = message.clone();
+    tampered["body"] = "Tampered".into();
+    remote
+        .send(json!({"command": "relay", "message": tampered}))
+        .await;
+    remote.assert_error("invalid_signature").await;
+    let message = remote_user.sign(json!({
+        "from": "mallory#esmp.example.org",
+        "to": ["alice#esmp.example.org"],
+        "type": "text",
+        "body": "Hi"
+    }));
+    remote
+        .send(json!({"command": "relay", "message": message}))
+        .await;
+    remote.assert_error("forbidden").await;
+    for message in [
+        json!({"to": ["alice#esmp.example.org"], "type": "text", "body": "Hi"}),
+        json!({"from": "frank", "to": ["alice#esmp.example.org"], "type": "text", "body": "Hi"}),
+    ] {
+        let message = remote_user.sign(message);
+        remote
+            .send(json!({"command": "relay", "message": message}))
+            .await;
+        remote.assert_error("invalid_message").await;
+    }
+    let message = remote_user.sign(json!({
+        "from": "frank#remote.example.org",
+        "to": ["alice#esmp.example.org"],
+        "timestamp": now() - 3600,
+        "type": "text",
+        "body": "Hi"
+    }));
+    remote
+        .send(json!({"command": "relay", "message": message}))
+        .await;
+    remote.assert_error("expired").await;
+    let message = remote_user.sign(json!({
+        "from": "frank#remote.example.org",
+        "to": ["alice#esmp.example.org"],
+        "group_id": "esmp-federated-group",
+        "type": "text",
+        "body": "Hi"
+    }));
+    remote
+        .send(json!({"command": "relay", "message": message}))
+        .await;
+    remote.assert_error("invalid_message").await;
+    let message = remote_user.sign(json!({
+        "from": "frank#remote.example.org",
+        "to": ["carol#remote.example.org"],
+        "type": "text",
+        "body": "Hi"
+    }));
+    remote
+        .send(json!({"command": "relay", "message": message}))
+        .await;
+    remote.assert_error("forbidden").await;
+    remote
+        .send(json!({"to": ["alice#esmp.example.org"], "type": "text", "body": "Hi"}))
+        .await;
+    remote.assert_error("forbidden").await;
+
+    sender.assert_no_frames().await;
+    local.assert_no_frames().await;
+    remote.assert_no_frames().await;
+}
+
+/// Plays the part of a remote ESMP server, answering the relay command with
+/// the frame returned by `response`.
+async fn accept_relay(
+    listener: &TcpListener,
+    server_pubkey: &str,
+    response: impl FnOnce(&Value) -> Value,
+) -> Value {
+    let (stream, _) = tokio::time::timeout(Duration::from_secs(5), listener.accept())
+        .await
+        .expect("Timeout waiting for relay connection")
+        .unwrap();
+    let (reader, mut writer) = tokio::io::split(stream);
+    let mut reader = BufReader::new(reader).lines();
+
+    write_frame(
+        &mut writer,
+        json!({"type": "greeting", "nonce": "peer-nonce"}),
+    )
+    .await;
+    let hello = read_frame(&mut reader).await;
+    assert_eq!(hello["command"], "hello");
+    write_frame(
+        &mut writer,
+        json!({"type": "hello", "version": 1, "capabilities": []}),
+    )
+    .await;
+
+    let federate = read_frame(&mut reader).await;
+    assert_eq!(federate["command"], "federate");
+    assert_eq!(federate["domain"], "esmp.example.org");
+    assert_eq!(federate["nonce"], "peer-nonce");
+    assert_signed(&federate, server_pubkey);
+    write_frame(
+        &mut writer,
+        json!({"type": "federated", "id": &federate["id"], "domain": "esmp.example.org"}),
+    )
+    .await;
+
+    let relay = read_frame(&mut reader).await;
+    assert_eq!(relay["command"], "relay");
+    assert_signed(&relay, server_pubkey);
+    write_frame(&mut writer, response(&relay)).await;
+
+    relay["message"].clone()
+}
+
+fn ack_frame(relay: &Value) -> Value {
+    json!({"type": "ack", "id": &relay["id"], "message_ids": [1]})
+}
+
+fn error_frame(relay: &Value, code: &str) -> Value {
+    json!({"type": "error", "id": &relay["id"], "code": code, "reason": "Rejected by peer"})
+}
+
+fn assert_signed(value: &Value, pubkey: &str) {
+    assert_eq!(value["sender_pubkey"], pubkey);
+    assert!(verify_signature(
+        pubkey,
+        value["signature"].as_str().unwrap(),
+        &signing_input(value).unwrap()
+    ));
+}
+
+async fn read_frame(reader: &mut Lines<BufReader<ReadHalf<TcpStream>>>) -> Value {
+    let line = tokio::time::timeout(Duration::from_secs(5), reader.next_line())
+        .await
+        .expect("Timeout waiting for relay frame")
+        .unwrap()
+        .expect("Relay connection closed");
+    serde_json::from_str(&line).unwrap()
+}
+
+async fn write_frame(writer: &mut WriteHalf<TcpStream>, value: Value) {
+    let mut bytes = serde_json::to_vec(&value).unwrap();
+    bytes.push(b'\n');
+    writer.write_all(&bytes).await.unwrap();
+    writer.flush().await.unwrap();
+}
@@ -0,0 +1,143 @@
+/*
+ * SPDX-FileCopyrightText: 2020 Stalwart Labs LLC <hello@stalw.art>
+ *
+ * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
+ */
+
+use common::{
+    Server,
+    ipc::{EsmpGatewayEvent, EsmpIngestStatus},
+};
+use esmp::{canonical::signing_input, crypto::verify_signature};
+use serde_json::json;
+use smtp::queue::spool::SmtpSpool;
+use tokio::sync::oneshot;
+use utils::BlobHash;
+
+use super::{EsmpConnection, SERVER_KEY_SEED, pubkey_from_seed};
+
+pub async fn test(server: &Server) {
+    println!("Running e-mail gateway tests...");
+
+    let mut alice = EsmpConnection::connect(50).await;
+    alice.login().await;
+
+    // E-mail recipients are submitted to the SMTP queue
+    let message_id = alice
+        .send(json!({
+            "from": "alice#esmp.example.org",
+            "to": ["bob@example.net"],
+            "type": "text",
+            "body": {"subject": "Greetings", "text": "Hello from ESMP"}
+        }))
+        .await;
+    assert_eq!(
+        alice.assert_read("ack").await["queued"],
+        json!(["bob@example.net"])
+    );
+    let events = server.next_event().await;
+    assert_eq!(events.len(), 1);
+    let message = server.read_message(events[0].queue_id).await.unwrap();
+    assert_eq!(message.return_path, "alice@esmp.example.org");
+    assert_eq!(message.recipients.len(), 1);
+    assert_eq!(message.recipients[0].address, "bob@example.net");
+    let contents = String::from_utf8(
+        server
+            .blob_store()
+            .get_blob(message.blob_hash.as_slice(), 0..usize::MAX)
+            .await
+            .unwrap()
+            .unwrap(),
+    )
+    .unwrap();
+    assert!(contents.contains("Subject: Greetings"), "{contents}");
+    assert!(contents.contains("Hello from ESMP"), "{contents}");
+    assert!(
+        contents.contains(&format!("X-ESMP-Sender-Key: {}", alice.pubkey)),
+        "{contents}"
+    );
+    assert!(
+        contents.contains(&format!("X-ESMP-Message-Id: {message_id}")),
+        "{contents}"
+    );
+    message.remove(server, events[0].due).await;
+
+    // E-mail recipients require a sender address
+    alice
+        .send(json!({"to": ["bob@example.net"], "type": "text", "body": "Hi"}))
+        .await;
+    alice.assert_error("invalid_message").await;
+
+    // Inbound mail for accounts with ESMP keys is converted to direct messages
+    let raw_message = concat!(
+        "Authentication-Results: esmp.example.org;\r\n",
+        "\tdkim=pass header.d=example.net header.s=default;\r\n",
+        "\tspf=pass smtp.mailfrom=bob@example.net;\r\n",
+        "\tdmarc=pass header.from=example.net\r\n",
+        "Authentication-Results: esmp.example.org; dkim=fail\r\n",
+        "From: Bob <bob@example.net>\r\n",
+        "To: alice@esmp.example.org, nobody@esmp.example.org\r\n",
+        "Subject: Hi Alice\r\n",
+        "Message-ID: <gateway-test@example.net>\r\n",
+        "\r\n",
+        "Hello from e-mail\r\n"
+    )
+    .as_bytes();
+    let message_blob = BlobHash::generate(raw_message);
+    server
+        .blob_store()
+        .put_blob(message_blob.as_slice(), raw_message)
+        .await
+        .unwrap();
+    let (result_tx, result_rx) = oneshot::channel();
+    server
+        .inner
+        .ipc
+        .esmp_gateway_tx
+        .send(EsmpGatewayEvent::Ingest {
+            sender_address: "bob@example.net".to_string(),
+            recipients: vec![
+                "alice@esmp.example.org".to_string(),
+                "nobody@esmp.example.org".to_string(),
+            ],
+            message_blob,
+            session_id: 0,
+            result_tx,
+        })
+        .await
+        .unwrap();
+    assert_eq!(
+        result_rx.await.unwrap(),
+        vec![EsmpIngestStatus::Delivered, EsmpIngestStatus::Skipped]
+    );
+
+    let pushed = alice.assert_read("push").await["message"].clone();
+    alice.send(json!({"command": "fetch"})).await;
+    let messages = alice.assert_read("messages").await;
+    let message = messages["messages"].as_array().unwrap().last().unwrap()["message"].clone();
+    assert_eq!(message, pushed);
+    let server_pubkey = pubkey_from_seed(SERVER_KEY_SEED);
+    assert_eq!(message["type"], "email");
+    assert_eq!(message["to"], json!(["alice#esmp.example.org"]));
+    assert_eq!(message["sender_pubkey"], server_pubkey.as_str());
+    assert!(verify_signature(
+        &server_pubkey,
+        message["signature"].as_str().unwrap(),
+        &signing_input(&message).unwrap()
+    ));
+    assert_eq!(message["body"]["mail_from"], "bob@example.net");
+    assert_eq!(message["body"]["from"], "bob@example.net");
+    assert_eq!(message["body"]["subject"], "Hi Alice");
+    assert_eq!(message["body"]["message_id"], "gateway-test@example.net");
+    assert!(
+        message["body"]["text"]
+            .as_str()
+            .unwrap()
+            .contains("Hello from e-mail")
+    );
+    assert_eq!(message["body"]["auth"]["dkim"], "pass");
+    assert_eq!(message["body"]["auth"]["spf"], "pass");
+    assert_eq!(message["body"]["auth"]["dmarc"], "pass");
+
+    alice.assert_no_frames().await;
+}
@@ -0,0 +1,153 @@
+/*
+ * SPDX-FileCopyrightText: 2020 Stalwart Labs LLC <hello@stalw.art>
+ *
+ * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
+ */
+
+use serde_json::{Value, json};
+
+use super::EsmpConnection;
+
+const GROUP_ID: &str = "esmp-policy-group";
+const RACE_GROUP_ID: &str = "esmp-race-group";
+
+pub async fn test() {
+    println!("Running group policy tests...");
+
+    let mut alice = EsmpConnection::connect(30).await;
+    let mut bob = EsmpConnection::connect(31).await;
+    alice.login().await;
+    bob.login().await;
+    let alice_pubkey = alice.pubkey.clone();
+    let bob_pubkey = bob.pubkey.clone();
+
+    // Create group
+    alice
+        .send(system("group_created", &alice_pubkey, None))
+        .await;
+    alice.assert_read("ack").await;
+
+    // Non-members cannot post or manage the group
+    bob.send(text("Hello")).await;
+    bob.assert_error("forbidden").await;
+    let mut rename = system("group_renamed", &bob_pubkey, None);
+    rename["new_name"] = "Bob's group".into();
+    bob.send(rename).await;
+    bob.assert_error("forbidden").await;
+
+    // Actors must match the signer
+    bob.send(system("joined", &alice_pubkey, None)).await;
+    bob.assert_error("forbidden").await;
+
+    // Members can post
+    bob.send(system("joined", &bob_pubkey, None)).await;
+    bob.assert_read("ack").await;
+    alice.assert_read("push").await;
+    bob.send(system("joined", &bob_pubkey, None)).await;
+    bob.assert_error("forbidden").await;
+    bob.send(text("Hello")).await;
+    bob.assert_read("ack").await;
+    alice.assert_read("push").await;
+
+    // Only admins can remove or promote members
+    bob.send(system("removed", &bob_pubkey, Some(&alice_pubkey)))
+        .await;
+    bob.assert_error("forbidden").await;
+    bob.send(system("admin_assigned", &bob_pubkey, Some(&bob_pubkey)))
+        .await;
+    bob.assert_error("forbidden").await;
+
+    // Groups cannot be left without an admin
+    alice
+        .send(system("admin_revoked", &alice_pubkey, Some(&alice_pubkey)))
+        .await;
+    alice.assert_error("forbidden").await;
+    alice.send(system("left", &alice_pubkey, None)).await;
+    alice.assert_error("forbidden").await;
+
+    // Promote bob, who then removes alice
+    alice
+        .send(system("admin_assigned", &alice_pubkey, Some(&bob_pubkey)))
+        .await;
+    alice.assert_read("ack").await;
+    bob.assert_read("push").await;
+    bob.send(system("removed", &bob_pubkey, Some(&alice_pubkey)))
+        .await;
+    bob.assert_read("ack").await;
+
+    // Removed members can no longer post
+    alice.send(text("Hello")).await;
+    alice.assert_error("forbidden").await;
+
+    // Groups cannot be recreated and must exist before posting
+    alice
+        .send(system("group_created", &alice_pubkey, None))
+        .await;
+    alice.assert_error("forbidden").await;
+    let mut message = text("Hello");
+    message["group_id"] = "esmp-missing-group".into();
+    alice.send(message).await;
+    alice.assert_error("forbidden").await;
+
+    // Concurrent attempts to create a group only create it once
+    alice
+        .send(race_system(&alice_pubkey, "group_created"))
+        .await;
+    bob.send(race_system(&bob_pubkey, "group_created")).await;
+    let results = [alice.read().await, bob.read().await];
+    let acks = results
+        .iter()
+        .filter(|frame| frame["type"] == "ack")
+        .count();
+    assert_eq!(acks, 1, "{results:?}");
+    assert!(
+        results
+            .iter()
+            .any(|frame| frame["type"] == "error" && frame["code"] == "forbidden"),
+        "{results:?}"
+    );
+    let (creator, member, member_pubkey) = if results[0]["type"] == "ack" {
+        (&mut alice, &mut bob, &bob_pubkey)
+    } else {
+        (&mut bob, &mut alice, &alice_pubkey)
+    };
+    member.send(race_system(member_pubkey, "joined")).await;
+    member.assert_read("ack").await;
+    creator.assert_read("push").await;
+
+    // Concurrent posts are all appended to the group
+    alice
+        .send(json!({"to": [], "group_id": RACE_GROUP_ID, "type": "text", "body": "Hello from alice"}))
+        .await;
+    bob.send(
+        json!({"to": [], "group_id": RACE_GROUP_ID, "type": "text", "body": "Hello from bob"}),
+    )
+    .await;
+    for connection in [&mut alice, &mut bob] {
+        let mut frames = [connection.read().await, connection.read().await]
+            .into_iter()
+            .map(|frame| frame["type"].as_str().unwrap().to_string())
+            .collect::<Vec<_>>();
+        frames.sort();
+        assert_eq!(frames, ["ack", "push"]);
+    }
+
+    alice.assert_no_frames().await;
+    bob.assert_no_frames().await;
+}
+
+fn text(body: &str) -> Value {
+    json!({"to": [], "group_id": GROUP_ID, "type": "text", "body": body})
+}
+
+fn system(subtype: &str, actor: &str, target: Option<&str>) -> Value {
+    let mut message = json!({"to": [], "group_id": GROUP_ID, "type": "system", "subtype": subtype, "actor": actor, "body": ""});
+    if let Some(target) = target {
+        message["target"] = target.into();
+    }
+    message
+}
+
+fn race_system(actor: &str, subtype: &str) -> Value {
+    json!({"to": [], "group_id": RACE_GROUP_ID, "type": "system", "subtype": subtype, "actor": actor, "body": ""})
+}
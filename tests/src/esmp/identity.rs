@@ -0,0 +1,127 @@
+/*
+ * SPDX-FileCopyrightText: 2020 Stalwart Labs LLC <hello@stalw.art>
+ *
+ * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
+ */
+
+use common::Server;
+use directory::{
+    QueryBy,
+    backend::internal::{
+        PrincipalField, PrincipalUpdate, PrincipalValue,
+        lookup::DirectoryStore,
+        manage::{ManageDirectory, UpdatePrincipal},
+    },
+};
+use serde_json::json;
+
+use crate::directory::internal::TestInternalDirectory;
+
+use super::EsmpConnection;
+
+pub async fn test(server: &Server) {
+    println!("Running identity tests...");
+
+    let mut alice = EsmpConnection::connect(50).await;
+    let mut bob = EsmpConnection::connect(51).await;
+    let mut mallory = EsmpConnection::connect(52).await;
+    alice.login().await;
+    bob.login().await;
+    mallory.login().await;
+    let alice_pubkey = alice.pubkey.clone();
+    let bob_pubkey = bob.pubkey.clone();
+
+    // Bind keys to directory principals
+    let store = server.store();
+    store
+        .create_test_user("alice", "secret", "Alice", &["alice@esmp.example.org"])
+        .await;
+    store
+        .create_test_user("bob", "secret", "Bob", &["bob@esmp.example.org"])
+        .await;
+    for (login, pubkey) in [("alice", &alice_pubkey), ("bob", &bob_pubkey)] {
+        store
+            .update_principal(UpdatePrincipal::by_name(login).with_updates(vec![
+                PrincipalUpdate::add_item(
+                    PrincipalField::EsmpKeys,
+                    PrincipalValue::String(pubkey.to_string()),
+                ),
+            ]))
+            .await
+            .unwrap();
+    }
+    assert_eq!(
+        store
+            .query(QueryBy::Name("alice"), false)
+            .await
+            .unwrap()
+            .unwrap()
+            .esmp_keys(),
+        std::slice::from_ref(&alice_pubkey)
+    );
+
+    // Look up the keys bound to an address
+    mallory
+        .send(json!({"command": "lookup_keys", "address": "Alice#esmp.example.org"}))
+        .await;
+    let response = mallory.assert_read("keys").await;
+    assert_eq!(response["address"], "Alice#esmp.example.org");
+    assert_eq!(response["keys"], json!([&alice_pubkey]));
+    mallory
+        .send(json!({"command": "lookup_keys", "address": "nobody#esmp.example.org"}))
+        .await;
+    assert_eq!(mallory.assert_read("keys").await["keys"], json!([]));
+    mallory
+        .send(json!({"command": "lookup_keys", "address": "alice@esmp.example.org"}))
+        .await;
+    mallory.assert_error("invalid_request").await;
+
+    // Sender addresses must be bound to the signing key
+    mallory
+        .send(json!({"from": "alice#esmp.example.org", "to": [&bob_pubkey], "type": "text", "body": "Hi"}))
+        .await;
+    mallory.assert_error("forbidden").await;
+    bob.assert_no_frames().await;
+    alice
+        .send(json!({"from": "alice#esmp.example.org", "to": ["bob#esmp.example.org"], "type": "text", "body": "Hi"}))
+        .await;
+    alice.assert_read("ack").await;
+
+    // Messages sent to an address are pushed to the keys bound to it
+    let push = bob.assert_read("push").await;
+    assert_eq!(push["message"]["from"], "alice#esmp.example.org");
+    assert_eq!(push["message"]["to"], json!(["bob#esmp.example.org"]));
+    bob.send(json!({"command": "fetch"})).await;
+    let messages = bob.assert_read("messages").await;
+    assert_eq!(
+        messages["messages"].as_array().unwrap().last().unwrap()["message"]["from"],
+        "alice#esmp.example.org"
+    );
+
+    // Address inboxes can only be accessed by their bound keys
+    mallory
+        .send(json!({"command": "fetch", "inbox": "bob#esmp.example.org"}))
+        .await;
+    mallory.assert_error("forbidden").await;
+    bob.send(json!({"command": "fetch", "inbox": "bob#esmp.example.org"}))
+        .await;
+    bob.assert_read("messages").await;
+
+    // Removed keys lose access to the address
+    store
+        .update_principal(UpdatePrincipal::by_name("bob").with_updates(vec![
+            PrincipalUpdate::remove_item(
+                PrincipalField::EsmpKeys,
+                PrincipalValue::String(bob_pubkey.clone()),
+            ),
+        ]))
+        .await
+        .unwrap();
+    bob.send(json!({"command": "fetch", "inbox": "bob#esmp.example.org"}))
+        .await;
+    bob.assert_error("forbidden").await;
+
+    alice.assert_no_frames().await;
+    bob.assert_no_frames().await;
+    mallory.assert_no_frames().await;
+}
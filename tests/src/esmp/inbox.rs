@@ -0,0 +1,160 @@
+/*
+ * SPDX-FileCopyrightText: 2020 Stalwart Labs LLC <hello@stalw.art>
+ *
+ * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
+ */
+
+use serde_json::json;
+
+use super::EsmpConnection;
+
+pub async fn test() {
+    println!("Running inbox tests...");
+
+    let mut alice = EsmpConnection::connect(10).await;
+    let mut bob = EsmpConnection::connect(11).await;
+    alice.login().await;
+    bob.login().await;
+    let alice_pubkey = alice.pubkey.clone();
+    let bob_pubkey = bob.pubkey.clone();
+
+    // Obtain the current inbox state
+    bob.send(json!({"command": "sync"})).await;
+    let state = bob.assert_read("changes").await["state"].as_u64().unwrap();
+
+    // Direct messages are acknowledged to the sender and pushed to the recipient
+    let message_id = alice
+        .send(json!({"to": [&bob_pubkey], "type": "text", "body": "Hello Bob"}))
+        .await;
+    let ack = alice.assert_read("ack").await;
+    assert_eq!(ack["id"], message_id.as_str());
+    let inbox_id = ack["message_ids"][0].as_u64().unwrap();
+    let push = bob.assert_read("push").await;
+    assert_eq!(push["id"], inbox_id);
+    assert_eq!(push["message"]["id"], message_id.as_str());
+    assert_eq!(push["message"]["body"], "Hello Bob");
+    alice.assert_no_frames().await;
+
+    // Fetch unacknowledged messages
+    bob.send(json!({"command": "fetch"})).await;
+    let messages = bob.assert_read("messages").await;
+    assert_eq!(messages["messages"].as_array().unwrap().len(), 1);
+    assert_eq!(messages["messages"][0]["id"], inbox_id);
+    assert_eq!(messages["messages"][0]["acked"], false);
+    assert_eq!(messages["has_more"], false);
+
+    // Acknowledge the message
+    bob.send(json!({"command": "ack", "ids": [inbox_id, 9999]}))
+        .await;
+    assert_eq!(bob.assert_read("ok").await["ids"], json!([inbox_id]));
+    bob.send(json!({"command": "fetch"})).await;
+    assert_eq!(bob.assert_read("messages").await["messages"], json!([]));
+    bob.send(json!({"command": "fetch", "include_acked": true}))
+        .await;
+    let messages = bob.assert_read("messages").await;
+    assert_eq!(messages["messages"][0]["acked"], true);
+
+    // Changes since the initial state
+    bob.send(json!({"command": "sync", "since": state})).await;
+    let changes = bob.assert_read("changes").await;
+    assert_eq!(changes["created"], json!([inbox_id]));
+    assert_eq!(changes["destroyed"], json!([]));
+    let state = changes["state"].as_u64().unwrap();
+
+    // Delete the message
+    bob.send(json!({"command": "delete", "ids": [inbox_id]}))
+        .await;
+    assert_eq!(bob.assert_read("ok").await["ids"], json!([inbox_id]));
+    bob.send(json!({"command": "sync", "since": state})).await;
+    let changes = bob.assert_read("changes").await;
+    assert_eq!(changes["created"], json!([]));
+    assert_eq!(changes["destroyed"], json!([inbox_id]));
+    let state = changes["state"].as_u64().unwrap();
+
+    // Changes are tracked per inbox
+    alice.send(json!({"command": "sync"})).await;
+    let alice_state = alice.assert_read("changes").await["state"]
+        .as_u64()
+        .unwrap();
+    bob.send(json!({"to": [&alice_pubkey], "type": "text", "body": "Hello Alice"}))
+        .await;
+    bob.assert_read("ack").await;
+    alice.assert_read("push").await;
+    bob.send(json!({"command": "sync", "since": state})).await;
+    let changes = bob.assert_read("changes").await;
+    assert_eq!(changes["created"], json!([]));
+    assert_eq!(changes["state"], state);
+    alice
+        .send(json!({"command": "sync", "since": alice_state}))
+        .await;
+    let changes = alice.assert_read("changes").await;
+    assert_eq!(changes["created"].as_array().unwrap().len(), 1);
+    assert_ne!(changes["state"], alice_state);
+
+    // Concurrent deliveries to a new recipient share the same inbox
+    let mut carol = EsmpConnection::connect(12).await;
+    let carol_pubkey = carol.pubkey.clone();
+    alice
+        .send(json!({"to": [&carol_pubkey], "type": "text", "body": "Hello Carol"}))
+        .await;
+    bob.send(json!({"to": [&carol_pubkey], "type": "text", "body": "Hello Carol"}))
+        .await;
+    alice.assert_read("ack").await;
+    bob.assert_read("ack").await;
+    carol.login().await;
+    carol.send(json!({"command": "fetch"})).await;
+    assert_eq!(
+        carol.assert_read("messages").await["messages"]
+            .as_array()
+            .unwrap()
+            .len(),
+        2
+    );
+    carol.assert_no_frames().await;
+
+    // Inboxes of other keys cannot be accessed
+    alice
+        .send(json!({"command": "fetch", "inbox": &bob_pubkey}))
+        .await;
+    alice.assert_error("forbidden").await;
+
+    // Group messages are pushed to members other than the sender
+    alice
+        .send(json!({"to": [], "group_id": "esmp-test-group", "type": "system", "subtype": "group_created", "actor": &alice_pubkey, "body": ""}))
+        .await;
+    alice.assert_read("ack").await;
+    bob.send(json!({"to": [], "group_id": "esmp-test-group", "type": "system", "subtype": "joined", "actor": &bob_pubkey, "body": ""}))
+        .await;
+    bob.assert_read("ack").await;
+    alice.assert_read("push").await;
+    let message_id = alice
+        .send(
+            json!({"to": [], "group_id": "esmp-test-group", "type": "text", "body": "Hello group"}),
+        )
+        .await;
+    alice.assert_read("ack").await;
+    let push = bob.assert_read("push").await;
+    assert_eq!(push["group_id"], "esmp-test-group");
+    assert_eq!(push["message"]["id"], message_id.as_str());
+    alice.assert_no_frames().await;
+
+    // Only the sessions of the recipient identity are woken up
+    let mut bob_device = EsmpConnection::connect(11).await;
+    bob_device.login().await;
+    alice
+        .send(json!({"to": [&bob_pubkey], "type": "text", "body": "Hello again"}))
+        .await;
+    alice.assert_read("ack").await;
+    bob.assert_read("push").await;
+    bob_device.assert_read("push").await;
+    alice
+        .send(
+            json!({"to": [], "group_id": "esmp-test-group", "type": "text", "body": "Hello again"}),
+        )
+        .await;
+    alice.assert_read("ack").await;
+    bob.assert_read("push").await;
+    bob_device.assert_read("push").await;
+    alice.assert_no_frames().await;
+    carol.assert_no_frames().await;
+}
@@ -0,0 +1,314 @@
+/*
+ * SPDX-FileCopyrightText: 2020 Stalwart Labs LLC <hello@stalw.art>
+ *
+ * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
+ */
+
+use esmp::{canonical::canonicalize, group::invite::encode_invite};
+use reqwest::{Method, StatusCode};
+use serde_json::json;
+use store::write::now;
+
+use super::{
+    EsmpConnection,
+    api::{encode, signed},
+};
+
+const GROUP_ID: &str = "esmp-invite-group";
+
+pub async fn test() {
+    println!("Running ESMP invite and join request tests...");
+
+    let mut alice = EsmpConnection::connect(97).await;
+    let mut bob = EsmpConnection::connect(98).await;
+    let mut carol = EsmpConnection::connect(99).await;
+    let mut dave = EsmpConnection::connect(100).await;
+    alice.login().await;
+    bob.login().await;
+    carol.login().await;
+    dave.login().await;
+    let alice_pubkey = alice.pubkey.clone();
+    let bob_pubkey = bob.pubkey.clone();
+    let carol_pubkey = carol.pubkey.clone();
+    let dave_pubkey = dave.pubkey.clone();
+    let group_path = format!("/api/esmp/groups/{GROUP_ID}");
+
+    // Join policies are validated with the message
+    alice
+        .send(json!({"to": [], "group_id": GROUP_ID, "type": "system", "subtype": "group_created", "actor": &alice_pubkey, "body": {"join_policy": "members_only"}}))
+        .await;
+    alice.assert_error("invalid_message").await;
+    alice
+        .send(json!({"to": [], "group_id": GROUP_ID, "type": "system", "subtype": "group_created", "actor": &alice_pubkey, "body": {"join_policy": "invite_only"}}))
+        .await;
+    alice.assert_read("ack").await;
+
+    // Invite-only groups cannot be joined or asked to join without an invite
+    bob.send(json!({"to": [], "group_id": GROUP_ID, "type": "system", "subtype": "joined", "actor": &bob_pubkey, "body": {}}))
+        .await;
+    assert_eq!(
+        bob.assert_error("forbidden").await["reason"],
+        "Joining this group requires an invite"
+    );
+    bob.send(json!({"to": [], "group_id": GROUP_ID, "type": "system", "subtype": "join_requested", "actor": &bob_pubkey, "body": {}}))
+        .await;
+    assert_eq!(
+        bob.assert_error("forbidden").await["reason"],
+        "Group does not accept join requests"
+    );
+
+    // Invites must expire within the maximum invite lifetime
+    alice
+        .send(json!({"to": [], "group_id": GROUP_ID, "type": "system", "subtype": "invited", "actor": &alice_pubkey, "body": {}}))
+        .await;
+    alice.assert_error("invalid_message").await;
+    for expires in [now() - 1, now() + 60 * 86400] {
+        alice
+            .send(json!({"to": [], "group_id": GROUP_ID, "type": "system", "subtype": "invited", "actor": &alice_pubkey, "body": {"expires": expires}}))
+            .await;
+        assert_eq!(
+            alice.assert_error("forbidden").await["reason"],
+            "Invites must expire within the maximum invite lifetime"
+        );
+    }
+
+    // Invite bob, the token carries the signed invite
+    let invite = alice.sign(json!({"to": [], "group_id": GROUP_ID, "type": "system", "subtype": "invited", "actor": &alice_pubkey, "target": &bob_pubkey, "body": {"expires": now() + 3600}}));
+    alice.send_raw(&invite.to_string()).await;
+    alice.assert_read("ack").await;
+    let token = encode_invite(&canonicalize(&invite));
+
+    // Invites can only be used by their invitee and cannot be tampered with
+    carol
+        .send(json!({"to": [], "group_id": GROUP_ID, "type": "system", "subtype": "joined", "actor": &carol_pubkey, "body": {"invite": &token}}))
+        .await;
+    assert_eq!(
+        carol.assert_error("forbidden").await["reason"],
+        "Invite was issued to another identity"
+    );
+    let mut tampered = invite.clone();
+    tampered["target"] = carol_pubkey.clone().into();
+    carol
+        .send(json!({"to": [], "group_id": GROUP_ID, "type": "system", "subtype": "joined", "actor": &carol_pubkey, "body": {"invite": encode_invite(&canonicalize(&tampered))}}))
+        .await;
+    assert_eq!(
+        carol.assert_error("forbidden").await["reason"],
+        "Invite was not issued to this group"
+    );
+    carol
+        .send(json!({"to": [], "group_id": GROUP_ID, "type": "system", "subtype": "joined", "actor": &carol_pubkey, "body": {"invite": "not-a-token"}}))
+        .await;
+    assert_eq!(
+        carol.assert_error("forbidden").await["reason"],
+        "Invalid invite"
+    );
+    bob.send(json!({"to": [], "group_id": GROUP_ID, "type": "system", "subtype": "joined", "actor": &bob_pubkey, "body": {"invite": &token}}))
+        .await;
+    bob.assert_read("ack").await;
+    alice.assert_read("push").await;
+
+    // Only admins can issue invites
+    bob.send(json!({"to": [], "group_id": GROUP_ID, "type": "system", "subtype": "invited", "actor": &bob_pubkey, "body": {"expires": now() + 3600}}))
+        .await;
+    assert_eq!(
+        bob.assert_error("forbidden").await["reason"],
+        "Only group admins can perform this action"
+    );
+
+    // Require approval to join the group
+    let update = alice.sign(json!({"to": [], "group_id": GROUP_ID, "type": "system", "subtype": "join_policy_updated", "actor": &alice_pubkey, "body": {"join_policy": "approval_required"}}));
+    let (status, response) = signed(
+        &alice,
+        Method::PUT,
+        &group_path,
+        Some(json!({"messages": [update]})),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK, "{response}");
+    assert_eq!(response["data"]["join_policy"], "approval_required");
+    bob.assert_read("push").await;
+
+    // Carol asks to join, once
+    carol
+        .send(json!({"to": [], "group_id": GROUP_ID, "type": "system", "subtype": "join_requested", "actor": &carol_pubkey, "body": {}}))
+        .await;
+    carol.assert_read("ack").await;
+    alice.assert_read("push").await;
+    bob.assert_read("push").await;
+    carol
+        .send(json!({"to": [], "group_id": GROUP_ID, "type": "system", "subtype": "join_requested", "actor": &carol_pubkey, "body": {}}))
+        .await;
+    assert_eq!(
+        carol.assert_error("forbidden").await["reason"],
+        "Join request is already pending"
+    );
+
+    // Pending requests are only listed to admins
+    let requests_path = format!("{group_path}/requests");
+    let (status, response) = signed(&bob, Method::GET, &requests_path, None).await;
+    assert_eq!(status, StatusCode::FORBIDDEN, "{response}");
+    let (status, response) = signed(&alice, Method::GET, &requests_path, None).await;
+    assert_eq!(status, StatusCode::OK, "{response}");
+    let requests = response["data"].as_array().unwrap();
+    assert_eq!(requests.len(), 1, "{response}");
+    assert_eq!(requests[0]["identity"], carol_pubkey.as_str());
+
+    // Approving the request adds carol to the group
+    let approve = bob.sign(json!({"to": [], "group_id": GROUP_ID, "type": "system", "subtype": "join_approved", "actor": &bob_pubkey, "target": &carol_pubkey, "body": {}}));
+    let (status, response) = signed(
+        &bob,
+        Method::POST,
+        &format!("{requests_path}/{}", encode(&carol_pubkey)),
+        Some(approve),
+    )
+    .await;
+    assert_eq!(status, StatusCode::FORBIDDEN, "{response}");
+    let approve = alice.sign(json!({"to": [], "group_id": GROUP_ID, "type": "system", "subtype": "join_approved", "actor": &alice_pubkey, "target": &carol_pubkey, "body": {}}));
+    for (method, body) in [
+        (Method::DELETE, approve.clone()),
+        (
+            Method::POST,
+            alice.sign(json!({"to": [], "group_id": GROUP_ID, "type": "system", "subtype": "join_approved", "actor": &alice_pubkey, "target": &dave_pubkey, "body": {}})),
+        ),
+    ] {
+        let (status, response) = signed(
+            &alice,
+            method,
+            &format!("{requests_path}/{}", encode(&carol_pubkey)),
+            Some(body),
+        )
+        .await;
+        assert_eq!(status, StatusCode::BAD_REQUEST, "{response}");
+    }
+    let (status, response) = signed(
+        &alice,
+        Method::POST,
+        &format!("{requests_path}/{}", encode(&carol_pubkey)),
+        Some(approve),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK, "{response}");
+    assert_eq!(response["data"], json!([]));
+    bob.assert_read("push").await;
+    assert_eq!(
+        carol.assert_read("push").await["message"]["subtype"],
+        "join_approved"
+    );
+    let (_, response) = signed(&carol, Method::GET, &group_path, None).await;
+    assert!(
+        response["data"]["members"]
+            .as_array()
+            .unwrap()
+            .contains(&json!(carol_pubkey)),
+        "{response}"
+    );
+
+    // Dave needs an invite or approval, and is rejected over ESMP
+    dave.send(json!({"to": [], "group_id": GROUP_ID, "type": "system", "subtype": "joined", "actor": &dave_pubkey, "body": {}}))
+        .await;
+    assert_eq!(
+        dave.assert_error("forbidden").await["reason"],
+        "Joining this group requires an invite or the approval of an admin"
+    );
+    dave.send(json!({"to": [], "group_id": GROUP_ID, "type": "system", "subtype": "join_requested", "actor": &dave_pubkey, "body": {}}))
+        .await;
+    dave.assert_read("ack").await;
+    alice.assert_read("push").await;
+    bob.assert_read("push").await;
+    carol.assert_read("push").await;
+    bob.send(json!({"to": [], "group_id": GROUP_ID, "type": "system", "subtype": "join_rejected", "actor": &bob_pubkey, "target": &dave_pubkey, "body": {}}))
+        .await;
+    assert_eq!(
+        bob.assert_error("forbidden").await["reason"],
+        "Only group admins can perform this action"
+    );
+    alice
+        .send(json!({"to": [], "group_id": GROUP_ID, "type": "system", "subtype": "join_rejected", "actor": &alice_pubkey, "target": &dave_pubkey, "body": {}}))
+        .await;
+    alice.assert_read("ack").await;
+    bob.assert_read("push").await;
+    carol.assert_read("push").await;
+    alice
+        .send(json!({"to": [], "group_id": GROUP_ID, "type": "system", "subtype": "join_approved", "actor": &alice_pubkey, "target": &dave_pubkey, "body": {}}))
+        .await;
+    assert_eq!(
+        alice.assert_error("forbidden").await["reason"],
+        "Target has no pending join request"
+    );
+    let (_, response) = signed(&alice, Method::GET, &requests_path, None).await;
+    assert_eq!(response["data"], json!([]));
+
+    // Invites issued over HTTP are shared as links
+    let invite = alice.sign(json!({"to": [], "group_id": GROUP_ID, "type": "system", "subtype": "invited", "actor": &alice_pubkey, "body": {"expires": now() + 600}}));
+    let (status, response) = signed(
+        &alice,
+        Method::POST,
+        &format!("{group_path}/invites"),
+        Some(invite.clone()),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK, "{response}");
+    let token = response["data"]["token"].as_str().unwrap().to_string();
+    assert_eq!(token, encode_invite(&canonicalize(&invite)));
+    assert_eq!(
+        response["data"]["link"],
+        format!("esmp://esmp.example.org/join?invite={token}")
+    );
+    assert_eq!(response["data"]["expires"], invite["body"]["expires"]);
+    bob.assert_read("push").await;
+    carol.assert_read("push").await;
+    for expires in [now() - 1, now() + 365 * 86400] {
+        let invite = alice.sign(json!({"to": [], "group_id": GROUP_ID, "type": "system", "subtype": "invited", "actor": &alice_pubkey, "body": {"expires": expires}}));
+        let (status, response) = signed(
+            &alice,
+            Method::POST,
+            &format!("{group_path}/invites"),
+            Some(invite),
+        )
+        .await;
+        assert_eq!(status, StatusCode::BAD_REQUEST, "{response}");
+    }
+
+    // Invites can be revoked by admins before they are used
+    let invite = alice.sign(json!({"to": [], "group_id": GROUP_ID, "type": "system", "subtype": "invited", "actor": &alice_pubkey, "body": {"expires": now() + 600}}));
+    let (status, response) = signed(
+        &alice,
+        Method::POST,
+        &format!("{group_path}/invites"),
+        Some(invite),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK, "{response}");
+    let revoked_token = response["data"]["token"].as_str().unwrap().to_string();
+    bob.assert_read("push").await;
+    carol.assert_read("push").await;
+    let revoke_path = format!("{group_path}/invites/{revoked_token}");
+    let revoke = alice.sign(json!({"to": [], "group_id": GROUP_ID, "type": "system", "subtype": "invite_revoked", "actor": &alice_pubkey, "body": {"invite": &token}}));
+    let (status, response) = signed(&alice, Method::DELETE, &revoke_path, Some(revoke)).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST, "{response}");
+    let revoke = bob.sign(json!({"to": [], "group_id": GROUP_ID, "type": "system", "subtype": "invite_revoked", "actor": &bob_pubkey, "body": {"invite": &revoked_token}}));
+    let (status, response) = signed(&bob, Method::DELETE, &revoke_path, Some(revoke)).await;
+    assert_eq!(status, StatusCode::FORBIDDEN, "{response}");
+    let revoke = alice.sign(json!({"to": [], "group_id": GROUP_ID, "type": "system", "subtype": "invite_revoked", "actor": &alice_pubkey, "body": {"invite": &revoked_token}}));
+    let (status, response) = signed(&alice, Method::DELETE, &revoke_path, Some(revoke)).await;
+    assert_eq!(status, StatusCode::OK, "{response}");
+    bob.assert_read("push").await;
+    carol.assert_read("push").await;
+    dave.send(json!({"to": [], "group_id": GROUP_ID, "type": "system", "subtype": "joined", "actor": &dave_pubkey, "body": {"invite": &revoked_token}}))
+        .await;
+    assert_eq!(
+        dave.assert_error("forbidden").await["reason"],
+        "Invite has been revoked"
+    );
+    dave.send(json!({"to": [], "group_id": GROUP_ID, "type": "system", "subtype": "joined", "actor": &dave_pubkey, "body": {"invite": &token}}))
+        .await;
+    dave.assert_read("ack").await;
+    alice.assert_read("push").await;
+    bob.assert_read("push").await;
+    carol.assert_read("push").await;
+
+    alice.assert_no_frames().await;
+    bob.assert_no_frames().await;
+    carol.assert_no_frames().await;
+    dave.assert_no_frames().await;
+}
@@ -0,0 +1,208 @@
+/*
+ * SPDX-FileCopyrightText: 2020 Stalwart Labs LLC <hello@stalw.art>
+ *
+ * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
+ */
+
+use serde_json::{Value, json};
+use store::write::now;
+
+use super::EsmpConnection;
+
+const GROUP_ID: &str = "esmp-keys-group";
+
+pub async fn test() {
+    println!("Running key rotation tests...");
+
+    let mut alice = EsmpConnection::connect(60).await;
+    let mut bob = EsmpConnection::connect(63).await;
+    alice.login().await;
+    bob.login().await;
+    let alice_pubkey = alice.pubkey.clone();
+    let bob_pubkey = bob.pubkey.clone();
+
+    // Add a device key, which must consent to joining the identity
+    let mut phone = EsmpConnection::connect(61).await;
+    let phone_pubkey = phone.pubkey.clone();
+    alice
+        .send(key_statement("add_key", &alice_pubkey, &phone))
+        .await;
+    let key_set = alice.assert_read("key_set").await;
+    assert_eq!(key_set["key_set"]["identity"], alice_pubkey.as_str());
+    assert_eq!(
+        key_set["key_set"]["keys"][1]["pubkey"],
+        phone_pubkey.as_str()
+    );
+    assert_eq!(key_set["key_set"]["keys"][1]["device"], "device");
+    let mut laptop = EsmpConnection::connect(62).await;
+    let laptop_pubkey = laptop.pubkey.clone();
+    let mut statement = key_statement("add_key", &alice_pubkey, &laptop);
+    statement["key_signature"] = alice.signature(&statement).into();
+    alice.send(statement).await;
+    alice.assert_error("invalid_signature").await;
+    bob.send(json!({"command": "fetch_key_set", "pubkey": &phone_pubkey}))
+        .await;
+    assert_eq!(
+        bob.assert_read("key_set").await["key_set"]["identity"],
+        alice_pubkey.as_str()
+    );
+    bob.send(json!({"command": "fetch_key_set", "pubkey": &bob_pubkey}))
+        .await;
+    assert_eq!(bob.assert_read("key_set").await["key_set"], Value::Null);
+
+    // Messages sent to any device key are delivered to the identity
+    phone.login().await;
+    let message_id = bob
+        .send(json!({"to": [&phone_pubkey], "type": "text", "body": "Hi"}))
+        .await;
+    bob.assert_read("ack").await;
+    for device in [&mut alice, &mut phone] {
+        assert_eq!(
+            device.assert_read("push").await["message"]["id"],
+            message_id.as_str()
+        );
+    }
+    phone.send(json!({"command": "fetch"})).await;
+    let messages = phone.assert_read("messages").await;
+    assert_eq!(messages["messages"].as_array().unwrap().len(), 1);
+
+    // Group membership is resolved through the identity
+    alice
+        .send(system("group_created", &alice_pubkey, &alice_pubkey))
+        .await;
+    alice.assert_read("ack").await;
+    phone.assert_read("push").await;
+    phone.send(text()).await;
+    phone.assert_read("ack").await;
+    alice.assert_read("push").await;
+    phone
+        .send(system("joined", &phone_pubkey, &phone_pubkey))
+        .await;
+    phone.assert_error("forbidden").await;
+
+    // Rotated keys can no longer sign messages
+    phone
+        .send(key_statement("rotate_key", &alice_pubkey, &laptop))
+        .await;
+    let key_set = phone.assert_read("key_set").await;
+    assert!(key_set["key_set"]["keys"][1]["revoked_at"].is_u64());
+    phone.send(text()).await;
+    phone.assert_error("key_revoked").await;
+    laptop.login().await;
+    laptop.send(text()).await;
+    laptop.assert_read("ack").await;
+    alice.assert_read("push").await;
+    phone.assert_read("push").await;
+
+    // The identity key cannot be revoked by other keys, only rotated
+    laptop
+        .send(json!({"command": "revoke_key", "pubkey": &alice_pubkey}))
+        .await;
+    assert_eq!(
+        laptop.assert_error("forbidden").await["reason"],
+        "The identity key cannot be revoked, rotate it instead"
+    );
+    let tablet = EsmpConnection::connect(64).await;
+    let tablet_pubkey = tablet.pubkey.clone();
+    alice
+        .send(key_statement("rotate_key", &alice_pubkey, &tablet))
+        .await;
+    alice.assert_read("key_set").await;
+    alice.send(text()).await;
+    alice.assert_error("key_revoked").await;
+
+    // Revocations cannot be backdated past the replay window
+    laptop
+        .send(json!({"command": "revoke_key", "pubkey": &tablet_pubkey, "revoked_at": 1}))
+        .await;
+    let key_set = laptop.assert_read("key_set").await;
+    assert_eq!(
+        key_set["key_set"]["keys"][3]["pubkey"],
+        tablet_pubkey.as_str()
+    );
+    assert!(
+        key_set["key_set"]["keys"][3]["revoked_at"]
+            .as_u64()
+            .unwrap()
+            >= now() - 300,
+        "{key_set}"
+    );
+
+    // Revoked keys are rejected after their revocation time
+    laptop
+        .send(json!({"command": "revoke_key", "pubkey": &laptop_pubkey}))
+        .await;
+    laptop.assert_error("forbidden").await;
+    laptop
+        .send(
+            json!({"command": "revoke_key", "pubkey": &laptop_pubkey, "revoked_at": now() + 3600}),
+        )
+        .await;
+    laptop.assert_error("invalid_request").await;
+
+    // Keys belong to a single identity
+    bob.send(json!({"command": "revoke_key", "pubkey": &laptop_pubkey}))
+        .await;
+    bob.assert_error("forbidden").await;
+    bob.send(key_statement("add_key", &bob_pubkey, &laptop))
+        .await;
+    bob.assert_error("forbidden").await;
+    let desktop = EsmpConnection::connect(65).await;
+    laptop
+        .send(key_statement("add_key", &alice_pubkey, &desktop))
+        .await;
+    bob.send(key_statement("add_key", &bob_pubkey, &desktop))
+        .await;
+    let results = [laptop.read().await, bob.read().await];
+    assert_eq!(
+        results
+            .iter()
+            .filter(|frame| frame["type"] == "key_set")
+            .count(),
+        1,
+        "{results:?}"
+    );
+    assert!(
+        results
+            .iter()
+            .any(|frame| frame["reason"] == "Key already belongs to another identity"),
+        "{results:?}"
+    );
+
+    // Revoked keys cannot authenticate
+    let mut revoked = EsmpConnection::connect(60).await;
+    revoked
+        .send_unsigned(json!({"command": "hello", "versions": [1]}))
+        .await;
+    revoked.assert_read("hello").await;
+    let nonce = revoked.nonce.clone();
+    revoked
+        .send(json!({"command": "authenticate", "nonce": nonce}))
+        .await;
+    revoked.assert_error("key_revoked").await;
+
+    alice.assert_no_frames().await;
+    bob.assert_no_frames().await;
+    phone.assert_no_frames().await;
+    laptop.assert_no_frames().await;
+}
+
+fn key_statement(command: &str, identity: &str, new_key: &EsmpConnection) -> Value {
+    let timestamp = now();
+    let proof = json!({"identity": identity, "pubkey": &new_key.pubkey, "timestamp": timestamp});
+    json!({
+        "command": command,
+        "timestamp": timestamp,
+        "pubkey": &new_key.pubkey,
+        "device": "device",
+        "key_signature": new_key.signature(&proof),
+    })
+}
+
+fn text() -> Value {
+    json!({"to": [], "group_id": GROUP_ID, "type": "text", "body": "Hello"})
+}
+
+fn system(subtype: &str, actor: &str, target: &str) -> Value {
+    json!({"to": [], "group_id": GROUP_ID, "type": "system", "subtype": subtype, "actor": actor, "target": target, "body": ""})
+}
@@ -0,0 +1,116 @@
+/*
+ * SPDX-FileCopyrightText: 2020 Stalwart Labs LLC <hello@stalw.art>
+ *
+ * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
+ */
+
+use std::time::Duration;
+
+use serde_json::json;
+
+use super::EsmpConnection;
+
+const LIMITED: &str = "127.0.0.1:5889";
+
+pub async fn test() {
+    println!("Running limits tests...");
+
+    // Oversized frames close the connection
+    let mut client = EsmpConnection::connect_to(LIMITED, 20).await;
+    client.send_raw(&"a".repeat(4096)).await;
+    client.assert_error("frame_too_large").await;
+    client.assert_closed().await;
+
+    // Frames without a line terminator cannot grow unbounded
+    let mut client = EsmpConnection::connect_to(LIMITED, 20).await;
+    client.send_bytes("a".repeat(4096).as_bytes()).await;
+    client.assert_error("frame_too_large").await;
+    client.assert_closed().await;
+
+    // Limits are configured per listener
+    let mut client = EsmpConnection::connect(20).await;
+    client.send_raw(&"a".repeat(4096)).await;
+    client.assert_error("parse").await;
+
+    // Per-connection frame rate
+    let mut client = EsmpConnection::connect_to(LIMITED, 21).await;
+    client.login().await;
+    for _ in 0..8 {
+        client.send(json!({"command": "fetch"})).await;
+        client.assert_read("messages").await;
+    }
+    client.send(json!({"command": "fetch"})).await;
+    client.assert_error("rate_limited").await;
+
+    // Per-sender quotas are shared by all sessions of a key
+    let recipient = EsmpConnection::connect(23).await.pubkey;
+    let mut client = EsmpConnection::connect_to(LIMITED, 22).await;
+    client.login().await;
+    for _ in 0..3 {
+        client
+            .send(json!({"to": [&recipient], "type": "text", "body": "Hi"}))
+            .await;
+        client.assert_read("ack").await;
+    }
+    let mut client = EsmpConnection::connect_to(LIMITED, 22).await;
+    client.login().await;
+    client
+        .send(json!({"to": [&recipient], "type": "text", "body": "Hi"}))
+        .await;
+    client.assert_error("rate_limited").await;
+
+    // Messages are limited in their number of recipients, which have to be keys or addresses
+    let mut client = EsmpConnection::connect_to(LIMITED, 28).await;
+    client.login().await;
+    let recipients = [&recipient, "bob#esmp.example.org", "bob@example.net"];
+    client
+        .send(json!({"to": &recipients[..2], "cc": &recipients[2..], "type": "text", "body": "Hi"}))
+        .await;
+    assert_eq!(
+        client.assert_error("invalid_message").await["reason"],
+        "Message exceeds the maximum number of recipients"
+    );
+    client
+        .send(json!({"to": [&recipient, "not a key"], "type": "text", "body": "Hi"}))
+        .await;
+    assert_eq!(
+        client.assert_error("invalid_message").await["reason"],
+        "Recipients must be public keys or addresses"
+    );
+    client
+        .send(json!({"to": [&recipient, &recipient], "cc": [&recipient], "type": "text", "body": "Hi"}))
+        .await;
+    client.assert_read("ack").await;
+
+    // Key package claims are limited per requester and per target
+    let target = EsmpConnection::connect(26).await.pubkey;
+    let mut client = EsmpConnection::connect_to(LIMITED, 25).await;
+    client.login().await;
+    for _ in 0..2 {
+        client
+            .send(json!({"command": "fetch_key_package", "pubkey": &target}))
+            .await;
+        client.assert_read("key_package").await;
+    }
+    client
+        .send(json!({"command": "fetch_key_package", "pubkey": &target}))
+        .await;
+    client.assert_error("rate_limited").await;
+    let mut client = EsmpConnection::connect_to(LIMITED, 27).await;
+    client.login().await;
+    client
+        .send(json!({"command": "fetch_key_package", "pubkey": &target}))
+        .await;
+    client.assert_read("key_package").await;
+    client
+        .send(json!({"command": "fetch_key_package", "pubkey": &target}))
+        .await;
+    client.assert_error("rate_limited").await;
+
+    // Idle sessions are closed
+    let mut client = EsmpConnection::connect_to(LIMITED, 24).await;
+    client.login().await;
+    tokio::time::sleep(Duration::from_millis(2500)).await;
+    client.assert_error("timeout").await;
+    client.assert_closed().await;
+}
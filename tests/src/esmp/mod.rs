@@ -0,0 +1,472 @@
+/*
+ * SPDX-FileCopyrightText: 2020 Stalwart Labs LLC <hello@stalw.art>
+ *
+ * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
+ */
+
+pub mod api;
+pub mod basic;
+pub mod blob;
+pub mod e2ee;
+pub mod encryption;
+pub mod federation;
+pub mod gateway;
+pub mod group;
+pub mod identity;
+pub mod inbox;
+pub mod invite;
+pub mod keys;
+pub mod limits;
+pub mod purge;
+pub mod reference;
+pub mod spam;
+pub mod webpush;
+pub mod websocket;
+
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use base64::{Engine, engine::general_purpose};
+use common::{
+    Caches, Core, Data, Inner, Server,
+    config::{
+        server::{Listeners, ServerProtocol},
+        telemetry::Telemetry,
+    },
+    core::BuildServer,
+    manager::boot::build_ipc,
+};
+use ed25519_dalek::SigningKey;
+use esmp::{EsmpSessionManager, SpawnEsmpServices, canonical::signing_input, crypto::sign_message};
+use http::HttpSessionManager;
+use serde_json::{Value, json};
+use services::SpawnServices;
+use store::{Stores, write::now};
+use tokio::{
+    io::{
+        AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader, Lines, ReadHalf,
+        WriteHalf,
+    },
+    net::TcpStream,
+    sync::watch,
+};
+use utils::config::Config;
+
+use crate::{AssertConfig, store::TempDir};
+
+#[tokio::test]
+pub async fn esmp_tests() {
+    // Prepare settings
+    let start_time = Instant::now();
+    let delete = true;
+    let handle = init_esmp_tests(
+        &std::env::var("STORE")
+            .expect("Missing store type. Try running `STORE=<store_type> cargo test`"),
+        delete,
+    )
+    .await;
+
+    basic::test().await;
+    inbox::test().await;
+    group::test().await;
+    e2ee::test().await;
+    identity::test(&handle.server).await;
+    keys::test().await;
+    federation::test().await;
+    gateway::test(&handle.server).await;
+    api::test().await;
+    encryption::test(&handle.server).await;
+    blob::test().await;
+    websocket::test().await;
+    webpush::test().await;
+    spam::test(&handle.server).await;
+    reference::test().await;
+    invite::test().await;
+    limits::test().await;
+    purge::test(&handle.server).await;
+
+    // Print elapsed time
+    let elapsed = start_time.elapsed();
+    println!(
+        "Elapsed: {}.{:03}s",
+        elapsed.as_secs(),
+        elapsed.subsec_millis()
+    );
+
+    // Remove test data
+    if delete {
+        handle.temp_dir.delete();
+    }
+}
+
+#[allow(dead_code)]
+pub struct EsmpTest {
+    server: Server,
+    temp_dir: TempDir,
+    shutdown_tx: watch::Sender<bool>,
+}
+
+async fn init_esmp_tests(store_id: &str, delete_if_exists: bool) -> EsmpTest {
+    // Load and parse config
+    let temp_dir = TempDir::new("esmp_tests", delete_if_exists);
+    let mut config = Config::new(
+        SERVER
+            .replace("{STORE}", store_id)
+            .replace("{TMP}", &temp_dir.path.display().to_string())
+            .replace(
+                "{SERVER_KEY}",
+                &general_purpose::STANDARD.encode([SERVER_KEY_SEED; 32]),
+            )
+            .replace("{SERVER_PUBKEY}", &pubkey_from_seed(SERVER_KEY_SEED))
+            .replace("{PEER_PUBKEY}", &pubkey_from_seed(PEER_KEY_SEED))
+            .replace(
+                "{LEVEL}",
+                &std::env::var("LOG").unwrap_or_else(|_| "disable".to_string()),
+            ),
+    )
+    .unwrap();
+    config.resolve_all_macros().await;
+
+    // Parse servers
+    let mut servers = Listeners::parse(&mut config);
+
+    // Bind ports and drop privileges
+    servers.bind_and_drop_priv(&mut config);
+
+    // Build stores
+    let stores = Stores::parse_all(&mut config, false).await;
+
+    // Parse core
+    let tracers = Telemetry::parse(&mut config, &stores);
+    let core = Core::parse(&mut config, stores, Default::default()).await;
+    let data = Data::parse(&mut config);
+    let cache = Caches::parse(&mut config);
+
+    let store = core.storage.data.clone();
+    let (ipc, mut ipc_rxs) = build_ipc(&mut config, false);
+    let inner = Arc::new(Inner {
+        shared_core: core.into_shared(),
+        data,
+        ipc,
+        cache,
+    });
+
+    // Parse acceptors
+    servers.parse_tcp_acceptors(&mut config, inner.clone());
+
+    // Enable tracing
+    tracers.enable(true);
+
+    // Start services
+    config.assert_no_errors();
+    ipc_rxs.spawn_services(inner.clone());
+    ipc_rxs.spawn_esmp_services(inner.clone());
+
+    // Spawn servers
+    let (shutdown_tx, _) = servers.spawn(|server, acceptor, shutdown_rx| {
+        match &server.protocol {
+            ServerProtocol::Esmp => server.spawn(
+                EsmpSessionManager::new(inner.clone()),
+                inner.clone(),
+                acceptor,
+                shutdown_rx,
+            ),
+            ServerProtocol::Http => server.spawn(
+                HttpSessionManager::new(inner.clone()),
+                inner.clone(),
+                acceptor,
+                shutdown_rx,
+            ),
+            _ => unreachable!(),
+        };
+    });
+
+    if delete_if_exists {
+        store.destroy().await;
+    }
+
+    EsmpTest {
+        server: inner.build_server(),
+        temp_dir,
+        shutdown_tx,
+    }
+}
+
+pub const SERVER_KEY_SEED: u8 = 70;
+pub const PEER_KEY_SEED: u8 = 71;
+
+pub fn pubkey_from_seed(seed: u8) -> String {
+    general_purpose::STANDARD.encode(
+        SigningKey::from_bytes(&[seed; 32])
+            .verifying_key()
+            .as_bytes(),
+    )
+}
+
+pub struct EsmpConnection<S = TcpStream> {
+    reader: Lines<BufReader<ReadHalf<S>>>,
+    writer: WriteHalf<S>,
+    key: SigningKey,
+    pub pubkey: String,
+    pub nonce: String,
+    next_id: u32,
+}
+
+impl EsmpConnection {
+    pub async fn connect(seed: u8) -> Self {
+        Self::connect_to("127.0.0.1:5888", seed).await
+    }
+
+    pub async fn connect_to(addr: &str, seed: u8) -> Self {
+        Self::connect_stream(TcpStream::connect(addr).await.unwrap(), seed).await
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> EsmpConnection<S> {
+    pub async fn connect_stream(stream: S, seed: u8) -> Self {
+        let (reader, writer) = tokio::io::split(stream);
+        let key = SigningKey::from_bytes(&[seed; 32]);
+        let mut conn = EsmpConnection {
+            reader: BufReader::new(reader).lines(),
+            writer,
+            pubkey: pubkey_from_seed(seed),
+            key,
+            nonce: String::new(),
+            next_id: 0,
+        };
+        conn.nonce = conn.assert_read("greeting").await["nonce"]
+            .as_str()
+            .unwrap()
+            .to_string();
+        conn
+    }
+
+    pub async fn login(&mut self) {
+        self.send_unsigned(json!({"command": "hello", "versions": [1]}))
+            .await;
+        self.assert_read("hello").await;
+        let nonce = self.nonce.clone();
+        self.send(json!({"command": "authenticate", "nonce": nonce}))
+            .await;
+        assert_eq!(
+            self.assert_read("authenticated").await["pubkey"],
+            self.pubkey.as_str()
+        );
+    }
+
+    pub async fn read(&mut self) -> Value {
+        match tokio::time::timeout(Duration::from_millis(1500), self.reader.next_line()).await {
+            Ok(Ok(Some(line))) => serde_json::from_str(&line).unwrap(),
+            Ok(Ok(None)) => panic!("Connection closed by server."),
+            Ok(Err(err)) => panic!("Connection broken: {err}"),
+            Err(_) => panic!("Timeout while waiting for server response."),
+        }
+    }
+
+    pub async fn assert_read(&mut self, typ: &str) -> Value {
+        let frame = self.read().await;
+        assert_eq!(frame["type"], typ, "unexpected frame: {frame}");
+        frame
+    }
+
+    pub async fn assert_error(&mut self, code: &str) -> Value {
+        let frame = self.assert_read("error").await;
+        assert_eq!(frame["code"], code, "unexpected error: {frame}");
+        frame
+    }
+
+    pub async fn assert_closed(&mut self) {
+        match tokio::time::timeout(Duration::from_millis(1500), self.reader.next_line()).await {
+            Ok(Ok(None) | Err(_)) => (),
+            Ok(Ok(Some(line))) => panic!("Unexpected frame: {line}"),
+            Err(_) => panic!("Timeout while waiting for connection to close."),
+        }
+    }
+
+    pub async fn assert_no_frames(&mut self) {
+        if let Ok(Ok(Some(line))) =
+            tokio::time::timeout(Duration::from_millis(500), self.reader.next_line()).await
+        {
+            panic!("Unexpected frame: {line}");
+        }
+    }
+
+    pub fn sign(&mut self, mut value: Value) -> Value {
+        let object = value.as_object_mut().unwrap();
+        self.next_id += 1;
+        object.entry("version").or_insert_with(|| Value::from(1));
+        // Ids are unique across connections, as keys are reused between tests
+        object
+            .entry("id")
+            .or_insert_with(|| Value::from(format!("msg-{}-{}", self.nonce, self.next_id)));
+        object
+            .entry("timestamp")
+            .or_insert_with(|| Value::from(now()));
+        let signature = self.signature(&value);
+        let object = value.as_object_mut().unwrap();
+        object.insert("signature".to_string(), Value::from(signature));
+        object.insert(
+            "sender_pubkey".to_string(),
+            Value::from(self.pubkey.clone()),
+        );
+        value
+    }
+
+    pub fn signature(&self, value: &Value) -> String {
+        sign_message(&self.key, &signing_input(value).unwrap())
+    }
+
+    pub async fn send(&mut self, value: Value) -> String {
+        let value = self.sign(value);
+        self.send_raw(&value.to_string()).await;
+        value["id"].as_str().unwrap().to_string()
+    }
+
+    pub async fn send_unsigned(&mut self, value: Value) {
+        self.send_raw(&value.to_string()).await;
+    }
+
+    pub async fn send_raw(&mut self, text: &str) {
+        self.send_bytes(text.as_bytes()).await;
+        self.send_bytes(b"\n").await;
+    }
+
+    pub async fn send_bytes(&mut self, bytes: &[u8]) {
+        self.writer.write_all(bytes).await.unwrap();
+    }
+}
+
+const SERVER: &str = r#"
+[server]
+hostname = "esmp.example.org"
+
+[server.listener.esmp]
+bind = ["127.0.0.1:5888"]
+protocol = "esmp"
+max-connections = 81920
+
+[server.listener.esmp-limited]
+bind = ["127.0.0.1:5889"]
+protocol = "esmp"
+esmp.limits.frame-size = 2048
+esmp.limits.recipients = 2
+esmp.timeout.idle = "2s"
+esmp.rate-limit.connection = "10/1m"
+esmp.rate-limit.sender = "3/1d"
+esmp.rate-limit.key-package.requester = "2/1d"
+esmp.rate-limit.key-package.target = "3/1d"
+
+[server.listener.http]
+bind = ["127.0.0.1:5880"]
+protocol = "http"
+
+[server.socket]
+reuse-addr = true
+
+[esmp.replay]
+window = "5m"
+
+[esmp.encryption]
+active = "current"
+
+[esmp.blob]
+max-size = 2048
+quota = 4096
+
+[esmp.encryption.key]
+previous = "ERERERERERERERERERERERERERERERERERERERERERE="
+current = "IiIiIiIiIiIiIiIiIiIiIiIiIiIiIiIiIiIiIiIiIiI="
+
+[esmp.federation]
+enable = true
+domain = "esmp.example.org"
+key = "{SERVER_KEY}"
+timeout = "2s"
+
+[esmp.federation.peer."esmp.example.org"]
+host = "127.0.0.1"
+port = 5888
+tls = false
+key = "{SERVER_PUBKEY}"
+
+[esmp.federation.peer."remote.example.org"]
+host = "127.0.0.1"
+port = 5890
+tls = false
+key = "{PEER_PUBKEY}"
+
+[esmp.federation.peer."offline.example.org"]
+host = "127.0.0.1"
+port = 5891
+tls = false
+
+[esmp.queue]
+retry = ["1s"]
+expire = "3s"
+
+[esmp.gateway]
+outbound = true
+inbound = true
+
+[esmp.spam]
+enable = true
+action = "quarantine"
+threshold = 5.0
+
+[spam-filter.list.scores]
+URL_ONLY = 6.0
+
+[spam-filter.pyzor]
+enable = false
+
+[spam-filter.reputation]
+enable = true
+
+[store."sqlite"]
+type = "sqlite"
+path = "{TMP}/sqlite.db"
+
+[store."rocksdb"]
+type = "rocksdb"
+path = "{TMP}/rocks.db"
+
+[store."foundationdb"]
+type = "foundationdb"
+
+[store."postgresql"]
+type = "postgresql"
+host = "localhost"
+port = 5432
+database = "stalwart"
+user = "postgres"
+password = "mysecretpassword"
+
+[store."mysql"]
+type = "mysql"
+host = "localhost"
+port = 3307
+database = "stalwart"
+user = "root"
+password = "password"
+
+[storage]
+data = "{STORE}"
+fts = "{STORE}"
+blob = "{STORE}"
+lookup = "{STORE}"
+directory = "{STORE}"
+
+[directory."{STORE}"]
+type = "internal"
+store = "{STORE}"
+
+[tracer.console]
+type = "console"
+level = "{LEVEL}"
+multiline = false
+ansi = true
+disabled-events = ["network.*"]
+
+"#;
@@ -0,0 +1,103 @@
+/*
+ * SPDX-FileCopyrightText: 2020 Stalwart Labs LLC <hello@stalw.art>
+ *
+ * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
+ */
+
+use std::sync::Arc;
+
+use common::{ESMP_ACCOUNT_ID, Server, esmp_inbox_account_id, manager::backup::BackupParams};
+use email::message::delete::EmailDeletion;
+use esmp::inbox::persist::InboxStore;
+use jmap_proto::types::collection::SyncCollection;
+use reqwest::StatusCode;
+use serde_json::json;
+use store::query::log::Query;
+
+use super::{
+    EsmpConnection,
+    blob::{download, upload},
+};
+use crate::store::TempDir;
+
+pub async fn test(server: &Server) {
+    println!("Running purge and backup tests...");
+
+    let mut alice = EsmpConnection::connect(101).await;
+    let mut bob = EsmpConnection::connect(102).await;
+    alice.login().await;
+    bob.login().await;
+    let bob_pubkey = bob.pubkey.clone();
+
+    let (status, response) = upload(&alice, "", "text/plain", b"Purged notes").await;
+    assert_eq!(status, StatusCode::CREATED, "{response}");
+    let blob_id = response["data"]["blob_id"].as_str().unwrap().to_string();
+    let blob_path = format!("/api/esmp/blobs/{blob_id}");
+    for text in ["One", "Two", "Three", "Four"] {
+        alice
+            .send(json!({"to": [&bob_pubkey], "type": "text", "body": {"text": text, "attachments": [{"blob_id": &blob_id}]}}))
+            .await;
+        alice.assert_read("ack").await;
+        bob.assert_read("push").await;
+    }
+
+    // ESMP change logs are purged along with those of principals
+    let inbox_account_id = esmp_inbox_account_id(
+        server
+            .inbox_document_id(&bob_pubkey)
+            .await
+            .unwrap()
+            .unwrap(),
+    );
+    assert_eq!(changes(server, inbox_account_id).await, 4);
+    assert!(changes(server, ESMP_ACCOUNT_ID).await > 4);
+    let mut core = server.core.as_ref().clone();
+    core.jmap.changes_max_history = Some(1);
+    Server {
+        inner: server.inner.clone(),
+        core: Arc::new(core),
+    }
+    .purge_accounts()
+    .await;
+    let inbox_changes = changes(server, inbox_account_id).await;
+    assert!(inbox_changes <= 2, "{inbox_changes}");
+    let account_changes = changes(server, ESMP_ACCOUNT_ID).await;
+    assert!(account_changes <= 2, "{account_changes}");
+
+    // ESMP data survives a backup and restore
+    let temp_dir = TempDir::new("esmp_backup_tests", true);
+    server
+        .core
+        .backup(BackupParams::new(temp_dir.path.clone()))
+        .await;
+    server.store().destroy().await;
+    server.core.restore(temp_dir.path.clone()).await;
+    temp_dir.delete();
+    assert_eq!(changes(server, inbox_account_id).await, inbox_changes);
+    assert_eq!(changes(server, ESMP_ACCOUNT_ID).await, account_changes);
+
+    // Restored files are still linked to their records
+    server
+        .store()
+        .purge_blobs(server.blob_store().clone())
+        .await
+        .unwrap();
+    for conn in [&alice, &bob] {
+        let (status, contents, _) = download(Some(conn), &blob_path).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(contents, b"Purged notes");
+    }
+
+    alice.assert_no_frames().await;
+    bob.assert_no_frames().await;
+}
+
+async fn changes(server: &Server, account_id: u32) -> usize {
+    server
+        .store()
+        .changes(account_id, SyncCollection::EsmpInbox, Query::All)
+        .await
+        .unwrap()
+        .changes
+        .len()
+}
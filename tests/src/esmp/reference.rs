@@ -0,0 +1,201 @@
+/*
+ * SPDX-FileCopyrightText: 2020 Stalwart Labs LLC <hello@stalw.art>
+ *
+ * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
+ */
+
+use reqwest::Method;
+use serde_json::{Value, json};
+
+use super::{EsmpConnection, api::signed};
+
+const GROUP_ID: &str = "esmp-reference-group";
+
+pub async fn test() {
+    println!("Running ESMP edit, retract and reaction tests...");
+
+    let mut alice = EsmpConnection::connect(94).await;
+    let mut bob = EsmpConnection::connect(95).await;
+    let mut carol = EsmpConnection::connect(96).await;
+    alice.login().await;
+    bob.login().await;
+    carol.login().await;
+    let alice_pubkey = alice.pubkey.clone();
+    let bob_pubkey = bob.pubkey.clone();
+
+    // Create a group with bob as a member
+    alice
+        .send(json!({"to": [], "group_id": GROUP_ID, "type": "system", "subtype": "group_created", "actor": &alice_pubkey, "body": {}}))
+        .await;
+    alice.assert_read("ack").await;
+    bob.send(json!({"to": [], "group_id": GROUP_ID, "type": "system", "subtype": "joined", "actor": &bob_pubkey, "body": ""}))
+        .await;
+    bob.assert_read("ack").await;
+    alice.assert_read("push").await;
+    alice
+        .send(
+            json!({"id": "hello", "to": [], "group_id": GROUP_ID, "type": "text", "body": "Helo"}),
+        )
+        .await;
+    alice.assert_read("ack").await;
+    bob.assert_read("push").await;
+
+    // References are validated before the group policy
+    for message in [
+        json!({"to": [], "group_id": GROUP_ID, "type": "reaction", "ref_id": "hello", "body": {}}),
+        json!({"to": [], "group_id": GROUP_ID, "type": "edit", "body": "Hello"}),
+        json!({"to": [], "group_id": GROUP_ID, "type": "text", "ref_id": "hello", "body": "Hi"}),
+        json!({"to": [], "group_id": GROUP_ID, "type": "retract", "ref_id": "hello", "reply_to": "hello", "body": {}}),
+    ] {
+        bob.send(message).await;
+        bob.assert_error("invalid_message").await;
+    }
+
+    // Replies must name an existing message
+    bob.send(json!({"to": [], "group_id": GROUP_ID, "type": "text", "reply_to": "missing", "body": "Hi"}))
+        .await;
+    bob.assert_error("forbidden").await;
+    bob.send(json!({"to": [], "group_id": GROUP_ID, "type": "text", "reply_to": "hello", "body": "Hi Alice"}))
+        .await;
+    bob.assert_read("ack").await;
+    alice.assert_read("push").await;
+
+    // Only the author can edit or retract a message
+    for typ in ["edit", "retract"] {
+        bob.send(
+            json!({"to": [], "group_id": GROUP_ID, "type": typ, "ref_id": "hello", "body": "Bye"}),
+        )
+        .await;
+        assert_eq!(
+            bob.assert_error("forbidden").await["reason"],
+            "Only the original author can edit or retract a message"
+        );
+    }
+    alice
+        .send(json!({"to": [], "group_id": GROUP_ID, "type": "edit", "ref_id": "hello", "body": "Hello"}))
+        .await;
+    alice.assert_read("ack").await;
+    assert_eq!(bob.assert_read("push").await["message"]["type"], "edit");
+
+    // Any member can react, once per reaction
+    bob.send(json!({"id": "bob-like", "to": [], "group_id": GROUP_ID, "type": "reaction", "ref_id": "hello", "body": {"reaction": "+1"}}))
+        .await;
+    bob.assert_read("ack").await;
+    alice.assert_read("push").await;
+    bob.send(json!({"to": [], "group_id": GROUP_ID, "type": "reaction", "ref_id": "hello", "body": {"reaction": "+1"}}))
+        .await;
+    bob.assert_error("forbidden").await;
+    alice
+        .send(json!({"to": [], "group_id": GROUP_ID, "type": "reaction", "ref_id": "hello", "body": {"reaction": "+1"}}))
+        .await;
+    alice.assert_read("ack").await;
+    bob.assert_read("push").await;
+
+    // The history shows messages with their latest edit and grouped reactions
+    let entry = history_entry(&alice, "hello").await;
+    assert_eq!(entry["message"]["body"], "Helo");
+    assert_eq!(entry["edit"]["body"], "Hello");
+    assert_eq!(
+        entry["reactions"],
+        json!([{"reaction": "+1", "count": 2, "identities": [&bob_pubkey, &alice_pubkey]}])
+    );
+    assert_eq!(entry["retracted"], false);
+
+    // Retracted reactions are removed from the message
+    bob.send(json!({"to": [], "group_id": GROUP_ID, "type": "retract", "ref_id": "bob-like", "body": {}}))
+        .await;
+    bob.assert_read("ack").await;
+    alice.assert_read("push").await;
+    let entry = history_entry(&bob, "hello").await;
+    assert_eq!(
+        entry["reactions"],
+        json!([{"reaction": "+1", "count": 1, "identities": [&alice_pubkey]}])
+    );
+
+    // Retracted messages are kept as tombstones without contents
+    alice
+        .send(json!({"to": [], "group_id": GROUP_ID, "type": "retract", "ref_id": "hello", "body": {}}))
+        .await;
+    alice.assert_read("ack").await;
+    bob.assert_read("push").await;
+    let (_, response) = signed(
+        &bob,
+        Method::GET,
+        &format!("/api/esmp/groups/{GROUP_ID}/messages"),
+        None,
+    )
+    .await;
+    let tombstone = response["data"]["messages"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|entry| entry["retracted"] == true && entry["message"].is_null())
+        .cloned()
+        .unwrap_or_else(|| panic!("{response}"));
+    assert!(tombstone.get("edit").is_none(), "{tombstone}");
+    assert!(tombstone.get("reactions").is_none(), "{tombstone}");
+    alice
+        .send(json!({"to": [], "group_id": GROUP_ID, "type": "edit", "ref_id": "hello", "body": "Hello again"}))
+        .await;
+    alice.assert_error("forbidden").await;
+
+    // Direct messages are only retracted by their author
+    alice
+        .send(
+            json!({"id": "dm-1", "to": [&bob_pubkey], "type": "text", "body": "Dinner at eight?"}),
+        )
+        .await;
+    let dm_id = alice.assert_read("ack").await["message_ids"][0].clone();
+    bob.assert_read("push").await;
+    carol
+        .send(json!({"id": "dm-2", "to": [&bob_pubkey], "type": "text", "body": "Lunch at noon?"}))
+        .await;
+    let carol_dm_id = carol.assert_read("ack").await["message_ids"][0].clone();
+    bob.assert_read("push").await;
+    carol
+        .send(json!({"to": [&bob_pubkey], "type": "retract", "ref_id": "dm-1", "body": {}}))
+        .await;
+    carol.assert_read("ack").await;
+    bob.assert_read("push").await;
+    assert!(inbox_ids(&mut bob).await.contains(&dm_id));
+    alice
+        .send(json!({"to": [&bob_pubkey], "type": "retract", "ref_id": "dm-1", "body": {}}))
+        .await;
+    alice.assert_read("ack").await;
+    assert_eq!(bob.assert_read("push").await["message"]["type"], "retract");
+    let ids = inbox_ids(&mut bob).await;
+    assert!(!ids.contains(&dm_id));
+    assert!(ids.contains(&carol_dm_id));
+
+    alice.assert_no_frames().await;
+    bob.assert_no_frames().await;
+    carol.assert_no_frames().await;
+}
+
+async fn history_entry(conn: &EsmpConnection, id: &str) -> Value {
+    let (_, response) = signed(
+        conn,
+        Method::GET,
+        &format!("/api/esmp/groups/{GROUP_ID}/messages"),
+        None,
+    )
+    .await;
+    response["data"]["messages"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|entry| entry["message"]["id"] == id)
+        .cloned()
+        .unwrap_or_else(|| panic!("{response}"))
+}
+
+async fn inbox_ids(conn: &mut EsmpConnection) -> Vec<Value> {
+    conn.send(json!({"command": "fetch", "include_acked": true}))
+        .await;
+    conn.assert_read("messages").await["messages"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|entry| entry["id"].clone())
+        .collect()
+}
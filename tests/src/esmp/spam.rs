@@ -0,0 +1,101 @@
+/*
+ * SPDX-FileCopyrightText: 2020 Stalwart Labs LLC <hello@stalw.art>
+ *
+ * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
+ */
+
+use common::{KV_REPUTATION_ESMP, Server};
+use serde_json::json;
+use spam_filter::analysis::reputation::SpamFilterAnalyzeReputation;
+
+use super::EsmpConnection;
+
+pub async fn test(server: &Server) {
+    println!("Running ESMP spam filter tests...");
+
+    let mut alice = EsmpConnection::connect(92).await;
+    let mut bob = EsmpConnection::connect(93).await;
+    alice.login().await;
+    bob.login().await;
+    let alice_pubkey = alice.pubkey.clone();
+    let bob_pubkey = bob.pubkey.clone();
+
+    // Legitimate messages are delivered untagged
+    alice
+        .send(json!({"to": [&bob_pubkey], "type": "text", "body": "Lunch tomorrow?"}))
+        .await;
+    let ham_id = alice.assert_read("ack").await["message_ids"][0]
+        .as_u64()
+        .unwrap();
+    let push = bob.assert_read("push").await;
+    assert_eq!(push["id"], ham_id);
+    assert!(push.get("spam_score").is_none(), "{push}");
+
+    // Spam is accepted but held in quarantine without being pushed
+    alice
+        .send(json!({"to": [&bob_pubkey], "type": "text", "body": "https://win.example.net/prize"}))
+        .await;
+    let spam_id = alice.assert_read("ack").await["message_ids"][0]
+        .as_u64()
+        .unwrap();
+    bob.assert_no_frames().await;
+    bob.send(json!({"command": "fetch"})).await;
+    let messages = bob.assert_read("messages").await;
+    assert_eq!(messages["messages"].as_array().unwrap().len(), 1);
+    assert_eq!(messages["messages"][0]["id"], ham_id);
+    bob.send(json!({"command": "fetch", "quarantined": true}))
+        .await;
+    let messages = bob.assert_read("messages").await;
+    assert_eq!(messages["messages"].as_array().unwrap().len(), 1);
+    assert_eq!(messages["messages"][0]["id"], spam_id);
+    assert!(messages["messages"][0]["spam_score"].as_f64().unwrap() >= 5.0);
+
+    // Training as ham releases the message from the quarantine
+    bob.send(json!({"command": "train", "class": "ham", "ids": [spam_id, 9999]}))
+        .await;
+    assert_eq!(bob.assert_read("ok").await["ids"], json!([spam_id]));
+    bob.send(json!({"command": "fetch", "quarantined": true}))
+        .await;
+    assert_eq!(bob.assert_read("messages").await["messages"], json!([]));
+    bob.send(json!({"command": "fetch", "after": ham_id})).await;
+    let messages = bob.assert_read("messages").await;
+    assert_eq!(messages["messages"][0]["id"], spam_id);
+    assert!(messages["messages"][0].get("spam_score").is_none());
+
+    // Training as spam moves the message to the quarantine
+    bob.send(json!({"command": "train", "class": "spam", "ids": [ham_id]}))
+        .await;
+    assert_eq!(bob.assert_read("ok").await["ids"], json!([ham_id]));
+    bob.send(json!({"command": "fetch", "quarantined": true}))
+        .await;
+    let messages = bob.assert_read("messages").await;
+    assert_eq!(messages["messages"].as_array().unwrap().len(), 1);
+    assert_eq!(messages["messages"][0]["id"], ham_id);
+
+    // Repeated reports do not train the message again
+    let reputation = server
+        .spam_filter_reputation(0, KV_REPUTATION_ESMP, alice_pubkey.as_bytes(), 0.0, true)
+        .await;
+    assert!(reputation.is_some());
+    bob.send(json!({"command": "train", "class": "spam", "ids": [ham_id]}))
+        .await;
+    assert_eq!(bob.assert_read("ok").await["ids"], json!([ham_id]));
+    assert_eq!(
+        server
+            .spam_filter_reputation(0, KV_REPUTATION_ESMP, alice_pubkey.as_bytes(), 0.0, true)
+            .await,
+        reputation
+    );
+
+    // Messages of other inboxes cannot be trained on
+    alice
+        .send(json!({"command": "train", "class": "spam", "ids": [spam_id]}))
+        .await;
+    assert_eq!(alice.assert_read("ok").await["ids"], json!([]));
+    bob.send(json!({"command": "train", "class": "junk", "ids": [spam_id]}))
+        .await;
+    bob.assert_error("parse").await;
+
+    alice.assert_no_frames().await;
+    bob.assert_no_frames().await;
+}
@@ -0,0 +1,161 @@
+/*
+ * SPDX-FileCopyrightText: 2020 Stalwart Labs LLC <hello@stalw.art>
+ *
+ * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
+ */
+
+use std::sync::Arc;
+
+use base64::{Engine, engine::general_purpose};
+use common::{Caches, Core, Data, Inner, config::server::Listeners};
+use jmap_proto::types::id::Id;
+use serde_json::json;
+use tokio::sync::mpsc;
+use utils::config::Config;
+
+use crate::{
+    AssertConfig, add_test_certs,
+    jmap::push_subscription::{
+        PushMessage, PushServer, SessionManager, expect_nothing, expect_push,
+    },
+};
+
+use super::EsmpConnection;
+
+const PUSH_SERVER: &str = r#"
+[server]
+hostname = "'esmp-push.example.org'"
+
+[server.listener.push]
+bind = ['127.0.0.1:5892']
+protocol = 'http'
+tls.implicit = true
+
+[server.socket]
+reuse-addr = true
+
+[certificate.default]
+cert = '%{file:{CERT}}%'
+private-key = '%{file:{PK}}%'
+default = true
+"#;
+
+pub async fn test() {
+    println!("Running Web Push tests...");
+
+    // Start mock push server
+    let (event_tx, mut event_rx) = mpsc::channel::<PushMessage>(100);
+    let (keypair, auth_secret) = ece::generate_keypair_and_auth_secret().unwrap();
+    let p256dh = general_purpose::URL_SAFE_NO_PAD.encode(keypair.pub_as_raw().unwrap());
+    let auth = general_purpose::URL_SAFE_NO_PAD.encode(auth_secret);
+    let push_server = Arc::new(PushServer {
+        keypair: keypair.raw_components().unwrap(),
+        auth_secret: auth_secret.to_vec(),
+        tx: event_tx,
+        fail_requests: false.into(),
+    });
+    let mut settings = Config::new(add_test_certs(PUSH_SERVER)).unwrap();
+    settings.resolve_all_macros().await;
+    let mock_inner = Arc::new(Inner {
+        shared_core: Core::parse(&mut settings, Default::default(), Default::default())
+            .await
+            .into_shared(),
+        data: Data::parse(&mut settings),
+        cache: Caches::parse(&mut settings),
+        ..Default::default()
+    });
+    settings.errors.clear();
+    settings.warnings.clear();
+    let mut servers = Listeners::parse(&mut settings);
+    servers.parse_tcp_acceptors(&mut settings, mock_inner.clone());
+    servers.bind_and_drop_priv(&mut settings);
+    settings.assert_no_errors();
+    let _shutdown_tx = servers.spawn(|server, acceptor, shutdown_rx| {
+        server.spawn(
+            SessionManager::from(push_server.clone()),
+            mock_inner.clone(),
+            acceptor,
+            shutdown_rx,
+        );
+    });
+
+    let mut alice = EsmpConnection::connect(90).await;
+    let mut bob = EsmpConnection::connect(91).await;
+    alice.login().await;
+    bob.login().await;
+    let alice_pubkey = alice.pubkey.clone();
+    let bob_pubkey = bob.pubkey.clone();
+    let account_id = Id::from(esmp::ESMP_ACCOUNT_ID);
+
+    // Endpoints and keys are validated
+    bob.send(json!({"command": "register_push", "url": "http://127.0.0.1:5892/push"}))
+        .await;
+    bob.assert_error("invalid_request").await;
+    bob.send(json!({
+        "command": "register_push",
+        "url": "https://127.0.0.1:5892/push",
+        "keys": {"p256dh": &auth, "auth": &auth},
+    }))
+    .await;
+    bob.assert_error("invalid_request").await;
+    bob.send(
+        json!({"command": "register_push", "url": "https://127.0.0.1:5892/push", "expires": 1}),
+    )
+    .await;
+    bob.assert_error("invalid_request").await;
+
+    // Register an encrypted subscription for the device key
+    bob.send(json!({
+        "command": "register_push",
+        "url": "https://127.0.0.1:5892/push",
+        "keys": {"p256dh": &p256dh, "auth": &auth},
+    }))
+    .await;
+    let registered = bob.assert_read("push_registered").await;
+    assert_eq!(registered["pubkey"], bob_pubkey.as_str());
+    assert!(registered["expires"].as_u64().unwrap() > 0);
+
+    // Deliveries wake up the recipient with a minimal state change
+    alice
+        .send(json!({"to": [&bob_pubkey], "type": "text", "body": "Wake up"}))
+        .await;
+    alice.assert_read("ack").await;
+    bob.assert_read("push").await;
+    match expect_push(&mut event_rx).await {
+        PushMessage::EsmpStateChange(state_change) => {
+            assert_eq!(
+                state_change
+                    .changed
+                    .get(&account_id)
+                    .unwrap()
+                    .iter()
+                    .map(|(state_type, _)| state_type.as_str())
+                    .collect::<Vec<_>>(),
+                ["EsmpInboxMessage"]
+            );
+        }
+        message => panic!("Expected an ESMP state change: {message:?}"),
+    }
+
+    // Deliveries to other identities do not wake up the device
+    bob.send(json!({"to": [&alice_pubkey], "type": "text", "body": "Awake"}))
+        .await;
+    bob.assert_read("ack").await;
+    alice.assert_read("push").await;
+    expect_nothing(&mut event_rx).await;
+
+    // Unregistered devices are no longer woken up
+    bob.send(json!({"command": "unregister_push"})).await;
+    bob.assert_read("ok").await;
+    bob.send(json!({"command": "unregister_push"})).await;
+    bob.assert_error("invalid_request").await;
+    alice
+        .send(json!({"to": [&bob_pubkey], "type": "text", "body": "Still there?"}))
+        .await;
+    alice.assert_read("ack").await;
+    bob.assert_read("push").await;
+    expect_nothing(&mut event_rx).await;
+
+    alice.assert_no_frames().await;
+    bob.assert_no_frames().await;
+}
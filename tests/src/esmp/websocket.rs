@@ -0,0 +1,95 @@
+/*
+ * SPDX-FileCopyrightText: 2020 Stalwart Labs LLC <hello@stalw.art>
+ *
+ * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
+ */
+
+use futures::{SinkExt, StreamExt};
+use reqwest::StatusCode;
+use serde_json::json;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader, DuplexStream};
+use tokio_tungstenite::{connect_async, tungstenite::Message};
+
+use super::EsmpConnection;
+
+pub async fn test() {
+    println!("Running WebSocket tests...");
+
+    // Plain requests cannot be upgraded
+    let response = reqwest::get("http://127.0.0.1:5880/api/esmp/ws")
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+    // WebSocket sessions use the same handshake as TCP sessions
+    let mut alice = connect_websocket(85).await;
+    let mut bob = EsmpConnection::connect(86).await;
+    alice.send(json!({"command": "fetch", "id": "early"})).await;
+    assert_eq!(alice.assert_error("bad_sequence").await["id"], "early");
+    alice.login().await;
+    bob.login().await;
+    let alice_pubkey = alice.pubkey.clone();
+    let bob_pubkey = bob.pubkey.clone();
+
+    // Messages are pushed across transports
+    let message_id = bob
+        .send(json!({"to": [&alice_pubkey], "type": "text", "body": "Hello from TCP"}))
+        .await;
+    let inbox_id = bob.assert_read("ack").await["message_ids"][0].clone();
+    let push = alice.assert_read("push").await;
+    assert_eq!(push["id"], inbox_id);
+    assert_eq!(push["message"]["id"], message_id.as_str());
+    alice
+        .send(json!({"to": [&bob_pubkey], "type": "text", "body": "Hello from WebSocket"}))
+        .await;
+    alice.assert_read("ack").await;
+    assert_eq!(
+        bob.assert_read("push").await["message"]["body"],
+        "Hello from WebSocket"
+    );
+    alice
+        .send(json!({"command": "ack", "ids": [&inbox_id]}))
+        .await;
+    assert_eq!(alice.assert_read("ok").await["ids"], json!([inbox_id]));
+
+    // Frames are validated as on the TCP transport
+    alice.send_raw("{not json").await;
+    alice.assert_error("parse").await;
+
+    alice.assert_no_frames().await;
+    bob.assert_no_frames().await;
+}
+
+/// Bridges a WebSocket session to a byte stream, one text message per line.
+async fn connect_websocket(seed: u8) -> EsmpConnection<DuplexStream> {
+    let (mut ws, _) = connect_async("ws://127.0.0.1:5880/api/esmp/ws")
+        .await
+        .unwrap();
+    let (client, server) = tokio::io::duplex(1024 * 1024);
+    let (reader, mut writer) = tokio::io::split(server);
+    let mut lines = BufReader::new(reader).lines();
+
+    tokio::spawn(async move {
+        loop {
+            tokio::select! {
+                message = ws.next() => match message {
+                    Some(Ok(Message::Text(text))) => {
+                        writer.write_all(text.as_bytes()).await.unwrap();
+                        writer.write_all(b"\n").await.unwrap();
+                    }
+                    Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                    Some(Ok(_)) => (),
+                },
+                line = lines.next_line() => match line {
+                    Ok(Some(line)) => ws.send(Message::Text(line.into())).await.unwrap(),
+                    _ => {
+                        let _ = ws.close(None).await;
+                        break;
+                    }
+                },
+            }
+        }
+    });
+
+    EsmpConnection::connect_stream(client, seed).await
+}
@@ -36,6 +36,7 @@ use common::{
     core::BuildServer,
     manager::boot::build_ipc,
 };
+use esmp::EsmpSessionManager;
 use http::HttpSessionManager;
 use imap::core::ImapSessionManager;
 use imap_proto::ResponseType;
@@ -222,6 +223,12 @@ async fn init_imap_tests(store_id: &str, delete_if_exists: bool) -> IMAPTest {
                 acceptor,
                 shutdown_rx,
             ),
+            ServerProtocol::Esmp => server.spawn(
+                EsmpSessionManager::new(inner.clone()),
+                inner.clone(),
+                acceptor,
+                shutdown_rx,
+            ),
         };
     });
 
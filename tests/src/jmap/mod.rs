@@ -25,6 +25,7 @@ use common::{
 };
 use email::message::delete::EmailDeletion;
 use enterprise::{EnterpriseCore, insert_test_metrics};
+use esmp::EsmpSessionManager;
 use http::HttpSessionManager;
 use hyper::{Method, header::AUTHORIZATION};
 use imap::core::ImapSessionManager;
@@ -355,6 +356,12 @@ async fn init_jmap_tests(store_id: &str, delete_if_exists: bool) -> JMAPTest {
                 acceptor,
                 shutdown_rx,
             ),
+            ServerProtocol::Esmp => server.spawn(
+                EsmpSessionManager::new(inner.clone()),
+                inner.clone(),
+                acceptor,
+                shutdown_rx,
+            ),
         };
     });
 
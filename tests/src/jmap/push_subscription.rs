@@ -20,14 +20,14 @@ use hyper::{StatusCode, body, header::CONTENT_ENCODING, server::conn::http1, ser
 use hyper_util::rt::TokioIo;
 use jmap_client::{mailbox::Role, push_subscription::Keys};
 use jmap_proto::{
-    response::status::StateChangeResponse,
-    types::{id::Id, type_state::DataType},
+    response::status::{StateChangeResponse, StateChangeType},
+    types::{id::Id, state::State, type_state::DataType},
 };
 use services::state_manager::ece::ece_encrypt;
 use store::ahash::AHashSet;
 
 use tokio::sync::mpsc;
-use utils::config::Config;
+use utils::{config::Config, map::vec_map::VecMap};
 
 use crate::{
     AssertConfig, add_test_certs,
@@ -241,17 +241,25 @@ impl From<Arc<PushServer>> for SessionManager {
     }
 }
 pub struct PushServer {
-    keypair: EcKeyComponents,
-    auth_secret: Vec<u8>,
-    tx: mpsc::Sender<PushMessage>,
-    fail_requests: AtomicBool,
+    pub keypair: EcKeyComponents,
+    pub auth_secret: Vec<u8>,
+    pub tx: mpsc::Sender<PushMessage>,
+    pub fail_requests: AtomicBool,
 }
 
 #[derive(serde::Deserialize, Debug)]
 #[serde(untagged)]
-enum PushMessage {
+pub enum PushMessage {
     StateChange(StateChangeResponse),
     Verification(PushVerification),
+    EsmpStateChange(EsmpStateChange),
+}
+
+#[derive(serde::Deserialize, Debug)]
+pub struct EsmpStateChange {
+    #[serde(rename = "@type")]
+    _type: StateChangeType,
+    pub changed: VecMap<Id, VecMap<String, State>>,
 }
 
 impl PushMessage {
@@ -276,7 +284,7 @@ enum PushVerificationType {
 }
 
 #[derive(serde::Deserialize, Debug)]
-struct PushVerification {
+pub struct PushVerification {
     #[serde(rename = "@type")]
     _type: PushVerificationType,
     #[serde(rename = "pushSubscriptionId")]
@@ -348,7 +356,7 @@ impl common::listener::SessionManager for SessionManager {
     }
 }
 
-async fn expect_push(event_rx: &mut mpsc::Receiver<PushMessage>) -> PushMessage {
+pub async fn expect_push(event_rx: &mut mpsc::Receiver<PushMessage>) -> PushMessage {
     match tokio::time::timeout(Duration::from_millis(1500), event_rx.recv()).await {
         Ok(Some(push)) => {
             //println!("Push received: {:?}", push);
@@ -360,7 +368,7 @@ async fn expect_push(event_rx: &mut mpsc::Receiver<PushMessage>) -> PushMessage
     }
 }
 
-async fn expect_nothing(event_rx: &mut mpsc::Receiver<PushMessage>) {
+pub async fn expect_nothing(event_rx: &mut mpsc::Receiver<PushMessage>) {
     match tokio::time::timeout(Duration::from_millis(1000), event_rx.recv()).await {
         Err(_) => {}
         message => {
@@ -369,7 +377,7 @@ async fn expect_nothing(event_rx: &mut mpsc::Receiver<PushMessage>) {
     }
 }
 
-async fn assert_state(event_rx: &mut mpsc::Receiver<PushMessage>, id: &Id, state: &[DataType]) {
+pub async fn assert_state(event_rx: &mut mpsc::Receiver<PushMessage>, id: &Id, state: &[DataType]) {
     assert_eq!(
         expect_push(event_rx)
             .await
@@ -20,6 +20,8 @@ pub mod cluster;
 #[cfg(test)]
 pub mod directory;
 #[cfg(test)]
+pub mod esmp;
+#[cfg(test)]
 pub mod http_server;
 #[cfg(test)]
 pub mod imap;
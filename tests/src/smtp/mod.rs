@@ -271,7 +271,10 @@ impl TestSMTP {
                         acceptor,
                         shutdown_rx,
                     ),
-                    ServerProtocol::Imap | ServerProtocol::Pop3 | ServerProtocol::ManageSieve => {
+                    ServerProtocol::Imap
+                    | ServerProtocol::Pop3
+                    | ServerProtocol::ManageSieve
+                    | ServerProtocol::Esmp => {
                         unreachable!()
                     }
                 };
@@ -26,6 +26,7 @@ use dav_proto::{
     xml_pretty_print,
 };
 use directory::Permission;
+use esmp::EsmpSessionManager;
 use groupware::{DavResourceName, cache::GroupwareCache};
 use http::HttpSessionManager;
 use hyper::{HeaderMap, Method, StatusCode, header::AUTHORIZATION};
@@ -190,6 +191,12 @@ async fn init_webdav_tests(store_id: &str, delete_if_exists: bool) -> WebDavTest
                 acceptor,
                 shutdown_rx,
             ),
+            ServerProtocol::Esmp => server.spawn(
+                EsmpSessionManager::new(inner.clone()),
+                inner.clone(),
+                acceptor,
+                shutdown_rx,
+            ),
         };
     });
 